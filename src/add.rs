@@ -1,10 +1,11 @@
 use std::fs;
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
 use clap::Args;
 
-use crate::{config, cron, init, systemctl, unit};
+use crate::{bluegreen, config, cron, health, init, output, schema, systemctl, unit};
 
 #[derive(Args)]
 pub struct AddOptions {
@@ -48,6 +49,12 @@ pub struct AddOptions {
     /// Max log level to store (emerg/alert/crit/err/warning/notice/info/debug)
     #[arg(long)]
     pub log_level_max: Option<String>,
+    /// Run as this user (User=). Requires --system
+    #[arg(long)]
+    pub user: Option<String>,
+    /// Run with this group (Group=). Requires --system
+    #[arg(long)]
+    pub group: Option<String>,
     /// Randomized delay for timer trigger (e.g., 5m, 30s). Timer only
     #[arg(long)]
     pub random_delay: Option<String>,
@@ -57,21 +64,160 @@ pub struct AddOptions {
     /// Disable failure notification for this unit
     #[arg(long)]
     pub no_notify: bool,
+    /// Service type: simple, exec, notify, forking (@service only, default: simple)
+    #[arg(long = "type")]
+    pub service_type: Option<String>,
+    /// PID file of the main process (@service with --type forking only)
+    #[arg(long)]
+    pub pid_file: Option<String>,
+    /// Watchdog interval; the service must send WATCHDOG=1 (e.g., 30s). Requires --type notify
+    #[arg(long)]
+    pub watchdog: Option<String>,
+    /// Command run by `sdtab reload` (e.g., "/bin/kill -HUP $MAINPID"). @service only
+    #[arg(long)]
+    pub exec_reload: Option<String>,
+    /// Health probe command; the service is restarted after repeated failures (@service only)
+    #[arg(long)]
+    pub health_cmd: Option<String>,
+    /// Interval between health probes (default: 30s)
+    #[arg(long, requires = "health_cmd")]
+    pub health_interval: Option<String>,
+    /// Consecutive failed probes before restarting the service (default: 3)
+    #[arg(long, requires = "health_cmd")]
+    pub health_retries: Option<u32>,
+    /// Blue/green port slots (e.g., 3000,3001); enables `restart --strategy blue-green`
+    #[arg(long, value_delimiter = ',', requires = "health_cmd")]
+    pub ports: Vec<u16>,
+    /// Build step run by `sdtab deploy` in the workdir (e.g., "npm ci"). Repeatable, in order
+    #[arg(long)]
+    pub deploy_step: Vec<String>,
+    /// Step run when the service fails after `sdtab deploy`. Repeatable, in order
+    #[arg(long)]
+    pub rollback_step: Vec<String>,
+    /// Append stdout/stderr to this file (rotated by size before each run)
+    #[arg(long)]
+    pub output_file: Option<String>,
+    /// Rotate the output file once it exceeds this size (default: 10M)
+    #[arg(long)]
+    pub output_max_size: Option<String>,
+    /// Rotated output files to keep (default: 5)
+    #[arg(long)]
+    pub output_keep: Option<u32>,
+    /// Mail non-empty output of each run to this address via sendmail
+    #[arg(long)]
+    pub mail_to: Option<String>,
     /// Preview generated unit files without creating them
     #[arg(long)]
     pub dry_run: bool,
 }
 
+/// How long `add`/`apply` wait for a started service to become ready.
+const READY_TIMEOUT_SECS: u64 = 90;
+/// How long a `simple`/`exec` service must stay active before it counts as ready.
+const READY_SETTLE_SECS: u64 = 3;
+
 pub fn run(opts: AddOptions) -> Result<()> {
     warn_cgroups_v2(&opts);
+    check_run_as(opts.user.as_deref(), opts.group.as_deref())?;
     let parsed = cron::parse(&opts.schedule)?;
     if parsed.is_service {
         run_service(opts)
     } else {
+        if opts.service_type.is_some()
+            || opts.pid_file.is_some()
+            || opts.watchdog.is_some()
+            || opts.exec_reload.is_some()
+        {
+            bail!("--type, --pid-file, --watchdog and --exec-reload are only valid for @service");
+        }
+        if opts.health_cmd.is_some() || !opts.ports.is_empty() {
+            bail!("--health-cmd and --ports are only valid for @service");
+        }
+        if !opts.deploy_step.is_empty() || !opts.rollback_step.is_empty() {
+            bail!("--deploy-step and --rollback-step are only valid for @service");
+        }
         run_timer(opts, parsed)
     }
 }
 
+/// Validate the readiness-related options of a daemon service.
+pub fn validate_service_type(
+    service_type: Option<&str>,
+    pid_file: Option<&str>,
+    watchdog: Option<&str>,
+) -> Result<()> {
+    let ty = service_type.unwrap_or("simple");
+    match ty {
+        "simple" | "exec" | "notify" | "forking" => {}
+        _ => bail!(
+            "Invalid service type '{}'. Must be one of: simple, exec, notify, forking",
+            ty
+        ),
+    }
+    if pid_file.is_some() && ty != "forking" {
+        bail!("--pid-file requires --type forking");
+    }
+    if watchdog.is_some() && ty != "notify" {
+        bail!("--watchdog requires --type notify (the service must send WATCHDOG=1)");
+    }
+    Ok(())
+}
+
+/// The health probe interval becomes the probe timer's `OnUnitActiveSec=`:
+/// a time span like `timeout_stop`, and a finite one.
+pub fn validate_health_interval(interval: Option<&str>) -> Result<()> {
+    let Some(interval) = interval else {
+        return Ok(());
+    };
+    if let Some(problem) = schema::check_value(schema::Kind::Duration, interval) {
+        bail!("Invalid health interval: {}", problem);
+    }
+    if interval.trim() == "infinity" {
+        bail!("Invalid health interval: the probe needs a finite interval (e.g. 30s)");
+    }
+    Ok(())
+}
+
+/// Wait until a freshly started service is ready. On failure, print the
+/// last journal lines so the user sees why it did not come up.
+pub fn wait_for_ready(name: &str, service_type: Option<&str>) -> Result<()> {
+    wait_for_ready_unit(&bluegreen::service_unit(name), service_type)
+}
+
+/// Like `wait_for_ready`, for an explicit unit (e.g. a blue/green instance).
+pub fn wait_for_ready_unit(service_unit: &str, service_type: Option<&str>) -> Result<()> {
+    // notify/forking units signal readiness themselves; the others are given
+    // a short settle window to catch crashes during init.
+    let settle = match service_type {
+        Some("notify") | Some("forking") => Duration::ZERO,
+        _ => Duration::from_secs(READY_SETTLE_SECS),
+    };
+    if let Err(e) = systemctl::wait_until_ready(
+        service_unit,
+        Duration::from_secs(READY_TIMEOUT_SECS),
+        settle,
+    ) {
+        let tail = systemctl::journal_tail(service_unit, 10);
+        if !tail.is_empty() {
+            eprintln!("  Last log lines of {}:", service_unit);
+            for line in &tail {
+                eprintln!("    {}", line);
+            }
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// `User=`/`Group=` only work for the system manager; a user manager can't
+/// switch to another account.
+pub fn check_run_as(user: Option<&str>, group: Option<&str>) -> Result<()> {
+    if (user.is_some() || group.is_some()) && !init::system() {
+        bail!("user and group can only be set with --system (user units always run as you)");
+    }
+    Ok(())
+}
+
 fn warn_cgroups_v2(opts: &AddOptions) {
     let needs_v2 = opts.memory_max.is_some()
         || opts.cpu_quota.is_some()
@@ -117,6 +263,14 @@ fn run_timer(opts: AddOptions, parsed: cron::CronSchedule) -> Result<()> {
     };
 
     let on_failure = resolve_on_failure(no_notify)?;
+    let output = output::resolve(
+        &name,
+        opts.output_file.as_deref(),
+        opts.output_max_size.as_deref(),
+        opts.output_keep,
+        opts.mail_to.as_deref(),
+        &config::load()?.output,
+    )?;
 
     let config = unit::UnitConfig {
         name: name.clone(),
@@ -135,11 +289,30 @@ fn run_timer(opts: AddOptions, parsed: cron::CronSchedule) -> Result<()> {
         exec_start_pre: opts.exec_start_pre,
         exec_stop_post: opts.exec_stop_post,
         log_level_max: opts.log_level_max,
+        user: opts.user,
+        group: opts.group,
         random_delay: opts.random_delay,
         env: opts.env,
         original_command,
         on_failure,
         no_notify,
+        service_type: None,
+        pid_file: None,
+        watchdog: None,
+        exec_reload: None,
+        health_cmd: None,
+        health_interval: None,
+        health_retries: None,
+        ports: Vec::new(),
+        deploy_steps: Vec::new(),
+        rollback_steps: Vec::new(),
+        output_file: opts.output_file,
+        output_max_size: opts.output_max_size,
+        output_keep: opts.output_keep,
+        mail_to: opts.mail_to,
+        origin: None,
+        project: None,
+        output,
     };
 
     let service_content = unit::generate_service(&config);
@@ -202,17 +375,33 @@ fn run_service(opts: AddOptions) -> Result<()> {
         }
     }
 
+    validate_service_type(
+        opts.service_type.as_deref(),
+        opts.pid_file.as_deref(),
+        opts.watchdog.as_deref(),
+    )?;
+    validate_health_interval(opts.health_interval.as_deref())?;
+
     if let Some(ref path) = opts.env_file {
         if !Path::new(path).exists() {
             bail!("Environment file not found: {}", path);
         }
     }
 
+    if !opts.ports.is_empty() {
+        bluegreen::validate_ports(&opts.ports)?;
+    }
+
     let name = opts.name.unwrap_or_else(|| unit::derive_name(&opts.command));
 
     let unit_dir = init::unit_dir()?;
-    let service_path = Path::new(&unit_dir).join(unit::service_filename(&name));
-    if service_path.exists() {
+    let service_filename = if opts.ports.is_empty() {
+        unit::service_filename(&name)
+    } else {
+        unit::template_filename(&name)
+    };
+    let service_path = Path::new(&unit_dir).join(&service_filename);
+    if Path::new(&unit_dir).join(bluegreen::definition_filename(&name)).exists() {
         bail!(
             "Service '{}' already exists. Remove it first with: sdtab remove {}",
             name,
@@ -230,6 +419,14 @@ fn run_service(opts: AddOptions) -> Result<()> {
     };
 
     let on_failure = resolve_on_failure(no_notify)?;
+    let output = output::resolve(
+        &name,
+        opts.output_file.as_deref(),
+        opts.output_max_size.as_deref(),
+        opts.output_keep,
+        opts.mail_to.as_deref(),
+        &config::load()?.output,
+    )?;
 
     let config = unit::UnitConfig {
         name: name.clone(),
@@ -247,18 +444,42 @@ fn run_service(opts: AddOptions) -> Result<()> {
         exec_start_pre: opts.exec_start_pre,
         exec_stop_post: opts.exec_stop_post,
         log_level_max: opts.log_level_max,
+        user: opts.user,
+        group: opts.group,
         random_delay: None, // timer only
         env: opts.env,
         original_command,
         on_failure,
         no_notify,
+        service_type: opts.service_type.clone(),
+        pid_file: opts.pid_file,
+        watchdog: opts.watchdog,
+        exec_reload: opts.exec_reload,
+        health_cmd: opts.health_cmd,
+        health_interval: opts.health_interval,
+        health_retries: opts.health_retries,
+        ports: opts.ports.clone(),
+        deploy_steps: opts.deploy_step,
+        rollback_steps: opts.rollback_step,
+        output_file: opts.output_file,
+        output_max_size: opts.output_max_size,
+        output_keep: opts.output_keep,
+        mail_to: opts.mail_to,
+        origin: None,
+        project: None,
+        output,
     };
 
     let service_content = unit::generate_daemon_service(&config);
 
     if dry_run {
-        println!("--- {} ---", unit::service_filename(&name));
+        println!("--- {} ---", service_filename);
         print!("{}", service_content);
+        if config.health_cmd.is_some() {
+            println!();
+            println!("--- {} ---", unit::health_timer_filename(&name));
+            print!("{}", unit::generate_health_timer(&config));
+        }
         return Ok(());
     }
 
@@ -266,20 +487,52 @@ fn run_service(opts: AddOptions) -> Result<()> {
         .with_context(|| format!("Failed to write {}", service_path.display()))?;
 
     println!("Created: {}", service_path.display());
+    if !config.ports.is_empty() {
+        bluegreen::write_slots(&name, &config.ports)?;
+    }
+
+    if config.health_cmd.is_some() {
+        health::write_units(&config, Path::new(&unit_dir))?;
+        println!("Created: {}", Path::new(&unit_dir).join(unit::health_timer_filename(&name)).display());
+    }
 
     systemctl::daemon_reload()?;
-    let service_unit = unit::service_filename(&name);
+    let service_unit = bluegreen::service_unit(&name);
     if let Err(e) = systemctl::enable_and_start(&service_unit) {
         return bail_start_failure("Service", &name, &e);
     }
+    println!("Waiting for '{}' to become ready...", name);
+    if let Err(e) = wait_for_ready(&name, opts.service_type.as_deref()) {
+        return bail_start_failure("Service", &name, &e);
+    }
+    if let Some(ref cmd) = config.health_cmd {
+        systemctl::enable_and_start(&unit::health_timer_filename(&name))?;
+        println!(
+            "  Health:  {} (every {}, restart after {} failures)",
+            cmd,
+            config.health_interval.as_deref().unwrap_or(unit::DEFAULT_HEALTH_INTERVAL),
+            config.health_retries.unwrap_or(unit::DEFAULT_HEALTH_RETRIES)
+        );
+    }
 
     let restart_display = opts.restart.as_deref().unwrap_or("always");
     println!("Service '{}' is now active.", name);
     println!("  Command: {}", resolved_command);
     println!("  Restart: {}", restart_display);
+    if let Some(ref ty) = opts.service_type {
+        println!("  Type:    {}", ty);
+    }
     if let Some(ref ef) = opts.env_file {
         println!("  EnvFile: {}", ef);
     }
+    if !config.ports.is_empty() {
+        println!(
+            "  Slots:   blue={} green={} (live: {})",
+            config.ports[0],
+            config.ports[1],
+            bluegreen::active_slot(&name)
+        );
+    }
 
     Ok(())
 }
@@ -294,13 +547,13 @@ fn bail_start_failure(kind: &str, name: &str, e: &anyhow::Error) -> Result<()> {
     bail!("{} '{}' failed to start: {}", kind, name, e);
 }
 
-fn resolve_on_failure(no_notify: bool) -> Result<Option<String>> {
+pub fn resolve_on_failure(no_notify: bool) -> Result<Option<String>> {
     if no_notify {
         return Ok(None);
     }
     let cfg = config::load()?;
     if cfg.notify.slack_webhook.is_some() {
-        Ok(Some("sdtab-notify@%n.service".to_string()))
+        Ok(Some(unit::notify_on_failure()))
     } else {
         Ok(None)
     }
@@ -0,0 +1,757 @@
+//! `sdtab adopt` — bring hand-written systemd user units under sdtab.
+//!
+//! A foreign `foo.service` (plus `foo.timer`) becomes `sdtab-foo.service`
+//! (plus `sdtab-foo.timer`). By default the unit is regenerated from sdtab's
+//! template; directives sdtab doesn't model are kept verbatim in an
+//! `adopted.conf` drop-in. With `--wrap` the original file text is kept as-is
+//! and only sdtab's metadata keys are added. Either way the timer's
+//! `Persistent=` stamp file moves with it, so a renamed timer doesn't treat
+//! its next run as missed and fire immediately.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::unit_file::UnitFile;
+use crate::{add, config, cron, init, output, systemctl, unit};
+
+/// Drop-in holding the directives sdtab's template doesn't cover.
+const EXTRAS_DROPIN: &str = "adopted.conf";
+
+/// `[Service]` keys that map onto sdtab's own fields.
+const MAPPED_SERVICE_KEYS: &[&str] = &[
+    "ExecStart",
+    "WorkingDirectory",
+    "Environment",
+    "MemoryMax",
+    "CPUQuota",
+    "IOWeight",
+    "TimeoutStopSec",
+    "LogLevelMax",
+    "User",
+    "Group",
+    "PIDFile",
+    "WatchdogSec",
+    "ExecReload",
+    "SyslogIdentifier",
+];
+
+/// Result of planning one adoption: the files to write and what didn't carry over.
+#[derive(Debug, Default)]
+struct Plan {
+    is_timer: bool,
+    schedule: Option<String>,
+    service: String,
+    timer: Option<String>,
+    service_extras: Option<String>,
+    timer_extras: Option<String>,
+    /// Lossy steps, reported to the user.
+    notes: Vec<String>,
+}
+
+/// Settings that come from the environment rather than the foreign unit.
+struct Defaults {
+    on_failure: Option<String>,
+    output: unit::OutputTarget,
+    home: String,
+}
+
+pub fn run(unit: Option<&str>, all: bool, wrap: bool, dry_run: bool) -> Result<()> {
+    let unit_dir = init::unit_dir()?;
+    let names = match unit {
+        Some(u) => vec![foreign_name(u)?],
+        None if all => foreign_units(Path::new(&unit_dir))?,
+        None => bail!("Specify a unit to adopt, or --all."),
+    };
+    if names.is_empty() {
+        println!("No hand-written units found in {}.", unit_dir);
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("Dry run — no changes will be made.");
+        println!();
+    }
+
+    let mut adopted = 0;
+    let mut failed = 0;
+    for name in &names {
+        match adopt(name, Path::new(&unit_dir), wrap, dry_run) {
+            Ok(plan) => {
+                adopted += 1;
+                let kind = if plan.is_timer { "timer" } else { "service" };
+                println!("✓ {} → {}{} ({})", name, init::prefix(), name, kind);
+                if let Some(ref schedule) = plan.schedule {
+                    println!("    schedule: {}", schedule);
+                }
+                if plan.service_extras.is_some() || plan.timer_extras.is_some() {
+                    println!("    kept unmapped directives in {}", EXTRAS_DROPIN);
+                }
+                for note in &plan.notes {
+                    println!("    lossy: {}", note);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("✗ {}: {:#}", name, e);
+            }
+        }
+    }
+
+    if adopted > 0 && !dry_run {
+        println!();
+        println!("Adopted {} unit(s). Originals were moved to {}.", adopted, backup_dir()?.display());
+    }
+    if failed > 0 {
+        bail!("{} unit(s) could not be adopted.", failed);
+    }
+    Ok(())
+}
+
+/// `foo`, `foo.service` or `foo.timer` → `foo`.
+fn foreign_name(unit: &str) -> Result<String> {
+    let name = unit
+        .strip_suffix(".service")
+        .or_else(|| unit.strip_suffix(".timer"))
+        .unwrap_or(unit);
+    if name.starts_with(init::prefix()) {
+        bail!("'{}' is already managed by sdtab.", unit);
+    }
+    if name.contains('@') {
+        bail!("Template units like '{}' cannot be adopted.", unit);
+    }
+    Ok(name.to_string())
+}
+
+/// Regular `*.service` files in the unit dir that sdtab doesn't own. Templates
+/// and services another unit activates (a socket, a path, a timer of a
+/// different name) are skipped: adopting them would cut them off from their
+/// trigger.
+fn foreign_units(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        // Symlinks point at units installed elsewhere (packages, `systemctl link`).
+        if entry.file_type()?.is_file() {
+            files.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    let triggered = triggered_services(dir, &files)?;
+    let mut names: Vec<String> = files
+        .iter()
+        .filter_map(|file| file.strip_suffix(".service"))
+        .filter(|name| !name.starts_with(init::prefix()) && !name.contains('@'))
+        .filter(|name| !triggered.contains(&format!("{}.service", name)))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Services activated by a `.socket` or `.path` unit, or by a timer whose
+/// name differs from the service's (`Unit=` or the same-named service).
+fn triggered_services(dir: &Path, files: &[String]) -> Result<BTreeSet<String>> {
+    let mut triggered = BTreeSet::new();
+    for file in files {
+        let Some((stem, section)) = [(".socket", "Socket"), (".path", "Path"), (".timer", "Timer")]
+            .into_iter()
+            .find_map(|(suffix, section)| file.strip_suffix(suffix).map(|stem| (stem, section)))
+        else {
+            continue;
+        };
+        let own = format!("{}.service", stem);
+        let unit = UnitFile::load_effective(&dir.join(file))?;
+        let target = unit.get(section, "Unit").unwrap_or(&own).to_string();
+        if section != "Timer" || target != own {
+            triggered.insert(target);
+        }
+    }
+    Ok(triggered)
+}
+
+fn adopt(name: &str, dir: &Path, wrap: bool, dry_run: bool) -> Result<Plan> {
+    let old_service = dir.join(format!("{}.service", name));
+    let old_timer = dir.join(format!("{}.timer", name));
+    if !old_service.exists() {
+        bail!("{} not found.", old_service.display());
+    }
+    if dir.join(unit::service_filename(name)).exists() || dir.join(unit::template_filename(name)).exists() {
+        bail!("sdtab already has a unit named '{}'.", name);
+    }
+
+    // --wrap keeps the base file and copies its drop-ins alongside; the
+    // regenerated unit folds drop-ins into the effective configuration.
+    let service = if wrap {
+        UnitFile::parse(&fs::read_to_string(&old_service)?)
+    } else {
+        UnitFile::load_effective(&old_service)?
+    };
+    let timer = if !old_timer.exists() {
+        None
+    } else if wrap {
+        Some(UnitFile::parse(&fs::read_to_string(&old_timer)?))
+    } else {
+        Some(UnitFile::load_effective(&old_timer)?)
+    };
+
+    let ctx = Defaults {
+        on_failure: add::resolve_on_failure(false)?,
+        output: output::resolve(name, None, None, None, None, &config::load()?.output)?,
+        home: std::env::var("HOME").unwrap_or_default(),
+    };
+    let plan = if wrap {
+        plan_wrap(name, &service, timer.as_ref())?
+    } else {
+        plan_rewrite(name, &service, timer.as_ref(), &ctx)?
+    };
+    if dry_run {
+        return Ok(plan);
+    }
+
+    let old_unit = if plan.is_timer {
+        format!("{}.timer", name)
+    } else {
+        format!("{}.service", name)
+    };
+    let new_unit = if plan.is_timer {
+        unit::timer_filename(name)
+    } else {
+        unit::service_filename(name)
+    };
+    let new_service = dir.join(unit::service_filename(name));
+    let new_timer = dir.join(unit::timer_filename(name));
+    let new_files = [new_service.as_path(), new_timer.as_path()];
+
+    // Write and load the new units while the original keeps running, so a
+    // unit that doesn't load leaves nothing to undo but the new files.
+    let written = write_plan(&plan, wrap, &old_service, &old_timer, &new_service, &new_timer)
+        .and_then(|()| systemctl::daemon_reload())
+        .and_then(|()| verify_loaded(&new_unit));
+    if let Err(e) = written {
+        remove_files(&new_files);
+        systemctl::daemon_reload()?;
+        return Err(e);
+    }
+
+    let props = systemctl::show_many(std::slice::from_ref(&old_unit), &["UnitFileState", "ActiveState"]).unwrap_or_default();
+    let was_enabled = props.get(&old_unit, "UnitFileState") == Some("enabled");
+    let was_active = props.get(&old_unit, "ActiveState") == Some("active");
+
+    // Stop the original before starting the new unit so the two never run
+    // side by side; put it back if the switch doesn't complete.
+    let mut moved = Vec::new();
+    let switched = switch_over(name, &old_unit, &new_unit, was_enabled || was_active, &old_service, &old_timer, &mut moved);
+    if let Err(e) = switched {
+        let _ = systemctl::stop_and_disable(&new_unit);
+        let restored = restore(&moved, &new_files, name)
+            .and_then(|()| systemctl::daemon_reload())
+            .and_then(|()| if was_enabled { systemctl::enable(&old_unit) } else { Ok(()) })
+            .and_then(|()| if was_active { systemctl::start(&old_unit) } else { Ok(()) });
+        if let Err(restore_error) = restored {
+            bail!("{:#}; restoring '{}' did not finish either: {:#}", e, old_unit, restore_error);
+        }
+        bail!("{:#}; '{}' was restored", e, old_unit);
+    }
+    if !(was_enabled || was_active) {
+        println!("  '{}' was not enabled; run `sdtab enable {}` to start it.", old_unit, name);
+    }
+    Ok(plan)
+}
+
+fn write_plan(plan: &Plan, wrap: bool, old_service: &Path, old_timer: &Path, new_service: &Path, new_timer: &Path) -> Result<()> {
+    fs::write(new_service, &plan.service).with_context(|| format!("Failed to write {}", new_service.display()))?;
+    write_extras(new_service, plan.service_extras.as_deref())?;
+    if let Some(ref content) = plan.timer {
+        fs::write(new_timer, content).with_context(|| format!("Failed to write {}", new_timer.display()))?;
+        write_extras(new_timer, plan.timer_extras.as_deref())?;
+    }
+    if wrap {
+        copy_dropins(old_service, new_service)?;
+        if plan.timer.is_some() {
+            copy_dropins(old_timer, new_timer)?;
+        }
+    }
+    Ok(())
+}
+
+/// systemd loads a unit with a bad setting as `bad-setting`/`error` rather
+/// than refusing the reload, so check before the original is stopped.
+fn verify_loaded(unit: &str) -> Result<()> {
+    let props = systemctl::show_many(&[unit.to_string()], &["LoadState"])?;
+    match props.get(unit, "LoadState") {
+        Some("loaded") => Ok(()),
+        state => bail!("{} did not load ({})", unit, state.unwrap_or("unknown")),
+    }
+}
+
+/// Stop the original, move it and its drop-ins to the backup dir (recorded
+/// in `moved`), and start the new unit in its place.
+fn switch_over(
+    name: &str,
+    old_unit: &str,
+    new_unit: &str,
+    start: bool,
+    old_service: &Path,
+    old_timer: &Path,
+    moved: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    if start {
+        systemctl::stop_and_disable(old_unit)?;
+    }
+    if old_timer.exists() {
+        move_stamp(&format!("{}.timer", name), &unit::timer_filename(name))?;
+    }
+
+    let backup = backup_dir()?;
+    fs::create_dir_all(&backup).with_context(|| format!("Failed to create {}", backup.display()))?;
+    for old in [old_service, old_timer] {
+        let mut dropin_dir = old.as_os_str().to_owned();
+        dropin_dir.push(".d");
+        for path in [old.to_path_buf(), PathBuf::from(dropin_dir)] {
+            if path.exists() {
+                let target = move_to(&path, &backup)?;
+                moved.push((path, target));
+            }
+        }
+    }
+
+    systemctl::daemon_reload()?;
+    if start {
+        systemctl::enable_and_start(new_unit)?;
+    }
+    Ok(())
+}
+
+/// Undo `switch_over`'s file moves and remove the new unit files.
+fn restore(moved: &[(PathBuf, PathBuf)], new_files: &[&Path], name: &str) -> Result<()> {
+    remove_files(new_files);
+    for (original, backup) in moved.iter().rev() {
+        fs::rename(backup, original).with_context(|| format!("Failed to move {} back", backup.display()))?;
+    }
+    move_stamp(&unit::timer_filename(name), &format!("{}.timer", name))
+}
+
+/// Remove unit files and their drop-in dirs, ignoring ones that don't exist.
+fn remove_files(files: &[&Path]) {
+    for file in files {
+        let mut dropin_dir = file.as_os_str().to_owned();
+        dropin_dir.push(".d");
+        let _ = fs::remove_file(file);
+        let _ = fs::remove_dir_all(&dropin_dir);
+    }
+}
+
+/// Regenerate the unit from sdtab's template.
+fn plan_rewrite(name: &str, service: &UnitFile, timer: Option<&UnitFile>, ctx: &Defaults) -> Result<Plan> {
+    let mut plan = Plan { is_timer: timer.is_some(), ..Default::default() };
+
+    let exec_start = service.get_all("Service", "ExecStart");
+    let command = match exec_start.as_slice() {
+        [command] => command.to_string(),
+        [] => bail!("no ExecStart= found"),
+        _ => bail!("multiple ExecStart= lines are not supported; use --wrap"),
+    };
+    let get = |key: &str| service.get("Service", key).map(|v| v.to_string());
+    let single = |key: &str| match service.get_all("Service", key).as_slice() {
+        [value] => Some(value.to_string()),
+        _ => None,
+    };
+
+    // Keys sdtab maps only under some conditions; anything else goes to the drop-in.
+    let mut mapped: Vec<&str> = MAPPED_SERVICE_KEYS.to_vec();
+    let env_file = single("EnvironmentFile").filter(|f| !f.starts_with('-'));
+    let exec_start_pre = single("ExecStartPre");
+    let exec_stop_post = single("ExecStopPost");
+    for (key, value) in [
+        ("EnvironmentFile", &env_file),
+        ("ExecStartPre", &exec_start_pre),
+        ("ExecStopPost", &exec_stop_post),
+    ] {
+        if value.is_some() {
+            mapped.push(key);
+        }
+    }
+    let service_type = get("Type");
+    let restart = get("Restart");
+    let mut restart_policy = None;
+    let mut daemon_type = None;
+    if plan.is_timer {
+        if service_type.as_deref().unwrap_or("oneshot") == "oneshot" {
+            mapped.push("Type");
+        }
+    } else {
+        if let Some(ty) = service_type.filter(|t| add::validate_service_type(Some(t), None, None).is_ok()) {
+            daemon_type = Some(ty).filter(|t| t != "simple");
+            mapped.push("Type");
+        }
+        if let Some(policy) = restart.filter(|r| matches!(r.as_str(), "always" | "on-failure" | "no")) {
+            restart_policy = Some(policy);
+            mapped.push("Restart");
+        } else if service.get("Service", "Restart").is_none() {
+            // systemd's default is Restart=no; sdtab's is always.
+            restart_policy = Some("no".to_string());
+        }
+    }
+    let identifier = format!("{}{}", init::prefix(), name);
+    if let Some(id) = get("SyslogIdentifier").filter(|id| *id != identifier) {
+        plan.notes.push(format!("SyslogIdentifier={} replaced by {}", id, identifier));
+    }
+
+    let mut extras = String::new();
+    for section in ["Unit", "Service"] {
+        let keys: &[&str] = if section == "Unit" { &["Description"] } else { &mapped };
+        let lines: Vec<String> = service
+            .entries()
+            .filter(|(s, k, _)| *s == section && !keys.contains(k))
+            .map(|(_, k, v)| format!("{}={}", k, v))
+            .collect();
+        if !lines.is_empty() {
+            extras.push_str(&format!("[{}]\n{}\n", section, lines.join("\n")));
+        }
+    }
+    note_install(service, if plan.is_timer { None } else { Some(unit::default_target()) }, &mut plan.notes);
+    for (section, key, value) in service.entries().filter(|(s, _, _)| !["Unit", "Service", "Install"].contains(s)) {
+        plan.notes.push(format!("[{}] {}={} dropped", section, key, value));
+    }
+
+    let mut random_delay = None;
+    let mut timer_extras = String::new();
+    let schedule = match timer {
+        Some(timer) => {
+            let (cron_expr, schedule) = timer_schedule(timer)?;
+            random_delay = timer.get("Timer", "RandomizedDelaySec").map(|v| v.to_string());
+            let trigger = if schedule.on_boot_sec.is_some() { "OnBootSec" } else { "OnCalendar" };
+            let timer_keys = [trigger, "RandomizedDelaySec", "Unit"];
+            let lines: Vec<String> = timer
+                .entries()
+                .filter(|(s, k, _)| *s == "Timer" && !timer_keys.contains(k))
+                .filter(|(_, k, v)| !(*k == "Persistent" && *v == "true"))
+                .map(|(_, k, v)| format!("{}={}", k, v))
+                .collect();
+            if !lines.is_empty() {
+                timer_extras = format!("[Timer]\n{}\n", lines.join("\n"));
+            }
+            if let Some(target) = timer.get("Timer", "Unit").filter(|u| *u != format!("{}.service", name)) {
+                plan.notes.push(format!("Unit={} dropped; the timer now starts {}", target, unit::service_filename(name)));
+            }
+            note_install(timer, Some("timers.target"), &mut plan.notes);
+            plan.schedule = Some(cron_expr.clone());
+            Some((cron_expr, schedule))
+        }
+        None => None,
+    };
+
+    let description = service.get("Unit", "Description").unwrap_or(name).to_string();
+    let workdir = get("WorkingDirectory").unwrap_or_else(|| ctx.home.clone());
+    let (cron_expr, schedule) = match schedule {
+        Some((expr, schedule)) => (Some(expr), Some(schedule)),
+        None => (None, None),
+    };
+    let config = unit::UnitConfig {
+        name: name.to_string(),
+        command: command.clone(),
+        workdir,
+        description,
+        cron_expr,
+        schedule,
+        restart_policy,
+        env_file,
+        memory_max: get("MemoryMax"),
+        cpu_quota: get("CPUQuota"),
+        io_weight: get("IOWeight"),
+        timeout_stop: get("TimeoutStopSec"),
+        exec_start_pre,
+        exec_stop_post,
+        log_level_max: get("LogLevelMax"),
+        user: get("User"),
+        group: get("Group"),
+        random_delay,
+        env: service.get_all("Service", "Environment").into_iter().map(|v| v.to_string()).collect(),
+        original_command: Some(command),
+        on_failure: ctx.on_failure.clone(),
+        service_type: daemon_type,
+        pid_file: get("PIDFile"),
+        watchdog: get("WatchdogSec"),
+        exec_reload: get("ExecReload"),
+        output: ctx.output.clone(),
+        ..Default::default()
+    };
+
+    if plan.is_timer {
+        plan.service = unit::generate_service(&config);
+        plan.timer = Some(unit::generate_timer(&config));
+    } else {
+        plan.service = unit::generate_daemon_service(&config);
+    }
+    plan.service_extras = Some(extras).filter(|e| !e.is_empty());
+    plan.timer_extras = Some(timer_extras).filter(|e| !e.is_empty());
+    Ok(plan)
+}
+
+/// Keep the original text; add sdtab's metadata and point the timer at the new service.
+fn plan_wrap(name: &str, service: &UnitFile, timer: Option<&UnitFile>) -> Result<Plan> {
+    let mut plan = Plan { is_timer: timer.is_some(), ..Default::default() };
+    let command = service.get("Service", "ExecStart").context("no ExecStart= found")?;
+
+    let mut meta = unit::meta_line("type", if plan.is_timer { "timer" } else { "service" });
+    meta.push_str(&unit::meta_line("template-version", &unit::TEMPLATE_VERSION.to_string()));
+    if let Some(timer) = timer {
+        let calendar = timer.get_all("Timer", "OnCalendar").join("; ");
+        match timer_schedule(timer) {
+            Ok((cron_expr, _)) => {
+                meta.push_str(&unit::meta_line("cron", &cron_expr));
+                plan.schedule = Some(cron_expr);
+            }
+            Err(_) => plan.notes.push(format!(
+                "OnCalendar={} has no cron form; sdtab export cannot reproduce the schedule",
+                calendar
+            )),
+        }
+    } else {
+        let restart = service.get("Service", "Restart").unwrap_or("no");
+        meta.push_str(&unit::meta_line("restart", restart));
+    }
+    meta.push_str(&unit::meta_line("command", command));
+
+    let mut file = service.clone();
+    let description = service.get("Unit", "Description").unwrap_or(name).to_string();
+    file.set("Unit", "Description", &format!("[sdtab] {}: {}", name, description));
+    let text = file.to_string();
+    let lines: Vec<&str> = text.lines().collect();
+    plan.service = unit::insert_metadata(&lines, &meta);
+
+    if let Some(timer) = timer {
+        let mut file = timer.clone();
+        if timer.get("Timer", "Unit").is_some() {
+            file.set("Timer", "Unit", &unit::service_filename(name));
+        }
+        plan.timer = Some(file.to_string());
+    }
+    Ok(plan)
+}
+
+/// The timer's trigger as an sdtab schedule.
+fn timer_schedule(timer: &UnitFile) -> Result<(String, cron::CronSchedule)> {
+    let calendars = timer.get_all("Timer", "OnCalendar");
+    let expr = match calendars.as_slice() {
+        [calendar] => cron::from_calendar(calendar)
+            .with_context(|| format!("OnCalendar={} has no cron equivalent; use --wrap", calendar))?,
+        [] if timer.get("Timer", "OnBootSec") == Some("1min") => "@reboot".to_string(),
+        [] => bail!("timer has no OnCalendar= schedule sdtab can express; use --wrap"),
+        _ => bail!("multiple OnCalendar= lines have no single cron form; use --wrap"),
+    };
+    let schedule = cron::parse(&expr)?;
+    Ok((expr, schedule))
+}
+
+/// sdtab always installs into `default.target` (`multi-user.target` with
+/// `--system`) / `timers.target`.
+fn note_install(file: &UnitFile, expected: Option<&str>, notes: &mut Vec<String>) {
+    for (_, key, value) in file.entries().filter(|(s, _, _)| *s == "Install") {
+        if key == "WantedBy" && Some(value) == expected {
+            continue;
+        }
+        notes.push(format!("[Install] {}={} dropped", key, value));
+    }
+}
+
+fn write_extras(unit_path: &Path, extras: Option<&str>) -> Result<()> {
+    let Some(extras) = extras else { return Ok(()) };
+    let mut dir = unit_path.as_os_str().to_owned();
+    dir.push(".d");
+    let dir = PathBuf::from(dir);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(EXTRAS_DROPIN);
+    let content = format!("# Kept by `sdtab adopt`: directives sdtab does not manage\n{}", extras);
+    fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn copy_dropins(old: &Path, new: &Path) -> Result<()> {
+    let mut old_dir = old.as_os_str().to_owned();
+    old_dir.push(".d");
+    let Ok(entries) = fs::read_dir(&old_dir) else { return Ok(()) };
+    let mut new_dir = new.as_os_str().to_owned();
+    new_dir.push(".d");
+    fs::create_dir_all(&new_dir)?;
+    for entry in entries {
+        let entry = entry?;
+        fs::copy(entry.path(), Path::new(&new_dir).join(entry.file_name()))
+            .with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+    }
+    Ok(())
+}
+
+/// Rename `stamp-<old>.timer` so `Persistent=` keeps the last trigger time.
+fn move_stamp(old_timer: &str, new_timer: &str) -> Result<()> {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let stamps = Path::new(&home).join(".local/share/systemd/timers");
+    let old = stamps.join(format!("stamp-{}", old_timer));
+    if old.exists() {
+        let new = stamps.join(format!("stamp-{}", new_timer));
+        fs::rename(&old, &new).with_context(|| format!("Failed to move {}", old.display()))?;
+    }
+    Ok(())
+}
+
+fn backup_dir() -> Result<PathBuf> {
+    Ok(Path::new(&init::state_dir()?).join("adopted"))
+}
+
+fn move_to(path: &Path, dir: &Path) -> Result<PathBuf> {
+    let target = dir.join(path.file_name().context("path has no file name")?);
+    fs::rename(path, &target).with_context(|| format!("Failed to move {}", path.display()))?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx() -> Defaults {
+        Defaults { on_failure: None, output: unit::OutputTarget::default(), home: "/home/me".to_string() }
+    }
+
+    const BACKUP_SERVICE: &str = "\
+[Unit]
+Description=Nightly backup
+Requires=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart=/usr/bin/restic backup /home
+ExecStartPre=/bin/mount /mnt/backup
+ExecStartPre=/bin/sleep 5
+Nice=10
+MemoryMax=1G
+";
+
+    const BACKUP_TIMER: &str = "\
+[Unit]
+Description=Nightly backup timer
+
+[Timer]
+OnCalendar=*-*-* 03:30:00
+Persistent=true
+AccuracySec=1min
+
+[Install]
+WantedBy=timers.target
+";
+
+    #[test]
+    fn rewrite_maps_known_keys_and_keeps_extras() {
+        let service = UnitFile::parse(BACKUP_SERVICE);
+        let timer = UnitFile::parse(BACKUP_TIMER);
+        let plan = plan_rewrite("backup", &service, Some(&timer), &ctx()).unwrap();
+
+        assert!(plan.is_timer);
+        assert_eq!(plan.schedule.as_deref(), Some("@daily/3:30"));
+        assert!(plan.service.contains("X-Sdtab-Cron=@daily/3:30"));
+        assert!(plan.service.contains("MemoryMax=1G"));
+        assert!(plan.service.contains("WorkingDirectory=/home/me"));
+        assert!(plan.timer.as_deref().unwrap().contains("OnCalendar=*-*-* 03:30:00"));
+        // Two ExecStartPre= lines don't fit sdtab's single hook, so both stay verbatim.
+        assert_eq!(
+            plan.service_extras.as_deref(),
+            Some("[Unit]\nRequires=network-online.target\n[Service]\nExecStartPre=/bin/mount /mnt/backup\nExecStartPre=/bin/sleep 5\nNice=10\n")
+        );
+        assert_eq!(plan.timer_extras.as_deref(), Some("[Timer]\nAccuracySec=1min\n"));
+        assert!(plan.notes.is_empty());
+    }
+
+    #[test]
+    fn rewrite_reports_lossy_steps() {
+        let service = UnitFile::parse(
+            "[Service]\nExecStart=/usr/bin/app\nSyslogIdentifier=app\n\n[Install]\nWantedBy=graphical-session.target\n",
+        );
+        let plan = plan_rewrite("app", &service, None, &ctx()).unwrap();
+        assert!(!plan.is_timer);
+        assert!(plan.service.contains("Restart=no"));
+        assert_eq!(
+            plan.notes,
+            vec![
+                "SyslogIdentifier=app replaced by sdtab-app".to_string(),
+                "[Install] WantedBy=graphical-session.target dropped".to_string(),
+            ]
+        );
+
+        let timer = UnitFile::parse("[Timer]\nOnCalendar=*-*-* 00:00:30\n");
+        let err = plan_rewrite("app", &service, Some(&timer), &ctx()).unwrap_err();
+        assert!(err.to_string().contains("use --wrap"));
+    }
+
+    #[test]
+    fn wrap_keeps_text_and_adds_metadata() {
+        let service = UnitFile::parse(BACKUP_SERVICE);
+        let timer = UnitFile::parse("[Timer]\nOnCalendar=*-*-* 00:00:30\nUnit=backup.service\n");
+        let plan = plan_wrap("backup", &service, Some(&timer)).unwrap();
+
+        assert!(plan.service.starts_with("[Unit]\nX-Sdtab-Type=timer\n"));
+        assert!(plan.service.contains("Description=[sdtab] backup: Nightly backup\n"));
+        assert!(plan.service.contains("ExecStartPre=/bin/sleep 5\nNice=10\n"));
+        assert_eq!(plan.timer.as_deref(), Some("[Timer]\nOnCalendar=*-*-* 00:00:30\nUnit=sdtab-backup.service\n"));
+        assert_eq!(plan.notes.len(), 1);
+        assert!(plan.notes[0].contains("no cron form"));
+    }
+
+    fn unit_home(tag: &str) -> (crate::testdir::TestDir, PathBuf) {
+        let home = crate::testdir::TestDir::new(&format!("adopt-{}", tag));
+        let dir = home.join(".config/systemd/user");
+        fs::create_dir_all(&dir).unwrap();
+        (home, dir)
+    }
+
+    #[test]
+    fn all_skips_templates_and_triggered_services() {
+        let (_home, dir) = unit_home("foreign");
+        for file in [
+            "backup.service",
+            "backup.timer",
+            "report.service",
+            "worker@.service",
+            "echo.service",
+            "echo.socket",
+            "sync.service",
+            "watch.path",
+            "sdtab-web.service",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(dir.join("watch.path"), "[Path]\nPathChanged=/srv/in\nUnit=sync.service\n").unwrap();
+        fs::write(dir.join("nightly.timer"), "[Timer]\nOnCalendar=daily\nUnit=report.service\n").unwrap();
+
+        assert_eq!(foreign_units(&dir).unwrap(), vec!["backup"]);
+    }
+
+    #[test]
+    fn failed_switch_restores_the_original() {
+        use crate::backend;
+        use crate::fake::FakeBackend;
+        use std::rc::Rc;
+
+        let (home, dir) = unit_home("restore");
+        fs::write(dir.join("backup.service"), BACKUP_SERVICE).unwrap();
+        fs::write(dir.join("backup.timer"), "[Timer]\nOnCalendar=*-*-* 03:00:00\n").unwrap();
+        let fake = Rc::new(FakeBackend::default());
+        let adopt = || init::with_home(&home, || backend::with_backend(fake.clone(), || adopt("backup", &dir, true, false)));
+        init::with_home(&home, || backend::with_backend(fake.clone(), || {
+            systemctl::daemon_reload().unwrap();
+            systemctl::enable_and_start("backup.timer").unwrap();
+        }));
+
+        fake.fail_next("sdtab-backup.timer");
+        let err = adopt().unwrap_err();
+        assert!(format!("{:#}", err).contains("'backup.timer' was restored"), "{:#}", err);
+        assert!(dir.join("backup.service").exists() && dir.join("backup.timer").exists());
+        assert!(!dir.join("sdtab-backup.service").exists() && !dir.join("sdtab-backup.timer").exists());
+        let state = fake.state("backup.timer");
+        assert!(state.enabled && state.active);
+
+        adopt().unwrap();
+        assert!(!dir.join("backup.timer").exists());
+        assert!(fake.state("sdtab-backup.timer").active);
+        assert!(!fake.state("backup.timer").active);
+    }
+}
@@ -2,10 +2,10 @@ use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::path::Path;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use crate::sdtabfile::{self, Sdtabfile, ServiceEntry, TimerEntry};
-use crate::{config, cron, init, parse_unit, remove, systemctl, unit};
+use crate::{add, bluegreen, config, cron, diff, drift, health, init, output, parse_unit, plan, remove, snapshot, systemctl, unit};
 
 enum DiffStatus {
     Added,
@@ -18,81 +18,66 @@ struct DiffEntry {
     name: String,
     unit_type: parse_unit::UnitType,
     status: DiffStatus,
+    /// Sdtabfile the entry comes from (for removals, the unit's origin)
+    origin: Option<String>,
+    /// What differs from the installed unit (only for `Changed`)
+    changes: Vec<FieldChange>,
+    /// Enablement to converge the installed unit to, when it differs from
+    /// the entry's `enabled`. Files may be unchanged (a state-only change).
+    state: Option<bool>,
 }
 
-pub fn run(file: &str, prune: bool, dry_run: bool) -> Result<()> {
-    let toml_content = fs::read_to_string(file)
-        .with_context(|| format!("Failed to read {}", file))?;
-    let sdtabfile: Sdtabfile = toml::from_str(&toml_content)
-        .with_context(|| format!("Failed to parse {}", file))?;
+/// One setting of a changed unit, rendered for display.
+struct FieldChange {
+    field: &'static str,
+    old: String,
+    new: String,
+}
+
+pub fn run(
+    file: &str,
+    prune: bool,
+    dry_run: bool,
+    show_diff: bool,
+    no_rollback: bool,
+    profile: Option<&str>,
+) -> Result<()> {
+    // A saved plan fixes the files, profile and --prune it was made with.
+    let (sdtabfile, prune, host, profile, planned) = if plan::is_plan(file) {
+        if prune || profile.is_some() {
+            bail!("--prune and --profile are fixed when the plan is made; pass them to sdtab plan");
+        }
+        let plan = plan::load(file)?;
+        (plan.desired.into_sdtabfile(), plan.prune, plan.host, plan.profile, Some(plan.actions))
+    } else {
+        let target = sdtabfile::Target::current(profile)?;
+        (sdtabfile::load(file, &target)?, prune, target.hostname.unwrap_or_default(), target.profile, None)
+    };
 
     let current_units = parse_unit::scan_all_units()?;
     let mut current_map: BTreeMap<String, &parse_unit::ParsedUnit> = BTreeMap::new();
     for unit in &current_units {
         current_map.insert(unit.name.clone(), unit);
     }
-
-    let mut diff_entries: Vec<DiffEntry> = Vec::new();
-    let mut desired_names: HashSet<String> = HashSet::new();
-
-    // Process timers
-    for (name, entry) in &sdtabfile.timers {
-        desired_names.insert(name.clone());
-
-        let status = match current_map.get(name) {
-            None => DiffStatus::Added,
-            Some(current) => {
-                if timer_matches(current, entry) {
-                    DiffStatus::Unchanged
-                } else {
-                    DiffStatus::Changed
-                }
-            }
-        };
-
-        diff_entries.push(DiffEntry {
-            name: name.clone(),
-            unit_type: parse_unit::UnitType::Timer,
-            status,
-        });
-    }
-
-    // Process services
-    for (name, entry) in &sdtabfile.services {
-        desired_names.insert(name.clone());
-
-        let status = match current_map.get(name) {
-            None => DiffStatus::Added,
-            Some(current) => {
-                if service_matches(current, entry) {
-                    DiffStatus::Unchanged
-                } else {
-                    DiffStatus::Changed
-                }
-            }
-        };
-
-        diff_entries.push(DiffEntry {
-            name: name.clone(),
-            unit_type: parse_unit::UnitType::Service,
-            status,
-        });
-    }
-
-    // Find units to prune
-    for unit in &current_units {
-        if !desired_names.contains(&unit.name) {
-            diff_entries.push(DiffEntry {
-                name: unit.name.clone(),
-                unit_type: unit.unit_type.clone(),
-                status: DiffStatus::Removed,
-            });
+    let diff_entries = diff_entries(&sdtabfile, &current_units, &current_map)?;
+    if let Some(planned) = planned {
+        let key = |a: &plan::Action| (a.name.clone(), a.unit_type.clone(), a.action.clone());
+        let now: Vec<_> = actions(&sdtabfile, &diff_entries, &current_map, prune).iter().map(key).collect();
+        if now != planned.iter().map(key).collect::<Vec<_>>() {
+            bail!("{} no longer matches what applying it would do. Run sdtab plan again", file);
         }
     }
 
+    // With several files, say which one each unit comes from.
+    let source = |entry: &DiffEntry| match &entry.origin {
+        Some(origin) if sdtabfile.files.len() > 1 => format!(" from {}", display_path(origin)),
+        _ => String::new(),
+    };
+
     // Display summary
     let mut added = 0;
     let mut changed = 0;
+    let mut switched = 0;
     let mut unchanged = 0;
     let mut removed = 0;
 
@@ -100,20 +85,37 @@ pub fn run(file: &str, prune: bool, dry_run: bool) -> Result<()> {
         let type_label = entry.unit_type.label();
         match entry.status {
             DiffStatus::Added => {
-                println!("  + {} ({})", entry.name, type_label);
+                let disabled = if desired_enabled(&sdtabfile, entry) { "" } else { ", disabled" };
+                println!("  + {} ({}){}{}", entry.name, type_label, source(entry), disabled);
                 added += 1;
             }
             DiffStatus::Changed => {
-                println!("  ~ {} ({})", entry.name, type_label);
+                println!("  ~ {} ({}){}", entry.name, type_label, source(entry));
+                for change in &entry.changes {
+                    println!("      {}: {} → {}", change.field, change.old, change.new);
+                }
+                if let Some(enabled) = entry.state {
+                    println!("      enabled: {} → {}", !enabled, enabled);
+                }
+                if matches!(entry.unit_type, parse_unit::UnitType::Service) && sdtabfile.services[&entry.name].health_cmd.is_none() {
+                    print_deleted_health_units(&entry.name)?;
+                }
                 changed += 1;
             }
+            // Files as they are, only enablement to converge.
+            DiffStatus::Unchanged if entry.state.is_some() => {
+                let verb = if entry.state == Some(true) { "enable" } else { "disable" };
+                println!("  * {} ({}){}: {}", entry.name, type_label, source(entry), verb);
+                switched += 1;
+            }
             DiffStatus::Unchanged => {
-                println!("  = {} ({})", entry.name, type_label);
+                println!("  = {} ({}){}", entry.name, type_label, source(entry));
                 unchanged += 1;
             }
             DiffStatus::Removed => {
                 if prune {
-                    println!("  - {} ({})", entry.name, type_label);
+                    println!("  - {} ({}){}", entry.name, type_label, source(entry));
+                    print_deleted_health_units(&entry.name)?;
                     removed += 1;
                 }
             }
@@ -130,7 +132,7 @@ pub fn run(file: &str, prune: bool, dry_run: bool) -> Result<()> {
             println!();
             println!("Warning: the following units are not in the file:");
             for entry in &orphans {
-                println!("  {} ({})", entry.name, entry.unit_type.label());
+                println!("  {} ({}){}", entry.name, entry.unit_type.label(), source(entry));
             }
             println!("Use --prune to remove them.");
         }
@@ -138,26 +140,103 @@ pub fn run(file: &str, prune: bool, dry_run: bool) -> Result<()> {
 
     println!();
 
+    if show_diff {
+        for entry in &diff_entries {
+            if (matches!(entry.status, DiffStatus::Unchanged) && entry.state.is_none())
+                || (matches!(entry.status, DiffStatus::Removed) && !prune)
+            {
+                continue;
+            }
+            print_unit_diff(&sdtabfile, entry, current_map.get(&entry.name).copied())?;
+        }
+    }
+
     if dry_run {
         println!(
-            "Dry run: {} to add, {} to update, {} unchanged, {} to remove",
-            added, changed, unchanged, removed
+            "Dry run: {} to add, {} to update, {} to enable/disable, {} unchanged, {} to remove",
+            added, changed, switched, unchanged, removed
         );
+        // What this host gets once profiles, hosts, defaults and vars are resolved.
+        let profile = match &profile {
+            Some(profile) => format!(", profile {}", profile),
+            None => String::new(),
+        };
+        println!();
+        println!("Effective Sdtabfile (host {}{}):", host, profile);
+        println!();
+        print!("{}", sdtabfile::to_toml(&sdtabfile, false)?);
         return Ok(());
     }
 
-    if added == 0 && changed == 0 && removed == 0 {
+    if added == 0 && changed == 0 && switched == 0 && removed == 0 {
+        drift::record(&sdtabfile)?;
         println!("Nothing to do. All {} unit(s) are up to date.", unchanged);
         return Ok(());
     }
 
+    // Snapshot everything about to change, so a failure part way leaves the
+    // host as it was rather than half applied, and `sdtab rollback` can undo it.
+    let affected: Vec<String> = diff_entries
+        .iter()
+        .filter(|e| match e.status {
+            DiffStatus::Added | DiffStatus::Changed => true,
+            DiffStatus::Removed => prune,
+            DiffStatus::Unchanged => e.state.is_some(),
+        })
+        .map(|e| e.name.clone())
+        .collect();
+    let snapshot = snapshot::take(file, &affected)?;
+    snapshot.save()?;
+
+    let mut failed = String::new();
+    if let Err(e) = execute(&sdtabfile, &diff_entries, &current_map, prune, &mut failed) {
+        let failed = if failed.is_empty() { "daemon-reload".to_string() } else { format!("'{}'", failed) };
+        if no_rollback {
+            bail!("Applying {} failed: {:#}. Undo the applied part with: sdtab rollback", failed, e);
+        }
+        eprintln!("Applying {} failed: {:#}", failed, e);
+        eprintln!("Rolling back {} unit(s)...", affected.len());
+        if let Err(restore_error) = snapshot.restore() {
+            bail!(
+                "Applying {} failed ({:#}) and the rollback did not finish: {:#}. \
+                 Retry with: sdtab rollback",
+                failed,
+                e,
+                restore_error
+            );
+        }
+        let _ = snapshot.delete();
+        bail!("Applying {} failed: {:#}. Rolled back {} unit(s) to their previous state", failed, e, affected.len());
+    }
+
+    drift::record(&sdtabfile)?;
+
+    println!();
+    println!(
+        "Applied: {} added, {} updated, {} enabled/disabled, {} unchanged, {} removed",
+        added, changed, switched, unchanged, removed
+    );
+
+    Ok(())
+}
+
+/// Write, remove, enable and restart units as `diff_entries` says. `failed`
+/// is left naming the entry being worked on (empty for the daemon-reload).
+fn execute(
+    sdtabfile: &Sdtabfile,
+    diff_entries: &[DiffEntry],
+    current_map: &BTreeMap<String, &parse_unit::ParsedUnit>,
+    prune: bool,
+    failed: &mut String,
+) -> Result<()> {
     // Apply changes: write all files first, then single daemon-reload
     let mut needs_reload = false;
-    for entry in &diff_entries {
+    for entry in diff_entries {
+        failed.clone_from(&entry.name);
         match entry.status {
             DiffStatus::Unchanged => {}
             DiffStatus::Changed | DiffStatus::Added => {
-                write_entry(&sdtabfile, &entry.name, &entry.unit_type)?;
+                write_entry(sdtabfile, &entry.name, &entry.unit_type)?;
                 needs_reload = true;
             }
             DiffStatus::Removed => {
@@ -170,18 +249,45 @@ pub fn run(file: &str, prune: bool, dry_run: bool) -> Result<()> {
 
     // Single daemon-reload, then enable new units and restart changed units
     if needs_reload {
+        failed.clear();
         systemctl::daemon_reload()?;
-        for entry in &diff_entries {
+        for entry in diff_entries {
+            failed.clone_from(&entry.name);
             match entry.status {
+                // Installed disabled: the files are all it gets.
+                DiffStatus::Added if !desired_enabled(sdtabfile, entry) => {}
                 DiffStatus::Added => {
                     // New units need enable + start
                     let unit_name = match entry.unit_type {
                         parse_unit::UnitType::Timer => unit::timer_filename(&entry.name),
-                        parse_unit::UnitType::Service => unit::service_filename(&entry.name),
+                        parse_unit::UnitType::Service => bluegreen::service_unit(&entry.name),
                     };
                     systemctl::enable_and_start(&unit_name)?;
+                    if let parse_unit::UnitType::Service = entry.unit_type {
+                        let desired = &sdtabfile.services[&entry.name];
+                        add::wait_for_ready(&entry.name, desired.service_type.as_deref())
+                            .with_context(|| format!("Service '{}' is not ready", entry.name))?;
+                        if desired.health_cmd.is_some() {
+                            systemctl::enable_and_start(&unit::health_timer_filename(&entry.name))?;
+                        }
+                    }
                 }
+                // Enabling starts it with the new files; disabling stops it.
+                DiffStatus::Changed if entry.state.is_some() || !desired_enabled(sdtabfile, entry) => {}
                 DiffStatus::Changed => {
+                    // Health settings live in the companion timer: (re)start it
+                    // whenever they differ, independent of the service restart.
+                    if let parse_unit::UnitType::Service = entry.unit_type {
+                        let desired = &sdtabfile.services[&entry.name];
+                        let health_changed = current_map
+                            .get(&entry.name)
+                            .is_none_or(|current| health_needs_restart(current, desired));
+                        if desired.health_cmd.is_some() && health_changed {
+                            let health_timer = unit::health_timer_filename(&entry.name);
+                            systemctl::enable_and_start(&health_timer)?;
+                            systemctl::restart(&health_timer)?;
+                        }
+                    }
                     // Changed units: selective restart
                     let restart_needed = match entry.unit_type {
                         parse_unit::UnitType::Timer => {
@@ -195,12 +301,27 @@ pub fn run(file: &str, prune: bool, dry_run: bool) -> Result<()> {
                             })
                         }
                     };
-                    if restart_needed {
+                    let blue_green = matches!(entry.unit_type, parse_unit::UnitType::Service)
+                        && !sdtabfile.services[&entry.name].ports.is_empty();
+                    if restart_needed && blue_green {
+                        let desired = &sdtabfile.services[&entry.name];
+                        bluegreen::switch_slots(
+                            &entry.name,
+                            &desired.ports,
+                            desired.health_cmd.as_deref(),
+                            desired.service_type.as_deref(),
+                        )?;
+                    } else if restart_needed {
                         let unit_name = match entry.unit_type {
                             parse_unit::UnitType::Timer => unit::timer_filename(&entry.name),
                             parse_unit::UnitType::Service => unit::service_filename(&entry.name),
                         };
                         systemctl::restart(&unit_name)?;
+                        if let parse_unit::UnitType::Service = entry.unit_type {
+                            let desired = &sdtabfile.services[&entry.name];
+                            add::wait_for_ready(&entry.name, desired.service_type.as_deref())
+                                .with_context(|| format!("Service '{}' is not ready", entry.name))?;
+                        }
                     }
                 }
                 _ => {}
@@ -208,44 +329,439 @@ pub fn run(file: &str, prune: bool, dry_run: bool) -> Result<()> {
         }
     }
 
-    println!();
-    println!(
-        "Applied: {} added, {} updated, {} unchanged, {} removed",
-        added, changed, unchanged, removed
-    );
+    // Converge enablement last, so a unit being enabled starts with its new files.
+    for entry in diff_entries {
+        let Some(enabled) = entry.state else {
+            continue;
+        };
+        failed.clone_from(&entry.name);
+        let primary = parse_unit::primary_unit(&entry.name, &entry.unit_type);
+        let health_timer = match entry.unit_type {
+            parse_unit::UnitType::Service if sdtabfile.services[&entry.name].health_cmd.is_some() => {
+                Some(unit::health_timer_filename(&entry.name))
+            }
+            _ => None,
+        };
+        if enabled {
+            systemctl::enable_and_start(&primary)?;
+            if let parse_unit::UnitType::Service = entry.unit_type {
+                let desired = &sdtabfile.services[&entry.name];
+                add::wait_for_ready(&entry.name, desired.service_type.as_deref())
+                    .with_context(|| format!("Service '{}' is not ready", entry.name))?;
+            }
+            if let Some(health_timer) = &health_timer {
+                systemctl::enable_and_start(health_timer)?;
+            }
+        } else {
+            // The probe would restart the service it is watching.
+            if let Some(health_timer) = &health_timer {
+                systemctl::stop_and_disable(health_timer)?;
+            }
+            systemctl::stop_and_disable(&primary)?;
+        }
+    }
+    Ok(())
+}
+
+/// The `enabled` of the entry behind `entry`; removed units have none.
+fn desired_enabled(sdtabfile: &Sdtabfile, entry: &DiffEntry) -> bool {
+    match entry.unit_type {
+        parse_unit::UnitType::Timer => sdtabfile.timers.get(&entry.name).is_none_or(|t| t.enabled),
+        parse_unit::UnitType::Service => sdtabfile.services.get(&entry.name).is_none_or(|s| s.enabled),
+    }
+}
+
+/// Entries whose installed unit is enabled (or running) when it should be
+/// disabled, or the other way round: name → the enablement to converge to.
+/// A unit systemd hasn't loaded yet has no state to compare.
+fn state_changes(
+    sdtabfile: &Sdtabfile,
+    current_map: &BTreeMap<String, &parse_unit::ParsedUnit>,
+) -> Result<BTreeMap<String, bool>> {
+    let desired = sdtabfile
+        .timers
+        .iter()
+        .map(|(name, entry)| (name, parse_unit::UnitType::Timer, entry.enabled))
+        .chain(sdtabfile.services.iter().map(|(name, entry)| (name, parse_unit::UnitType::Service, entry.enabled)))
+        .filter(|(name, _, _)| current_map.contains_key(name.as_str()))
+        .map(|(name, unit_type, enabled)| (name, parse_unit::primary_unit(name, &unit_type), enabled))
+        .collect::<Vec<_>>();
+    let mut changes = BTreeMap::new();
+    if desired.is_empty() {
+        return Ok(changes);
+    }
+    let units: Vec<String> = desired.iter().map(|(_, unit_name, _)| unit_name.clone()).collect();
+    let props = systemctl::show_many(&units, &["UnitFileState", "ActiveState"])?;
+    for (name, unit_name, enabled) in desired {
+        let file_state = props.get(&unit_name, "UnitFileState");
+        let active = props.get(&unit_name, "ActiveState") == Some("active");
+        if enabled && file_state == Some("disabled") {
+            changes.insert(name.clone(), true);
+        } else if !enabled && (file_state == Some("enabled") || active) {
+            changes.insert(name.clone(), false);
+        }
+    }
+    Ok(changes)
+}
+
+/// Compare the desired units with the installed ones. Units missing from
+/// the file come back as `Removed` whether or not --prune is given.
+fn diff_entries(
+    sdtabfile: &Sdtabfile,
+    current_units: &[parse_unit::ParsedUnit],
+    current_map: &BTreeMap<String, &parse_unit::ParsedUnit>,
+) -> Result<Vec<DiffEntry>> {
+    let mut diff_entries: Vec<DiffEntry> = Vec::new();
+    let mut desired_names: HashSet<String> = HashSet::new();
+    let mut state_changes = state_changes(sdtabfile, current_map)?;
+    let output_defaults = config::load()?.output;
+
+    // Process timers
+    for (name, entry) in &sdtabfile.timers {
+        desired_names.insert(name.clone());
+
+        let (status, changes) = match current_map.get(name.as_str()) {
+            None => (DiffStatus::Added, Vec::new()),
+            Some(current) => {
+                let mut changes = timer_changes(current, entry);
+                let output = output::resolve(
+                    name,
+                    entry.output_file.as_deref(),
+                    entry.output_max_size.as_deref(),
+                    entry.output_keep,
+                    entry.mail_to.as_deref(),
+                    &output_defaults,
+                )?;
+                output_change(&mut changes, current, &output);
+                changes.extend(source_changes(current, sdtabfile, name));
+                if changes.is_empty() {
+                    (DiffStatus::Unchanged, changes)
+                } else {
+                    (DiffStatus::Changed, changes)
+                }
+            }
+        };
+
+        diff_entries.push(DiffEntry {
+            name: name.clone(),
+            unit_type: parse_unit::UnitType::Timer,
+            status,
+            origin: sdtabfile.origin(name).map(str::to_string),
+            changes,
+            state: state_changes.remove(name),
+        });
+    }
+
+    // Process services
+    for (name, entry) in &sdtabfile.services {
+        desired_names.insert(name.clone());
 
+        let (status, changes) = match current_map.get(name.as_str()) {
+            None => (DiffStatus::Added, Vec::new()),
+            Some(current) => {
+                if current.ports.is_empty() != entry.ports.is_empty() {
+                    bail!(
+                        "'{}': switching between a single instance and blue/green slots is not supported. \
+                         Remove it first with: sdtab remove {}",
+                        name,
+                        name
+                    );
+                }
+                let mut changes = service_changes(current, entry);
+                let output = output::resolve(
+                    name,
+                    entry.output_file.as_deref(),
+                    entry.output_max_size.as_deref(),
+                    entry.output_keep,
+                    entry.mail_to.as_deref(),
+                    &output_defaults,
+                )?;
+                output_change(&mut changes, current, &output);
+                changes.extend(source_changes(current, sdtabfile, name));
+                if changes.is_empty() {
+                    (DiffStatus::Unchanged, changes)
+                } else {
+                    (DiffStatus::Changed, changes)
+                }
+            }
+        };
+
+        diff_entries.push(DiffEntry {
+            name: name.clone(),
+            unit_type: parse_unit::UnitType::Service,
+            status,
+            origin: sdtabfile.origin(name).map(str::to_string),
+            changes,
+            state: state_changes.remove(name),
+        });
+    }
+
+    // Find units to prune: only those of the projects or files at hand, so
+    // applying one project's file leaves other projects' units alone.
+    for unit in current_units {
+        let in_scope = sdtabfile.in_scope(unit.origin.as_deref(), unit.project.as_deref());
+        if !desired_names.contains(&unit.name) && in_scope {
+            diff_entries.push(DiffEntry {
+                name: unit.name.clone(),
+                unit_type: unit.unit_type.clone(),
+                status: DiffStatus::Removed,
+                origin: unit.origin.clone(),
+                changes: Vec::new(),
+                state: None,
+            });
+        }
+    }
+    Ok(diff_entries)
+}
+
+/// The plan `run` would carry out for `sdtabfile` right now.
+pub fn plan_actions(sdtabfile: &Sdtabfile, prune: bool) -> Result<Vec<plan::Action>> {
+    let current_units = parse_unit::scan_all_units()?;
+    let mut current_map: BTreeMap<String, &parse_unit::ParsedUnit> = BTreeMap::new();
+    for unit in &current_units {
+        current_map.insert(unit.name.clone(), unit);
+    }
+    let diff_entries = diff_entries(sdtabfile, &current_units, &current_map)?;
+    Ok(actions(sdtabfile, &diff_entries, &current_map, prune))
+}
+
+fn actions(
+    sdtabfile: &Sdtabfile,
+    diff_entries: &[DiffEntry],
+    current_map: &BTreeMap<String, &parse_unit::ParsedUnit>,
+    prune: bool,
+) -> Vec<plan::Action> {
+    let mut actions = Vec::new();
+    for entry in diff_entries {
+        let action = match (&entry.status, entry.state) {
+            (DiffStatus::Added, _) => "add",
+            (DiffStatus::Changed, _) => "change",
+            (DiffStatus::Unchanged, Some(true)) => "enable",
+            (DiffStatus::Unchanged, Some(false)) => "disable",
+            (DiffStatus::Removed, _) if prune => "remove",
+            (DiffStatus::Removed | DiffStatus::Unchanged, _) => continue,
+        };
+        let mut changes: Vec<plan::Change> = entry
+            .changes
+            .iter()
+            .map(|c| plan::Change { field: c.field.to_string(), old: c.old.clone(), new: c.new.clone() })
+            .collect();
+        if let Some(enabled) = entry.state {
+            changes.push(plan::Change { field: "enabled".to_string(), old: (!enabled).to_string(), new: enabled.to_string() });
+        }
+        actions.push(plan::Action {
+            name: entry.name.clone(),
+            unit_type: entry.unit_type.label().to_string(),
+            action: action.to_string(),
+            origin: entry.origin.clone(),
+            changes,
+            restarts: restarts(sdtabfile, entry, current_map.get(&entry.name).copied()),
+        });
+    }
+    actions
+}
+
+/// How the installed unit differs from entry `name`, installed value as `old`.
+pub fn field_changes(sdtabfile: &Sdtabfile, name: &str, current: &parse_unit::ParsedUnit) -> Vec<plan::Change> {
+    let changes = match current.unit_type {
+        parse_unit::UnitType::Timer => match sdtabfile.timers.get(name) {
+            Some(entry) => timer_changes(current, entry),
+            None => Vec::new(),
+        },
+        parse_unit::UnitType::Service => match sdtabfile.services.get(name) {
+            Some(entry) => service_changes(current, entry),
+            None => Vec::new(),
+        },
+    };
+    changes
+        .into_iter()
+        .map(|c| plan::Change { field: c.field.to_string(), old: c.old, new: c.new })
+        .collect()
+}
+
+/// Differences in the file and project the unit was last applied from.
+fn source_changes(current: &parse_unit::ParsedUnit, sdtabfile: &Sdtabfile, name: &str) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let origin = |path: Option<&str>| path.map(display_path);
+    field(&mut changes, "origin", &origin(current.origin.as_deref()), &origin(sdtabfile.origin(name)));
+    field(&mut changes, "project", &current.project, &sdtabfile.project(name).map(str::to_string));
+    changes
+}
+
+/// List the `-health` companion units applying deletes along with `name`.
+fn print_deleted_health_units(name: &str) -> Result<()> {
+    let files = health::unit_files(name, Path::new(&init::unit_dir()?));
+    if !files.is_empty() {
+        let names: Vec<String> = files.iter().filter_map(|f| f.file_name()).map(|f| f.to_string_lossy().to_string()).collect();
+        println!("      deletes: {}", names.join(", "));
+    }
     Ok(())
 }
 
+/// Print a unified diff of the entry's unit files against the installed
+/// ones, and what applying it restarts.
+fn print_unit_diff(sdtabfile: &Sdtabfile, entry: &DiffEntry, current: Option<&parse_unit::ParsedUnit>) -> Result<()> {
+    let unit_dir = init::unit_dir()?;
+    let desired = match entry.status {
+        DiffStatus::Removed => Vec::new(),
+        _ => entry_files(sdtabfile, &entry.name, &entry.unit_type)?,
+    };
+    let mut file_names: Vec<String> = match entry.unit_type {
+        parse_unit::UnitType::Timer => vec![unit::service_filename(&entry.name), unit::timer_filename(&entry.name)],
+        parse_unit::UnitType::Service => vec![
+            unit::service_filename(&entry.name),
+            unit::template_filename(&entry.name),
+            unit::health_service_filename(&entry.name),
+            unit::health_timer_filename(&entry.name),
+        ],
+    };
+    file_names.retain(|file_name| {
+        desired.iter().any(|(desired_name, _)| desired_name == file_name) || Path::new(&unit_dir).join(file_name).exists()
+    });
+
+    for file_name in &file_names {
+        let path = Path::new(&unit_dir).join(file_name);
+        let old = fs::read_to_string(&path).unwrap_or_default();
+        let new = desired
+            .iter()
+            .find(|(desired_name, _)| desired_name == file_name)
+            .map(|(_, content)| content.as_str());
+        let old_label = if path.exists() { format!("a/{}", file_name) } else { "/dev/null".to_string() };
+        let new_label = if new.is_some() { format!("b/{}", file_name) } else { "/dev/null".to_string() };
+        print!("{}", diff::unified(&old, new.unwrap_or(""), &old_label, &new_label));
+    }
+
+    let restarts: Vec<String> = restarts(sdtabfile, entry, current)
+        .into_iter()
+        .map(|unit_name| {
+            if unit_name.ends_with("@.service") {
+                format!("{} (blue/green switch)", unit_name)
+            } else {
+                unit_name
+            }
+        })
+        .collect();
+    let primary = parse_unit::primary_unit(&entry.name, &entry.unit_type);
+    let restart = match (&entry.status, entry.state) {
+        (DiffStatus::Removed, _) => "stops and removes it".to_string(),
+        (_, Some(false)) => format!("stops and disables {}", primary),
+        (DiffStatus::Added, _) if !desired_enabled(sdtabfile, entry) => "installs it disabled".to_string(),
+        (DiffStatus::Unchanged, None) => return Ok(()),
+        (DiffStatus::Added | DiffStatus::Unchanged, _) | (DiffStatus::Changed, Some(true)) => {
+            format!("enables and starts {}", restarts.join(" and "))
+        }
+        (DiffStatus::Changed, None) if restarts.is_empty() => {
+            "no restart needed; the next start picks up the new files".to_string()
+        }
+        (DiffStatus::Changed, None) => format!("restarts {}", restarts.join(" and ")),
+    };
+    println!("# {}: {}", entry.name, restart);
+    println!();
+    Ok(())
+}
+
+/// Units applying the entry starts (new units) or restarts (changed ones).
+/// Blue/green services are listed by their template.
+fn restarts(sdtabfile: &Sdtabfile, entry: &DiffEntry, current: Option<&parse_unit::ParsedUnit>) -> Vec<String> {
+    let name = &entry.name;
+    let mut restarts = Vec::new();
+    if entry.state == Some(false) || !desired_enabled(sdtabfile, entry) {
+        return restarts;
+    }
+    if entry.state == Some(true) {
+        // Enabled from scratch: started, whatever else changed.
+        restarts.push(parse_unit::primary_unit(name, &entry.unit_type));
+        if let parse_unit::UnitType::Service = entry.unit_type {
+            if sdtabfile.services[name].health_cmd.is_some() {
+                restarts.push(unit::health_timer_filename(name));
+            }
+        }
+        return restarts;
+    }
+    match (&entry.status, &entry.unit_type) {
+        (DiffStatus::Unchanged | DiffStatus::Removed, _) => {}
+        (DiffStatus::Added | DiffStatus::Changed, parse_unit::UnitType::Timer) => {
+            if current.is_none_or(|current| timer_needs_restart(current, &sdtabfile.timers[name])) {
+                restarts.push(unit::timer_filename(name));
+            }
+        }
+        (DiffStatus::Added | DiffStatus::Changed, parse_unit::UnitType::Service) => {
+            let desired = &sdtabfile.services[name];
+            if current.is_none_or(|current| service_needs_restart(current, desired)) {
+                restarts.push(if desired.ports.is_empty() {
+                    unit::service_filename(name)
+                } else {
+                    unit::template_filename(name)
+                });
+            }
+            if desired.health_cmd.is_some() && current.is_none_or(|current| health_needs_restart(current, desired)) {
+                restarts.push(unit::health_timer_filename(name));
+            }
+        }
+    }
+    restarts
+}
+
+/// `path` relative to the current directory when it lies below it.
+pub fn display_path(path: &str) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| Path::new(path).strip_prefix(cwd).ok().map(|p| p.display().to_string()))
+        .unwrap_or_else(|| path.to_string())
+}
+
 /// Write unit files for an entry (both add and update use this).
 /// Does NOT daemon-reload or enable/start — the caller handles that.
-fn write_entry(sdtabfile: &Sdtabfile, name: &str, unit_type: &parse_unit::UnitType) -> Result<()> {
+pub fn write_entry(sdtabfile: &Sdtabfile, name: &str, unit_type: &parse_unit::UnitType) -> Result<()> {
     let unit_dir = init::unit_dir()?;
     let dir_path = Path::new(&unit_dir);
 
+    for (file_name, content) in entry_files(sdtabfile, name, unit_type)? {
+        let path = dir_path.join(file_name);
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    if let parse_unit::UnitType::Service = unit_type {
+        let entry = &sdtabfile.services[name];
+        if !entry.ports.is_empty() {
+            bluegreen::write_slots(name, &entry.ports)?;
+        }
+        if entry.health_cmd.is_none() {
+            health::remove_units(name, dir_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The unit files an entry is written as: (file name, content).
+pub fn entry_files(sdtabfile: &Sdtabfile, name: &str, unit_type: &parse_unit::UnitType) -> Result<Vec<(String, String)>> {
+    let mut files = Vec::new();
     match unit_type {
         parse_unit::UnitType::Timer => {
             let entry = &sdtabfile.timers[name];
-            let unit_config = build_timer_config(name, entry)?;
-
-            let service_path = dir_path.join(unit::service_filename(name));
-            fs::write(&service_path, unit::generate_service(&unit_config))
-                .with_context(|| format!("Failed to write {}", service_path.display()))?;
-
-            let timer_path = dir_path.join(unit::timer_filename(name));
-            fs::write(&timer_path, unit::generate_timer(&unit_config))
-                .with_context(|| format!("Failed to write {}", timer_path.display()))?;
+            let unit_config = build_timer_config(name, entry, sdtabfile.origin(name), sdtabfile.project(name))?;
+            files.push((unit::service_filename(name), unit::generate_service(&unit_config)));
+            files.push((unit::timer_filename(name), unit::generate_timer(&unit_config)));
         }
         parse_unit::UnitType::Service => {
             let entry = &sdtabfile.services[name];
-            let unit_config = build_service_config(name, entry)?;
-
-            let service_path = dir_path.join(unit::service_filename(name));
-            fs::write(&service_path, unit::generate_daemon_service(&unit_config))
-                .with_context(|| format!("Failed to write {}", service_path.display()))?;
+            let unit_config = build_service_config(name, entry, sdtabfile.origin(name), sdtabfile.project(name))?;
+
+            let service_filename = if unit_config.ports.is_empty() {
+                unit::service_filename(name)
+            } else {
+                unit::template_filename(name)
+            };
+            files.push((service_filename, unit::generate_daemon_service(&unit_config)));
+            if unit_config.health_cmd.is_some() {
+                files.push((
+                    unit::health_service_filename(name),
+                    unit::generate_health_service(&unit_config, &init::sdtab_bin()),
+                ));
+                files.push((unit::health_timer_filename(name), unit::generate_health_timer(&unit_config)));
+            }
         }
     }
-    Ok(())
+    Ok(files)
 }
 
 fn resolve_on_failure(no_notify: bool) -> Result<Option<String>> {
@@ -254,13 +770,16 @@ fn resolve_on_failure(no_notify: bool) -> Result<Option<String>> {
     }
     let cfg = config::load()?;
     if cfg.notify.slack_webhook.is_some() {
-        Ok(Some("sdtab-notify@%n.service".to_string()))
+        Ok(Some(unit::notify_on_failure()))
     } else {
         Ok(None)
     }
 }
 
-fn build_timer_config(name: &str, entry: &TimerEntry) -> Result<unit::UnitConfig> {
+fn build_timer_config(name: &str, entry: &TimerEntry, origin: Option<&str>, project: Option<&str>) -> Result<unit::UnitConfig> {
+    if let Err(e) = add::check_run_as(entry.user.as_deref(), entry.group.as_deref()) {
+        bail!("'{}': {}", name, e);
+    }
     let parsed = cron::parse(&entry.schedule)?;
     let resolved_command = init::resolve_command(&entry.command)?;
     let description = entry.description.clone().unwrap_or_else(|| entry.command.clone());
@@ -271,6 +790,14 @@ fn build_timer_config(name: &str, entry: &TimerEntry) -> Result<unit::UnitConfig
         None
     };
     let on_failure = resolve_on_failure(entry.no_notify)?;
+    let output = output::resolve(
+        name,
+        entry.output_file.as_deref(),
+        entry.output_max_size.as_deref(),
+        entry.output_keep,
+        entry.mail_to.as_deref(),
+        &config::load()?.output,
+    )?;
 
     Ok(unit::UnitConfig {
         name: name.to_string(),
@@ -288,15 +815,52 @@ fn build_timer_config(name: &str, entry: &TimerEntry) -> Result<unit::UnitConfig
         exec_start_pre: entry.exec_start_pre.clone(),
         exec_stop_post: entry.exec_stop_post.clone(),
         log_level_max: entry.log_level_max.clone(),
+        user: entry.user.clone(),
+        group: entry.group.clone(),
         random_delay: entry.random_delay.clone(),
         env: entry.env.clone(),
         original_command,
         on_failure,
         no_notify: entry.no_notify,
+        service_type: None,
+        pid_file: None,
+        watchdog: None,
+        exec_reload: None,
+        health_cmd: None,
+        health_interval: None,
+        health_retries: None,
+        ports: Vec::new(),
+        deploy_steps: vec![],
+        rollback_steps: vec![],
+        output_file: entry.output_file.clone(),
+        output_max_size: entry.output_max_size.clone(),
+        output_keep: entry.output_keep,
+        mail_to: entry.mail_to.clone(),
+        origin: origin.map(str::to_string),
+        project: project.map(str::to_string),
+        output,
     })
 }
 
-fn build_service_config(name: &str, entry: &ServiceEntry) -> Result<unit::UnitConfig> {
+fn build_service_config(name: &str, entry: &ServiceEntry, origin: Option<&str>, project: Option<&str>) -> Result<unit::UnitConfig> {
+    if let Err(e) = add::check_run_as(entry.user.as_deref(), entry.group.as_deref()) {
+        bail!("'{}': {}", name, e);
+    }
+    add::validate_service_type(
+        entry.service_type.as_deref(),
+        entry.pid_file.as_deref(),
+        entry.watchdog.as_deref(),
+    )
+    .with_context(|| format!("Invalid service '{}'", name))?;
+    add::validate_health_interval(entry.health_interval.as_deref())
+        .with_context(|| format!("Invalid service '{}'", name))?;
+    if !entry.ports.is_empty() {
+        bluegreen::validate_ports(&entry.ports)
+            .with_context(|| format!("Invalid service '{}'", name))?;
+        if entry.health_cmd.is_none() {
+            bail!("Invalid service '{}': ports requires health_cmd", name);
+        }
+    }
     let resolved_command = init::resolve_command(&entry.command)?;
     let description = entry.description.clone().unwrap_or_else(|| entry.command.clone());
     let original_command = if resolved_command != entry.command {
@@ -305,6 +869,14 @@ fn build_service_config(name: &str, entry: &ServiceEntry) -> Result<unit::UnitCo
         None
     };
     let on_failure = resolve_on_failure(entry.no_notify)?;
+    let output = output::resolve(
+        name,
+        entry.output_file.as_deref(),
+        entry.output_max_size.as_deref(),
+        entry.output_keep,
+        entry.mail_to.as_deref(),
+        &config::load()?.output,
+    )?;
 
     Ok(unit::UnitConfig {
         name: name.to_string(),
@@ -322,11 +894,30 @@ fn build_service_config(name: &str, entry: &ServiceEntry) -> Result<unit::UnitCo
         exec_start_pre: entry.exec_start_pre.clone(),
         exec_stop_post: entry.exec_stop_post.clone(),
         log_level_max: entry.log_level_max.clone(),
+        user: entry.user.clone(),
+        group: entry.group.clone(),
         random_delay: None,
         env: entry.env.clone(),
         original_command,
         on_failure,
         no_notify: entry.no_notify,
+        service_type: entry.service_type.clone(),
+        pid_file: entry.pid_file.clone(),
+        watchdog: entry.watchdog.clone(),
+        exec_reload: entry.exec_reload.clone(),
+        health_cmd: entry.health_cmd.clone(),
+        health_interval: entry.health_interval.clone(),
+        health_retries: entry.health_retries,
+        ports: entry.ports.clone(),
+        deploy_steps: entry.deploy_steps.clone(),
+        rollback_steps: entry.rollback_steps.clone(),
+        output_file: entry.output_file.clone(),
+        output_max_size: entry.output_max_size.clone(),
+        output_keep: entry.output_keep,
+        mail_to: entry.mail_to.clone(),
+        origin: origin.map(str::to_string),
+        project: project.map(str::to_string),
+        output,
     })
 }
 
@@ -352,52 +943,172 @@ fn service_needs_restart(current: &parse_unit::ParsedUnit, desired: &ServiceEntr
         || current.exec_start_pre != desired.exec_start_pre
         || current.exec_stop_post != desired.exec_stop_post
         || current.log_level_max != desired.log_level_max
+        || current.user != desired.user
+        || current.group != desired.group
         || current.env != desired.env
+        || current.service_type != desired_type(desired)
+        || current.pid_file != desired.pid_file
+        || current.watchdog != desired.watchdog
+        || current.exec_reload != desired.exec_reload
+        || current.ports != desired.ports
 }
 
-fn timer_matches(current: &parse_unit::ParsedUnit, desired: &TimerEntry) -> bool {
-    let cron = current.cron_expr.as_deref().unwrap_or("");
-    cron == desired.schedule
-        && current.command == desired.command
-        && current.workdir == desired.workdir
-        && sdtabfile::desc_matches(&current.description, &current.command, &desired.description)
-        && current.env_file == desired.env_file
-        && current.memory_max == desired.memory_max
-        && current.cpu_quota == desired.cpu_quota
-        && current.io_weight == desired.io_weight
-        && current.timeout_stop == desired.timeout_stop
-        && current.exec_start_pre == desired.exec_start_pre
-        && current.exec_stop_post == desired.exec_stop_post
-        && current.log_level_max == desired.log_level_max
-        && current.random_delay == desired.random_delay
-        && current.env == desired.env
-        && current.no_notify == desired.no_notify
-}
-
-fn service_matches(current: &parse_unit::ParsedUnit, desired: &ServiceEntry) -> bool {
-    let current_restart = current.restart_policy.as_deref().unwrap_or("always");
-    let desired_restart = desired.restart.as_deref().unwrap_or("always");
-    current.command == desired.command
-        && current.workdir == desired.workdir
-        && sdtabfile::desc_matches(&current.description, &current.command, &desired.description)
-        && current_restart == desired_restart
-        && current.env_file == desired.env_file
-        && current.memory_max == desired.memory_max
-        && current.cpu_quota == desired.cpu_quota
-        && current.io_weight == desired.io_weight
-        && current.timeout_stop == desired.timeout_stop
-        && current.exec_start_pre == desired.exec_start_pre
-        && current.exec_stop_post == desired.exec_stop_post
-        && current.log_level_max == desired.log_level_max
-        && current.env == desired.env
-        && current.no_notify == desired.no_notify
+/// `Type=`, with the default `simple` as None the way the parser reports it.
+fn desired_type(desired: &ServiceEntry) -> Option<String> {
+    desired.service_type.clone().filter(|t| t != "simple")
+}
+
+/// Health probe settings changed → the companion health timer must be restarted.
+/// The service itself keeps running.
+fn health_needs_restart(current: &parse_unit::ParsedUnit, desired: &ServiceEntry) -> bool {
+    current.health_cmd != desired.health_cmd
+        || current.health_interval != desired.health_interval
+        || current.health_retries != desired.health_retries
+}
+
+fn timer_changes(current: &parse_unit::ParsedUnit, desired: &TimerEntry) -> Vec<FieldChange> {
+    let mut c = Vec::new();
+    field(&mut c, "schedule", &current.cron_expr.clone().unwrap_or_default(), &desired.schedule);
+    field(&mut c, "command", &current.command, &desired.command);
+    field(&mut c, "workdir", &current.workdir, &desired.workdir);
+    description_change(&mut c, current, desired.description.as_ref());
+    field(&mut c, "env_file", &current.env_file, &desired.env_file);
+    field(&mut c, "memory_max", &current.memory_max, &desired.memory_max);
+    field(&mut c, "cpu_quota", &current.cpu_quota, &desired.cpu_quota);
+    field(&mut c, "io_weight", &current.io_weight, &desired.io_weight);
+    field(&mut c, "timeout_stop", &current.timeout_stop, &desired.timeout_stop);
+    field(&mut c, "exec_start_pre", &current.exec_start_pre, &desired.exec_start_pre);
+    field(&mut c, "exec_stop_post", &current.exec_stop_post, &desired.exec_stop_post);
+    field(&mut c, "log_level_max", &current.log_level_max, &desired.log_level_max);
+    field(&mut c, "user", &current.user, &desired.user);
+    field(&mut c, "group", &current.group, &desired.group);
+    field(&mut c, "random_delay", &current.random_delay, &desired.random_delay);
+    field(&mut c, "env", &current.env, &desired.env);
+    field(&mut c, "no_notify", &current.no_notify, &desired.no_notify);
+    field(&mut c, "output_file", &current.output_file, &desired.output_file);
+    field(&mut c, "output_max_size", &current.output_max_size, &desired.output_max_size);
+    field(&mut c, "output_keep", &current.output_keep, &desired.output_keep);
+    field(&mut c, "mail_to", &current.mail_to, &desired.mail_to);
+    c
+}
+
+fn service_changes(current: &parse_unit::ParsedUnit, desired: &ServiceEntry) -> Vec<FieldChange> {
+    let current_restart = current.restart_policy.clone().unwrap_or_else(|| "always".to_string());
+    let desired_restart = desired.restart.clone().unwrap_or_else(|| "always".to_string());
+    let mut c = Vec::new();
+    field(&mut c, "command", &current.command, &desired.command);
+    field(&mut c, "workdir", &current.workdir, &desired.workdir);
+    description_change(&mut c, current, desired.description.as_ref());
+    field(&mut c, "restart", &current_restart, &desired_restart);
+    field(&mut c, "env_file", &current.env_file, &desired.env_file);
+    field(&mut c, "memory_max", &current.memory_max, &desired.memory_max);
+    field(&mut c, "cpu_quota", &current.cpu_quota, &desired.cpu_quota);
+    field(&mut c, "io_weight", &current.io_weight, &desired.io_weight);
+    field(&mut c, "timeout_stop", &current.timeout_stop, &desired.timeout_stop);
+    field(&mut c, "exec_start_pre", &current.exec_start_pre, &desired.exec_start_pre);
+    field(&mut c, "exec_stop_post", &current.exec_stop_post, &desired.exec_stop_post);
+    field(&mut c, "log_level_max", &current.log_level_max, &desired.log_level_max);
+    field(&mut c, "user", &current.user, &desired.user);
+    field(&mut c, "group", &current.group, &desired.group);
+    field(&mut c, "env", &current.env, &desired.env);
+    field(&mut c, "no_notify", &current.no_notify, &desired.no_notify);
+    field(&mut c, "type", &current.service_type, &desired_type(desired));
+    field(&mut c, "pid_file", &current.pid_file, &desired.pid_file);
+    field(&mut c, "watchdog", &current.watchdog, &desired.watchdog);
+    field(&mut c, "exec_reload", &current.exec_reload, &desired.exec_reload);
+    field(&mut c, "health_cmd", &current.health_cmd, &desired.health_cmd);
+    field(&mut c, "health_interval", &current.health_interval, &desired.health_interval);
+    field(&mut c, "health_retries", &current.health_retries, &desired.health_retries);
+    field(&mut c, "ports", &current.ports, &desired.ports);
+    field(&mut c, "deploy_steps", &current.deploy_steps, &desired.deploy_steps);
+    field(&mut c, "rollback_steps", &current.rollback_steps, &desired.rollback_steps);
+    field(&mut c, "output_file", &current.output_file, &desired.output_file);
+    field(&mut c, "output_max_size", &current.output_max_size, &desired.output_max_size);
+    field(&mut c, "output_keep", &current.output_keep, &desired.output_keep);
+    field(&mut c, "mail_to", &current.mail_to, &desired.mail_to);
+    c
+}
+
+/// Where output goes once config.toml's `[output]` defaults are filled in.
+/// Only the entry's own options are kept in the unit's metadata, so a changed
+/// default only shows up here.
+fn output_change(changes: &mut Vec<FieldChange>, current: &parse_unit::ParsedUnit, desired: &unit::OutputTarget) {
+    if !changes.iter().any(|c| c.field.starts_with("output_") || c.field == "mail_to") {
+        field(changes, "output", &current.output, &output::summary(desired));
+    }
+}
+
+fn description_change(changes: &mut Vec<FieldChange>, current: &parse_unit::ParsedUnit, desired: Option<&String>) {
+    let desired = desired.cloned();
+    if !sdtabfile::desc_matches(&current.description, &current.command, &desired) {
+        let old = sdtabfile::description_if_different(&current.description, &current.command);
+        changes.push(FieldChange { field: "description", old: old.show(), new: desired.show() });
+    }
+}
+
+/// Record `name` when the installed and desired values differ.
+fn field<T: PartialEq + FieldValue>(changes: &mut Vec<FieldChange>, name: &'static str, old: &T, new: &T) {
+    if old != new {
+        changes.push(FieldChange { field: name, old: old.show(), new: new.show() });
+    }
+}
+
+/// How a setting reads in the change list.
+trait FieldValue {
+    fn show(&self) -> String;
 }
 
+impl FieldValue for String {
+    fn show(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl FieldValue for bool {
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl FieldValue for u16 {
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl FieldValue for u32 {
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<T: FieldValue> FieldValue for Option<T> {
+    fn show(&self) -> String {
+        match self {
+            Some(value) => value.show(),
+            None => "(unset)".to_string(),
+        }
+    }
+}
+
+impl<T: FieldValue> FieldValue for Vec<T> {
+    fn show(&self) -> String {
+        format!("[{}]", self.iter().map(FieldValue::show).collect::<Vec<_>>().join(", "))
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn timer_matches(current: &parse_unit::ParsedUnit, desired: &TimerEntry) -> bool {
+        timer_changes(current, desired).is_empty()
+    }
+
+    fn service_matches(current: &parse_unit::ParsedUnit, desired: &ServiceEntry) -> bool {
+        service_changes(current, desired).is_empty()
+    }
+
     #[test]
     fn test_parse_toml_timers() {
         let toml = r#"
@@ -507,9 +1218,28 @@ workdir = "/home/user/app"
             exec_start_pre: None,
             exec_stop_post: None,
             log_level_max: None,
+            user: None,
+            group: None,
             random_delay: None,
             env: vec![],
             no_notify: false,
+            service_type: None,
+            pid_file: None,
+            watchdog: None,
+            exec_reload: None,
+            health_cmd: None,
+            health_interval: None,
+            health_retries: None,
+            ports: vec![],
+            deploy_steps: vec![],
+            rollback_steps: vec![],
+            output_file: None,
+            output_max_size: None,
+            output_keep: None,
+            mail_to: None,
+            output: None,
+            origin: None,
+            project: None,
             template_version: unit::TEMPLATE_VERSION,
         }
     }
@@ -528,9 +1258,16 @@ workdir = "/home/user/app"
             exec_start_pre: None,
             exec_stop_post: None,
             log_level_max: None,
+            user: None,
+            group: None,
             random_delay: None,
             env: vec![],
             no_notify: false,
+            enabled: true,
+            output_file: None,
+            output_max_size: None,
+            output_keep: None,
+            mail_to: None,
         }
     }
 
@@ -548,8 +1285,25 @@ workdir = "/home/user/app"
             exec_start_pre: None,
             exec_stop_post: None,
             log_level_max: None,
+            user: None,
+            group: None,
             env: vec![],
             no_notify: false,
+            enabled: true,
+            service_type: None,
+            pid_file: None,
+            watchdog: None,
+            exec_reload: None,
+            health_cmd: None,
+            health_interval: None,
+            health_retries: None,
+            ports: vec![],
+            deploy_steps: vec![],
+            rollback_steps: vec![],
+            output_file: None,
+            output_max_size: None,
+            output_keep: None,
+            mail_to: None,
         }
     }
 
@@ -635,9 +1389,16 @@ workdir = "/home/user/app"
             exec_start_pre: p.exec_start_pre.clone(),
             exec_stop_post: p.exec_stop_post.clone(),
             log_level_max: p.log_level_max.clone(),
+            user: p.user.clone(),
+            group: p.group.clone(),
             random_delay: p.random_delay.clone(),
             env: p.env.clone(),
             no_notify: p.no_notify,
+            enabled: true,
+            output_file: p.output_file.clone(),
+            output_max_size: p.output_max_size.clone(),
+            output_keep: p.output_keep,
+            mail_to: p.mail_to.clone(),
         }
     }
 
@@ -655,8 +1416,25 @@ workdir = "/home/user/app"
             exec_start_pre: p.exec_start_pre.clone(),
             exec_stop_post: p.exec_stop_post.clone(),
             log_level_max: p.log_level_max.clone(),
+            user: p.user.clone(),
+            group: p.group.clone(),
             env: p.env.clone(),
             no_notify: p.no_notify,
+            enabled: true,
+            service_type: p.service_type.clone(),
+            pid_file: p.pid_file.clone(),
+            watchdog: p.watchdog.clone(),
+            exec_reload: p.exec_reload.clone(),
+            health_cmd: p.health_cmd.clone(),
+            health_interval: p.health_interval.clone(),
+            health_retries: p.health_retries,
+            ports: p.ports.clone(),
+            deploy_steps: p.deploy_steps.clone(),
+            rollback_steps: p.rollback_steps.clone(),
+            output_file: p.output_file.clone(),
+            output_max_size: p.output_max_size.clone(),
+            output_keep: p.output_keep,
+            mail_to: p.mail_to.clone(),
         }
     }
 
@@ -681,9 +1459,28 @@ workdir = "/home/user/app"
             exec_start_pre: Some("/bin/true".to_string()),
             exec_stop_post: Some("/bin/false".to_string()),
             log_level_max: Some("warning".to_string()),
+            user: None,
+            group: None,
             random_delay: Some("5m".to_string()),
             env: vec!["FOO=bar".to_string()],
             no_notify: true,
+            service_type: None,
+            pid_file: None,
+            watchdog: None,
+            exec_reload: None,
+            health_cmd: None,
+            health_interval: None,
+            health_retries: None,
+            ports: vec![],
+            deploy_steps: vec![],
+            rollback_steps: vec![],
+            output_file: Some("/home/user/logs/job.log".to_string()),
+            output_max_size: Some("1M".to_string()),
+            output_keep: Some(3),
+            mail_to: Some("ops@example.com".to_string()),
+            output: None,
+            origin: None,
+            project: None,
             template_version: unit::TEMPLATE_VERSION,
         }
     }
@@ -705,9 +1502,28 @@ workdir = "/home/user/app"
             exec_start_pre: Some("/bin/true".to_string()),
             exec_stop_post: Some("/bin/false".to_string()),
             log_level_max: Some("warning".to_string()),
+            user: None,
+            group: None,
             random_delay: None,
             env: vec!["FOO=bar".to_string()],
             no_notify: true,
+            service_type: Some("notify".to_string()),
+            pid_file: None,
+            watchdog: Some("30s".to_string()),
+            exec_reload: Some("/bin/kill -HUP $MAINPID".to_string()),
+            health_cmd: Some("curl -fs localhost:3000/health".to_string()),
+            health_interval: Some("15s".to_string()),
+            health_retries: Some(5),
+            ports: vec![3000, 3001],
+            deploy_steps: vec!["npm ci".to_string(), "npm run build".to_string()],
+            rollback_steps: vec!["git checkout HEAD@{1}".to_string()],
+            output_file: Some("/home/user/logs/job.log".to_string()),
+            output_max_size: Some("1M".to_string()),
+            output_keep: Some(3),
+            mail_to: Some("ops@example.com".to_string()),
+            output: None,
+            origin: None,
+            project: None,
             template_version: unit::TEMPLATE_VERSION,
         }
     }
@@ -748,9 +1564,15 @@ workdir = "/home/user/app"
             ("exec_start_pre", TimerEntry { exec_start_pre: Some("/bin/echo".into()), ..base.clone() }),
             ("exec_stop_post", TimerEntry { exec_stop_post: Some("/bin/echo".into()), ..base.clone() }),
             ("log_level_max", TimerEntry { log_level_max: Some("err".into()), ..base.clone() }),
+            ("user", TimerEntry { user: Some("www-data".into()), ..base.clone() }),
+            ("group", TimerEntry { group: Some("www-data".into()), ..base.clone() }),
             ("random_delay", TimerEntry { random_delay: Some("10m".into()), ..base.clone() }),
             ("env", TimerEntry { env: vec!["BAR=baz".into()], ..base.clone() }),
             ("no_notify", TimerEntry { no_notify: false, ..base.clone() }),
+            ("output_file", TimerEntry { output_file: Some("/tmp/o.log".into()), ..base.clone() }),
+            ("output_max_size", TimerEntry { output_max_size: Some("5M".into()), ..base.clone() }),
+            ("output_keep", TimerEntry { output_keep: Some(9), ..base.clone() }),
+            ("mail_to", TimerEntry { mail_to: Some("a@b.c".into()), ..base.clone() }),
         ];
 
         for (field, mutated) in &mutations {
@@ -758,6 +1580,8 @@ workdir = "/home/user/app"
                 !timer_matches(&current, mutated),
                 "timer_matches should detect change in '{}'", field
             );
+            let changed: Vec<&str> = timer_changes(&current, mutated).iter().map(|c| c.field).collect();
+            assert_eq!(changed, vec![*field]);
         }
     }
 
@@ -779,8 +1603,24 @@ workdir = "/home/user/app"
             ("exec_start_pre", ServiceEntry { exec_start_pre: Some("/bin/echo".into()), ..base.clone() }),
             ("exec_stop_post", ServiceEntry { exec_stop_post: Some("/bin/echo".into()), ..base.clone() }),
             ("log_level_max", ServiceEntry { log_level_max: Some("err".into()), ..base.clone() }),
+            ("user", ServiceEntry { user: Some("www-data".into()), ..base.clone() }),
+            ("group", ServiceEntry { group: Some("www-data".into()), ..base.clone() }),
             ("env", ServiceEntry { env: vec!["BAR=baz".into()], ..base.clone() }),
             ("no_notify", ServiceEntry { no_notify: false, ..base.clone() }),
+            ("service_type", ServiceEntry { service_type: Some("exec".into()), ..base.clone() }),
+            ("pid_file", ServiceEntry { pid_file: Some("/run/x.pid".into()), ..base.clone() }),
+            ("watchdog", ServiceEntry { watchdog: Some("10s".into()), ..base.clone() }),
+            ("exec_reload", ServiceEntry { exec_reload: Some("/bin/true".into()), ..base.clone() }),
+            ("health_cmd", ServiceEntry { health_cmd: Some("true".into()), ..base.clone() }),
+            ("health_interval", ServiceEntry { health_interval: Some("1m".into()), ..base.clone() }),
+            ("health_retries", ServiceEntry { health_retries: Some(9), ..base.clone() }),
+            ("ports", ServiceEntry { ports: vec![4000, 4001], ..base.clone() }),
+            ("deploy_steps", ServiceEntry { deploy_steps: vec!["make".into()], ..base.clone() }),
+            ("rollback_steps", ServiceEntry { rollback_steps: vec!["make undo".into()], ..base.clone() }),
+            ("output_file", ServiceEntry { output_file: Some("/tmp/o.log".into()), ..base.clone() }),
+            ("output_max_size", ServiceEntry { output_max_size: Some("5M".into()), ..base.clone() }),
+            ("output_keep", ServiceEntry { output_keep: Some(9), ..base.clone() }),
+            ("mail_to", ServiceEntry { mail_to: Some("a@b.c".into()), ..base.clone() }),
         ];
 
         for (field, mutated) in &mutations {
@@ -813,11 +1653,36 @@ workdir = "/home/user/app"
             exec_start_pre: Some("/bin/true".to_string()),
             exec_stop_post: Some("/bin/false".to_string()),
             log_level_max: Some("warning".to_string()),
+            user: None,
+            group: None,
             random_delay: Some("5m".to_string()),
             env: vec!["FOO=bar".to_string(), "BAZ=qux".to_string()],
             original_command: Some("echo hello".to_string()),
             on_failure: Some("sdtab-notify@%n.service".to_string()),
             no_notify: false,
+            service_type: None,
+            pid_file: None,
+            watchdog: None,
+            exec_reload: None,
+            health_cmd: None,
+            health_interval: None,
+            health_retries: None,
+            ports: vec![],
+            deploy_steps: vec![],
+            rollback_steps: vec![],
+            output_file: Some("/home/user/logs/job.log".to_string()),
+            output_max_size: Some("1M".to_string()),
+            output_keep: Some(3),
+            mail_to: Some("ops@example.com".to_string()),
+            origin: Some("/home/user/Sdtabfile.toml".to_string()),
+            project: Some("billing".to_string()),
+            output: unit::OutputTarget {
+                file: Some("/home/user/logs/job.log".to_string()),
+                max_size: "1M".to_string(),
+                keep: 3,
+                mail_to: Some("ops@example.com".to_string()),
+                sdtab_bin: "/usr/bin/sdtab".to_string(),
+            },
         }
     }
 
@@ -838,11 +1703,36 @@ workdir = "/home/user/app"
             exec_start_pre: Some("/bin/true".to_string()),
             exec_stop_post: Some("/bin/false".to_string()),
             log_level_max: Some("err".to_string()),
+            user: Some("www-data".to_string()),
+            group: Some("www-data".to_string()),
             random_delay: None,
             env: vec!["NODE_ENV=production".to_string()],
             original_command: Some("node index.js".to_string()),
             on_failure: Some("sdtab-notify@%n.service".to_string()),
             no_notify: false,
+            service_type: Some("notify".to_string()),
+            pid_file: None,
+            watchdog: Some("30s".to_string()),
+            exec_reload: Some("/bin/kill -HUP $MAINPID".to_string()),
+            health_cmd: Some("curl -fs localhost:3000/health".to_string()),
+            health_interval: Some("15s".to_string()),
+            health_retries: Some(5),
+            ports: vec![3000, 3001],
+            deploy_steps: vec!["npm ci".to_string(), "npm run build".to_string()],
+            rollback_steps: vec!["git checkout HEAD@{1}".to_string()],
+            output_file: Some("/home/user/logs/job.log".to_string()),
+            output_max_size: Some("1M".to_string()),
+            output_keep: Some(3),
+            mail_to: Some("ops@example.com".to_string()),
+            origin: Some("/home/user/Sdtabfile.toml".to_string()),
+            project: Some("billing".to_string()),
+            output: unit::OutputTarget {
+                file: Some("/home/user/logs/job.log".to_string()),
+                max_size: "1M".to_string(),
+                keep: 3,
+                mail_to: Some("ops@example.com".to_string()),
+                sdtab_bin: "/usr/bin/sdtab".to_string(),
+            },
         }
     }
 
@@ -858,7 +1748,7 @@ workdir = "/home/user/app"
         );
 
         // original_command is set, so parsed.command should be the original
-        assert_eq!(parsed.command, "echo hello", "command (via sdtab:command)");
+        assert_eq!(parsed.command, "echo hello", "command (via X-Sdtab-Command)");
         assert_eq!(parsed.workdir, config.workdir, "workdir");
         assert_eq!(parsed.description, config.description, "description");
         assert_eq!(parsed.cron_expr, config.cron_expr, "cron_expr");
@@ -870,9 +1760,15 @@ workdir = "/home/user/app"
         assert_eq!(parsed.exec_start_pre, config.exec_start_pre, "exec_start_pre");
         assert_eq!(parsed.exec_stop_post, config.exec_stop_post, "exec_stop_post");
         assert_eq!(parsed.log_level_max, config.log_level_max, "log_level_max");
+        assert_eq!(parsed.user, config.user, "user");
+        assert_eq!(parsed.group, config.group, "group");
         assert_eq!(parsed.random_delay, config.random_delay, "random_delay");
         assert_eq!(parsed.env, config.env, "env");
         assert_eq!(parsed.no_notify, config.no_notify, "no_notify");
+        assert_eq!(parsed.output_file, config.output_file, "output_file");
+        assert_eq!(parsed.mail_to, config.mail_to, "mail_to");
+        assert_eq!(parsed.origin, config.origin, "origin");
+        assert_eq!(parsed.project, config.project, "project");
     }
 
     #[test]
@@ -885,7 +1781,7 @@ workdir = "/home/user/app"
             "roundtrip", &service_str, None, &global_env,
         );
 
-        assert_eq!(parsed.command, "node index.js", "command (via sdtab:command)");
+        assert_eq!(parsed.command, "node index.js", "command (via X-Sdtab-Command)");
         assert_eq!(parsed.workdir, config.workdir, "workdir");
         assert_eq!(parsed.description, config.description, "description");
         assert_eq!(parsed.restart_policy, config.restart_policy, "restart_policy");
@@ -897,7 +1793,457 @@ workdir = "/home/user/app"
         assert_eq!(parsed.exec_start_pre, config.exec_start_pre, "exec_start_pre");
         assert_eq!(parsed.exec_stop_post, config.exec_stop_post, "exec_stop_post");
         assert_eq!(parsed.log_level_max, config.log_level_max, "log_level_max");
+        assert_eq!(parsed.user, config.user, "user");
+        assert_eq!(parsed.group, config.group, "group");
         assert_eq!(parsed.env, config.env, "env");
         assert_eq!(parsed.no_notify, config.no_notify, "no_notify");
+        assert_eq!(parsed.service_type, config.service_type, "service_type");
+        assert_eq!(parsed.pid_file, config.pid_file, "pid_file");
+        assert_eq!(parsed.watchdog, config.watchdog, "watchdog");
+        assert_eq!(parsed.exec_reload, config.exec_reload, "exec_reload");
+        assert_eq!(parsed.health_cmd, config.health_cmd, "health_cmd");
+        assert_eq!(parsed.health_interval, config.health_interval, "health_interval");
+        assert_eq!(parsed.health_retries, config.health_retries, "health_retries");
+        assert_eq!(parsed.ports, config.ports, "ports");
+        assert_eq!(parsed.deploy_steps, config.deploy_steps, "deploy_steps");
+        assert_eq!(parsed.rollback_steps, config.rollback_steps, "rollback_steps");
+        assert_eq!(parsed.output_file, config.output_file, "output_file");
+        assert_eq!(parsed.output_max_size, config.output_max_size, "output_max_size");
+        assert_eq!(parsed.output_keep, config.output_keep, "output_keep");
+        assert_eq!(parsed.mail_to, config.mail_to, "mail_to");
+        assert_eq!(parsed.exec_start_pre, config.exec_start_pre, "exec_start_pre (output hook ignored)");
+    }
+
+    #[test]
+    fn test_service_needs_restart_type_changed() {
+        let current = make_parsed_unit("web", parse_unit::UnitType::Service);
+        let desired = ServiceEntry {
+            service_type: Some("notify".to_string()),
+            ..make_service_entry()
+        };
+        assert!(service_needs_restart(&current, &desired));
+    }
+
+    #[test]
+    fn test_health_change_restarts_probe_not_service() {
+        let current = make_parsed_unit("web", parse_unit::UnitType::Service);
+        let desired = ServiceEntry {
+            health_cmd: Some("curl -fs localhost/health".to_string()),
+            ..make_service_entry()
+        };
+        assert!(health_needs_restart(&current, &desired));
+        assert!(!service_needs_restart(&current, &desired));
+    }
+
+    #[test]
+    fn test_user_and_group_need_system_mode() {
+        let desired = ServiceEntry {
+            user: Some("www-data".to_string()),
+            ..make_service_entry()
+        };
+        let err = build_service_config("web", &desired, None, None).err().unwrap();
+        assert_eq!(err.to_string(), "'web': user and group can only be set with --system (user units always run as you)");
+    }
+
+    #[test]
+    fn health_interval_must_be_a_time_span() {
+        let interval = |value: &str| ServiceEntry {
+            health_cmd: Some("curl -fs localhost/health".to_string()),
+            health_interval: Some(value.to_string()),
+            ..make_service_entry()
+        };
+        assert!(build_service_config("web", &interval("1min 30s"), None, None).is_ok());
+        let err = build_service_config("web", &interval("often"), None, None).err().unwrap();
+        assert_eq!(
+            format!("{:#}", err),
+            "Invalid service 'web': Invalid health interval: 'often' is not a duration (e.g. 30s, 5min, 1h 30min)"
+        );
+        assert!(build_service_config("web", &interval("infinity"), None, None).is_err());
+    }
+
+    mod against_fake {
+        use std::rc::Rc;
+
+        use super::*;
+        use crate::backend;
+        use crate::fake::FakeBackend;
+        use crate::testdir::TestDir;
+
+        fn home(tag: &str) -> TestDir {
+            let dir = TestDir::new(&format!("apply-{}", tag));
+            fs::create_dir_all(dir.join(".config/systemd/user")).unwrap();
+            dir
+        }
+
+        /// Write `toml` as the Sdtabfile and apply it against `fake` with `home` as $HOME.
+        fn apply(home: &Path, fake: &Rc<FakeBackend>, toml: &str, prune: bool) {
+            let file = home.join("Sdtabfile.toml");
+            fs::write(&file, toml).unwrap();
+            apply_path(home, fake, &file, prune).unwrap();
+        }
+
+        fn apply_path(home: &Path, fake: &Rc<FakeBackend>, path: &Path, prune: bool) -> Result<()> {
+            init::with_home(home, || backend::with_backend(fake.clone(), || run(path.to_str().unwrap(), prune, false, false, false, None)))
+        }
+
+        const TIMER: &str = r#"
+[timers.backup]
+schedule = "@daily/3"
+command = "./backup.sh --full"
+workdir = "/tmp"
+no_notify = true
+"#;
+
+        const SERVICE: &str = r#"
+[services.web]
+command = "./web.sh app.js"
+workdir = "/tmp"
+type = "notify"
+no_notify = true
+"#;
+
+        #[test]
+        fn failed_health_restart_keeps_the_streak() {
+            let home = home("health-restart");
+            let fake = Rc::new(FakeBackend::default());
+            let probed = format!("{}health_cmd = \"false\"\nhealth_retries = 1\n", SERVICE);
+            apply(&home, &fake, &probed, false);
+            let check = || init::with_home(&home, || backend::with_backend(fake.clone(), || health::run_check("web")));
+            let state = || init::with_home(&home, || health::load_state("web")).unwrap();
+
+            fake.fail_next("sdtab-web.service");
+            assert!(check().is_err());
+            assert_eq!((state().consecutive_failures, state().restarts), (1, 0));
+
+            check().unwrap();
+            assert_eq!((state().consecutive_failures, state().restarts), (0, 1));
+
+            let dir = home.join(".config/systemd/user");
+            let removed = init::with_home(&home, || backend::with_backend(fake.clone(), || health::remove_units("web", &dir))).unwrap();
+            assert_eq!(removed, vec![dir.join("sdtab-health@web.service"), dir.join("sdtab-health@web.timer")]);
+        }
+
+        #[test]
+        fn adds_then_leaves_unchanged_units_alone() {
+            let home = home("add");
+            let fake = Rc::new(FakeBackend::default());
+
+            apply(&home, &fake, &format!("{}{}", TIMER, SERVICE), false);
+            assert_eq!(
+                fake.take_calls(),
+                vec!["daemon-reload", "enable --now sdtab-backup.timer", "enable --now sdtab-web.service"]
+            );
+            assert!(home.join(".config/systemd/user/sdtab-backup.service").exists());
+            assert!(fake.state("sdtab-web.service").active);
+
+            apply(&home, &fake, &format!("{}{}", TIMER, SERVICE), false);
+            assert!(fake.take_calls().is_empty());
+        }
+
+        #[test]
+        fn explicit_defaults_match_what_is_installed() {
+            let home = home("defaults");
+            let fake = Rc::new(FakeBackend::default());
+            let service = SERVICE.replace("\"notify\"", "\"simple\"");
+            apply(&home, &fake, &service, false);
+            fake.take_calls();
+
+            apply(&home, &fake, &service, false);
+            assert!(fake.take_calls().is_empty());
+        }
+
+        #[test]
+        fn output_defaults_from_config_are_applied_to_installed_units() {
+            let home = home("output");
+            let fake = Rc::new(FakeBackend::default());
+            apply(&home, &fake, TIMER, false);
+            fake.take_calls();
+
+            let logs = home.join("logs");
+            let config = init::with_home(&home, || config::config_path().unwrap());
+            fs::create_dir_all(Path::new(&config).parent().unwrap()).unwrap();
+            fs::write(&config, format!("[output]\ndir = \"{}\"\nkeep = 3\n", logs.display())).unwrap();
+            apply(&home, &fake, TIMER, false);
+            assert_eq!(fake.take_calls(), vec!["daemon-reload"]);
+            let service = fs::read_to_string(home.join(".config/systemd/user/sdtab-backup.service")).unwrap();
+            assert!(service.contains(&format!("StandardOutput=append:{}/backup.log", logs.display())), "{}", service);
+
+            apply(&home, &fake, TIMER, false);
+            assert!(fake.take_calls().is_empty());
+        }
+
+        #[test]
+        fn restarts_only_what_the_change_needs() {
+            let home = home("change");
+            let fake = Rc::new(FakeBackend::default());
+            apply(&home, &fake, &format!("{}{}", TIMER, SERVICE), false);
+            fake.take_calls();
+
+            // A timer's command takes effect on the next run: rewrite, no restart.
+            let new_command = TIMER.replace("--full", "--incremental");
+            apply(&home, &fake, &format!("{}{}", new_command, SERVICE), false);
+            assert_eq!(fake.take_calls(), vec!["daemon-reload"]);
+
+            // A new schedule restarts the timer; a new command restarts the service.
+            let new_schedule = new_command.replace("@daily/3", "@daily/4");
+            let new_service = SERVICE.replace("app.js", "server.js");
+            apply(&home, &fake, &format!("{}{}", new_schedule, new_service), false);
+            assert_eq!(
+                fake.take_calls(),
+                vec!["daemon-reload", "restart sdtab-backup.timer", "restart sdtab-web.service"]
+            );
+        }
+
+        #[test]
+        fn prune_removes_units_missing_from_the_file() {
+            let home = home("prune");
+            let fake = Rc::new(FakeBackend::default());
+            apply(&home, &fake, &format!("{}{}", TIMER, SERVICE), false);
+            fake.take_calls();
+
+            apply(&home, &fake, TIMER, false);
+            assert!(fake.take_calls().is_empty());
+            assert!(home.join(".config/systemd/user/sdtab-web.service").exists());
+
+            apply(&home, &fake, TIMER, true);
+            assert_eq!(fake.take_calls(), vec!["disable --now sdtab-web.service", "daemon-reload"]);
+            assert!(!home.join(".config/systemd/user/sdtab-web.service").exists());
+            assert!(!fake.state("sdtab-web.service").enabled);
+        }
+
+        #[test]
+        fn prune_only_touches_units_from_the_applied_files() {
+            let home = home("scope");
+            let fake = Rc::new(FakeBackend::default());
+            let jobs = home.join("jobs");
+            fs::create_dir_all(&jobs).unwrap();
+            fs::write(jobs.join("a.toml"), TIMER).unwrap();
+            fs::write(jobs.join("b.toml"), SERVICE).unwrap();
+            apply_path(&home, &fake, &jobs, false).unwrap();
+            let service = fs::read_to_string(home.join(".config/systemd/user/sdtab-web.service")).unwrap();
+            assert!(service.contains("X-Sdtab-Origin=b.toml\n"));
+            fake.take_calls();
+
+            // a.toml on its own doesn't cover b.toml's service.
+            apply_path(&home, &fake, &jobs.join("a.toml"), true).unwrap();
+            assert!(fake.take_calls().is_empty());
+            assert!(home.join(".config/systemd/user/sdtab-web.service").exists());
+
+            // The directory does, so dropping b.toml from it prunes the service.
+            fs::remove_file(jobs.join("b.toml")).unwrap();
+            apply_path(&home, &fake, &jobs, true).unwrap();
+            assert_eq!(fake.take_calls(), vec!["disable --now sdtab-web.service", "daemon-reload"]);
+
+            fs::write(jobs.join("c.toml"), TIMER).unwrap();
+            let err = apply_path(&home, &fake, &jobs, false).unwrap_err().to_string();
+            assert_eq!(
+                err,
+                format!(
+                    "'backup' is defined in both {} and {}",
+                    jobs.join("a.toml").display(),
+                    jobs.join("c.toml").display()
+                )
+            );
+        }
+
+        #[test]
+        fn moved_checkouts_keep_their_origins() {
+            let home = home("moved");
+            let fake = Rc::new(FakeBackend::default());
+            let (old, new) = (home.join("old"), home.join("new"));
+            fs::create_dir_all(&old).unwrap();
+            fs::write(old.join("Sdtabfile.toml"), format!("{}{}", TIMER, SERVICE)).unwrap();
+            apply_path(&home, &fake, &old.join("Sdtabfile.toml"), false).unwrap();
+            fake.take_calls();
+
+            fs::rename(&old, &new).unwrap();
+            fs::write(new.join("Sdtabfile.toml"), TIMER).unwrap();
+            apply_path(&home, &fake, &new.join("Sdtabfile.toml"), true).unwrap();
+            assert_eq!(fake.take_calls(), vec!["disable --now sdtab-web.service", "daemon-reload"]);
+        }
+
+        #[test]
+        fn prune_covers_units_without_an_origin() {
+            let home = home("no-origin");
+            let fake = Rc::new(FakeBackend::default());
+            let jobs = home.join("jobs");
+            fs::create_dir_all(&jobs).unwrap();
+            fs::write(jobs.join("b.toml"), SERVICE).unwrap();
+            apply_path(&home, &fake, &jobs, false).unwrap();
+            // As an sdtab from before origins would have left it.
+            let unit = home.join(".config/systemd/user/sdtab-web.service");
+            let content = fs::read_to_string(&unit).unwrap();
+            fs::write(&unit, content.replace("X-Sdtab-Origin=b.toml\n", "")).unwrap();
+            fake.take_calls();
+
+            fs::write(jobs.join("a.toml"), TIMER).unwrap();
+            apply_path(&home, &fake, &jobs.join("a.toml"), true).unwrap();
+            assert!(fake.take_calls().contains(&"disable --now sdtab-web.service".to_string()));
+            assert!(!unit.exists());
+        }
+
+        #[test]
+        fn project_prune_leaves_hand_added_units_alone() {
+            let home = home("hand-added");
+            let fake = Rc::new(FakeBackend::default());
+            let (manual, billing) = (home.join("manual.toml"), home.join("billing.toml"));
+            fs::write(&manual, SERVICE).unwrap();
+            apply_path(&home, &fake, &manual, false).unwrap();
+            // As `sdtab add` leaves it: no origin and no project.
+            let unit = home.join(".config/systemd/user/sdtab-web.service");
+            let content = fs::read_to_string(&unit).unwrap();
+            fs::write(&unit, content.replace("X-Sdtab-Origin=manual.toml\n", "")).unwrap();
+            fake.take_calls();
+
+            fs::write(&billing, format!("project = \"billing\"\n{}", TIMER)).unwrap();
+            apply_path(&home, &fake, &billing, true).unwrap();
+            assert!(!fake.take_calls().iter().any(|call| call.contains("sdtab-web")));
+            assert!(unit.exists());
+
+            let jobs = home.join("jobs.toml");
+            fs::write(home.join("extra.toml"), TIMER).unwrap();
+            fs::write(&jobs, "include = [\"extra.toml\"]\n").unwrap();
+            apply_path(&home, &fake, &jobs, true).unwrap();
+            assert!(unit.exists(), "a file with includes leaves it alone too");
+        }
+
+        #[test]
+        fn prune_is_scoped_to_the_project() {
+            let home = home("project");
+            let fake = Rc::new(FakeBackend::default());
+            let (billing, search) = (home.join("billing.toml"), home.join("search.toml"));
+            fs::write(&billing, format!("project = \"billing\"\n{}", TIMER)).unwrap();
+            fs::write(&search, format!("project = \"search\"\n{}", SERVICE)).unwrap();
+            apply_path(&home, &fake, &billing, false).unwrap();
+            apply_path(&home, &fake, &search, false).unwrap();
+            let timer = fs::read_to_string(home.join(".config/systemd/user/sdtab-backup.service")).unwrap();
+            assert!(timer.contains("X-Sdtab-Project=billing\n"));
+            fake.take_calls();
+
+            apply_path(&home, &fake, &billing, true).unwrap();
+            assert!(fake.take_calls().is_empty());
+
+            // Another file of the same project covers the project's units wherever they came from.
+            let moved = home.join("billing-v2.toml");
+            fs::write(&moved, "project = \"billing\"\n").unwrap();
+            apply_path(&home, &fake, &moved, true).unwrap();
+            assert_eq!(fake.take_calls(), vec!["disable --now sdtab-backup.timer", "daemon-reload"]);
+            assert!(home.join(".config/systemd/user/sdtab-web.service").exists());
+        }
+
+        #[test]
+        fn saved_plan_applies_only_while_units_are_unchanged() {
+            let home = home("plan");
+            let fake = Rc::new(FakeBackend::default());
+            apply(&home, &fake, &format!("{}{}", TIMER, SERVICE), false);
+            fake.take_calls();
+
+            let file = home.join("Sdtabfile.toml");
+            fs::write(&file, format!("{}{}", TIMER.replace("@daily/3", "@daily/4"), SERVICE)).unwrap();
+            let plan_path = home.join("plan.json");
+            init::with_home(&home, || {
+                backend::with_backend(fake.clone(), || plan::run(file.to_str().unwrap(), plan_path.to_str(), false, None))
+            })
+            .unwrap();
+            let saved: plan::Plan = serde_json::from_str(&fs::read_to_string(&plan_path).unwrap()).unwrap();
+            assert_eq!(saved.actions.len(), 1);
+            assert_eq!((saved.actions[0].name.as_str(), saved.actions[0].action.as_str()), ("backup", "change"));
+            assert_eq!(saved.actions[0].restarts, vec!["sdtab-backup.timer"]);
+
+            // Someone edits a unit after the plan was made: refuse.
+            let web = home.join(".config/systemd/user/sdtab-web.service");
+            let original = fs::read_to_string(&web).unwrap();
+            fs::write(&web, format!("{}# edited\n", original)).unwrap();
+            let err = apply_path(&home, &fake, &plan_path, false).unwrap_err();
+            assert!(err.to_string().contains("changed since"), "{}", err);
+            assert!(fake.take_calls().is_empty());
+
+            fs::write(&web, original).unwrap();
+            apply_path(&home, &fake, &plan_path, false).unwrap();
+            assert_eq!(fake.take_calls(), vec!["daemon-reload", "restart sdtab-backup.timer"]);
+        }
+
+        #[test]
+        fn converges_enablement_without_touching_files() {
+            let home = home("enabled");
+            let fake = Rc::new(FakeBackend::default());
+            apply(&home, &fake, &format!("{}{}", TIMER, SERVICE), false);
+            fake.take_calls();
+
+            // A new disabled timer is installed but not started; a running
+            // service that should be disabled is stopped.
+            let report = TIMER.replace("backup", "report") + "enabled = false\n";
+            let disabled_web = format!("{}enabled = false\n", SERVICE);
+            apply(&home, &fake, &format!("{}{}{}", TIMER, report, disabled_web), false);
+            assert_eq!(fake.take_calls(), vec!["daemon-reload", "disable --now sdtab-web.service"]);
+            assert!(!fake.state("sdtab-web.service").active);
+            assert!(!fake.state("sdtab-report.timer").enabled);
+
+            apply(&home, &fake, &format!("{}{}{}", TIMER, report, disabled_web), false);
+            assert!(fake.take_calls().is_empty());
+
+            // `enabled` back to its default re-enables what `sdtab disable` or
+            // the file turned off.
+            init::with_home(&home, || {
+                backend::with_backend(fake.clone(), || systemctl::stop_and_disable("sdtab-backup.timer"))
+            })
+            .unwrap();
+            fake.take_calls();
+            let actions = init::with_home(&home, || {
+                backend::with_backend(fake.clone(), || {
+                    let path = home.join("Sdtabfile.toml");
+                    fs::write(&path, format!("{}{}{}", TIMER, report, SERVICE))?;
+                    let file = sdtabfile::load(path.to_str().unwrap(), &sdtabfile::Target::default())?;
+                    plan_actions(&file, false)
+                })
+            })
+            .unwrap();
+            let summary: Vec<(&str, &str)> = actions.iter().map(|a| (a.name.as_str(), a.action.as_str())).collect();
+            assert_eq!(summary, vec![("backup", "enable"), ("web", "enable")]);
+
+            apply(&home, &fake, &format!("{}{}{}", TIMER, report, SERVICE), false);
+            assert_eq!(fake.take_calls(), vec!["enable --now sdtab-backup.timer", "enable --now sdtab-web.service"]);
+        }
+
+        #[test]
+        fn failed_apply_rolls_back_every_unit() {
+            let home = home("rollback");
+            let fake = Rc::new(FakeBackend::default());
+            apply(&home, &fake, &format!("{}{}", TIMER, SERVICE), false);
+            fake.take_calls();
+            let unit_dir = home.join(".config/systemd/user");
+            let web_before = fs::read_to_string(unit_dir.join("sdtab-web.service")).unwrap();
+
+            // A new timer goes in, then restarting the changed service fails.
+            let report = TIMER.replace("backup", "report");
+            let file = home.join("Sdtabfile.toml");
+            fs::write(&file, format!("{}{}{}", TIMER, report, SERVICE.replace("app.js", "server.js"))).unwrap();
+            fake.fail_next("sdtab-web.service");
+            let err = apply_path(&home, &fake, &file, false).unwrap_err().to_string();
+            assert!(err.contains("Applying 'web' failed") && err.contains("Rolled back 2 unit(s)"), "{}", err);
+
+            assert!(!unit_dir.join("sdtab-report.timer").exists());
+            assert_eq!(fs::read_to_string(unit_dir.join("sdtab-web.service")).unwrap(), web_before);
+            assert!(!fake.state("sdtab-report.timer").active);
+            assert!(fake.state("sdtab-web.service").active);
+            let calls = fake.take_calls();
+            assert!(calls.contains(&"disable --now sdtab-report.timer".to_string()), "{:?}", calls);
+            assert_eq!(calls.last().unwrap(), "restart sdtab-web.service");
+
+            // Without rollback the half-applied state stays.
+            fake.fail_next("sdtab-web.service");
+            let err = init::with_home(&home, || {
+                backend::with_backend(fake.clone(), || run(file.to_str().unwrap(), false, false, false, true, None))
+            })
+            .unwrap_err();
+            assert!(err.to_string().contains("Applying 'web' failed"), "{}", err);
+            assert!(unit_dir.join("sdtab-report.timer").exists());
+
+            // `sdtab rollback` undoes the last apply.
+            fake.take_calls();
+            init::with_home(&home, || backend::with_backend(fake.clone(), || snapshot::run(None, false))).unwrap();
+            assert!(!unit_dir.join("sdtab-report.timer").exists());
+            assert_eq!(fs::read_to_string(unit_dir.join("sdtab-web.service")).unwrap(), web_before);
+        }
     }
 }
@@ -0,0 +1,114 @@
+//! How sdtab talks to the systemd user manager.
+//!
+//! Modules call the free functions in `systemctl`, which forward to the
+//! backend picked here: the `systemctl` CLI (default), D-Bus via `busctl`
+//! (`dbus`) or the user's crontab (`crontab`), chosen by `SDTAB_BACKEND` or
+//! `backend` in config.toml. Tests swap in `fake::FakeBackend` with
+//! [`with_backend`] to run whole commands without a user manager.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{bail, Result};
+
+use crate::crontab::Crontab;
+use crate::dbus::Dbus;
+use crate::{config, init};
+use crate::systemctl::{Systemctl, UnitProperties};
+
+pub trait Backend {
+    fn daemon_reload(&self) -> Result<()>;
+    fn enable_and_start(&self, unit: &str) -> Result<()>;
+    fn stop_and_disable(&self, unit: &str) -> Result<()>;
+    /// Enable without starting, and the reverse, for restoring a unit's
+    /// enablement apart from whether it runs.
+    fn enable(&self, unit: &str) -> Result<()>;
+    fn disable(&self, unit: &str) -> Result<()>;
+    fn start(&self, unit: &str) -> Result<()>;
+    fn stop(&self, unit: &str) -> Result<()>;
+    fn restart(&self, unit: &str) -> Result<()>;
+    fn reload(&self, unit: &str) -> Result<()>;
+    /// `properties` of every unit, formatted like `systemctl show`.
+    fn show(&self, units: &[String], properties: &[&str]) -> Result<UnitProperties>;
+    /// Last `lines` journal lines of a unit, without metadata prefixes.
+    fn journal_tail(&self, unit: &str, lines: u32) -> Vec<String>;
+    /// Next `count` elapse times of an `OnCalendar=` expression.
+    fn next_runs(&self, on_calendar: &str, count: u32) -> Result<Vec<String>>;
+}
+
+thread_local! {
+    static OVERRIDE: RefCell<Option<Rc<dyn Backend>>> = const { RefCell::new(None) };
+}
+
+/// The backend for this thread: the test override, else `SDTAB_BACKEND`,
+/// else config.toml.
+pub fn current() -> Result<Rc<dyn Backend>> {
+    if let Some(backend) = OVERRIDE.with(|o| o.borrow().clone()) {
+        return Ok(backend);
+    }
+    if let Some(root) = init::root() {
+        bail!("--root {} edits an image offline and cannot reach systemd; use `sdtab render`", root);
+    }
+    match kind()?.as_str() {
+        "cli" => Ok(Rc::new(Systemctl)),
+        "dbus" => Ok(Rc::new(Dbus)),
+        "crontab" => Ok(Rc::new(Crontab)),
+        other => unreachable!("kind() returned {}", other),
+    }
+}
+
+/// The configured backend name: `SDTAB_BACKEND`, else `backend` in
+/// config.toml, else `cli`.
+pub fn kind() -> Result<String> {
+    select(std::env::var("SDTAB_BACKEND").ok())
+}
+
+fn select(env: Option<String>) -> Result<String> {
+    let (kind, source) = match env {
+        Some(kind) if !kind.is_empty() => (kind, "SDTAB_BACKEND"),
+        _ => (config::load()?.backend.unwrap_or_default(), "backend in config.toml"),
+    };
+    match kind.as_str() {
+        "" => Ok("cli".to_string()),
+        "cli" | "dbus" | "crontab" => Ok(kind),
+        other => bail!("Unknown {} '{}' (expected cli, dbus or crontab)", source, other),
+    }
+}
+
+/// Run `f` with `backend` in place of the real one.
+#[cfg(test)]
+pub fn with_backend<T>(backend: Rc<dyn Backend>, f: impl FnOnce() -> T) -> T {
+    let previous = OVERRIDE.with(|o| o.replace(Some(backend)));
+    let result = f();
+    OVERRIDE.with(|o| *o.borrow_mut() = previous);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn env_overrides_config_and_unknown_names_fail() {
+        let home = TestDir::new("backend");
+        init::with_home(&home, || {
+            assert_eq!(select(None).unwrap(), "cli");
+
+            let path = config::config_path().unwrap();
+            fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+            fs::write(&path, "backend = \"crontab\"\n").unwrap();
+            assert_eq!(select(None).unwrap(), "crontab");
+            assert_eq!(select(Some(String::new())).unwrap(), "crontab");
+            assert_eq!(select(Some("dbus".to_string())).unwrap(), "dbus");
+
+            let err = select(Some("launchd".to_string())).unwrap_err().to_string();
+            assert!(err.contains("SDTAB_BACKEND 'launchd'"), "{}", err);
+            fs::write(&path, "backend = \"cron\"\n").unwrap();
+            assert!(select(None).unwrap_err().to_string().contains("backend in config.toml 'cron'"));
+        });
+    }
+}
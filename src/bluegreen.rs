@@ -0,0 +1,333 @@
+//! Blue/green slots for services declared with two ports.
+//!
+//! Such a service is generated as a template `sdtab-<name>@.service` and runs
+//! as either `@blue` or `@green`. Each slot has an env file in
+//! `~/.config/sdtab/slots/<name>/` (`PORT=…`, `SDTAB_SLOT=…`), and the
+//! `active.env` symlink points at the live one, so a reverse proxy or script
+//! can follow it. `restart --strategy blue-green` starts the idle slot, waits
+//! for its health check, flips the symlink and only then stops the old slot.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{add, health, init, parse_unit, systemctl, unit};
+
+pub const SLOTS: [&str; 2] = ["blue", "green"];
+
+/// How long the idle slot has to pass its health check before we roll back.
+const HEALTHY_TIMEOUT: Duration = Duration::from_secs(60);
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const ACTIVE_LINK: &str = "active.env";
+
+/// True when `name` is defined as a blue/green template.
+pub fn is_blue_green(name: &str) -> bool {
+    init::unit_dir()
+        .map(|dir| Path::new(&dir).join(unit::template_filename(name)).exists())
+        .unwrap_or(false)
+}
+
+/// Filename holding the unit definition: the template for blue/green
+/// services, the plain `.service` otherwise.
+pub fn definition_filename(name: &str) -> String {
+    if is_blue_green(name) {
+        unit::template_filename(name)
+    } else {
+        unit::service_filename(name)
+    }
+}
+
+/// The unit systemd actually runs: the live instance for blue/green
+/// services, the plain `.service` otherwise.
+pub fn service_unit(name: &str) -> String {
+    if is_blue_green(name) {
+        unit::instance_filename(name, &active_slot(name))
+    } else {
+        unit::service_filename(name)
+    }
+}
+
+/// Unit pattern for `journalctl --user-unit`: both slots for blue/green
+/// services, so logs survive a switch.
+pub fn journal_unit(name: &str) -> String {
+    if is_blue_green(name) {
+        format!("{}{}@*.service", init::prefix(), name)
+    } else {
+        unit::service_filename(name)
+    }
+}
+
+pub fn slots_dir(name: &str) -> Result<PathBuf> {
+    Ok(Path::new(&init::host_path(&init::config_dir()?)).join("slots").join(name))
+}
+
+/// The live slot, read from the `active.env` symlink. Defaults to blue.
+pub fn active_slot(name: &str) -> String {
+    slots_dir(name)
+        .ok()
+        .and_then(|dir| active_slot_in(&dir))
+        .unwrap_or_else(|| SLOTS[0].to_string())
+}
+
+fn active_slot_in(dir: &Path) -> Option<String> {
+    let target = fs::read_link(dir.join(ACTIVE_LINK)).ok()?;
+    let stem = target.file_stem()?.to_string_lossy().to_string();
+    SLOTS.contains(&stem.as_str()).then_some(stem)
+}
+
+fn other_slot(slot: &str) -> &'static str {
+    if slot == SLOTS[0] {
+        SLOTS[1]
+    } else {
+        SLOTS[0]
+    }
+}
+
+/// Port assigned to `slot`.
+pub fn slot_port(ports: &[u16], slot: &str) -> Option<u16> {
+    let idx = SLOTS.iter().position(|s| *s == slot)?;
+    ports.get(idx).copied()
+}
+
+/// Environment handed to health probes of a slot.
+pub fn slot_env(ports: &[u16], slot: &str) -> Vec<(String, String)> {
+    let mut env = vec![("SDTAB_SLOT".to_string(), slot.to_string())];
+    if let Some(port) = slot_port(ports, slot) {
+        env.push(("PORT".to_string(), port.to_string()));
+    }
+    env
+}
+
+/// Validate `--ports`: exactly two distinct ports.
+pub fn validate_ports(ports: &[u16]) -> Result<()> {
+    if ports.len() != 2 {
+        bail!("--ports takes exactly two ports (blue,green), got {}", ports.len());
+    }
+    if ports[0] == ports[1] {
+        bail!("Blue and green ports must differ (both are {})", ports[0]);
+    }
+    Ok(())
+}
+
+/// Write the per-slot env files. The `active.env` link is created on first
+/// write (pointing at blue) and left alone afterwards.
+pub fn write_slots(name: &str, ports: &[u16]) -> Result<()> {
+    write_slots_in(&slots_dir(name)?, ports)
+}
+
+fn write_slots_in(dir: &Path, ports: &[u16]) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    for slot in SLOTS {
+        let port = slot_port(ports, slot).context("blue/green services need two ports")?;
+        let path = dir.join(format!("{}.env", slot));
+        fs::write(&path, format!("PORT={}\nSDTAB_SLOT={}\n", port, slot))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    if fs::symlink_metadata(dir.join(ACTIVE_LINK)).is_err() {
+        flip_in(dir, SLOTS[0])?;
+    }
+    Ok(())
+}
+
+/// A service's slot env files, by slot, and the slot `active.env` points at.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct SlotFiles {
+    pub env: BTreeMap<String, String>,
+    pub active: Option<String>,
+}
+
+/// The slot files of `name`, for putting back with [`restore_slots`];
+/// `None` when it has none.
+pub fn read_slots(name: &str) -> Result<Option<SlotFiles>> {
+    read_slots_in(&slots_dir(name)?)
+}
+
+fn read_slots_in(dir: &Path) -> Result<Option<SlotFiles>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let mut env = BTreeMap::new();
+    for slot in SLOTS {
+        let path = dir.join(format!("{}.env", slot));
+        if path.is_file() {
+            let content = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            env.insert(slot.to_string(), content);
+        }
+    }
+    Ok(Some(SlotFiles { env, active: active_slot_in(dir) }))
+}
+
+/// Put back what [`read_slots`] returned: `None` removes the slot files.
+pub fn restore_slots(name: &str, files: Option<&SlotFiles>) -> Result<()> {
+    restore_slots_in(&slots_dir(name)?, files)
+}
+
+fn restore_slots_in(dir: &Path, files: Option<&SlotFiles>) -> Result<()> {
+    let Some(files) = files else {
+        if dir.exists() {
+            fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        return Ok(());
+    };
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    for (slot, content) in &files.env {
+        let path = dir.join(format!("{}.env", slot));
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    match files.active {
+        Some(ref slot) => flip_in(dir, slot),
+        None => match fs::remove_file(dir.join(ACTIVE_LINK)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", ACTIVE_LINK))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+pub fn remove_slots(name: &str) -> Result<()> {
+    let dir = slots_dir(name)?;
+    if dir.exists() {
+        fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Point `active.env` at `slot` atomically (symlink + rename).
+fn flip_in(dir: &Path, slot: &str) -> Result<()> {
+    let tmp = dir.join(format!("{}.tmp", ACTIVE_LINK));
+    let _ = fs::remove_file(&tmp);
+    symlink(format!("{}.env", slot), &tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    fs::rename(&tmp, dir.join(ACTIVE_LINK))
+        .with_context(|| format!("Failed to switch {} to {}", ACTIVE_LINK, slot))
+}
+
+/// `restart --strategy blue-green` for one service.
+pub fn restart(svc: &parse_unit::ParsedUnit) -> Result<()> {
+    if svc.ports.is_empty() {
+        bail!(
+            "'{}' has no port slots; add it with --ports to use blue-green restarts.",
+            svc.name
+        );
+    }
+    switch_slots(&svc.name, &svc.ports, svc.health_cmd.as_deref(), svc.service_type.as_deref())
+}
+
+/// Start the idle slot, wait until it is ready and healthy, then make it
+/// live and stop the old slot. The old slot keeps serving if anything fails.
+pub fn switch_slots(
+    name: &str,
+    ports: &[u16],
+    health_cmd: Option<&str>,
+    service_type: Option<&str>,
+) -> Result<()> {
+    let Some(health_cmd) = health_cmd else {
+        bail!("'{}' has no --health-cmd; blue-green restarts need one to judge the new slot.", name);
+    };
+
+    let dir = slots_dir(name)?;
+    let live = active_slot(name);
+    let idle = other_slot(&live);
+    let live_unit = unit::instance_filename(name, &live);
+    let idle_unit = unit::instance_filename(name, idle);
+
+    println!("  starting {} (port {})", idle_unit, slot_port(ports, idle).unwrap_or(0));
+    if let Err(e) = systemctl::start(&idle_unit)
+        .and_then(|_| add::wait_for_ready_unit(&idle_unit, service_type))
+        .and_then(|_| wait_healthy(health_cmd, &slot_env(ports, idle)))
+    {
+        eprintln!("  {} did not become healthy — rolling back", idle_unit);
+        let _ = systemctl::stop_and_disable(&idle_unit);
+        bail!("blue-green restart of '{}' failed, {} still serving: {}", name, live, e);
+    }
+
+    flip_in(&dir, idle)?;
+    systemctl::enable_and_start(&idle_unit)?;
+    systemctl::stop_and_disable(&live_unit)?;
+    println!("  switched {} → {}", live, idle);
+    Ok(())
+}
+
+/// Probe until the health command succeeds or `HEALTHY_TIMEOUT` passes.
+fn wait_healthy(cmd: &str, env: &[(String, String)]) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        let (ok, _, error) = health::probe(cmd, env, health::PROBE_TIMEOUT);
+        if ok {
+            return Ok(());
+        }
+        if start.elapsed() >= HEALTHY_TIMEOUT {
+            bail!(
+                "health check still failing after {}s: {}",
+                HEALTHY_TIMEOUT.as_secs(),
+                error.unwrap_or_default()
+            );
+        }
+        thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+
+    #[test]
+    fn write_slots_defaults_to_blue_and_keeps_active() {
+        let home = TestDir::new("bg");
+        let dir = home.join("write");
+        write_slots_in(&dir, &[3000, 3001]).unwrap();
+        assert_eq!(active_slot_in(&dir), Some("blue".to_string()));
+        let green = fs::read_to_string(dir.join("green.env")).unwrap();
+        assert!(green.contains("PORT=3001"));
+
+        flip_in(&dir, "green").unwrap();
+        write_slots_in(&dir, &[3000, 3001]).unwrap();
+        assert_eq!(active_slot_in(&dir), Some("green".to_string()));
+        let active = fs::read_to_string(dir.join(ACTIVE_LINK)).unwrap();
+        assert!(active.contains("SDTAB_SLOT=green"));
+    }
+
+    #[test]
+    fn restored_slots_match_what_was_read() {
+        let home = TestDir::new("bg");
+        let dir = home.join("web");
+        assert_eq!(read_slots_in(&dir).unwrap(), None);
+
+        write_slots_in(&dir, &[3000, 3001]).unwrap();
+        let before = read_slots_in(&dir).unwrap().unwrap();
+        assert_eq!(before.active.as_deref(), Some("blue"));
+
+        write_slots_in(&dir, &[4000, 4001]).unwrap();
+        flip_in(&dir, "green").unwrap();
+        restore_slots_in(&dir, Some(&before)).unwrap();
+        assert_eq!(read_slots_in(&dir).unwrap(), Some(before));
+
+        restore_slots_in(&dir, None).unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn slot_ports_and_env() {
+        let ports = [8080, 8081];
+        assert_eq!(slot_port(&ports, "blue"), Some(8080));
+        assert_eq!(slot_port(&ports, "green"), Some(8081));
+        assert_eq!(other_slot("green"), "blue");
+        assert!(slot_env(&ports, "green").contains(&("PORT".to_string(), "8081".to_string())));
+    }
+
+    #[test]
+    fn validate_ports_rejects_bad_input() {
+        assert!(validate_ports(&[3000, 3001]).is_ok());
+        assert!(validate_ports(&[3000]).is_err());
+        assert!(validate_ports(&[3000, 3000]).is_err());
+    }
+}
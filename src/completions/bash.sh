@@ -5,21 +5,31 @@ _sdtab() {
     words=("${COMP_WORDS[@]}")
     cword=$COMP_CWORD
 
-    local subcommands="init add list remove edit logs restart run status enable disable export apply doctor completions"
+    local subcommands="init add list remove edit logs restart deploy reload run status enable disable export validate apply plan rollback drift render adopt import doctor completions"
 
     if [[ $cword -eq 1 ]]; then
+        if [[ "$cur" == -* ]]; then
+            COMPREPLY=($(compgen -W "--system --unit-dir --prefix --root" -- "$cur"))
+            return
+        fi
         COMPREPLY=($(compgen -W "$subcommands" -- "$cur"))
         return
     fi
 
     local cmd="${words[1]}"
     case "$cmd" in
-        logs|status|edit|remove|enable|disable|restart|run)
+        logs|status|edit|remove|enable|disable|restart|deploy|reload|run)
             if [[ "$cur" == -* ]]; then
                 case "$cmd" in
                     logs)
                         COMPREPLY=($(compgen -W "-f --follow -n --lines -p --priority --all --failed --since" -- "$cur"))
                         ;;
+                    restart)
+                        COMPREPLY=($(compgen -W "--all --strategy" -- "$cur"))
+                        ;;
+                    deploy)
+                        COMPREPLY=($(compgen -W "--grace" -- "$cur"))
+                        ;;
                 esac
                 return
             fi
@@ -30,15 +40,59 @@ _sdtab() {
         apply|export)
             if [[ "$cur" == -* ]]; then
                 case "$cmd" in
-                    apply) COMPREPLY=($(compgen -W "--prune --dry-run" -- "$cur")) ;;
-                    export) COMPREPLY=($(compgen -W "-o --output" -- "$cur")) ;;
+                    apply) COMPREPLY=($(compgen -W "--prune --dry-run --diff --no-rollback --profile" -- "$cur")) ;;
+                    export) COMPREPLY=($(compgen -W "-o --output --format --factor-defaults --project" -- "$cur")) ;;
                 esac
                 return
             fi
+            if [[ "$prev" == "--format" ]]; then
+                COMPREPLY=($(compgen -W "toml crontab units json" -- "$cur"))
+                return
+            fi
+            COMPREPLY=($(compgen -f -- "$cur"))
+            ;;
+        rollback)
+            COMPREPLY=($(compgen -W "--list" -- "$cur"))
+            ;;
+        validate)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "--profile --schema" -- "$cur"))
+                return
+            fi
+            COMPREPLY=($(compgen -f -- "$cur"))
+            ;;
+        plan)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "-o --output --prune --profile" -- "$cur"))
+                return
+            fi
+            COMPREPLY=($(compgen -f -- "$cur"))
+            ;;
+        render)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "--profile" -- "$cur"))
+                return
+            fi
             COMPREPLY=($(compgen -f -- "$cur"))
             ;;
+        adopt)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "--all --wrap --dry-run" -- "$cur"))
+                return
+            fi
+            COMPREPLY=($(compgen -W "$(ls ~/.config/systemd/user 2>/dev/null | grep -E '\.(service|timer)$' | grep -v '^sdtab-')" -- "$cur"))
+            ;;
+        import)
+            if [[ $cword -eq 2 ]]; then
+                COMPREPLY=($(compgen -W "crontab" -- "$cur"))
+            elif [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "-o --output --dry-run" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -f -- "$cur"))
+            fi
+            ;;
         list)
-            COMPREPLY=($(compgen -W "--json --sort" -- "$cur"))
+            COMPREPLY=($(compgen -W "--json --sort --project" -- "$cur"))
             ;;
         init)
             COMPREPLY=($(compgen -W "--slack-webhook --slack-mention" -- "$cur"))
@@ -47,7 +101,7 @@ _sdtab() {
             COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
             ;;
         add)
-            COMPREPLY=($(compgen -W "--name --workdir --description --env-file --restart --memory-max --cpu-quota --io-weight --timeout-stop --exec-start-pre --exec-stop-post --log-level-max --random-delay --env --no-notify --dry-run" -- "$cur"))
+            COMPREPLY=($(compgen -W "--name --workdir --description --env-file --restart --memory-max --cpu-quota --io-weight --timeout-stop --exec-start-pre --exec-stop-post --log-level-max --random-delay --env --no-notify --type --pid-file --watchdog --exec-reload --health-cmd --health-interval --health-retries --ports --user --group --deploy-step --rollback-step --output-file --output-max-size --output-keep --mail-to --dry-run" -- "$cur"))
             ;;
     esac
 }
@@ -28,17 +28,32 @@ complete -c sdtab -n __sdtab_needs_command -a remove -d 'Remove a unit'
 complete -c sdtab -n __sdtab_needs_command -a edit -d 'Edit unit files'
 complete -c sdtab -n __sdtab_needs_command -a logs -d 'Show logs'
 complete -c sdtab -n __sdtab_needs_command -a restart -d 'Restart a unit'
+complete -c sdtab -n __sdtab_needs_command -a deploy -d 'Run deploy steps and restart a service'
+complete -c sdtab -n __sdtab_needs_command -a reload -d 'Reload a service via ExecReload'
 complete -c sdtab -n __sdtab_needs_command -a run -d 'Trigger a unit once manually'
 complete -c sdtab -n __sdtab_needs_command -a status -d 'Show detailed status'
 complete -c sdtab -n __sdtab_needs_command -a enable -d 'Enable a unit'
 complete -c sdtab -n __sdtab_needs_command -a disable -d 'Disable a unit'
 complete -c sdtab -n __sdtab_needs_command -a export -d 'Export configuration'
 complete -c sdtab -n __sdtab_needs_command -a apply -d 'Apply configuration'
+complete -c sdtab -n __sdtab_needs_command -a rollback -d 'Undo the last apply'
+complete -c sdtab -n __sdtab_needs_command -a validate -d 'Check an Sdtabfile'
+complete -c sdtab -n __sdtab_needs_command -a drift -d 'Report drift from the last apply'
+complete -c sdtab -n __sdtab_needs_command -a plan -d 'Save an apply plan as JSON'
+complete -c sdtab -n __sdtab_needs_command -a render -d 'Write units without contacting systemd'
+complete -c sdtab -n __sdtab_needs_command -a adopt -d 'Adopt hand-written units'
+complete -c sdtab -n __sdtab_needs_command -a import -d 'Import jobs from crontab'
 complete -c sdtab -n __sdtab_needs_command -a doctor -d 'Run health checks'
 complete -c sdtab -n __sdtab_needs_command -a completions -d 'Generate completion script'
 
+# Global options
+complete -c sdtab -l system -d 'Manage system units'
+complete -c sdtab -l unit-dir -d 'Unit directory' -r
+complete -c sdtab -l prefix -d 'Unit name prefix' -x
+complete -c sdtab -l root -d 'Write into an image root' -r
+
 # Dynamic unit name completion for name-taking subcommands
-for cmd in logs status edit remove enable disable restart run
+for cmd in logs status edit remove enable disable restart deploy reload run
     complete -c sdtab -n "__sdtab_using_command $cmd" -f -a '(__sdtab_unit_names)'
 end
 
@@ -50,16 +65,71 @@ complete -c sdtab -n '__sdtab_using_command logs' -l all -d 'Aggregate across al
 complete -c sdtab -n '__sdtab_using_command logs' -l failed -d 'Only failed units (implies --all)'
 complete -c sdtab -n '__sdtab_using_command logs' -l since -d 'Show entries newer than given time' -x
 
+# restart flags
+complete -c sdtab -n '__sdtab_using_command restart' -l all -d 'Restart all services'
+complete -c sdtab -n '__sdtab_using_command restart' -l strategy -d 'Restart strategy' -xa 'hard blue-green'
+
+# deploy flags
+complete -c sdtab -n '__sdtab_using_command deploy' -l grace -d 'Seconds to watch after restart' -x
+
 # completions
 complete -c sdtab -n '__sdtab_using_command completions' -f -a 'bash zsh fish'
 
 # apply
 complete -c sdtab -n '__sdtab_using_command apply' -l prune -d 'Remove units not in file'
 complete -c sdtab -n '__sdtab_using_command apply' -l dry-run -d 'Show changes without applying'
+complete -c sdtab -n '__sdtab_using_command apply' -l diff -d 'Show unit file diffs and restarts'
+complete -c sdtab -n '__sdtab_using_command apply' -l no-rollback -d 'Leave units as they are on failure'
+complete -c sdtab -n '__sdtab_using_command apply' -l profile -d 'Overlay a profile' -x
+complete -c sdtab -n '__sdtab_using_command render' -l profile -d 'Overlay a profile' -x
+
+# rollback
+complete -c sdtab -n '__sdtab_using_command rollback' -l list -d 'List snapshots'
+
+# validate
+complete -c sdtab -n '__sdtab_using_command validate' -l profile -d 'Overlay a profile' -x
+complete -c sdtab -n '__sdtab_using_command validate' -l schema -d 'Print the JSON Schema'
+
+# plan
+complete -c sdtab -n '__sdtab_using_command plan' -s o -l output -d 'Output file path' -r
+complete -c sdtab -n '__sdtab_using_command plan' -l prune -d 'Remove units not in file'
+complete -c sdtab -n '__sdtab_using_command plan' -l profile -d 'Overlay a profile' -x
+
+# adopt
+complete -c sdtab -n '__sdtab_using_command adopt' -l all -d 'Adopt every hand-written unit'
+complete -c sdtab -n '__sdtab_using_command adopt' -l wrap -d 'Keep the original unit text'
+complete -c sdtab -n '__sdtab_using_command adopt' -l dry-run -d 'Show what would be adopted'
+
+# import
+complete -c sdtab -n '__sdtab_using_command import' -a crontab -d 'Convert crontab entries'
+complete -c sdtab -n '__sdtab_using_command import' -s o -l output -d 'Write an Sdtabfile instead' -r
+complete -c sdtab -n '__sdtab_using_command import' -l dry-run -d 'Show what would be installed'
 
 # export
 complete -c sdtab -n '__sdtab_using_command export' -s o -l output -d 'Output file' -r
+complete -c sdtab -n '__sdtab_using_command export' -l format -d 'Output format' -xa 'toml crontab units json'
+complete -c sdtab -n '__sdtab_using_command export' -l factor-defaults -d 'Move shared values into [defaults]'
+complete -c sdtab -n '__sdtab_using_command export' -l project -d 'Only units of this project' -x
 
 # list
 complete -c sdtab -n '__sdtab_using_command list' -l json -d 'Output as JSON'
 complete -c sdtab -n '__sdtab_using_command list' -l sort -d 'Sort order' -xa 'time name'
+complete -c sdtab -n '__sdtab_using_command list' -l project -d 'Only units of this project' -x
+
+# add
+complete -c sdtab -n '__sdtab_using_command add' -l type -d 'Service type' -xa 'simple exec notify forking'
+complete -c sdtab -n '__sdtab_using_command add' -l pid-file -d 'PID file (forking)' -r
+complete -c sdtab -n '__sdtab_using_command add' -l watchdog -d 'Watchdog interval (notify)' -x
+complete -c sdtab -n '__sdtab_using_command add' -l exec-reload -d 'Reload command' -x
+complete -c sdtab -n '__sdtab_using_command add' -l health-cmd -d 'Health probe command' -x
+complete -c sdtab -n '__sdtab_using_command add' -l health-interval -d 'Interval between probes' -x
+complete -c sdtab -n '__sdtab_using_command add' -l health-retries -d 'Failures before restart' -x
+complete -c sdtab -n '__sdtab_using_command add' -l ports -d 'Blue/green port slots' -x
+complete -c sdtab -n '__sdtab_using_command add' -l user -d 'Run as user (--system)' -xa '(__fish_complete_users)'
+complete -c sdtab -n '__sdtab_using_command add' -l group -d 'Run as group (--system)' -xa '(__fish_complete_groups)'
+complete -c sdtab -n '__sdtab_using_command add' -l deploy-step -d 'Deploy build step' -x
+complete -c sdtab -n '__sdtab_using_command add' -l rollback-step -d 'Rollback step' -x
+complete -c sdtab -n '__sdtab_using_command add' -l output-file -d 'Append output to file' -r
+complete -c sdtab -n '__sdtab_using_command add' -l output-max-size -d 'Rotate output file past size' -x
+complete -c sdtab -n '__sdtab_using_command add' -l output-keep -d 'Rotated files to keep' -x
+complete -c sdtab -n '__sdtab_using_command add' -l mail-to -d 'Mail non-empty output' -x
@@ -10,17 +10,30 @@ _sdtab() {
         'edit:Edit unit files with $EDITOR'
         'logs:Show logs for a timer or service'
         'restart:Restart a timer or service'
+        'deploy:Run deploy steps and restart a service'
+        'reload:Reload a service via ExecReload'
         'run:Trigger a unit once manually (ignores timer schedule)'
         'status:Show detailed status of a timer or service'
         'enable:Enable (start) a timer or service'
         'disable:Disable (stop) a timer or service without removing'
-        'export:Export current configuration to TOML'
+        'export:Export current configuration'
         'apply:Apply configuration from a TOML file'
+        'rollback:Undo the last apply'
+        'validate:Check an Sdtabfile for unknown keys and bad values'
+        'drift:Report units that differ from the last apply'
+        'plan:Save what apply would do as a JSON plan'
+        'render:Write units from a TOML file without contacting systemd'
+        'adopt:Adopt hand-written systemd user units'
+        'import:Import jobs from crontab'
         'doctor:Run health checks'
         'completions:Generate shell completion script'
     )
 
     if (( CURRENT == 2 )); then
+        if [[ "${words[CURRENT]}" == -* ]]; then
+            _values 'option' '--system[Manage system units]' '--unit-dir[Unit directory]' '--prefix[Unit name prefix]' '--root[Write into an image root]'
+            return
+        fi
         _describe -t commands 'sdtab commands' subcommands
         return
     fi
@@ -28,7 +41,7 @@ _sdtab() {
     local cmd="${words[2]}"
 
     case "$cmd" in
-        logs|status|edit|remove|enable|disable|restart|run)
+        logs|status|edit|remove|enable|disable|restart|deploy|reload|run)
             if [[ "$cmd" == "logs" ]]; then
                 _arguments \
                     '(-f --follow)'{-f,--follow}'[Follow log output]' \
@@ -38,6 +51,15 @@ _sdtab() {
                     '--failed[Only failed units (implies --all)]' \
                     '--since[Show entries newer than given time]:time:' \
                     '*:unit:->units'
+            elif [[ "$cmd" == "deploy" ]]; then
+                _arguments \
+                    '--grace[Seconds to watch after restart]:seconds:' \
+                    '*:unit:->units'
+            elif [[ "$cmd" == "restart" ]]; then
+                _arguments \
+                    '--all[Restart all services]' \
+                    '--strategy[Restart strategy]:strategy:(hard blue-green)' \
+                    '*:unit:->units'
             else
                 _arguments '*:unit:->units'
             fi
@@ -53,16 +75,61 @@ _sdtab() {
             _arguments \
                 '--prune[Remove units not in the file]' \
                 '--dry-run[Show changes without applying]' \
+                '--diff[Show unit file diffs and restarts]' \
+                '--no-rollback[Leave units as they are on failure]' \
+                '--profile[Overlay a profile]:profile:' \
+                '*:file:_files -g "*.(toml|json)"'
+            ;;
+        rollback)
+            _arguments '--list[List snapshots]'
+            ;;
+        validate)
+            _arguments \
+                '--profile[Overlay a profile]:profile:' \
+                '--schema[Print the JSON Schema]' \
+                '*:file:_files -g "*.toml"'
+            ;;
+        plan)
+            _arguments \
+                '(-o --output)'{-o,--output}'[Output file path]:file:_files' \
+                '--prune[Remove units not in the file]' \
+                '--profile[Overlay a profile]:profile:' \
+                '*:file:_files -g "*.toml"'
+            ;;
+        render)
+            _arguments \
+                '--profile[Overlay a profile]:profile:' \
                 '*:file:_files -g "*.toml"'
             ;;
         export)
             _arguments \
-                '(-o --output)'{-o,--output}'[Output file path]:file:_files'
+                '(-o --output)'{-o,--output}'[Output file path]:file:_files' \
+                '--format[Output format]:format:(toml crontab units json)' \
+                '--factor-defaults[Move shared values into \[defaults\]]' \
+                '--project[Only units of this project]:project:'
+            ;;
+        adopt)
+            _arguments \
+                '--all[Adopt every hand-written unit]' \
+                '--wrap[Keep the original unit text]' \
+                '--dry-run[Show what would be adopted]' \
+                '*:unit:_files -W ~/.config/systemd/user -g "*.(service|timer)"'
+            ;;
+        import)
+            if (( CURRENT == 3 )); then
+                _values 'source' 'crontab[Convert crontab entries]'
+            else
+                _arguments \
+                    '(-o --output)'{-o,--output}'[Write an Sdtabfile instead of installing]:file:_files' \
+                    '--dry-run[Show what would be installed]' \
+                    '*:file:_files'
+            fi
             ;;
         list)
             _arguments \
                 '--json[Output as JSON]' \
-                '--sort[Sort order]:order:(time name)'
+                '--sort[Sort order]:order:(time name)' \
+                '--project[Only units of this project]:project:'
             ;;
         init)
             _arguments \
@@ -89,6 +156,22 @@ _sdtab() {
                 '--random-delay[Random delay]:duration:' \
                 '*--env[Environment variable]:KEY=VALUE:' \
                 '--no-notify[Disable failure notification]' \
+                '--type[Service type]:type:(simple exec notify forking)' \
+                '--pid-file[PID file (forking)]:file:_files' \
+                '--watchdog[Watchdog interval (notify)]:duration:' \
+                '--exec-reload[Reload command]:cmd:' \
+                '--health-cmd[Health probe command]:cmd:' \
+                '--health-interval[Interval between probes]:duration:' \
+                '--health-retries[Failures before restart]:count:' \
+                '--ports[Blue/green port slots]:ports:' \
+                '--user[Run as user (--system)]:user:_users' \
+                '--group[Run as group (--system)]:group:_groups' \
+                '*--deploy-step[Deploy build step]:cmd:' \
+                '*--rollback-step[Rollback step]:cmd:' \
+                '--output-file[Append output to file]:file:_files' \
+                '--output-max-size[Rotate output file past size]:size:' \
+                '--output-keep[Rotated files to keep]:count:' \
+                '--mail-to[Mail non-empty output]:address:' \
                 '--dry-run[Preview without creating]'
             ;;
     esac
@@ -7,8 +7,17 @@ use crate::init;
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct Config {
+    /// How sdtab talks to the scheduler: `cli` (default), `dbus` or `crontab`.
+    /// `SDTAB_BACKEND` overrides it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Sdtabfile profile `apply` and `render` use when no --profile is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
     #[serde(default)]
     pub notify: NotifyConfig,
+    #[serde(default, skip_serializing_if = "OutputConfig::is_empty")]
+    pub output: OutputConfig,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -19,8 +28,28 @@ pub struct NotifyConfig {
     pub slack_mention: Option<String>,
 }
 
+/// Defaults for units that don't set their own output options.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct OutputConfig {
+    /// Capture every unit's output to `<dir>/<name>.log`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mail_to: Option<String>,
+}
+
+impl OutputConfig {
+    fn is_empty(&self) -> bool {
+        self.dir.is_none() && self.max_size.is_none() && self.keep.is_none() && self.mail_to.is_none()
+    }
+}
+
 pub fn config_path() -> Result<String> {
-    let config_dir = init::config_dir()?;
+    let config_dir = init::host_path(&init::config_dir()?);
     Ok(format!("{}/config.toml", config_dir))
 }
 
@@ -39,7 +68,7 @@ pub fn load() -> Result<Config> {
 
 pub fn save(config: &Config) -> Result<()> {
     let path = config_path()?;
-    let config_dir = init::config_dir()?;
+    let config_dir = init::host_path(&init::config_dir()?);
     fs::create_dir_all(&config_dir)
         .with_context(|| format!("Failed to create {}", config_dir))?;
     let content = toml::to_string_pretty(config)
@@ -70,10 +99,13 @@ mod tests {
     #[test]
     fn test_serialize_with_webhook() {
         let config = Config {
+            backend: None,
+            profile: None,
             notify: NotifyConfig {
                 slack_webhook: Some("https://hooks.slack.com/services/T/B/X".to_string()),
                 slack_mention: None,
             },
+            output: OutputConfig::default(),
         };
         let toml_str = toml::to_string_pretty(&config).unwrap();
         assert!(toml_str.contains("slack_webhook = \"https://hooks.slack.com/services/T/B/X\""));
@@ -97,4 +129,19 @@ slack_webhook = "https://hooks.slack.com/services/T/B/X"
         let config: Config = toml::from_str("").unwrap();
         assert!(config.notify.slack_webhook.is_none());
     }
+
+    #[test]
+    fn test_deserialize_output_defaults() {
+        let toml_str = r#"
+[output]
+dir = "/home/user/logs"
+max_size = "1M"
+keep = 3
+mail_to = "ops@example.com"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.output.dir, Some("/home/user/logs".to_string()));
+        assert_eq!(config.output.keep, Some(3));
+        assert!(!toml::to_string_pretty(&Config::default()).unwrap().contains("[output]"));
+    }
 }
@@ -23,6 +23,19 @@ pub struct CronSchedule {
 pub fn parse(expr: &str) -> Result<CronSchedule> {
     let trimmed = expr.trim();
 
+    // A trailing timezone (`0 9 * * * Asia/Tokyo`, like crontab's CRON_TZ)
+    // goes on the end of OnCalendar=.
+    if let Some((rest, zone)) = trimmed.rsplit_once(char::is_whitespace).filter(|(_, zone)| is_timezone(zone)) {
+        let mut schedule = parse(rest)?;
+        let Some(ref mut calendar) = schedule.on_calendar else {
+            bail!("A timezone ({}) only applies to calendar schedules", zone);
+        };
+        calendar.push(' ');
+        calendar.push_str(zone);
+        schedule.display = schedule.display.map(|display| format!("{} {}", display, zone));
+        return Ok(schedule);
+    }
+
     // Try extended syntax first (e.g., @daily/9, @monday/9:30, @1st/8)
     if let Some(schedule) = parse_extended(trimmed)? {
         return Ok(schedule);
@@ -71,6 +84,14 @@ pub fn parse(expr: &str) -> Result<CronSchedule> {
     })
 }
 
+/// `Area/Location` names from the tz database, and `UTC`.
+fn is_timezone(s: &str) -> bool {
+    s == "UTC"
+        || (s.contains('/')
+            && s.starts_with(|c: char| c.is_ascii_uppercase())
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+')))
+}
+
 fn parse_special(expr: &str) -> Option<CronSchedule> {
     match expr {
         "@yearly" | "@annually" => Some(CronSchedule {
@@ -383,6 +404,143 @@ fn format_time_display(prefix: &str, hour: u32, minute: u32) -> String {
     format!("{}/{}", prefix, format_time(hour, minute))
 }
 
+/// Reverse of [`parse`]: map a systemd `OnCalendar=` expression back to the
+/// shortest schedule sdtab accepts (`@daily/9`, `@mon/9:30`, `*/15 * * * *`).
+/// Returns None when there is no cron equivalent (seconds, years, timezones,
+/// `~` last-day syntax).
+pub fn from_calendar(calendar: &str) -> Option<String> {
+    let cron = calendar_cron(calendar)?;
+    let expected = parse(&cron).ok()?.on_calendar;
+    let display = shorthand(&cron).filter(|d| parse(d).ok().map(|s| s.on_calendar) == Some(expected.clone()));
+    Some(display.unwrap_or(cron))
+}
+
+/// An sdtab schedule as a classic crontab expression, with numeric weekdays
+/// since not every cron accepts names in ranges (`Mon-Fri` → `1-5`).
+/// Returns None for `@service` and calendars cron can't express.
+pub fn to_crontab(expr: &str) -> Option<String> {
+    let schedule = parse(expr).ok()?;
+    if schedule.on_boot_sec.is_some() {
+        return Some("@reboot".to_string());
+    }
+    let cron = calendar_cron(schedule.on_calendar.as_deref()?)?;
+    let mut fields: Vec<String> = cron.split_whitespace().map(str::to_string).collect();
+    for (i, name) in DOW_NAMES.iter().enumerate() {
+        fields[4] = fields[4].replace(name, &i.to_string());
+    }
+    Some(fields.join(" "))
+}
+
+/// The five-field form of an `OnCalendar=` expression.
+fn calendar_cron(calendar: &str) -> Option<String> {
+    let normalized = match calendar.trim().to_lowercase().as_str() {
+        "minutely" => "*-*-* *:*:00".to_string(),
+        "hourly" => "*-*-* *:00:00".to_string(),
+        "daily" | "midnight" => "*-*-* 00:00:00".to_string(),
+        "weekly" => "Mon *-*-* 00:00:00".to_string(),
+        "monthly" => "*-*-01 00:00:00".to_string(),
+        "yearly" | "annually" => "*-01-01 00:00:00".to_string(),
+        _ => calendar.trim().to_string(),
+    };
+
+    let (mut dow, mut date, mut time) = (None, None, None);
+    for token in normalized.split_whitespace() {
+        let first = token.chars().next()?;
+        if first.is_ascii_alphabetic() && dow.is_none() {
+            dow = Some(calendar_dow(token)?);
+        } else if token.contains(':') && time.is_none() {
+            time = Some(token);
+        } else if token.contains('-') && date.is_none() {
+            date = Some(token);
+        } else {
+            return None;
+        }
+    }
+
+    let date: Vec<&str> = date.unwrap_or("*-*-*").split('-').collect();
+    let (month, dom) = match date.as_slice() {
+        ["*", month, dom] | [month, dom] => (*month, *dom),
+        _ => return None,
+    };
+    let time: Vec<&str> = time.unwrap_or("00:00:00").split(':').collect();
+    let (hour, minute) = match time.as_slice() {
+        [hour, minute] => (*hour, *minute),
+        [hour, minute, "00" | "0"] => (*hour, *minute),
+        _ => return None,
+    };
+
+    let cron = format!(
+        "{} {} {} {} {}",
+        calendar_field(minute, FieldType::Minute)?,
+        calendar_field(hour, FieldType::Hour)?,
+        calendar_field(dom, FieldType::DayOfMonth)?,
+        calendar_field(month, FieldType::Month)?,
+        dow.unwrap_or_else(|| "*".to_string()),
+    );
+    Some(cron)
+}
+
+/// `0/15` → `*/15`, `9..17` → `9-17`, `09` → `9`.
+fn calendar_field(field: &str, field_type: FieldType) -> Option<String> {
+    if field == "*" {
+        return Some("*".to_string());
+    }
+    let (min, max) = field_type.range();
+    let number = |s: &str| s.parse::<u32>().ok().filter(|n| (min..=max).contains(n));
+    let mut elements = Vec::new();
+    for element in field.split(',') {
+        if let Some((base, step)) = element.split_once('/') {
+            let step: u32 = step.parse().ok()?;
+            let start = if base == "*" { min } else { number(base)? };
+            if start == min {
+                elements.push(format!("*/{}", step));
+            } else {
+                elements.push(format!("{}-{}/{}", start, max, step));
+            }
+        } else if let Some((start, end)) = element.split_once("..") {
+            elements.push(format!("{}-{}", number(start)?, number(end)?));
+        } else {
+            elements.push(number(element)?.to_string());
+        }
+    }
+    Some(elements.join(","))
+}
+
+/// `Mon..Fri` → `Mon-Fri`, `monday,wed` → `Mon,Wed`.
+fn calendar_dow(field: &str) -> Option<String> {
+    let mut elements = Vec::new();
+    for element in field.split(',') {
+        if let Some((start, end)) = element.split_once("..") {
+            elements.push(format!("{}-{}", parse_dow_name(start).ok()?, parse_dow_name(end).ok()?));
+        } else {
+            elements.push(parse_dow_name(element).ok()?.to_string());
+        }
+    }
+    Some(elements.join(","))
+}
+
+/// The `@` form of a five-field expression, when one exists.
+fn shorthand(cron: &str) -> Option<String> {
+    let fields: Vec<&str> = cron.split_whitespace().collect();
+    let [minute, hour, dom, month, dow] = fields.as_slice() else { return None };
+    if *minute == "0" && [*hour, *dom, *month, *dow] == ["*"; 4] {
+        return Some("@hourly".to_string());
+    }
+    let (minute, hour): (u32, u32) = (minute.parse().ok()?, hour.parse().ok()?);
+    let midnight = minute == 0 && hour == 0;
+    let time = format_time(hour, minute);
+    match (*dom, *month, *dow) {
+        ("*", "*", "*") if midnight => Some("@daily".to_string()),
+        ("*", "*", "*") => Some(format!("@daily/{}", time)),
+        ("*", "*", "Mon") if midnight => Some("@weekly".to_string()),
+        ("*", "*", day) if DOW_NAMES.contains(&day) => Some(format!("@{}/{}", day.to_lowercase(), time)),
+        ("1", "*", "*") if midnight => Some("@monthly".to_string()),
+        ("1", "1", "*") if midnight => Some("@yearly".to_string()),
+        (day, "*", "*") => Some(format!("@{}/{}", ordinal(day.parse().ok()?), time)),
+        _ => None,
+    }
+}
+
 #[derive(Clone, Copy)]
 enum FieldType {
     Minute,
@@ -533,6 +691,17 @@ mod tests {
         assert_eq!(cal("0 9 * * *"), "*-*-* 09:00:00");
     }
 
+    #[test]
+    fn trailing_timezone() {
+        assert_eq!(cal("0 9 * * * Asia/Tokyo"), "*-*-* 09:00:00 Asia/Tokyo");
+        assert_eq!(cal("@daily UTC"), "*-*-* 00:00:00 UTC");
+        assert_eq!(display("@daily/9 America/New_York"), "@daily/9 America/New_York");
+        assert!(parse("@reboot UTC").is_err());
+        assert!(parse("0 9 * * * JST").is_err());
+        // cron has no zone per line, so the crontab backend can't run it.
+        assert_eq!(to_crontab("0 9 * * * Asia/Tokyo"), None);
+    }
+
     #[test]
     fn every_5_minutes() {
         assert_eq!(cal("*/5 * * * *"), "*-*-* *:0/5:00");
@@ -741,4 +910,40 @@ mod tests {
         let err = parse("@daily/abc").unwrap_err();
         assert!(err.to_string().contains("Invalid hour"));
     }
+
+    #[test]
+    fn from_calendar_prefers_shorthand() {
+        assert_eq!(from_calendar("daily").as_deref(), Some("@daily"));
+        assert_eq!(from_calendar("hourly").as_deref(), Some("@hourly"));
+        assert_eq!(from_calendar("*-*-* 09:30:00").as_deref(), Some("@daily/9:30"));
+        assert_eq!(from_calendar("Sat *-*-* 10:00").as_deref(), Some("@sat/10"));
+        assert_eq!(from_calendar("*-*-01 08:00:00").as_deref(), Some("@1st/8"));
+        assert_eq!(from_calendar("Mon *-*-* 00:00:00").as_deref(), Some("@weekly"));
+    }
+
+    #[test]
+    fn from_calendar_falls_back_to_five_fields() {
+        assert_eq!(from_calendar("Mon..Fri *-*-* 09:00:00").as_deref(), Some("0 9 * * Mon-Fri"));
+        assert_eq!(from_calendar("*-*-* *:0/15:00").as_deref(), Some("*/15 * * * *"));
+        assert_eq!(from_calendar("*-*-* 08..18:05").as_deref(), Some("5 8-18 * * *"));
+        assert_eq!(from_calendar("minutely").as_deref(), Some("* * * * *"));
+    }
+
+    #[test]
+    fn from_calendar_rejects_what_cron_cannot_express() {
+        assert_eq!(from_calendar("*-*-* 00:00:30"), None);
+        assert_eq!(from_calendar("2026-*-* 00:00:00"), None);
+        assert_eq!(from_calendar("*-*-* 09:00:00 Asia/Tokyo"), None);
+        assert_eq!(from_calendar("*-*~01 00:00:00"), None);
+    }
+
+    #[test]
+    fn to_crontab_expands_sdtab_syntax() {
+        assert_eq!(to_crontab("@daily/9:30").as_deref(), Some("30 9 * * *"));
+        assert_eq!(to_crontab("@mon/8").as_deref(), Some("0 8 * * 1"));
+        assert_eq!(to_crontab("0 9 * * Mon-Fri").as_deref(), Some("0 9 * * 1-5"));
+        assert_eq!(to_crontab("*/15 * * * *").as_deref(), Some("*/15 * * * *"));
+        assert_eq!(to_crontab("@reboot").as_deref(), Some("@reboot"));
+        assert_eq!(to_crontab("@service"), None);
+    }
 }
@@ -0,0 +1,411 @@
+//! Crontab backend for hosts without a user manager (no linger, restricted
+//! containers): `SDTAB_BACKEND=crontab` or `backend = "crontab"` in config.toml.
+//!
+//! Unit files stay the source of truth, so `list`, `remove`, `export` and
+//! `apply` work unchanged. Enabling a timer writes it as a job into a block
+//! of the user's crontab between sdtab markers; disabling removes it, and a
+//! daemon-reload regenerates every job from its unit file. Anything cron
+//! can't run the same way (services, resource limits, …) is an error.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+
+use crate::backend::Backend;
+use crate::parse_unit::ParsedUnit;
+use crate::systemctl::{self, UnitProperties};
+use crate::unit_file::UnitFile;
+use crate::{config, cron, export, init, parse_unit};
+
+const BEGIN: &str = "# BEGIN sdtab (managed by sdtab; edits here are overwritten)";
+const END: &str = "# END sdtab";
+/// Starts each job in the block: `# unit: sdtab-backup.timer`.
+const UNIT_MARKER: &str = "# unit: ";
+
+pub struct Crontab;
+
+/// sdtab's jobs, keyed by timer unit, each with its crontab lines.
+type Block = BTreeMap<String, Vec<String>>;
+
+impl Backend for Crontab {
+    fn daemon_reload(&self) -> Result<()> {
+        let (mut block, rest) = read()?;
+        for (unit, lines) in block.iter_mut() {
+            *lines = job_lines(unit)?;
+        }
+        block.retain(|_, lines| !lines.is_empty());
+        write(&block, &rest)
+    }
+
+    fn enable_and_start(&self, unit: &str) -> Result<()> {
+        if !unit.ends_with(".timer") {
+            bail!("{}: the crontab backend only runs timers; services need systemd", unit);
+        }
+        let lines = job_lines(unit)?;
+        if lines.is_empty() {
+            bail!("Unit {} not found.", unit);
+        }
+        let (mut block, rest) = read()?;
+        block.insert(unit.to_string(), lines);
+        write(&block, &rest)
+    }
+
+    fn stop_and_disable(&self, unit: &str) -> Result<()> {
+        let (mut block, rest) = read()?;
+        if block.remove(unit).is_some() {
+            write(&block, &rest)?;
+        }
+        Ok(())
+    }
+
+    // A timer in the crontab is both enabled and running, so these are the
+    // combined operations.
+    fn enable(&self, unit: &str) -> Result<()> {
+        self.enable_and_start(unit)
+    }
+
+    fn disable(&self, unit: &str) -> Result<()> {
+        self.stop_and_disable(unit)
+    }
+
+    fn stop(&self, unit: &str) -> Result<()> {
+        // A timer's service only runs while `start` waits for it.
+        if unit.ends_with(".timer") {
+            self.stop_and_disable(unit)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn start(&self, unit: &str) -> Result<()> {
+        match unit.strip_suffix(".service") {
+            // A timer's service: run the job once, now.
+            Some(_) => {
+                let path = Path::new(&init::unit_dir()?).join(unit);
+                let service = UnitFile::load_effective(&path)?;
+                let (command, _) = export::unescape_exec(service.get("Service", "ExecStart").context("ExecStart missing")?);
+                let workdir = service.get("Service", "WorkingDirectory").unwrap_or("/");
+                let status = Command::new("sh")
+                    .args(["-c", &with_global_env(&command)])
+                    .current_dir(workdir)
+                    .status()
+                    .context("Failed to run sh")?;
+                if !status.success() {
+                    bail!("{} failed ({})", unit, status);
+                }
+                Ok(())
+            }
+            None => self.enable_and_start(unit),
+        }
+    }
+
+    fn restart(&self, unit: &str) -> Result<()> {
+        self.enable_and_start(unit)
+    }
+
+    fn reload(&self, unit: &str) -> Result<()> {
+        bail!("{}: reload needs systemd; the crontab backend only runs timers", unit)
+    }
+
+    fn show(&self, units: &[String], properties: &[&str]) -> Result<UnitProperties> {
+        let (block, _) = read()?;
+        let unit_dir = init::unit_dir()?;
+        let mut props = UnitProperties::default();
+        for unit in units {
+            let loaded = Path::new(&unit_dir).join(unit).exists();
+            let scheduled = block.contains_key(unit);
+            let timer = unit.ends_with(".timer");
+            for property in properties {
+                let value = match *property {
+                    "LoadState" if loaded => "loaded",
+                    "LoadState" => "not-found",
+                    "ActiveState" if scheduled => "active",
+                    "ActiveState" => "inactive",
+                    "SubState" if scheduled => "waiting",
+                    "SubState" => "dead",
+                    "UnitFileState" if !loaded => "",
+                    "UnitFileState" if !timer => "static",
+                    "UnitFileState" if scheduled => "enabled",
+                    "UnitFileState" => "disabled",
+                    "Result" => "success",
+                    "MainPID" => "0",
+                    "NextElapseUSecRealtime" if scheduled && timer => {
+                        props.insert(unit, property, next_elapse(&Path::new(&unit_dir).join(unit)));
+                        continue;
+                    }
+                    _ => "",
+                };
+                props.insert(unit, property, value.to_string());
+            }
+        }
+        Ok(props)
+    }
+
+    fn journal_tail(&self, _unit: &str, _lines: u32) -> Vec<String> {
+        vec![]
+    }
+
+    fn next_runs(&self, on_calendar: &str, count: u32) -> Result<Vec<String>> {
+        // systemd-analyze works without a running manager.
+        systemctl::next_runs_cli(on_calendar, count)
+    }
+}
+
+/// The next run of a timer, as `systemctl show` prints it; empty if unknown.
+fn next_elapse(path: &Path) -> String {
+    UnitFile::load_effective(path)
+        .ok()
+        .and_then(|timer| timer.get("Timer", "OnCalendar").map(str::to_string))
+        .and_then(|on_calendar| systemctl::next_runs_cli(&on_calendar, 1).ok())
+        .and_then(|runs| runs.into_iter().next())
+        .unwrap_or_default()
+}
+
+/// The crontab lines for a timer unit, from its unit files; empty when the
+/// unit no longer exists.
+pub fn job_lines(timer: &str) -> Result<Vec<String>> {
+    let Some(name) = timer.strip_prefix(init::prefix()).and_then(|n| n.strip_suffix(".timer")) else {
+        bail!("{}: not an sdtab timer", timer);
+    };
+    let Some(unit) = parse_unit::scan_all_units()?.into_iter().find(|u| u.name == name) else {
+        return Ok(vec![]);
+    };
+    let path = Path::new(&init::unit_dir()?).join(crate::unit::service_filename(name));
+    let service = UnitFile::load_effective(&path)?;
+    let exec = service.get("Service", "ExecStart").unwrap_or(&unit.command).to_string();
+    let notify = config::load()?.notify.slack_webhook.is_some();
+
+    let mut job = export::cron_job(&unit, &exec, notify).map_err(|reason| anyhow::anyhow!("{}: {}", name, reason))?;
+    let timer_file = UnitFile::load_effective(&Path::new(&init::unit_dir()?).join(timer))?;
+    let mut unmappable: Vec<String> = job.dropped.iter().map(|d| d.to_string()).collect();
+    unmappable.extend(unmappable_timer_settings(&unit, &timer_file));
+    if !unmappable.is_empty() {
+        bail!(
+            "{}: the crontab backend can't run {}; remove them or use systemd",
+            name,
+            unmappable.join(", ")
+        );
+    }
+    job.command = with_global_env(&job.command);
+    let mut lines = vec![format!("{}{}", UNIT_MARKER, timer), format!("# {}: {}", unit.name, unit.description)];
+    lines.extend(job.lines());
+    Ok(lines)
+}
+
+/// `[Timer]` settings, drop-ins included, that cron can't honour: anything
+/// but the trigger sdtab writes for the schedule and `Persistent=true`, which
+/// sdtab always sets. A drop-in adding `OnBootSec=` or `AccuracySec=` would
+/// otherwise be silently ignored.
+fn unmappable_timer_settings(unit: &ParsedUnit, timer: &UnitFile) -> Vec<String> {
+    let expected = unit.cron_expr.as_deref().and_then(|expr| cron::parse(expr).ok());
+    let expected = |key: &str| match (key, &expected) {
+        ("OnCalendar", Some(schedule)) => schedule.on_calendar.clone(),
+        ("OnBootSec", Some(schedule)) => schedule.on_boot_sec.clone(),
+        _ => None,
+    };
+
+    // An empty assignment resets the list-valued settings, as in systemd.
+    let mut settings: Vec<(&str, &str)> = Vec::new();
+    for (section, key, value) in timer.entries() {
+        if section != "Timer" {
+            continue;
+        }
+        if value.is_empty() {
+            settings.retain(|(k, _)| *k != key);
+        } else {
+            settings.push((key, value));
+        }
+    }
+    settings
+        .into_iter()
+        .filter(|&(key, value)| match key {
+            "OnCalendar" | "OnBootSec" => expected(key).as_deref() != Some(value),
+            "Persistent" => !matches!(value, "true" | "yes" | "on" | "1"),
+            // Already reported as random_delay.
+            "RandomizedDelaySec" => unit.random_delay.is_none(),
+            _ => true,
+        })
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect()
+}
+
+/// Load sdtab's global env file first, as `EnvironmentFile=-` does for systemd.
+fn with_global_env(command: &str) -> String {
+    match init::global_env_path() {
+        Ok(path) if Path::new(&init::host_path(&path)).exists() => {
+            format!("set -a && . {} && set +a && {}", path, command)
+        }
+        _ => command.to_string(),
+    }
+}
+
+/// The jobs in sdtab's block, keyed by timer unit, and the rest of the crontab.
+fn read() -> Result<(Block, Vec<String>)> {
+    let output = Command::new("crontab").arg("-l").output().context("Failed to run crontab -l")?;
+    let text = listing(output.status.success(), &output.stdout, &output.stderr)?;
+    Ok(split_block(&text))
+}
+
+/// The text of `crontab -l`. It fails with "no crontab for <user>" when there
+/// is none yet; any other failure is an error, since writing back an empty
+/// table would wipe the user's jobs.
+fn listing(success: bool, stdout: &[u8], stderr: &[u8]) -> Result<String> {
+    let stderr = String::from_utf8_lossy(stderr);
+    if success {
+        Ok(String::from_utf8_lossy(stdout).to_string())
+    } else if stderr.contains("no crontab for ") {
+        Ok(String::new())
+    } else {
+        bail!("crontab -l failed: {}", stderr.trim())
+    }
+}
+
+fn write(block: &Block, rest: &[String]) -> Result<()> {
+    let text = join_block(block, rest);
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run crontab")?;
+    child
+        .stdin
+        .take()
+        .context("Failed to open crontab stdin")?
+        .write_all(text.as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("crontab rejected the new table");
+    }
+    Ok(())
+}
+
+fn split_block(text: &str) -> (Block, Vec<String>) {
+    let mut block = Block::new();
+    let mut rest = Vec::new();
+    let mut inside = false;
+    let mut current: Option<String> = None;
+    for line in text.lines() {
+        if line == BEGIN {
+            inside = true;
+        } else if line == END {
+            inside = false;
+            current = None;
+        } else if !inside {
+            rest.push(line.to_string());
+        } else if let Some(unit) = line.strip_prefix(UNIT_MARKER) {
+            current = Some(unit.to_string());
+            block.insert(unit.to_string(), vec![line.to_string()]);
+        } else if let Some(ref unit) = current {
+            block.get_mut(unit).expect("current unit is in the block").push(line.to_string());
+        }
+    }
+    (block, rest)
+}
+
+/// The user's own lines, then sdtab's block (omitted when empty).
+fn join_block(block: &Block, rest: &[String]) -> String {
+    let mut text: String = rest.iter().map(|l| format!("{}\n", l)).collect();
+    if !block.is_empty() {
+        text.push_str(BEGIN);
+        text.push('\n');
+        // cron mails any output by default; sdtab only mails when asked to.
+        text.push_str("MAILTO=\"\"\n");
+        for lines in block.values() {
+            for line in lines {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        text.push_str(END);
+        text.push('\n');
+    }
+    text
+}
+
+/// A crontab without sdtab's block, so `sdtab import crontab` doesn't import
+/// the jobs this backend wrote.
+pub fn strip_managed(text: &str) -> String {
+    split_block(text).1.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::fake::FakeBackend;
+    use crate::testdir::TestDir;
+    use crate::{apply, backend};
+
+    #[test]
+    fn only_a_missing_crontab_reads_as_empty() {
+        assert_eq!(listing(true, b"0 1 * * * ./mine.sh\n", b"").unwrap(), "0 1 * * * ./mine.sh\n");
+        assert_eq!(listing(false, b"", b"no crontab for alice\n").unwrap(), "");
+        let err = listing(false, b"", b"crontab: cannot open spool: Permission denied\n").unwrap_err();
+        assert_eq!(err.to_string(), "crontab -l failed: crontab: cannot open spool: Permission denied");
+    }
+
+    #[test]
+    fn job_lines_map_timers_and_reject_what_cron_cant_run() {
+        let home = TestDir::new("crontab");
+        fs::create_dir_all(home.join(".config/systemd/user")).unwrap();
+        let file = home.join("Sdtabfile.toml");
+        fs::write(
+            &file,
+            "[timers.backup]\nschedule = \"@daily\"\ncommand = \"./backup.sh\"\nworkdir = \"/tmp\"\nno_notify = true\n\n\
+             [timers.boot]\nschedule = \"@reboot\"\ncommand = \"./warm.sh\"\nworkdir = \"/tmp\"\nno_notify = true\n\n\
+             [timers.big]\nschedule = \"@hourly\"\ncommand = \"./big.sh\"\nworkdir = \"/tmp\"\nmemory_max = \"1G\"\nno_notify = true\n",
+        )
+        .unwrap();
+        let fake = Rc::new(FakeBackend::default());
+        init::with_home(&home, || {
+            backend::with_backend(fake, || apply::run(file.to_str().unwrap(), false, false, false, false, None)).unwrap();
+
+            let lines = job_lines("sdtab-backup.timer").unwrap();
+            assert_eq!(lines[0], "# unit: sdtab-backup.timer");
+            assert_eq!(lines.last().unwrap(), "0 0 * * * cd /tmp && ./backup.sh");
+            assert_eq!(job_lines("sdtab-boot.timer").unwrap().last().unwrap(), "@reboot cd /tmp && ./warm.sh");
+            assert!(job_lines("sdtab-gone.timer").unwrap().is_empty());
+
+            let err = job_lines("sdtab-big.timer").unwrap_err().to_string();
+            assert!(err.contains("can't run memory_max"), "{}", err);
+
+            // Timer settings from drop-ins count too.
+            let dropin = Path::new(&init::unit_dir().unwrap()).join("sdtab-backup.timer.d");
+            fs::create_dir_all(&dropin).unwrap();
+            fs::write(dropin.join("tweak.conf"), "[Timer]\nOnBootSec=5min\nPersistent=false\nAccuracySec=1h\n").unwrap();
+            let err = job_lines("sdtab-backup.timer").unwrap_err().to_string();
+            assert!(err.contains("can't run OnBootSec=5min, Persistent=false, AccuracySec=1h;"), "{}", err);
+
+            // Resetting the trigger to a different calendar isn't the schedule cron would run.
+            fs::write(dropin.join("tweak.conf"), "[Timer]\nOnCalendar=\nOnCalendar=weekly\n").unwrap();
+            let err = job_lines("sdtab-backup.timer").unwrap_err().to_string();
+            assert!(err.contains("can't run OnCalendar=weekly;"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn block_round_trips_and_keeps_user_lines() {
+        let text = "\
+MAILTO=me@example.com
+0 1 * * * ./mine.sh
+# BEGIN sdtab (managed by sdtab; edits here are overwritten)
+MAILTO=\"\"
+# unit: sdtab-backup.timer
+# backup: nightly
+0 3 * * * cd /srv && ./backup.sh
+# END sdtab
+";
+        let (mut block, rest) = split_block(text);
+        assert_eq!(rest, ["MAILTO=me@example.com", "0 1 * * * ./mine.sh"]);
+        assert_eq!(block["sdtab-backup.timer"].last().unwrap(), "0 3 * * * cd /srv && ./backup.sh");
+        assert_eq!(join_block(&block, &rest), text);
+
+        block.remove("sdtab-backup.timer");
+        assert_eq!(join_block(&block, &rest), "MAILTO=me@example.com\n0 1 * * * ./mine.sh\n");
+        assert_eq!(strip_managed(text), "MAILTO=me@example.com\n0 1 * * * ./mine.sh\n");
+    }
+}
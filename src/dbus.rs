@@ -0,0 +1,547 @@
+//! D-Bus backend: talks to `org.freedesktop.systemd1` on the user bus
+//! (the system bus with `--system`) through `busctl`, without going
+//! through `systemctl`.
+//!
+//! Property values are converted to the text `systemctl show` prints
+//! (timestamps as `Mon 2026-03-02 09:00:00 JST`, `ExecStart` as
+//! `{ path=… ; argv[]=… }`) so callers see the same values with either
+//! backend. `show` reads the common unit state for all units in one
+//! `ListUnitsByNames` call (and file states in one `ListUnitFilesByPatterns`)
+//! and only goes unit by unit, one `GetAll` per interface, for the rest.
+//! Start, stop and restart wait for their job like `systemctl` does. The
+//! journal and calendar evaluation have no systemd D-Bus API; they use
+//! `journalctl` and `systemd-analyze` like the CLI backend.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::backend::Backend;
+use crate::systemctl::{self, UnitProperties};
+
+const DESTINATION: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER: &str = "org.freedesktop.systemd1.Manager";
+
+/// Read from the unit file listing rather than from each unit.
+const FILE_PROPERTIES: &[&str] = &["UnitFileState", "FragmentPath"];
+
+pub struct Dbus;
+
+impl Backend for Dbus {
+    fn daemon_reload(&self) -> Result<()> {
+        call("Reload", &[])?;
+        Ok(())
+    }
+
+    fn enable_and_start(&self, unit: &str) -> Result<()> {
+        self.enable(unit)?;
+        run_job("StartUnit", unit)
+    }
+
+    fn stop_and_disable(&self, unit: &str) -> Result<()> {
+        run_job("StopUnit", unit)?;
+        call("DisableUnitFiles", &["asb", "1", unit, "false"])?;
+        call("Reload", &[])?;
+        Ok(())
+    }
+
+    fn enable(&self, unit: &str) -> Result<()> {
+        // EnableUnitFiles(files, runtime, force): without force, conflicting
+        // links are an error as with `systemctl enable`. Reload afterwards.
+        call("EnableUnitFiles", &["asbb", "1", unit, "false", "false"])?;
+        call("Reload", &[])?;
+        Ok(())
+    }
+
+    fn disable(&self, unit: &str) -> Result<()> {
+        call("DisableUnitFiles", &["asb", "1", unit, "false"])?;
+        call("Reload", &[])?;
+        Ok(())
+    }
+
+    fn start(&self, unit: &str) -> Result<()> {
+        run_job("StartUnit", unit)
+    }
+
+    fn stop(&self, unit: &str) -> Result<()> {
+        run_job("StopUnit", unit)
+    }
+
+    fn restart(&self, unit: &str) -> Result<()> {
+        run_job("RestartUnit", unit)
+    }
+
+    fn reload(&self, unit: &str) -> Result<()> {
+        call("ReloadUnit", &["ss", unit, "replace"])?;
+        Ok(())
+    }
+
+    fn show(&self, units: &[String], properties: &[&str]) -> Result<UnitProperties> {
+        let mut props = UnitProperties::default();
+        if units.is_empty() {
+            return Ok(props);
+        }
+        // The state everyone asks for comes from two Manager calls for all
+        // units; only what's left is fetched unit by unit.
+        let listed = parse_listed_units(&call_json("ListUnitsByNames", &names_arg("as", units))?)?;
+        let files = if properties.iter().any(|p| FILE_PROPERTIES.contains(p)) {
+            let mut args = names_arg("asas", &[]);
+            args.extend(names_arg("", units));
+            parse_unit_files(&call_json("ListUnitFilesByPatterns", &args)?)?
+        } else {
+            BTreeMap::new()
+        };
+
+        let mut timestamps = Vec::new();
+        for unit in units {
+            let Some(state) = listed.get(unit) else {
+                bail!("ListUnitsByNames did not return {}", unit);
+            };
+            let mut remaining = Vec::new();
+            for property in properties {
+                match state.get(property).or_else(|| file_property(&files, unit, property)) {
+                    Some(value) => props.insert(unit, property, value),
+                    None => remaining.push(*property),
+                }
+            }
+
+            // A unit that isn't loaded has nothing more to read.
+            if state.load_state != "loaded" {
+                continue;
+            }
+            // Like `systemctl show`, properties the unit's interfaces lack
+            // (`MainPID` of a timer, `NextElapseUSecRealtime` of a service)
+            // are left out rather than failing the call.
+            for interface in [type_interface(unit), "Unit"] {
+                if remaining.is_empty() {
+                    break;
+                }
+                let interface = format!("org.freedesktop.systemd1.{}", interface);
+                let all = get_all(&state.path, &interface)?;
+                remaining.retain(|name| {
+                    let Some(value) = all.get(*name) else {
+                        return true;
+                    };
+                    if is_timestamp(name) {
+                        let usec = value["data"].as_u64().unwrap_or(0);
+                        if usec == 0 {
+                            props.insert(unit, name, String::new());
+                        } else {
+                            timestamps.push((unit.clone(), name.to_string(), usec / 1_000_000));
+                        }
+                    } else {
+                        props.insert(unit, name, format_value(name, value));
+                    }
+                    false
+                });
+            }
+        }
+        if !timestamps.is_empty() {
+            let secs: Vec<u64> = timestamps.iter().map(|(_, _, s)| *s).collect();
+            for ((unit, name, _), text) in timestamps.iter().zip(format_timestamps(&secs)?) {
+                props.insert(unit, name, text);
+            }
+        }
+        Ok(props)
+    }
+
+    fn journal_tail(&self, unit: &str, lines: u32) -> Vec<String> {
+        systemctl::journal_tail_cli(unit, lines)
+    }
+
+    fn next_runs(&self, on_calendar: &str, count: u32) -> Result<Vec<String>> {
+        systemctl::next_runs_cli(on_calendar, count)
+    }
+}
+
+#[cfg(test)]
+type FakeBusctl = Box<dyn Fn(&[&str]) -> Result<String>>;
+
+#[cfg(test)]
+thread_local! {
+    static FAKE_BUSCTL: std::cell::RefCell<Option<FakeBusctl>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Run `f` with `busctl` answered by `fake` (given the arguments after the
+/// scope flag) instead of the real bus.
+#[cfg(test)]
+pub fn with_busctl<T>(fake: impl Fn(&[&str]) -> Result<String> + 'static, f: impl FnOnce() -> T) -> T {
+    let previous = FAKE_BUSCTL.with(|b| b.replace(Some(Box::new(fake))));
+    let result = f();
+    FAKE_BUSCTL.with(|b| *b.borrow_mut() = previous);
+    result
+}
+
+fn busctl(args: &[&str]) -> Result<String> {
+    #[cfg(test)]
+    if let Some(output) = FAKE_BUSCTL.with(|b| b.borrow().as_ref().map(|fake| fake(args))) {
+        return output;
+    }
+    let output = Command::new("busctl")
+        .arg(systemctl::scope_flag())
+        .args(args)
+        .output()
+        .context("Failed to execute busctl")?;
+    if !output.status.success() {
+        bail!(
+            "busctl {} {} failed: {}",
+            systemctl::scope_flag(),
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Call a `Manager` method; `args` is the D-Bus signature followed by the values.
+fn call(method: &str, args: &[&str]) -> Result<String> {
+    let mut full = vec!["call", DESTINATION, MANAGER_PATH, MANAGER, method];
+    full.extend(args);
+    busctl(&full)
+}
+
+/// Call a `Manager` method and return busctl's JSON reply.
+fn call_json(method: &str, args: &[String]) -> Result<Value> {
+    let mut full = vec!["--json=short", "call", DESTINATION, MANAGER_PATH, MANAGER, method];
+    full.extend(args.iter().map(String::as_str));
+    let output = busctl(&full)?;
+    serde_json::from_str(&output).with_context(|| format!("Unexpected busctl output: {}", output))
+}
+
+/// Every property of `interface` on the object at `path`, by name, as
+/// busctl's `{"type":…,"data":…}` values.
+fn get_all(path: &str, interface: &str) -> Result<serde_json::Map<String, Value>> {
+    let args = ["--json=short", "call", DESTINATION, path, "org.freedesktop.DBus.Properties", "GetAll", "s", interface];
+    let output = busctl(&args)?;
+    let reply: Value = serde_json::from_str(&output).with_context(|| format!("Unexpected busctl output: {}", output))?;
+    match reply["data"][0].as_object() {
+        Some(all) => Ok(all.clone()),
+        None => bail!("Unexpected GetAll reply: {}", output),
+    }
+}
+
+/// Queue a `StartUnit`/`StopUnit`/`RestartUnit` job for `unit` and wait until
+/// systemd has carried it out. A unit that then is `failed` after a start or
+/// restart is an error, as `systemctl` reports it.
+fn run_job(method: &str, unit: &str) -> Result<()> {
+    let reply = call_json(method, &["ss".to_string(), unit.to_string(), "replace".to_string()])?;
+    let Some(job) = reply["data"][0].as_str() else {
+        bail!("Unexpected {} reply: {}", method, reply);
+    };
+    // The job object goes away once the job is done.
+    while busctl(&["get-property", DESTINATION, job, "org.freedesktop.systemd1.Job", "State"]).is_ok() {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    if method == "StopUnit" {
+        return Ok(());
+    }
+    let listed = parse_listed_units(&call_json("ListUnitsByNames", &names_arg("as", &[unit.to_string()]))?)?;
+    if listed.get(unit).is_some_and(|state| state.active_state == "failed") {
+        bail!(
+            "{} failed to start. See: journalctl {} -u {}",
+            unit,
+            systemctl::scope_flag(),
+            unit
+        );
+    }
+    Ok(())
+}
+
+/// `signature`, then `names` as a busctl array argument.
+fn names_arg(signature: &str, names: &[String]) -> Vec<String> {
+    let mut args = Vec::new();
+    if !signature.is_empty() {
+        args.push(signature.to_string());
+    }
+    args.push(names.len().to_string());
+    args.extend(names.iter().cloned());
+    args
+}
+
+/// A unit's `ListUnitsByNames` row: the common state and its object path.
+struct ListedUnit {
+    description: String,
+    load_state: String,
+    active_state: String,
+    sub_state: String,
+    path: String,
+}
+
+impl ListedUnit {
+    fn get(&self, property: &str) -> Option<String> {
+        let value = match property {
+            "Description" => &self.description,
+            "LoadState" => &self.load_state,
+            "ActiveState" => &self.active_state,
+            "SubState" => &self.sub_state,
+            _ => return None,
+        };
+        Some(value.clone())
+    }
+}
+
+/// `ListUnitsByNames` reply: `a(ssssssouso)`, by unit name. It loads units
+/// the way `LoadUnit` does, so missing ones come back as `not-found`.
+fn parse_listed_units(reply: &Value) -> Result<BTreeMap<String, ListedUnit>> {
+    let Some(rows) = reply["data"][0].as_array() else {
+        bail!("Unexpected ListUnitsByNames reply: {}", reply);
+    };
+    let text = |row: &Value, i: usize| row[i].as_str().unwrap_or_default().to_string();
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let unit = ListedUnit {
+                description: text(row, 1),
+                load_state: text(row, 2),
+                active_state: text(row, 3),
+                sub_state: text(row, 4),
+                path: text(row, 6),
+            };
+            (text(row, 0), unit)
+        })
+        .collect())
+}
+
+/// `ListUnitFilesByPatterns` reply: `a(ss)` of file path and state, by unit name.
+fn parse_unit_files(reply: &Value) -> Result<BTreeMap<String, (String, String)>> {
+    let Some(rows) = reply["data"][0].as_array() else {
+        bail!("Unexpected ListUnitFilesByPatterns reply: {}", reply);
+    };
+    let mut files = BTreeMap::new();
+    for row in rows {
+        let path = row[0].as_str().unwrap_or_default();
+        let name = path.rsplit('/').next().unwrap_or_default();
+        files.insert(name.to_string(), (path.to_string(), row[1].as_str().unwrap_or_default().to_string()));
+    }
+    Ok(files)
+}
+
+/// `UnitFileState`/`FragmentPath` from the unit file listing. Instances have
+/// no file of their own, so they are left to `get-property`.
+fn file_property(files: &BTreeMap<String, (String, String)>, unit: &str, property: &str) -> Option<String> {
+    let (path, state) = match files.get(unit) {
+        Some((path, state)) => (path.clone(), state.clone()),
+        None if unit.contains('@') => return None,
+        None => (String::new(), String::new()),
+    };
+    match property {
+        "UnitFileState" => Some(state),
+        "FragmentPath" => Some(path),
+        _ => None,
+    }
+}
+
+fn type_interface(unit: &str) -> &'static str {
+    match unit.rsplit('.').next() {
+        Some("timer") => "Timer",
+        Some("socket") => "Socket",
+        Some("path") => "Path",
+        _ => "Service",
+    }
+}
+
+fn is_timestamp(property: &str) -> bool {
+    property.ends_with("Timestamp") || property.ends_with("USecRealtime") || property == "LastTriggerUSec"
+}
+
+/// The text `systemctl show` prints for a `busctl --json` value.
+fn format_value(property: &str, value: &Value) -> String {
+    let data = &value["data"];
+    match data {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => if *b { "yes" } else { "no" }.to_string(),
+        // Unset cgroup counters are UINT64_MAX.
+        Value::Number(n) if n.as_u64() == Some(u64::MAX) => "[not set]".to_string(),
+        Value::Number(n) => n.to_string(),
+        // a(sasbttttuii): path, argv, ignore-errors, timestamps, pid, code, status
+        Value::Array(commands) if property.starts_with("Exec") => commands
+            .iter()
+            .map(|command| {
+                let path = command[0].as_str().unwrap_or_default();
+                let argv: Vec<&str> = command[1]
+                    .as_array()
+                    .map(|a| a.iter().filter_map(Value::as_str).collect())
+                    .unwrap_or_default();
+                format!("{{ path={} ; argv[]={} ; }}", path, argv.join(" "))
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        other => other.to_string(),
+    }
+}
+
+/// `Mon 2026-03-02 09:00:00 JST` for each epoch second, in one `date` call.
+fn format_timestamps(secs: &[u64]) -> Result<Vec<String>> {
+    let mut child = Command::new("date")
+        .args(["-f", "-", "+%a %Y-%m-%d %H:%M:%S %Z"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to execute date")?;
+    let input: String = secs.iter().map(|s| format!("@{}\n", s)).collect();
+    child
+        .stdin
+        .take()
+        .context("Failed to open date stdin")?
+        .write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("date failed to format timestamps");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn formats_values_like_systemctl_show() {
+        let value: Value = serde_json::from_str(r#"{"type":"s","data":"active"}"#).unwrap();
+        assert_eq!(format_value("ActiveState", &value), "active");
+        let value: Value = serde_json::from_str(r#"{"type":"t","data":18446744073709551615}"#).unwrap();
+        assert_eq!(format_value("MemoryCurrent", &value), "[not set]");
+        let value: Value = serde_json::from_str(
+            r#"{"type":"a(sasbttttuii)","data":[["/usr/bin/node",["/usr/bin/node","app.js"],false,0,0,0,0,0,0,0]]}"#,
+        )
+        .unwrap();
+        assert_eq!(format_value("ExecStart", &value), "{ path=/usr/bin/node ; argv[]=/usr/bin/node app.js ; }");
+    }
+
+    #[test]
+    fn reads_batched_listings() {
+        let listed: Value = serde_json::from_str(
+            r#"{"type":"a(ssssssouso)","data":[[
+                ["sdtab-web.service","[sdtab] web","loaded","active","running","","/org/freedesktop/systemd1/unit/sdtab_2dweb_2eservice",0,"","/"],
+                ["sdtab-gone.timer","sdtab-gone.timer","not-found","inactive","dead","","/org/freedesktop/systemd1/unit/sdtab_2dgone_2etimer",0,"","/"]
+            ]]}"#,
+        )
+        .unwrap();
+        let listed = parse_listed_units(&listed).unwrap();
+        assert_eq!(listed["sdtab-web.service"].get("SubState").as_deref(), Some("running"));
+        assert_eq!(listed["sdtab-gone.timer"].get("LoadState").as_deref(), Some("not-found"));
+        assert_eq!(listed["sdtab-web.service"].path, "/org/freedesktop/systemd1/unit/sdtab_2dweb_2eservice");
+        assert_eq!(listed["sdtab-web.service"].get("MainPID"), None);
+
+        let files: Value = serde_json::from_str(
+            r#"{"type":"a(ss)","data":[[["/home/me/.config/systemd/user/sdtab-web.service","enabled"]]]}"#,
+        )
+        .unwrap();
+        let files = parse_unit_files(&files).unwrap();
+        assert_eq!(file_property(&files, "sdtab-web.service", "UnitFileState").as_deref(), Some("enabled"));
+        assert_eq!(file_property(&files, "sdtab-gone.timer", "UnitFileState").as_deref(), Some(""));
+        // Instances are looked up unit by unit.
+        assert_eq!(file_property(&files, "sdtab-api@blue.service", "UnitFileState"), None);
+    }
+
+    fn object_path(unit: &str) -> String {
+        format!("/org/freedesktop/systemd1/unit/{}", unit.replace('-', "_2d").replace('.', "_2e"))
+    }
+
+    /// `ListUnitsByNames` reply listing `units` as loaded, in `active_state`.
+    fn listing(units: &[&str], active_state: &str) -> String {
+        let rows: Vec<Value> = units
+            .iter()
+            .map(|u| json!([u, u, "loaded", active_state, "running", "", object_path(u), 0, "", "/"]))
+            .collect();
+        json!({ "type": "a(ssssssouso)", "data": [rows] }).to_string()
+    }
+
+    /// A bus where each interface has only its own properties, as systemd's does.
+    fn interfaces(args: &[&str]) -> Result<String> {
+        let all = match args {
+            ["--json=short", "call", _, _, _, "ListUnitsByNames", "as", _, units @ ..] => {
+                return Ok(listing(units, "active"));
+            }
+            [_, "call", _, _, _, "GetAll", "s", "org.freedesktop.systemd1.Timer"] => json!({
+                "NextElapseUSecRealtime": { "type": "t", "data": 1_772_409_600_000_000u64 },
+                "Result": { "type": "s", "data": "success" },
+            }),
+            [_, "call", _, _, _, "GetAll", "s", "org.freedesktop.systemd1.Service"] => json!({
+                "MainPID": { "type": "u", "data": 4242 },
+                "MemoryCurrent": { "type": "t", "data": 1_048_576 },
+                "ExecMainStartTimestamp": { "type": "t", "data": 0 },
+                "Result": { "type": "s", "data": "success" },
+                "ExecStart": { "type": "a(sasbttttuii)", "data": [["/usr/bin/node", ["/usr/bin/node", "app.js"], false, 0, 0, 0, 0, 0, 0, 0]] },
+                "WorkingDirectory": { "type": "s", "data": "/srv/web" },
+            }),
+            [_, "call", _, _, _, "GetAll", "s", "org.freedesktop.systemd1.Unit"] => json!({
+                "Id": { "type": "s", "data": "unit" },
+            }),
+            _ => bail!("unexpected busctl {}", args.join(" ")),
+        };
+        Ok(json!({ "type": "a{sv}", "data": [all] }).to_string())
+    }
+
+    #[test]
+    fn list_and_status_read_mixed_units() {
+        let units = vec![
+            "sdtab-web.service".to_string(),
+            "sdtab-backup.service".to_string(),
+            "sdtab-backup.timer".to_string(),
+        ];
+        with_busctl(interfaces, || {
+            let list = Dbus.show(&units, crate::list::PROPERTIES).unwrap();
+            assert_eq!(list.get("sdtab-web.service", "ActiveState"), Some("active"));
+            assert_eq!(list.get("sdtab-web.service", "NextElapseUSecRealtime"), None);
+            assert!(list.get("sdtab-backup.timer", "NextElapseUSecRealtime").is_some_and(|t| t.starts_with("Mon 2026-03-02")));
+
+            let status = Dbus.show(&units[1..], crate::status::PROPERTIES).unwrap();
+            assert_eq!(status.get("sdtab-backup.service", "MainPID"), Some("4242"));
+            assert_eq!(status.get("sdtab-backup.service", "ExecMainStartTimestamp"), Some(""));
+            assert_eq!(status.get("sdtab-backup.service", "WorkingDirectory"), Some("/srv/web"));
+            assert_eq!(status.get("sdtab-backup.timer", "Result"), Some("success"));
+            assert_eq!(status.get("sdtab-backup.timer", "MainPID"), None);
+            assert_eq!(status.get("sdtab-backup.timer", "ExecStart"), None);
+        });
+    }
+
+    #[test]
+    fn jobs_are_waited_for_and_failures_reported() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        let polls = Rc::new(Cell::new(0));
+        let fake = move |args: &[&str]| -> Result<String> {
+            recorded.borrow_mut().push(args.join(" "));
+            match args {
+                [.., "StartUnit", "ss", _, "replace"] => Ok(json!({ "type": "o", "data": ["/org/freedesktop/systemd1/job/7"] }).to_string()),
+                ["get-property", _, "/org/freedesktop/systemd1/job/7", ..] => {
+                    polls.set(polls.get() + 1);
+                    if polls.get() == 1 {
+                        Ok("s \"running\"".to_string())
+                    } else {
+                        bail!("Unknown object '/org/freedesktop/systemd1/job/7'")
+                    }
+                }
+                [.., "ListUnitsByNames", "as", _, unit] => Ok(listing(&[unit], "failed")),
+                _ => Ok(String::new()),
+            }
+        };
+        let err = with_busctl(fake, || Dbus.enable_and_start("sdtab-web.service")).unwrap_err();
+        assert!(err.to_string().starts_with("sdtab-web.service failed to start"), "{}", err);
+        let calls = calls.borrow();
+        let manager = format!("call {} {} {}", DESTINATION, MANAGER_PATH, MANAGER);
+        assert_eq!(calls[0], format!("{} EnableUnitFiles asbb 1 sdtab-web.service false false", manager));
+        // Polled until the job object was gone, then checked the unit.
+        assert_eq!(calls.iter().filter(|c| c.starts_with("get-property")).count(), 2);
+        assert!(calls.last().unwrap().contains("ListUnitsByNames"));
+    }
+
+    #[test]
+    fn picks_interface_by_unit_type() {
+        assert_eq!(type_interface("sdtab-backup.timer"), "Timer");
+        assert_eq!(type_interface("sdtab-web@blue.service"), "Service");
+        assert!(is_timestamp("NextElapseUSecRealtime"));
+        assert!(!is_timestamp("ActiveState"));
+    }
+}
@@ -0,0 +1,375 @@
+//! `sdtab deploy` — run a service's build steps, restart it, and roll back
+//! if it falls over.
+//!
+//! Steps run through `sh -c` in the service's workdir with the same
+//! environment the unit gets (global env file, `env_file`, `--env`). After the
+//! restart the service is watched for a grace period; if it enters `failed`
+//! the rollback steps run and the service is restarted on the restored tree.
+//! Every deploy is appended to `~/.local/state/sdtab/deploy/<name>.jsonl`.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::unit_file::UnitFile;
+use crate::{bluegreen, init, parse_unit, systemctl, unit};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Ok,
+    /// A deploy step failed; the service was not restarted.
+    StepFailed,
+    /// The service failed after the restart and had no rollback steps.
+    Failed,
+    /// The service failed after the restart; rollback steps ran and it was restarted.
+    RolledBack,
+    /// The service failed after the restart and a rollback step failed too.
+    RollbackFailed,
+}
+
+impl Outcome {
+    pub fn label(self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::StepFailed => "step-failed",
+            Outcome::Failed => "failed",
+            Outcome::RolledBack => "rolled-back",
+            Outcome::RollbackFailed => "rollback-failed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DeployRecord {
+    /// Unix timestamp (seconds) when the deploy started.
+    pub started: u64,
+    pub duration_secs: u64,
+    pub outcome: Outcome,
+    /// `git rev-parse --short HEAD` of the workdir after the steps, if it is a repo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_step: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn run(name: &str, grace_secs: u64) -> Result<()> {
+    let unit_dir = init::unit_dir()?;
+    let dir_path = Path::new(&unit_dir);
+
+    let service_path = dir_path.join(bluegreen::definition_filename(name));
+    let timer_path = dir_path.join(unit::timer_filename(name));
+
+    if !service_path.exists() {
+        bail!("'{}' not found.", name);
+    }
+    if timer_path.exists() {
+        bail!("'{}' is a timer; only services can be deployed.", name);
+    }
+
+    let service = UnitFile::load_effective(&service_path)?;
+    let global_env_path = init::global_env_path().unwrap_or_default();
+    let parsed = parse_unit::parse_unit_file(name, &service, None, &global_env_path);
+    if parsed.deploy_steps.is_empty() {
+        bail!(
+            "'{}' has no deploy steps. Add deploy_steps in the sdtab file, or use: sdtab restart {}",
+            name,
+            name
+        );
+    }
+
+    let env = deploy_env(&parsed, &global_env_path);
+    let start = Instant::now();
+    let mut record = DeployRecord {
+        started: now_secs(),
+        duration_secs: 0,
+        outcome: Outcome::Ok,
+        revision: None,
+        failed_step: None,
+        error: None,
+    };
+
+    let mut result = Ok(());
+    for step in &parsed.deploy_steps {
+        println!("→ {}", step);
+        if let Err(e) = run_step(step, &parsed.workdir, &env) {
+            record.outcome = Outcome::StepFailed;
+            record.failed_step = Some(step.clone());
+            result = Err(e);
+            break;
+        }
+    }
+    record.revision = git_revision(&parsed.workdir);
+
+    if result.is_ok() {
+        println!("→ restarting {}", name);
+        if let Err(e) = restart_and_watch(&parsed, Duration::from_secs(grace_secs)) {
+            eprintln!("✗ {}", e);
+            record.outcome = rollback(&parsed, &env, &mut record.failed_step);
+            result = Err(e);
+        }
+    }
+
+    record.duration_secs = start.elapsed().as_secs();
+    if let Err(ref e) = result {
+        record.error = Some(e.to_string());
+    }
+    if let Err(e) = append_history(name, &record) {
+        eprintln!("Warning: failed to record deploy history: {}", e);
+    }
+
+    match record.outcome {
+        Outcome::Ok => {
+            println!("Deployed '{}' in {}s.", name, record.duration_secs);
+            Ok(())
+        }
+        Outcome::StepFailed => bail!(
+            "Deploy of '{}' stopped at '{}'; the service was not restarted.",
+            name,
+            record.failed_step.as_deref().unwrap_or("")
+        ),
+        Outcome::RolledBack => bail!("Deploy of '{}' failed and was rolled back.", name),
+        Outcome::Failed => bail!(
+            "Deploy of '{}' failed and no rollback steps are configured. Check: sdtab logs {}",
+            name,
+            name
+        ),
+        Outcome::RollbackFailed => bail!(
+            "Deploy of '{}' failed and rollback step '{}' failed too. Check: sdtab logs {}",
+            name,
+            record.failed_step.as_deref().unwrap_or(""),
+            name
+        ),
+    }
+}
+
+/// Blue/green services switch slots (which keeps the old slot on failure);
+/// the rest are restarted and must not enter `failed` during `grace`.
+fn restart_and_watch(parsed: &parse_unit::ParsedUnit, grace: Duration) -> Result<()> {
+    if !parsed.ports.is_empty() {
+        return bluegreen::switch_slots(
+            &parsed.name,
+            &parsed.ports,
+            parsed.health_cmd.as_deref(),
+            parsed.service_type.as_deref(),
+        );
+    }
+    let service_unit = bluegreen::service_unit(&parsed.name);
+    systemctl::restart(&service_unit)?;
+    watch(&service_unit, grace)
+}
+
+fn watch(unit: &str, grace: Duration) -> Result<()> {
+    let deadline = Instant::now() + grace;
+    let units = [unit.to_string()];
+    while Instant::now() < deadline {
+        let props = systemctl::show_many(&units, &["ActiveState", "SubState"]).unwrap_or_default();
+        let active = props.get(unit, "ActiveState").unwrap_or_default();
+        let sub = props.get(unit, "SubState").unwrap_or_default();
+        if active == "failed" || sub == "auto-restart" {
+            bail!("{} failed within {}s of the restart ({})", unit, grace.as_secs(), sub);
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    Ok(())
+}
+
+/// Run the rollback steps and bring the service back on the restored tree.
+/// A blue/green switch never stopped the old slot, so it is not restarted.
+fn rollback(parsed: &parse_unit::ParsedUnit, env: &[(String, String)], failed_step: &mut Option<String>) -> Outcome {
+    if parsed.rollback_steps.is_empty() {
+        return Outcome::Failed;
+    }
+    for step in &parsed.rollback_steps {
+        println!("↩ {}", step);
+        if let Err(e) = run_step(step, &parsed.workdir, env) {
+            eprintln!("✗ {}", e);
+            *failed_step = Some(step.clone());
+            return Outcome::RollbackFailed;
+        }
+    }
+    if parsed.ports.is_empty() {
+        if let Err(e) = systemctl::restart(&bluegreen::service_unit(&parsed.name)) {
+            eprintln!("✗ restart after rollback: {}", e);
+            return Outcome::RollbackFailed;
+        }
+    }
+    Outcome::RolledBack
+}
+
+fn run_step(step: &str, workdir: &str, env: &[(String, String)]) -> Result<()> {
+    let status = Command::new("sh")
+        .args(["-c", step])
+        .current_dir(workdir)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to run '{}'", step))?;
+    if !status.success() {
+        bail!("'{}' exited with {}", step, status);
+    }
+    Ok(())
+}
+
+/// The unit's environment: global env file, then `env_file`, then `--env`,
+/// later entries overriding earlier ones like systemd does.
+fn deploy_env(parsed: &parse_unit::ParsedUnit, global_env_path: &str) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    if let Ok(content) = fs::read_to_string(init::host_path(global_env_path)) {
+        env.extend(parse_env_file(&content));
+    }
+    if let Some(ref path) = parsed.env_file {
+        if let Ok(content) = fs::read_to_string(path) {
+            env.extend(parse_env_file(&content));
+        }
+    }
+    env.extend(parse_env_file(&parsed.env.join("\n")));
+    env
+}
+
+/// Parse `KEY=VALUE` lines, skipping comments and stripping one level of quotes.
+fn parse_env_file(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| {
+            let l = l.strip_prefix("export ").unwrap_or(l);
+            let (key, val) = l.split_once('=')?;
+            let val = val.trim();
+            let val = val
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| val.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(val);
+            Some((key.trim().to_string(), val.to_string()))
+        })
+        .collect()
+}
+
+fn git_revision(workdir: &str) -> Option<String> {
+    let out = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(workdir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let rev = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (!rev.is_empty()).then_some(rev)
+}
+
+fn append_history(name: &str, record: &DeployRecord) -> Result<()> {
+    let path = history_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let line = serde_json::to_string(record).context("Failed to serialize deploy record")?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", line).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The last `limit` deploys, newest first. Unreadable lines are skipped.
+pub fn load_history(name: &str, limit: usize) -> Result<Vec<DeployRecord>> {
+    let path = history_path(name)?;
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    Ok(parse_history(&content, limit))
+}
+
+fn parse_history(content: &str, limit: usize) -> Vec<DeployRecord> {
+    let mut records: Vec<DeployRecord> = content
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect();
+    records.reverse();
+    records.truncate(limit);
+    records
+}
+
+fn history_path(name: &str) -> Result<PathBuf> {
+    let state_dir = init::state_dir()?;
+    Ok(Path::new(&state_dir).join("deploy").join(format!("{}.jsonl", name)))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_env_file_handles_quotes_and_comments() {
+        let env = parse_env_file("# comment\nFOO=bar\nexport QUOTED=\"a b\"\nSINGLE='x'\n\nNOEQ\n");
+        assert_eq!(
+            env,
+            vec![
+                ("FOO".to_string(), "bar".to_string()),
+                ("QUOTED".to_string(), "a b".to_string()),
+                ("SINGLE".to_string(), "x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn run_step_uses_workdir_and_env() {
+        let dir = fs::canonicalize(std::env::temp_dir()).unwrap();
+        let dir = dir.to_str().unwrap();
+        let env = vec![("DEPLOY_TEST".to_string(), "1".to_string())];
+        let step = format!("test \"$DEPLOY_TEST\" = 1 && test \"$(pwd -P)\" = '{}'", dir);
+        assert!(run_step(&step, dir, &env).is_ok());
+        let err = run_step("exit 4", dir, &env).unwrap_err();
+        assert!(err.to_string().contains("exit 4"));
+    }
+
+    #[test]
+    fn history_is_newest_first_and_skips_garbage() {
+        let old = DeployRecord {
+            started: 100,
+            duration_secs: 5,
+            outcome: Outcome::Ok,
+            revision: Some("abc123".to_string()),
+            failed_step: None,
+            error: None,
+        };
+        let new = DeployRecord {
+            started: 200,
+            duration_secs: 9,
+            outcome: Outcome::RolledBack,
+            revision: None,
+            failed_step: None,
+            error: Some("web failed".to_string()),
+        };
+        let content = format!(
+            "{}\nnot json\n{}\n",
+            serde_json::to_string(&old).unwrap(),
+            serde_json::to_string(&new).unwrap()
+        );
+        let records = parse_history(&content, 10);
+        assert_eq!(records, vec![new, old]);
+        assert_eq!(parse_history(&content, 1).len(), 1);
+        assert!(content.contains("\"outcome\":\"rolled-back\""));
+    }
+}
@@ -0,0 +1,124 @@
+//! Line-based unified diff, for previewing unit file changes.
+//!
+//! Unit files are a few dozen lines, so a plain LCS table is plenty.
+
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// One step of the edit script, with the positions in both inputs at that point.
+struct Op<'a> {
+    kind: Kind,
+    old: usize,
+    new: usize,
+    line: &'a str,
+}
+
+/// `diff -u` style output turning `old` into `new`; empty when they are equal.
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    if a == b {
+        return String::new();
+    }
+    let ops = edit_script(&a, &b);
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].kind != Kind::Equal).collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &k in &changes {
+        let start = k.saturating_sub(CONTEXT);
+        let end = (k + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (start, end) in hunks {
+        let ops = &ops[start..end];
+        let old_count = ops.iter().filter(|op| op.kind != Kind::Insert).count();
+        let new_count = ops.iter().filter(|op| op.kind != Kind::Delete).count();
+        // An empty side is addressed by the line before it.
+        let old_start = ops[0].old + usize::from(old_count > 0);
+        let new_start = ops[0].new + usize::from(new_count > 0);
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_count, new_start, new_count));
+        for op in ops {
+            let sign = match op.kind {
+                Kind::Equal => ' ',
+                Kind::Delete => '-',
+                Kind::Insert => '+',
+            };
+            out.push(sign);
+            out.push_str(op.line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn edit_script<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<Op<'a>> {
+    let (n, m) = (a.len(), b.len());
+    // lcs[i][j]: length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a[i] == b[j] {
+            ops.push(Op { kind: Kind::Equal, old: i, new: j, line: a[i] });
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op { kind: Kind::Delete, old: i, new: j, line: a[i] });
+            i += 1;
+        } else {
+            ops.push(Op { kind: Kind::Insert, old: i, new: j, line: b[j] });
+            j += 1;
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_inputs_have_no_diff() {
+        assert_eq!(unified("a\nb\n", "a\nb\n", "a", "b"), "");
+    }
+
+    #[test]
+    fn changes_get_context_and_separate_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\nTWO\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        assert_eq!(
+            unified(old, new, "a/x", "b/x"),
+            "--- a/x\n+++ b/x\n\
+             @@ -1,5 +1,5 @@\n 1\n-2\n+TWO\n 3\n 4\n 5\n\
+             @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n"
+        );
+    }
+
+    #[test]
+    fn new_and_deleted_files() {
+        assert_eq!(unified("", "a\nb\n", "/dev/null", "b/x"), "--- /dev/null\n+++ b/x\n@@ -0,0 +1,2 @@\n+a\n+b\n");
+        assert_eq!(unified("a\n", "", "a/x", "/dev/null"), "--- a/x\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-a\n");
+    }
+}
@@ -4,7 +4,7 @@ use std::process::Command;
 
 use anyhow::Result;
 
-use crate::{config, init, parse_unit, systemctl, unit};
+use crate::{backend, config, init, logs, parse_unit, systemctl, unit};
 
 pub fn run() -> Result<()> {
     let color = std::io::stdout().is_terminal();
@@ -13,16 +13,34 @@ pub fn run() -> Result<()> {
 
     println!("{}", heading("sdtab doctor", color));
 
-    // 1. linger
-    match check_linger() {
-        Ok(true) => ok_line("linger enabled", color),
-        Ok(false) => {
-            warn_line("linger not enabled — run `sdtab init` (or `loginctl enable-linger $USER`)", color);
-            warnings += 1;
+    let backend_kind = backend::kind().unwrap_or_else(|_| "cli".to_string());
+
+    // 1. linger (user mode) / privileges (system mode)
+    if init::system() {
+        match effective_uid() {
+            Ok(0) => ok_line("running as root", color),
+            Ok(uid) => {
+                err_line(&format!("--system needs root (running as uid {}) — use sudo", uid), color);
+                errors += 1;
+            }
+            Err(e) => {
+                warn_line(&format!("could not check privileges: {}", e), color);
+                warnings += 1;
+            }
         }
-        Err(e) => {
-            warn_line(&format!("could not check linger: {}", e), color);
-            warnings += 1;
+    } else if backend_kind == "crontab" {
+        info_line("linger: not needed with the crontab backend", color);
+    } else {
+        match check_linger() {
+            Ok(true) => ok_line("linger enabled", color),
+            Ok(false) => {
+                warn_line("linger not enabled — run `sdtab init` (or `loginctl enable-linger $USER`)", color);
+                warnings += 1;
+            }
+            Err(e) => {
+                warn_line(&format!("could not check linger: {}", e), color);
+                warnings += 1;
+            }
         }
     }
 
@@ -35,20 +53,39 @@ pub fn run() -> Result<()> {
         errors += 1;
     }
 
-    // 3. systemctl --user responding
-    match Command::new("systemctl").args(["--user", "is-system-running"]).output() {
+    // 3. systemctl responding; without a user manager, fall back to crontab
+    let scope = systemctl::scope_flag();
+    match Command::new("systemctl").args([scope, "is-system-running"]).output() {
+        Ok(out) if !init::system() && manager_unreachable(&out) => {
+            let reason = String::from_utf8_lossy(&out.stderr).trim().to_string();
+            if backend_kind == "crontab" {
+                info_line("systemctl --user unavailable; timers run from crontab (backend = crontab)", color);
+            } else if switch_to_crontab()? {
+                warn_line(
+                    &format!(
+                        "systemctl --user unavailable ({}) — switched to the crontab backend; only timers can run",
+                        if reason.is_empty() { "offline" } else { &reason }
+                    ),
+                    color,
+                );
+                warnings += 1;
+            } else {
+                err_line(&format!("systemctl --user unavailable: {}", reason), color);
+                errors += 1;
+            }
+        }
         Ok(out) => {
             let state = String::from_utf8_lossy(&out.stdout).trim().to_string();
             // degraded is common on user instance; accept anything except a failure to run
             if out.status.code().is_some() {
-                ok_line(&format!("systemctl --user responding ({})", state), color);
+                ok_line(&format!("systemctl {} responding ({})", scope, state), color);
             } else {
-                warn_line("systemctl --user returned no status", color);
+                warn_line(&format!("systemctl {} returned no status", scope), color);
                 warnings += 1;
             }
         }
         Err(e) => {
-            err_line(&format!("systemctl --user not responding: {}", e), color);
+            err_line(&format!("systemctl {} not responding: {}", scope, e), color);
             errors += 1;
         }
     }
@@ -64,12 +101,15 @@ pub fn run() -> Result<()> {
 
     if let Some(ref _url) = cfg.notify.slack_webhook {
         ok_line("slack webhook: configured", color);
-        let template = format!("{}/sdtab-notify@.service", unit_dir);
+        let template = format!("{}/{}", unit_dir, unit::notify_template_filename());
         if Path::new(&template).exists() {
-            ok_line("notify template sdtab-notify@.service present", color);
+            ok_line(&format!("notify template {} present", unit::notify_template_filename()), color);
         } else {
             warn_line(
-                "webhook configured but sdtab-notify@.service is missing — re-run `sdtab init`",
+                &format!(
+                    "webhook configured but {} is missing — re-run `sdtab init`",
+                    unit::notify_template_filename()
+                ),
                 color,
             );
             warnings += 1;
@@ -80,19 +120,10 @@ pub fn run() -> Result<()> {
 
     // 5. failed units
     let units = parse_unit::scan_all_units().unwrap_or_default();
-    let mut failed: Vec<String> = Vec::new();
-    for u in &units {
-        let service_unit = unit::service_filename(&u.name);
-        let timer_unit = unit::timer_filename(&u.name);
-        let svc_state =
-            systemctl::show_property(&service_unit, "ActiveState").unwrap_or_default();
-        let timer_state =
-            systemctl::show_property(&timer_unit, "ActiveState").unwrap_or_default();
-        if svc_state == "failed" || timer_state == "failed" {
-            let which = if svc_state == "failed" { "service" } else { "timer" };
-            failed.push(format!("{} ({}: failed)", u.name, which));
-        }
-    }
+    let failed: Vec<String> = logs::failed_units(&units)
+        .into_iter()
+        .map(|(name, which)| format!("{} ({}: failed)", name, which))
+        .collect();
 
     if units.is_empty() {
         info_line("no sdtab-managed units yet", color);
@@ -135,6 +166,34 @@ fn check_linger() -> Result<bool> {
     Ok(val == "yes")
 }
 
+/// `is-system-running` couldn't reach the manager at all (as opposed to
+/// reporting a state such as `degraded`).
+fn manager_unreachable(out: &std::process::Output) -> bool {
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    stdout.trim() == "offline" || stderr.contains("Failed to connect")
+}
+
+/// Set `backend = "crontab"` in config.toml when nothing else was chosen and
+/// `crontab` is installed. Returns whether it switched.
+fn switch_to_crontab() -> Result<bool> {
+    let mut cfg = config::load()?;
+    if cfg.backend.is_some() || std::env::var_os("SDTAB_BACKEND").is_some() {
+        return Ok(false);
+    }
+    if Command::new("crontab").arg("-l").output().is_err() {
+        return Ok(false);
+    }
+    cfg.backend = Some("crontab".to_string());
+    config::save(&cfg)?;
+    Ok(true)
+}
+
+fn effective_uid() -> Result<u32> {
+    let out = Command::new("id").arg("-u").output()?;
+    Ok(String::from_utf8_lossy(&out.stdout).trim().parse()?)
+}
+
 fn heading(text: &str, color: bool) -> String {
     if color {
         format!("\x1b[1m{}\x1b[0m", text)
@@ -0,0 +1,336 @@
+//! `sdtab drift`: how the installed units differ from what `apply` last
+//! wrote, e.g. after a hand edit on a production host.
+//!
+//! Every apply records the Sdtabfile it applied (its files and their hash,
+//! the resolved entries and the rendered unit files) as
+//! `<state dir>/applied/<key>.json`, one record per set of files, so
+//! projects applied separately keep their own baseline.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::sdtabfile::Sdtabfile;
+use crate::{apply, health, init, parse_unit, plan, unit};
+
+#[derive(Serialize, Deserialize)]
+struct Applied {
+    /// Sdtabfiles applied, in load order
+    files: Vec<String>,
+    /// [`plan::hash`] of their contents at the time
+    source_hash: String,
+    /// Unix timestamp (seconds) of the apply
+    applied: u64,
+    desired: plan::Desired,
+    /// Unit files apply wrote, by file name, and the drop-ins in place then
+    /// (`<unit>.d/<name>.conf`)
+    rendered: BTreeMap<String, String>,
+}
+
+enum Drift {
+    /// Unit files differ from what apply wrote: settings applied → now,
+    /// and the files that changed
+    Edited { changes: Vec<plan::Change>, files: Vec<String> },
+    /// Applied, but gone from the host
+    Removed,
+    /// On the host, but not in any applied Sdtabfile
+    Unmanaged,
+}
+
+struct Finding {
+    name: String,
+    unit_type: &'static str,
+    drift: Drift,
+}
+
+/// Remember `sdtabfile` as the state the host should be in.
+pub fn record(sdtabfile: &Sdtabfile) -> Result<()> {
+    let mut rendered = BTreeMap::new();
+    for name in sdtabfile.timers.keys() {
+        rendered.extend(apply::entry_files(sdtabfile, name, &parse_unit::UnitType::Timer)?);
+    }
+    for name in sdtabfile.services.keys() {
+        rendered.extend(apply::entry_files(sdtabfile, name, &parse_unit::UnitType::Service)?);
+    }
+    // Drop-ins in place at apply time are part of the baseline.
+    let unit_dir = init::unit_dir()?;
+    let names: Vec<String> = rendered.keys().cloned().collect();
+    for file_name in names {
+        rendered.extend(unit_files_on_disk(&unit_dir, &file_name).into_iter().filter(|(f, _)| f != &file_name));
+    }
+    let applied = Applied {
+        files: sdtabfile.files.clone(),
+        source_hash: source_hash(&sdtabfile.files),
+        applied: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        desired: plan::Desired::from_sdtabfile(sdtabfile),
+        rendered,
+    };
+
+    let dir = records_dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let key = plan::hash(sdtabfile.files.iter().map(|f| f.as_bytes()));
+    let path = dir.join(format!("{}.json", key));
+    let text = serde_json::to_string_pretty(&applied).context("Failed to serialize applied state")?;
+    fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn run() -> Result<()> {
+    let records = load_records()?;
+    if records.is_empty() {
+        bail!("Nothing to compare with: drift is measured against what sdtab apply last wrote");
+    }
+    for record in &records {
+        let files: Vec<String> = record.files.iter().map(|f| apply::display_path(f)).collect();
+        println!("Applied {} ({})", files.join(", "), health::format_ago(record.applied));
+    }
+    println!();
+
+    let findings = check(&records)?;
+    for finding in &findings {
+        match &finding.drift {
+            Drift::Edited { changes, files } => {
+                println!("  ~ {} ({}): edited since apply", finding.name, finding.unit_type);
+                for change in changes {
+                    println!("      {}: {} → {}", change.field, change.old, change.new);
+                }
+                if changes.is_empty() {
+                    println!("      {} changed, no setting differs", files.join(", "));
+                }
+            }
+            Drift::Removed => println!("  - {} ({}): removed since apply", finding.name, finding.unit_type),
+            Drift::Unmanaged => println!(
+                "  + {} ({}): not in any applied Sdtabfile (added with sdtab add?)",
+                finding.name, finding.unit_type
+            ),
+        }
+    }
+
+    // Not drift on the host, but worth knowing before re-applying.
+    for record in &records {
+        if source_hash(&record.files) != record.source_hash {
+            let files: Vec<String> = record.files.iter().map(|f| apply::display_path(f)).collect();
+            println!("Note: {} changed since it was applied", files.join(", "));
+        }
+    }
+
+    if findings.is_empty() {
+        let count: usize = records.iter().map(|r| r.desired.timers.len() + r.desired.services.len()).sum();
+        println!("No drift: {} unit(s) match the last apply.", count);
+        return Ok(());
+    }
+    println!();
+    println!(
+        "Drift: {} unit(s) differ from the last apply. Re-apply, or bring the change into the Sdtabfile.",
+        findings.len()
+    );
+    std::process::exit(1);
+}
+
+/// Compare the host with the recorded applies.
+fn check(records: &[Applied]) -> Result<Vec<Finding>> {
+    let unit_dir = init::unit_dir()?;
+    let current_units = parse_unit::scan_all_units()?;
+    let current: BTreeMap<&str, &parse_unit::ParsedUnit> =
+        current_units.iter().map(|u| (u.name.as_str(), u)).collect();
+
+    let mut findings = Vec::new();
+    let mut applied_names = BTreeSet::new();
+    // Newest first: a unit that moved between files counts once, as last applied.
+    for record in records.iter().rev() {
+        let sdtabfile = Sdtabfile {
+            timers: record.desired.timers.clone(),
+            services: record.desired.services.clone(),
+            ..Default::default()
+        };
+        let entries = sdtabfile
+            .timers
+            .keys()
+            .map(|name| (name, "timer"))
+            .chain(sdtabfile.services.keys().map(|name| (name, "service")));
+        for (name, unit_type) in entries {
+            if !applied_names.insert(name.clone()) {
+                continue;
+            }
+            let Some(unit) = current.get(name.as_str()) else {
+                findings.push(Finding { name: name.clone(), unit_type, drift: Drift::Removed });
+                continue;
+            };
+            // Always compare settings too: a drop-in can change one without
+            // touching the files apply wrote.
+            let changes: Vec<plan::Change> = apply::field_changes(&sdtabfile, name, unit)
+                .into_iter()
+                .map(|c| plan::Change { field: c.field, old: c.new, new: c.old })
+                .collect();
+            let applied: BTreeMap<&String, &String> =
+                record.rendered.iter().filter(|(file, _)| belongs_to(file, name)).collect();
+            let on_disk = entry_files_on_disk(&unit_dir, name);
+            let files: Vec<String> = applied
+                .keys()
+                .map(|file| file.to_string())
+                .chain(on_disk.keys().cloned())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .filter(|file| applied.get(file).copied() != on_disk.get(file))
+                .collect();
+            if !files.is_empty() || !changes.is_empty() {
+                findings.push(Finding { name: name.clone(), unit_type, drift: Drift::Edited { changes, files } });
+            }
+        }
+    }
+    for unit in &current_units {
+        if !applied_names.contains(&unit.name) {
+            findings.push(Finding { name: unit.name.clone(), unit_type: unit.unit_type.label(), drift: Drift::Unmanaged });
+        }
+    }
+    findings.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(findings)
+}
+
+/// The files apply may write for entry `name`.
+fn entry_file_names(name: &str) -> [String; 5] {
+    [
+        unit::service_filename(name),
+        unit::timer_filename(name),
+        unit::template_filename(name),
+        unit::health_service_filename(name),
+        unit::health_timer_filename(name),
+    ]
+}
+
+/// Whether `file`, a recorded file or drop-in (`<unit>.d/<name>.conf`), is
+/// one of entry `name`'s.
+fn belongs_to(file: &str, name: &str) -> bool {
+    let unit = file.split_once(".d/").map_or(file, |(unit, _)| unit);
+    entry_file_names(name).iter().any(|f| f == unit)
+}
+
+/// Entry `name`'s unit files and their drop-ins as they are on the host,
+/// keyed like [`Applied::rendered`].
+fn entry_files_on_disk(unit_dir: &str, name: &str) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    for file_name in entry_file_names(name) {
+        files.extend(unit_files_on_disk(unit_dir, &file_name));
+    }
+    files
+}
+
+/// `file_name` and the `.conf` drop-ins in `<file_name>.d/`, if they exist.
+fn unit_files_on_disk(unit_dir: &str, file_name: &str) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    if let Ok(content) = fs::read_to_string(Path::new(unit_dir).join(file_name)) {
+        files.insert(file_name.to_string(), content);
+    }
+    let dropin_dir = format!("{}.d", file_name);
+    if let Ok(entries) = fs::read_dir(Path::new(unit_dir).join(&dropin_dir)) {
+        for entry in entries.flatten() {
+            let conf = entry.file_name().to_string_lossy().to_string();
+            if !conf.ends_with(".conf") {
+                continue;
+            }
+            if let Ok(content) = fs::read_to_string(entry.path()) {
+                files.insert(format!("{}/{}", dropin_dir, conf), content);
+            }
+        }
+    }
+    files
+}
+
+fn source_hash(files: &[String]) -> String {
+    let contents: Vec<Vec<u8>> = files.iter().map(|f| fs::read(f).unwrap_or_default()).collect();
+    plan::hash(files.iter().map(|f| f.as_bytes()).chain(contents.iter().map(Vec::as_slice)))
+}
+
+fn load_records() -> Result<Vec<Applied>> {
+    let dir = records_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut records = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let content = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        records.push(
+            serde_json::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?,
+        );
+    }
+    records.sort_by_key(|record: &Applied| record.applied);
+    Ok(records)
+}
+
+fn records_dir() -> Result<PathBuf> {
+    Ok(Path::new(&init::state_dir()?).join("applied"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::backend;
+    use crate::fake::FakeBackend;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn reports_edits_removals_and_units_added_outside_apply() {
+        let home = TestDir::new("drift");
+        let unit_dir = home.join(".config/systemd/user");
+        fs::create_dir_all(&unit_dir).unwrap();
+        let file = home.join("Sdtabfile.toml");
+        fs::write(
+            &file,
+            "[timers.backup]\nschedule = \"@daily\"\ncommand = \"./backup.sh\"\nworkdir = \"/tmp\"\nno_notify = true\n\n\
+             [timers.report]\nschedule = \"@hourly\"\ncommand = \"./report.sh\"\nworkdir = \"/tmp\"\nno_notify = true\n\n\
+             [timers.nightly]\nschedule = \"@daily\"\ncommand = \"./nightly.sh\"\nworkdir = \"/tmp\"\nno_notify = true\n",
+        )
+        .unwrap();
+        let fake = Rc::new(FakeBackend::default());
+        let findings = init::with_home(&home, || {
+            backend::with_backend(fake.clone(), || {
+                apply::run(file.to_str().unwrap(), false, false, false, false, None).unwrap();
+                assert!(check(&load_records().unwrap()).unwrap().is_empty());
+
+                // Hand edit, a removal and an `sdtab add` behind apply's back.
+                let service = unit_dir.join("sdtab-backup.service");
+                let edited = fs::read_to_string(&service).unwrap().replace("./backup.sh", "./backup.sh --fast");
+                fs::write(&service, edited).unwrap();
+                fs::remove_file(unit_dir.join("sdtab-report.service")).unwrap();
+                fs::remove_file(unit_dir.join("sdtab-report.timer")).unwrap();
+                fs::copy(unit_dir.join("sdtab-backup.timer"), unit_dir.join("sdtab-adhoc.timer")).unwrap();
+                fs::write(unit_dir.join("sdtab-adhoc.service"), fs::read_to_string(&service).unwrap()).unwrap();
+                // Drop-ins change a unit without touching its files.
+                fs::create_dir_all(unit_dir.join("sdtab-nightly.service.d")).unwrap();
+                fs::write(unit_dir.join("sdtab-nightly.service.d/limit.conf"), "[Service]\nMemoryMax=1G\n").unwrap();
+                check(&load_records().unwrap()).unwrap()
+            })
+        });
+
+        let summary: Vec<(&str, &str)> = findings
+            .iter()
+            .map(|f| {
+                let kind = match f.drift {
+                    Drift::Edited { .. } => "edited",
+                    Drift::Removed => "removed",
+                    Drift::Unmanaged => "unmanaged",
+                };
+                (f.name.as_str(), kind)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("adhoc", "unmanaged"), ("backup", "edited"), ("nightly", "edited"), ("report", "removed")]
+        );
+        let Drift::Edited { changes, .. } = &findings[1].drift else { unreachable!() };
+        let fields: Vec<(&str, &str, &str)> =
+            changes.iter().map(|c| (c.field.as_str(), c.old.as_str(), c.new.as_str())).collect();
+        assert!(fields.contains(&("command", "\"./backup.sh\"", "\"./backup.sh --fast\"")), "{:?}", fields);
+        let Drift::Edited { changes, files } = &findings[2].drift else { unreachable!() };
+        assert_eq!(files, &["sdtab-nightly.service.d/limit.conf"]);
+        assert_eq!(changes.iter().map(|c| c.field.as_str()).collect::<Vec<_>>(), ["memory_max"]);
+    }
+}
@@ -4,13 +4,14 @@ use std::process::Command;
 
 use anyhow::{bail, Context, Result};
 
-use crate::{init, systemctl, unit};
+use crate::unit_file::UnitFile;
+use crate::{bluegreen, init, parse_unit, systemctl, unit};
 
 pub fn run(name: &str) -> Result<()> {
     let unit_dir = init::unit_dir()?;
     let dir_path = Path::new(&unit_dir);
 
-    let service_path = dir_path.join(unit::service_filename(name));
+    let service_path = dir_path.join(bluegreen::definition_filename(name));
     let timer_path = dir_path.join(unit::timer_filename(name));
 
     if !service_path.exists() {
@@ -21,15 +22,12 @@ pub fn run(name: &str) -> Result<()> {
 
     let is_timer = timer_path.exists();
 
-    // Save metadata lines before editing so we can restore them if deleted
+    // Save metadata before editing so we can restore keys that get deleted
     let original_content = fs::read_to_string(&service_path)
         .with_context(|| format!("Failed to read {}", service_path.display()))?;
-    let metadata_lines: Vec<&str> = original_content
-        .lines()
-        .filter(|l| l.starts_with("# sdtab:"))
-        .collect();
+    let metadata = metadata_of(&original_content);
 
-    eprintln!("Note: lines starting with '# sdtab:' are metadata used by sdtab list/export.");
+    eprintln!("Note: X-Sdtab-* keys in [Unit] are metadata used by sdtab list/export.");
 
     let mut cmd = Command::new(&editor);
     cmd.arg(&service_path);
@@ -43,37 +41,24 @@ pub fn run(name: &str) -> Result<()> {
     }
 
     // Restore any metadata keys that were removed during editing
-    if !metadata_lines.is_empty() {
-        let edited_content = fs::read_to_string(&service_path)
-            .with_context(|| format!("Failed to read {}", service_path.display()))?;
-
-        // Compare by key (e.g. "# sdtab:cron") to detect partial deletion
-        let edited_keys: Vec<&str> = edited_content
-            .lines()
-            .filter(|l| l.starts_with("# sdtab:"))
-            .filter_map(|l| l.split('=').next())
-            .collect();
-
-        let missing: Vec<&&str> = metadata_lines
-            .iter()
-            .filter(|l| {
-                let key = l.split('=').next().unwrap_or("");
-                !edited_keys.contains(&key)
-            })
-            .collect();
-
-        if !missing.is_empty() {
-            let missing_text: Vec<&str> = missing.iter().map(|l| **l).collect();
-            let restored = format!("{}\n{}", missing_text.join("\n"), edited_content);
-            fs::write(&service_path, &restored)
-                .with_context(|| format!("Failed to write {}", service_path.display()))?;
-            eprintln!("Warning: Restored missing sdtab metadata:");
-            for line in &missing_text {
-                eprintln!("  {}", line);
-            }
-            eprintln!("  These are required for sdtab list/export to work correctly.");
-            eprintln!("  To permanently remove this unit: sdtab remove {}", name);
+    let edited_content = fs::read_to_string(&service_path)
+        .with_context(|| format!("Failed to read {}", service_path.display()))?;
+    let edited_keys: Vec<String> = metadata_of(&edited_content).into_iter().map(|(k, _)| k).collect();
+    let missing: String = metadata
+        .iter()
+        .filter(|(k, _)| !edited_keys.contains(k))
+        .map(|(k, v)| unit::meta_line(k, v))
+        .collect();
+    if !missing.is_empty() {
+        let lines: Vec<&str> = edited_content.lines().collect();
+        fs::write(&service_path, unit::insert_metadata(&lines, &missing))
+            .with_context(|| format!("Failed to write {}", service_path.display()))?;
+        eprintln!("Warning: Restored missing sdtab metadata:");
+        for line in missing.lines() {
+            eprintln!("  {}", line);
         }
+        eprintln!("  These are required for sdtab list/export to work correctly.");
+        eprintln!("  To permanently remove this unit: sdtab remove {}", name);
     }
 
     // Reload daemon after editing
@@ -92,6 +77,10 @@ pub fn run(name: &str) -> Result<()> {
     Ok(())
 }
 
+fn metadata_of(content: &str) -> Vec<(String, String)> {
+    parse_unit::metadata(&UnitFile::parse(content))
+}
+
 /// Touch the systemd timer stamp file to mark "last triggered = now".
 /// This prevents Persistent=true from re-firing after a schedule change.
 fn touch_timer_stamp(name: &str) {
@@ -2,13 +2,13 @@ use std::path::Path;
 
 use anyhow::{bail, Result};
 
-use crate::{init, systemctl, unit};
+use crate::{bluegreen, init, systemctl, unit};
 
 pub fn run(name: &str) -> Result<()> {
     let unit_dir = init::unit_dir()?;
     let dir_path = Path::new(&unit_dir);
 
-    let service_path = dir_path.join(unit::service_filename(name));
+    let service_path = dir_path.join(bluegreen::definition_filename(name));
     let timer_path = dir_path.join(unit::timer_filename(name));
 
     if !service_path.exists() && !timer_path.exists() {
@@ -19,7 +19,7 @@ pub fn run(name: &str) -> Result<()> {
     let (unit_file, kind) = if is_timer {
         (unit::timer_filename(name), "timer")
     } else {
-        (unit::service_filename(name), "service")
+        (bluegreen::service_unit(name), "service")
     };
 
     if let Err(e) = systemctl::enable_and_start(&unit_file) {
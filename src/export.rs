@@ -1,20 +1,70 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::path::Path;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
-use crate::parse_unit;
+use crate::parse_unit::{self, ParsedUnit, UnitType};
 use crate::sdtabfile::{self, Sdtabfile, ServiceEntry, TimerEntry};
+use crate::{config, cron, init, systemctl, unit, unit_file, ExportFormat};
 
-pub fn run(output: Option<&str>) -> Result<()> {
-    let units = parse_unit::scan_all_units()?;
+pub fn run(output: Option<&str>, format: ExportFormat, factor_defaults: bool, project: Option<&str>) -> Result<()> {
+    if factor_defaults && !matches!(format, ExportFormat::Toml) {
+        bail!("--factor-defaults only applies to --format toml");
+    }
+    let mut units = parse_unit::scan_all_units()?;
+    if let Some(project) = project {
+        units.retain(|u| u.project.as_deref() == Some(project));
+    }
+    units.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let text = match format {
+        ExportFormat::Toml => {
+            let disabled = parse_unit::disabled_names(&units);
+            let mut sdtabfile = sdtabfile_of(units, &disabled);
+            sdtabfile.project = project.map(str::to_string);
+            sdtabfile::to_toml(&sdtabfile, factor_defaults).context("Failed to serialize to TOML")?
+        }
+        ExportFormat::Json => serde_json::to_string_pretty(&units).context("Failed to serialize to JSON")? + "\n",
+        ExportFormat::Crontab => {
+            let notify = config::load()?.notify.slack_webhook.is_some();
+            let path = std::env::var("PATH").ok();
+            let (text, warnings) = crontab_of(&units, notify, path.as_deref());
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            text
+        }
+        ExportFormat::Units => {
+            // clap requires -o for this format
+            let dir = output.context("--format units needs -o <dir>")?;
+            return write_units(&units, dir);
+        }
+    };
 
+    match output {
+        Some(path) => {
+            fs::write(path, &text)
+                .with_context(|| format!("Failed to write {}", path))?;
+            println!("Exported to: {}", path);
+        }
+        None => {
+            print!("{}", text);
+        }
+    }
+
+    Ok(())
+}
+
+/// Entries for `units`; those in `disabled` get `enabled = false`.
+fn sdtabfile_of(units: Vec<ParsedUnit>, disabled: &BTreeSet<String>) -> Sdtabfile {
     let mut timers = BTreeMap::new();
     let mut services = BTreeMap::new();
 
     for unit in units {
+        let enabled = !disabled.contains(&unit.name);
         match unit.unit_type {
-            parse_unit::UnitType::Timer => {
+            UnitType::Timer => {
                 let schedule = unit.cron_expr.unwrap_or_else(|| "?".to_string());
                 let description = sdtabfile::description_if_different(&unit.description, &unit.command);
                 timers.insert(
@@ -32,13 +82,20 @@ pub fn run(output: Option<&str>) -> Result<()> {
                         exec_start_pre: unit.exec_start_pre,
                         exec_stop_post: unit.exec_stop_post,
                         log_level_max: unit.log_level_max,
+                        user: unit.user,
+                        group: unit.group,
                         random_delay: unit.random_delay,
                         env: unit.env,
                         no_notify: unit.no_notify,
+                        enabled,
+                        output_file: unit.output_file,
+                        output_max_size: unit.output_max_size,
+                        output_keep: unit.output_keep,
+                        mail_to: unit.mail_to,
                     },
                 );
             }
-            parse_unit::UnitType::Service => {
+            UnitType::Service => {
                 let description = sdtabfile::description_if_different(&unit.description, &unit.command);
                 services.insert(
                     unit.name,
@@ -55,32 +112,305 @@ pub fn run(output: Option<&str>) -> Result<()> {
                         exec_start_pre: unit.exec_start_pre,
                         exec_stop_post: unit.exec_stop_post,
                         log_level_max: unit.log_level_max,
+                        user: unit.user,
+                        group: unit.group,
                         env: unit.env,
                         no_notify: unit.no_notify,
+                        enabled,
+                        service_type: unit.service_type,
+                        pid_file: unit.pid_file,
+                        watchdog: unit.watchdog,
+                        exec_reload: unit.exec_reload,
+                        health_cmd: unit.health_cmd,
+                        health_interval: unit.health_interval,
+                        health_retries: unit.health_retries,
+                        ports: unit.ports,
+                        deploy_steps: unit.deploy_steps,
+                        rollback_steps: unit.rollback_steps,
+                        output_file: unit.output_file,
+                        output_max_size: unit.output_max_size,
+                        output_keep: unit.output_keep,
+                        mail_to: unit.mail_to,
                     },
                 );
             }
         }
     }
 
-    let sdtabfile = Sdtabfile { timers, services };
-    let toml_str = toml::to_string_pretty(&sdtabfile)
-        .context("Failed to serialize to TOML")?;
+    Sdtabfile { timers, services, ..Default::default() }
+}
 
-    match output {
-        Some(path) => {
-            fs::write(path, &toml_str)
-                .with_context(|| format!("Failed to write {}", path))?;
-            println!("Exported to: {}", path);
+/// Render timers as crontab entries. Returns the crontab and a warning for
+/// every unit or option that didn't carry over.
+fn crontab_of(units: &[ParsedUnit], notify: bool, path: Option<&str>) -> (String, Vec<String>) {
+    let mut out = String::from("# Exported by sdtab. Install with: crontab <this file>\n");
+    // sdtab only mails when asked to (--mail-to); cron mails any output by default.
+    out.push_str("MAILTO=\"\"\n");
+    if let Some(path) = path {
+        out.push_str(&format!("PATH={}\n", path));
+    }
+    let mut warnings = Vec::new();
+
+    for unit in units {
+        let job = match cron_job(unit, &unit.command, notify) {
+            Ok(job) => job,
+            Err(reason) => {
+                warnings.push(format!("{}: {}; skipped", unit.name, reason));
+                continue;
+            }
+        };
+        if !job.dropped.is_empty() {
+            warnings.push(format!("{}: {} not exported", unit.name, job.dropped.join(", ")));
         }
-        None => {
-            print!("{}", toml_str);
+        warnings.extend(job.warnings.iter().map(|w| format!("{}: {}", unit.name, w)));
+
+        out.push('\n');
+        out.push_str(&format!("# {}: {}\n", unit.name, unit.description));
+        for line in job.lines() {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    (out, warnings)
+}
+
+/// One timer as a crontab job.
+pub struct CronJob {
+    /// Five cron fields, or `@reboot`.
+    pub schedule: String,
+    /// Shell command, with `%` not yet escaped for crontab.
+    pub command: String,
+    pub mail_to: Option<String>,
+    /// Options cron has no equivalent for.
+    pub dropped: Vec<&'static str>,
+    pub warnings: Vec<String>,
+}
+
+impl CronJob {
+    /// The crontab lines, with `MAILTO=` set around the job when it mails.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(ref addr) = self.mail_to {
+            lines.push(format!("MAILTO={}", addr));
+        }
+        // `%` is a newline in crontab commands
+        lines.push(format!("{} {}", self.schedule, self.command.replace('%', "\\%")));
+        if self.mail_to.is_some() {
+            lines.push("MAILTO=\"\"".to_string());
+        }
+        lines
+    }
+}
+
+/// Map a timer running `exec` (its `ExecStart=` or sdtab command) onto cron.
+/// Errors say why the unit can't be a cron job at all.
+pub fn cron_job(unit: &ParsedUnit, exec: &str, notify: bool) -> std::result::Result<CronJob, String> {
+    if matches!(unit.unit_type, UnitType::Service) {
+        return Err("services have no crontab equivalent".to_string());
+    }
+    let schedule = unit.cron_expr.as_deref().unwrap_or("?");
+    let Some(cron_expr) = cron::to_crontab(schedule) else {
+        return Err(format!("schedule '{}' has no crontab equivalent", schedule));
+    };
+
+    let mut dropped = Vec::new();
+    for (value, label) in [
+        (&unit.memory_max, "memory_max"),
+        (&unit.cpu_quota, "cpu_quota"),
+        (&unit.io_weight, "io_weight"),
+        (&unit.timeout_stop, "timeout_stop"),
+        (&unit.exec_start_pre, "exec_start_pre"),
+        (&unit.exec_stop_post, "exec_stop_post"),
+        (&unit.log_level_max, "log_level_max"),
+        (&unit.user, "user"),
+        (&unit.group, "group"),
+        (&unit.random_delay, "random_delay"),
+    ] {
+        if value.is_some() {
+            dropped.push(label);
+        }
+    }
+    if unit.output_file.is_some() {
+        dropped.push("output rotation");
+    }
+    if notify && !unit.no_notify {
+        dropped.push("failure notification");
+    }
+
+    let mut warnings = Vec::new();
+    let mut command = format!("cd {} && ", shell_quote(&unit.workdir));
+    if let Some(ref file) = unit.env_file {
+        command.push_str(&format!("set -a && . {} && set +a && ", shell_quote(file)));
+    }
+    for assignment in &unit.env {
+        let assignment = assignment.trim_matches('"');
+        if let Some((key, value)) = assignment.split_once('=') {
+            command.push_str(&format!("export {}={} && ", key, shell_quote(value)));
+        }
+    }
+    let (exec, specifiers) = unescape_exec(exec);
+    if !specifiers.is_empty() {
+        warnings.push(format!("systemd specifiers {} are left as-is", specifiers.join(" ")));
+    }
+    if let Some(ref file) = unit.output_file {
+        // In a subshell so the redirect covers every command of `a; b` or `a | b`.
+        command.push_str(&format!("( {} ) >> {} 2>&1", exec, shell_quote(file)));
+        if unit.mail_to.is_some() {
+            warnings.push("output goes to the file, so cron has nothing to mail".to_string());
+        }
+    } else {
+        command.push_str(&exec);
+    }
+
+    Ok(CronJob {
+        schedule: cron_expr,
+        command,
+        mail_to: unit.mail_to.clone(),
+        dropped,
+        warnings,
+    })
+}
+
+/// An `ExecStart=` line as a shell command: `%%` → `%` and `$$` → `$`, the
+/// escapes systemd strips itself. Other specifiers (`%h`, `%n`, …) only mean
+/// something to systemd and are returned so the caller can warn about them.
+pub fn unescape_exec(command: &str) -> (String, Vec<String>) {
+    let mut out = String::new();
+    let mut specifiers = Vec::new();
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some('%') => out.push('%'),
+                Some(spec) => {
+                    out.push('%');
+                    out.push(spec);
+                    specifiers.push(format!("%{}", spec));
+                }
+                None => out.push('%'),
+            }
+        } else if c == '$' && chars.peek() == Some(&'$') {
+            chars.next();
+            out.push('$');
+        } else {
+            out.push(c);
         }
     }
+    (out, specifiers)
+}
+
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+:,@%".contains(c)) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Copy every `sdtab-*` unit file and drop-in directory as-is, plus an
+/// install.sh that copies them into place and enables what is enabled here.
+fn write_units(units: &[ParsedUnit], dir: &str) -> Result<()> {
+    let unit_dir = init::unit_dir()?;
+    let out_dir = Path::new(dir);
+    fs::create_dir_all(out_dir).with_context(|| format!("Failed to create {}", dir))?;
+
+    let mut entries: Vec<_> = fs::read_dir(&unit_dir)
+        .with_context(|| format!("Failed to read {}", unit_dir))?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with(init::prefix()))
+        .collect();
+    entries.sort_by_key(|e| e.file_name());
 
+    let mut warnings = Vec::new();
+    let mut count = 0;
+    for entry in entries {
+        let name = entry.file_name();
+        let target = out_dir.join(&name);
+        if entry.path().is_dir() {
+            fs::create_dir_all(&target)?;
+            for conf in fs::read_dir(entry.path())? {
+                let conf = conf?;
+                fs::copy(conf.path(), target.join(conf.file_name()))
+                    .with_context(|| format!("Failed to copy {}", conf.path().display()))?;
+                warnings.extend(unit_warnings(&name.to_string_lossy(), &fs::read_to_string(conf.path())?));
+            }
+        } else {
+            fs::copy(entry.path(), &target).with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+            warnings.extend(unit_warnings(&name.to_string_lossy(), &fs::read_to_string(entry.path())?));
+            count += 1;
+        }
+    }
+
+    let disabled = parse_unit::disabled_names(units);
+    let mut enable = Vec::new();
+    for parsed in units.iter().filter(|parsed| !disabled.contains(&parsed.name)) {
+        enable.push(parse_unit::primary_unit(&parsed.name, &parsed.unit_type));
+        if Path::new(&unit_dir).join(unit::health_timer_filename(&parsed.name)).exists() {
+            enable.push(unit::health_timer_filename(&parsed.name));
+        }
+    }
+    let script_path = out_dir.join("install.sh");
+    fs::write(&script_path, install_script(&enable))
+        .with_context(|| format!("Failed to write {}", script_path.display()))?;
+    make_executable(&script_path)?;
+
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    println!("Exported {} unit file(s) to: {}", count, dir);
+    println!("Install on the target host with: {}", script_path.display());
     Ok(())
 }
 
+/// Things a unit file needs from the host it was exported from.
+pub fn unit_warnings(file: &str, content: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let parsed = unit_file::UnitFile::parse(content);
+    for (_, key, value) in parsed.entries() {
+        if key.starts_with("Exec")
+            && (unit::is_output_hook(value) || value.contains(" __health-check "))
+        {
+            warnings.push(format!("{}: {}= runs sdtab, which must be installed on the target host", file, key));
+        } else if key == "EnvironmentFile" && !value.starts_with('-') && !value.contains("%i") {
+            warnings.push(format!("{}: needs {} on the target host", file, value));
+        }
+    }
+    warnings
+}
+
+fn install_script(enable: &[String]) -> String {
+    let dest = if init::system() {
+        "/etc/systemd/system"
+    } else {
+        "${XDG_CONFIG_HOME:-$HOME/.config}/systemd/user"
+    };
+    let scope = systemctl::scope_flag();
+    let mut script = format!(
+        "#!/bin/sh\n\
+         # Installs the units exported by `sdtab export --format units`.\n\
+         set -eu\n\
+         src=$(cd \"$(dirname \"$0\")\" && pwd)\n\
+         dest=\"{}\"\n\
+         mkdir -p \"$dest\"\n\
+         cp -R \"$src\"/{}* \"$dest\"/\n\
+         systemctl {} daemon-reload\n",
+        dest,
+        init::prefix(),
+        scope
+    );
+    if !enable.is_empty() {
+        script.push_str(&format!("systemctl {} enable --now {}\n", scope, enable.join(" ")));
+    }
+    script
+}
+
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut perms = fs::metadata(path)?.permissions();
+    perms.set_mode(0o755);
+    fs::set_permissions(path, perms).with_context(|| format!("Failed to chmod {}", path.display()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,14 +442,21 @@ mod tests {
                 exec_start_pre: None,
                 exec_stop_post: None,
                 log_level_max: None,
+                user: None,
+                group: None,
                 random_delay: None,
                 env: vec![],
                 no_notify: false,
+                enabled: true,
+                output_file: None,
+                output_max_size: None,
+                output_keep: None,
+                mail_to: None,
             },
         );
         let sdtabfile = Sdtabfile {
             timers,
-            services: BTreeMap::new(),
+            ..Default::default()
         };
         let toml_str = toml::to_string_pretty(&sdtabfile).unwrap();
         assert!(toml_str.contains("[timers.report]"));
@@ -127,4 +464,112 @@ mod tests {
         assert!(toml_str.contains("description = \"daily report\""));
         assert!(!toml_str.contains("memory_max"));
     }
+
+    fn parsed(name: &str, service: &str) -> ParsedUnit {
+        parse_unit::parse_service_file(name, service, None, "/home/user/.config/sdtab/env")
+    }
+
+    #[test]
+    fn crontab_translates_timers_and_reports_the_rest() {
+        let report = parsed(
+            "report",
+            "\
+[Unit]
+X-Sdtab-Type=timer
+X-Sdtab-Cron=@mon/9:30
+X-Sdtab-Command=date +%%F
+X-Sdtab-Output-File=/tmp/report.log
+Description=[sdtab] report: weekly report
+
+[Service]
+Type=oneshot
+ExecStart=/usr/bin/date +%%F
+WorkingDirectory=/home/user/my project
+Environment=\"GREETING=hello world\"
+MemoryMax=512M
+",
+        );
+        let web = parsed(
+            "web",
+            "\
+[Unit]
+X-Sdtab-Type=service
+Description=[sdtab] web: node app.js
+
+[Service]
+ExecStart=/usr/bin/node app.js
+WorkingDirectory=/srv/web
+",
+        );
+
+        let (crontab, warnings) = crontab_of(&[report, web], true, Some("/usr/bin:/bin"));
+        assert!(crontab.contains("MAILTO=\"\"\nPATH=/usr/bin:/bin\n"));
+        assert!(crontab.contains("# report: weekly report\n"));
+        assert!(crontab.contains(
+            "30 9 * * 1 cd '/home/user/my project' && export GREETING='hello world' && ( date +\\%F ) >> /tmp/report.log 2>&1\n"
+        ));
+        assert!(!crontab.contains("node"));
+        assert_eq!(
+            warnings,
+            vec![
+                "report: memory_max, output rotation, failure notification not exported",
+                "web: services have no crontab equivalent; skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn crontab_env_and_output_cover_compound_commands() {
+        let backup = parsed(
+            "backup",
+            "\
+[Unit]
+X-Sdtab-Type=timer
+X-Sdtab-Cron=0 3 * * *
+X-Sdtab-Command=./dump.sh && gzip -f dump.sql; ./upload.sh
+X-Sdtab-Output-File=/var/log/backup.log
+
+[Service]
+Type=oneshot
+ExecStart=/bin/sh -c \"./dump.sh && gzip -f dump.sql; ./upload.sh\"
+WorkingDirectory=/srv/db
+Environment=\"BUCKET=s3://backups\"
+",
+        );
+        let (crontab, _) = crontab_of(&[backup], false, None);
+        assert!(crontab.contains(
+            "0 3 * * * cd /srv/db && export BUCKET=s3://backups && ( ./dump.sh && gzip -f dump.sql; ./upload.sh ) >> /var/log/backup.log 2>&1\n"
+        ), "{}", crontab);
+    }
+
+    #[test]
+    fn disabled_units_export_as_enabled_false() {
+        let unit = |name: &str| {
+            let service = format!("[Unit]\nX-Sdtab-Type=service\n\n[Service]\nExecStart=./{}\nWorkingDirectory=/srv\n", name);
+            parsed(name, &service)
+        };
+        let disabled = BTreeSet::from(["worker".to_string()]);
+        let toml = sdtabfile::to_toml(&sdtabfile_of(vec![unit("web"), unit("worker")], &disabled), false).unwrap();
+        let file = sdtabfile::parse(&toml, "/", &sdtabfile::Target::default()).unwrap();
+        assert!(file.services["web"].enabled);
+        assert!(!file.services["worker"].enabled);
+        assert_eq!(toml.matches("enabled = false").count(), 1, "{}", toml);
+    }
+
+    #[test]
+    fn unit_warnings_flag_host_dependencies() {
+        let service = "\
+[Service]
+ExecStartPre=-/usr/bin/sdtab __output-rotate report 10M 5 \"/tmp/report.log\"
+EnvironmentFile=-/home/user/.config/sdtab/env
+EnvironmentFile=/home/user/app.env
+";
+        assert_eq!(
+            unit_warnings("sdtab-report.service", service),
+            vec![
+                "sdtab-report.service: ExecStartPre= runs sdtab, which must be installed on the target host",
+                "sdtab-report.service: needs /home/user/app.env on the target host",
+            ]
+        );
+    }
 }
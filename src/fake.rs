@@ -0,0 +1,204 @@
+//! In-memory systemd for tests: tracks which unit files are loaded and each
+//! unit's enabled/active state, and records every call in order.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+use anyhow::{bail, Result};
+
+use crate::backend::Backend;
+use crate::init;
+use crate::systemctl::UnitProperties;
+
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct UnitState {
+    pub enabled: bool,
+    pub active: bool,
+    pub failed: bool,
+}
+
+#[derive(Default)]
+pub struct FakeBackend {
+    /// Unit files present at the last daemon-reload.
+    loaded: RefCell<BTreeSet<String>>,
+    units: RefCell<BTreeMap<String, UnitState>>,
+    calls: RefCell<Vec<String>>,
+    /// Units whose next start/restart fails.
+    fail_next: RefCell<BTreeSet<String>>,
+}
+
+impl FakeBackend {
+    /// Calls so far (`restart sdtab-x.timer`), then forget them.
+    pub fn take_calls(&self) -> Vec<String> {
+        self.calls.take()
+    }
+
+    pub fn state(&self, unit: &str) -> UnitState {
+        self.units.borrow().get(unit).cloned().unwrap_or_default()
+    }
+
+    pub fn set_failed(&self, unit: &str) {
+        let mut units = self.units.borrow_mut();
+        let state = units.entry(unit.to_string()).or_default();
+        state.active = false;
+        state.failed = true;
+    }
+
+    /// Make the next start, restart or enable of `unit` fail.
+    pub fn fail_next(&self, unit: &str) {
+        self.fail_next.borrow_mut().insert(unit.to_string());
+    }
+
+    fn check_failure(&self, unit: &str) -> Result<()> {
+        if self.fail_next.borrow_mut().remove(unit) {
+            bail!("Job for {} failed.", unit);
+        }
+        Ok(())
+    }
+
+    fn record(&self, call: String) {
+        self.calls.borrow_mut().push(call);
+    }
+
+    /// Instances (`sdtab-web@blue.service`) are backed by their own file or
+    /// their template's.
+    fn require_loaded(&self, unit: &str) -> Result<()> {
+        if self.loaded.borrow().contains(unit) {
+            return Ok(());
+        }
+        let file = match unit.split_once('@') {
+            Some((prefix, rest)) => match rest.rsplit_once('.') {
+                Some((_, suffix)) => format!("{}@.{}", prefix, suffix),
+                None => unit.to_string(),
+            },
+            None => unit.to_string(),
+        };
+        if !self.loaded.borrow().contains(&file) {
+            bail!("Unit {} not found.", unit);
+        }
+        Ok(())
+    }
+
+    fn update(&self, unit: &str, f: impl FnOnce(&mut UnitState)) {
+        f(self.units.borrow_mut().entry(unit.to_string()).or_default());
+    }
+}
+
+impl Backend for FakeBackend {
+    fn daemon_reload(&self) -> Result<()> {
+        self.record("daemon-reload".to_string());
+        let dir = init::unit_dir()?;
+        let files = fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        *self.loaded.borrow_mut() = files;
+        Ok(())
+    }
+
+    fn enable_and_start(&self, unit: &str) -> Result<()> {
+        self.record(format!("enable --now {}", unit));
+        self.require_loaded(unit)?;
+        self.check_failure(unit)?;
+        self.update(unit, |s| *s = UnitState { enabled: true, active: true, failed: false });
+        Ok(())
+    }
+
+    fn stop_and_disable(&self, unit: &str) -> Result<()> {
+        self.record(format!("disable --now {}", unit));
+        self.update(unit, |s| {
+            s.enabled = false;
+            s.active = false;
+        });
+        Ok(())
+    }
+
+    fn enable(&self, unit: &str) -> Result<()> {
+        self.record(format!("enable {}", unit));
+        self.require_loaded(unit)?;
+        self.update(unit, |s| s.enabled = true);
+        Ok(())
+    }
+
+    fn disable(&self, unit: &str) -> Result<()> {
+        self.record(format!("disable {}", unit));
+        self.update(unit, |s| s.enabled = false);
+        Ok(())
+    }
+
+    fn stop(&self, unit: &str) -> Result<()> {
+        self.record(format!("stop {}", unit));
+        self.update(unit, |s| s.active = false);
+        Ok(())
+    }
+
+    fn start(&self, unit: &str) -> Result<()> {
+        self.record(format!("start {}", unit));
+        self.require_loaded(unit)?;
+        self.check_failure(unit)?;
+        self.update(unit, |s| {
+            s.active = true;
+            s.failed = false;
+        });
+        Ok(())
+    }
+
+    fn restart(&self, unit: &str) -> Result<()> {
+        self.record(format!("restart {}", unit));
+        self.require_loaded(unit)?;
+        self.check_failure(unit)?;
+        self.update(unit, |s| {
+            s.active = true;
+            s.failed = false;
+        });
+        Ok(())
+    }
+
+    fn reload(&self, unit: &str) -> Result<()> {
+        self.record(format!("reload {}", unit));
+        self.require_loaded(unit)?;
+        Ok(())
+    }
+
+    fn show(&self, units: &[String], properties: &[&str]) -> Result<UnitProperties> {
+        let mut props = UnitProperties::default();
+        for unit in units {
+            let state = self.state(unit);
+            let loaded = self.require_loaded(unit).is_ok();
+            for property in properties {
+                let value = match *property {
+                    "ActiveState" if state.failed => "failed",
+                    "ActiveState" if state.active => "active",
+                    "ActiveState" => "inactive",
+                    "SubState" if state.failed => "failed",
+                    "SubState" if state.active => "running",
+                    "SubState" => "dead",
+                    "UnitFileState" if !loaded => "",
+                    "UnitFileState" if state.enabled => "enabled",
+                    "UnitFileState" => "disabled",
+                    "LoadState" if loaded => "loaded",
+                    "LoadState" => "not-found",
+                    "Result" if state.failed => "exit-code",
+                    "Result" => "success",
+                    "MainPID" => "0",
+                    _ => "",
+                };
+                props.insert(unit, property, value.to_string());
+            }
+        }
+        Ok(props)
+    }
+
+    fn journal_tail(&self, _unit: &str, _lines: u32) -> Vec<String> {
+        vec![]
+    }
+
+    fn next_runs(&self, _on_calendar: &str, _count: u32) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+}
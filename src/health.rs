@@ -0,0 +1,309 @@
+//! Health checks for daemon services.
+//!
+//! A service created with `--health-cmd` gets a companion
+//! `sdtab-health@<name>.timer` that runs `sdtab __health-check <name>` every
+//! interval. Each probe's outcome is recorded in
+//! `~/.local/state/sdtab/health/<name>.json`; after N consecutive failures the
+//! service is restarted and the counter reset. This catches services that hang
+//! without exiting, which `Restart=always` never notices.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::unit_file::UnitFile;
+use crate::{bluegreen, init, parse_unit, systemctl, unit};
+
+/// A probe that hasn't finished after this long counts as a failure.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct HealthState {
+    pub consecutive_failures: u32,
+    /// `None` until the first probe has run.
+    #[serde(default)]
+    pub last_ok: Option<bool>,
+    #[serde(default)]
+    pub last_latency_ms: u64,
+    /// Unix timestamp (seconds) of the last probe.
+    #[serde(default)]
+    pub last_checked: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Restarts triggered by the health check since the state file was created.
+    #[serde(default)]
+    pub restarts: u32,
+}
+
+/// Hidden `__health-check` subcommand, invoked by `sdtab-health@<name>.service`.
+pub fn run_check(name: &str) -> Result<()> {
+    let unit_dir = init::unit_dir()?;
+    let service_path = Path::new(&unit_dir).join(bluegreen::definition_filename(name));
+    let service = UnitFile::load_effective(&service_path)?;
+    let parsed = parse_unit::parse_unit_file(name, &service, None, "");
+    let Some(health_cmd) = parsed.health_cmd else {
+        bail!("'{}' has no health check configured.", name);
+    };
+    let retries = parsed.health_retries.unwrap_or(unit::DEFAULT_HEALTH_RETRIES);
+
+    // A stopped or restarting service is not "unhealthy" — don't count it.
+    let service_unit = bluegreen::service_unit(name);
+    let active = systemctl::show_property(&service_unit, "ActiveState").unwrap_or_default();
+    if active != "active" {
+        println!("skipped: {} is {}", service_unit, active);
+        return Ok(());
+    }
+
+    let mut state = load_state(name)?;
+    // Blue/green services are probed on the live slot's port.
+    let env = if parsed.ports.is_empty() {
+        Vec::new()
+    } else {
+        bluegreen::slot_env(&parsed.ports, &bluegreen::active_slot(name))
+    };
+    let (ok, latency, error) = probe(&health_cmd, &env, PROBE_TIMEOUT);
+    record_probe(&mut state, ok, latency, error, now_secs());
+
+    if ok {
+        println!("ok ({} ms)", state.last_latency_ms);
+    } else {
+        println!(
+            "failed ({}/{}): {}",
+            state.consecutive_failures,
+            retries,
+            state.last_error.as_deref().unwrap_or("")
+        );
+        if state.consecutive_failures >= retries {
+            eprintln!(
+                "{} failed {} consecutive health checks — restarting",
+                name, state.consecutive_failures
+            );
+            // Keep the streak if the restart fails, so the next check retries it.
+            let restarted = systemctl::restart(&service_unit);
+            if restarted.is_ok() {
+                state.consecutive_failures = 0;
+                state.restarts += 1;
+            }
+            save_state(name, &state)?;
+            return restarted;
+        }
+    }
+
+    save_state(name, &state)
+}
+
+/// Fold one probe result into the state.
+fn record_probe(state: &mut HealthState, ok: bool, latency: Duration, error: Option<String>, now: u64) {
+    state.last_ok = Some(ok);
+    state.last_latency_ms = latency.as_millis() as u64;
+    state.last_checked = now;
+    if ok {
+        state.consecutive_failures = 0;
+        state.last_error = None;
+    } else {
+        state.consecutive_failures += 1;
+        state.last_error = error;
+    }
+}
+
+/// Run the probe through `sh -c` with extra `env`, killing it after `timeout`.
+pub fn probe(cmd: &str, env: &[(String, String)], timeout: Duration) -> (bool, Duration, Option<String>) {
+    let start = Instant::now();
+    let mut child = match Command::new("sh")
+        .args(["-c", cmd])
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(c) => c,
+        Err(e) => return (false, start.elapsed(), Some(format!("failed to spawn: {}", e))),
+    };
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                let latency = start.elapsed();
+                if status.success() {
+                    return (true, latency, None);
+                }
+                return (false, latency, Some(format!("exited with {}", status)));
+            }
+            Ok(None) if start.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return (false, start.elapsed(), Some(format!("timed out after {}s", timeout.as_secs())));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(20)),
+            Err(e) => return (false, start.elapsed(), Some(e.to_string())),
+        }
+    }
+}
+
+/// Short status for `sdtab list`: `ok`, `failing 2/3`, or `pending`.
+pub fn summary(state: &HealthState, retries: u32) -> String {
+    match state.last_ok {
+        None => "pending".to_string(),
+        Some(true) => "ok".to_string(),
+        Some(false) => format!("failing {}/{}", state.consecutive_failures, retries),
+    }
+}
+
+/// Write the companion `.service`/`.timer` pair into `dir`.
+/// Does NOT daemon-reload or enable — the caller handles that.
+pub fn write_units(config: &unit::UnitConfig, dir: &Path) -> Result<()> {
+    let sdtab_bin = init::sdtab_bin();
+
+    let service_path = dir.join(unit::health_service_filename(&config.name));
+    fs::write(&service_path, unit::generate_health_service(config, &sdtab_bin))
+        .with_context(|| format!("Failed to write {}", service_path.display()))?;
+
+    let timer_path = dir.join(unit::health_timer_filename(&config.name));
+    fs::write(&timer_path, unit::generate_health_timer(config))
+        .with_context(|| format!("Failed to write {}", timer_path.display()))?;
+    Ok(())
+}
+
+/// The companion units of `name` present in `dir`.
+pub fn unit_files(name: &str, dir: &Path) -> Vec<PathBuf> {
+    [unit::health_service_filename(name), unit::health_timer_filename(name)]
+        .into_iter()
+        .map(|file_name| dir.join(file_name))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Stop the health timer and delete both companion units, if present.
+/// Returns the files removed.
+pub fn remove_units(name: &str, dir: &Path) -> Result<Vec<PathBuf>> {
+    let files = unit_files(name, dir);
+    if files.is_empty() {
+        return Ok(files);
+    }
+    if dir.join(unit::health_timer_filename(name)).exists() && init::root().is_none() {
+        if let Err(e) = systemctl::stop_and_disable(&unit::health_timer_filename(name)) {
+            eprintln!("Warning: failed to disable health timer: {}", e);
+        }
+    }
+    for path in &files {
+        fs::remove_file(path)?;
+    }
+    if let Ok(path) = state_path(name) {
+        let _ = fs::remove_file(path);
+    }
+    Ok(files)
+}
+
+pub fn load_state(name: &str) -> Result<HealthState> {
+    let path = state_path(name)?;
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HealthState::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn save_state(name: &str, state: &HealthState) -> Result<()> {
+    let path = state_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let json = serde_json::to_string_pretty(state).context("Failed to serialize health state")?;
+    fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn state_path(name: &str) -> Result<PathBuf> {
+    let state_dir = init::state_dir()?;
+    Ok(Path::new(&state_dir).join("health").join(format!("{}.json", name)))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Human-friendly age of a unix timestamp, e.g. "12s ago", "5m ago".
+pub fn format_ago(epoch: u64) -> String {
+    let elapsed = now_secs().saturating_sub(epoch);
+    if elapsed < 60 {
+        format!("{}s ago", elapsed)
+    } else if elapsed < 3600 {
+        format!("{}m ago", elapsed / 60)
+    } else if elapsed < 86400 {
+        format!("{}h ago", elapsed / 3600)
+    } else {
+        format!("{}d ago", elapsed / 86400)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_probe_counts_consecutive_failures() {
+        let mut state = HealthState::default();
+        record_probe(&mut state, false, Duration::from_millis(5), Some("exit 1".into()), 100);
+        record_probe(&mut state, false, Duration::from_millis(5), Some("exit 1".into()), 130);
+        assert_eq!(state.consecutive_failures, 2);
+        assert_eq!(state.last_ok, Some(false));
+        assert_eq!(state.last_checked, 130);
+
+        record_probe(&mut state, true, Duration::from_millis(12), None, 160);
+        assert_eq!(state.consecutive_failures, 0);
+        assert_eq!(state.last_latency_ms, 12);
+        assert!(state.last_error.is_none());
+    }
+
+    #[test]
+    fn summary_labels() {
+        let mut state = HealthState::default();
+        assert_eq!(summary(&state, 3), "pending");
+        state.last_ok = Some(true);
+        assert_eq!(summary(&state, 3), "ok");
+        state.last_ok = Some(false);
+        state.consecutive_failures = 2;
+        assert_eq!(summary(&state, 3), "failing 2/3");
+    }
+
+    #[test]
+    fn probe_reports_success_failure_and_timeout() {
+        assert!(probe("true", &[], PROBE_TIMEOUT).0);
+
+        let (ok, _, err) = probe("exit 3", &[], PROBE_TIMEOUT);
+        assert!(!ok);
+        assert!(err.unwrap().contains("3"));
+
+        let (ok, _, err) = probe("sleep 5", &[], Duration::from_millis(100));
+        assert!(!ok);
+        assert!(err.unwrap().contains("timed out"));
+
+        let env = vec![("PORT".to_string(), "3001".to_string())];
+        assert!(probe("test \"$PORT\" = 3001", &env, PROBE_TIMEOUT).0);
+    }
+
+    #[test]
+    fn state_roundtrip_json() {
+        let state = HealthState {
+            consecutive_failures: 1,
+            last_ok: Some(false),
+            last_latency_ms: 42,
+            last_checked: 1_700_000_000,
+            last_error: Some("exited with exit status: 7".into()),
+            restarts: 2,
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        let back: HealthState = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, state);
+    }
+}
@@ -0,0 +1,521 @@
+//! `sdtab import crontab` — turn a `crontab -l` listing into sdtab timers.
+//!
+//! Each job goes through `cron::parse` and the `add` code path (or into an
+//! Sdtabfile with `-o`). cron runs jobs through `/bin/sh -c`, while systemd
+//! runs `ExecStart=` directly, so commands that rely on the shell are wrapped
+//! in `sh -c` and escaped for systemd (`%` specifiers, `$` expansion).
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+use crate::add::{self, AddOptions};
+use crate::sdtabfile::{self, Sdtabfile, TimerEntry};
+use crate::{cron, crontab, parse_unit, unit};
+
+const MONTH_NAMES: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// Characters that make cron's `/bin/sh -c` matter.
+const SHELL_CHARS: &[char] = &[
+    '|', '&', ';', '<', '>', '(', ')', '$', '`', '\\', '"', '\'', '*', '?', '~', '{', '}', '[', ']', '#', '=',
+];
+
+/// A crontab job converted to sdtab terms.
+#[derive(Debug, PartialEq)]
+struct Job {
+    line_no: usize,
+    schedule: String,
+    /// Ready for `ExecStart=` (wrapped and escaped as needed).
+    command: String,
+    /// The job's command as written, used for naming and description.
+    source: String,
+    description: Option<String>,
+    env: Vec<String>,
+    workdir: Option<String>,
+    mail_to: Option<String>,
+}
+
+/// A line that could not be converted.
+#[derive(Debug, PartialEq)]
+struct Skipped {
+    line_no: usize,
+    line: String,
+    reason: String,
+}
+
+/// Environment lines in effect for the jobs that follow them.
+#[derive(Default)]
+struct CronEnv {
+    shell: Option<String>,
+    mail_to: Option<String>,
+    tz: Option<String>,
+    home: Option<String>,
+    vars: Vec<(String, String)>,
+}
+
+pub fn run_crontab(file: Option<&str>, output: Option<&str>, dry_run: bool) -> Result<()> {
+    // Jobs in sdtab's own block (crontab backend) are already sdtab units.
+    let text = crontab::strip_managed(&read_crontab(file)?);
+    let (jobs, skipped) = parse_crontab(&text);
+
+    let mut taken: HashSet<String> = parse_unit::scan_all_units()?.into_iter().map(|u| u.name).collect();
+    let named: Vec<(String, Job)> = jobs
+        .into_iter()
+        .map(|job| {
+            let name = unique_name(&unit::derive_name(&job.source), &taken);
+            taken.insert(name.clone());
+            (name, job)
+        })
+        .collect();
+
+    let home = std::env::var("HOME").unwrap_or_default();
+    let mut failed = 0;
+    if let Some(path) = output {
+        let timers: BTreeMap<String, TimerEntry> =
+            named.iter().map(|(name, job)| (name.clone(), timer_entry(job, &home))).collect();
+        let toml_str = toml::to_string_pretty(&Sdtabfile { timers, ..Default::default() })
+            .context("Failed to serialize to TOML")?;
+        fs::write(path, &toml_str).with_context(|| format!("Failed to write {}", path))?;
+        for (name, job) in &named {
+            report(name, job);
+        }
+        println!("Wrote {} timer(s) to {}. Review it, then: sdtab apply {}", named.len(), path, path);
+    } else {
+        for (name, job) in named {
+            report(&name, &job);
+            if let Err(e) = add::run(add_options(name.clone(), job, &home, dry_run)) {
+                failed += 1;
+                eprintln!("✗ {}: {:#}", name, e);
+            }
+        }
+    }
+
+    if !skipped.is_empty() {
+        eprintln!();
+        eprintln!("Not imported ({}):", skipped.len());
+        for s in &skipped {
+            eprintln!("  line {}: {}", s.line_no, s.line);
+            eprintln!("    {}", s.reason);
+        }
+    }
+    if failed > 0 {
+        bail!("{} job(s) failed to install.", failed);
+    }
+    Ok(())
+}
+
+fn report(name: &str, job: &Job) {
+    println!("✓ line {} → {} ({})", job.line_no, name, job.schedule);
+}
+
+/// `None` runs `crontab -l`; `-` reads stdin.
+fn read_crontab(file: Option<&str>) -> Result<String> {
+    match file {
+        Some("-") => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text).context("Failed to read stdin")?;
+            Ok(text)
+        }
+        Some(path) => fs::read_to_string(path).with_context(|| format!("Failed to read {}", path)),
+        None => {
+            let output = Command::new("crontab").arg("-l").output().context("Failed to run crontab -l")?;
+            if !output.status.success() {
+                bail!("crontab -l failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        }
+    }
+}
+
+fn parse_crontab(text: &str) -> (Vec<Job>, Vec<Skipped>) {
+    let mut env = CronEnv::default();
+    let mut jobs = Vec::new();
+    let mut skipped = Vec::new();
+    let mut comment: Option<String> = None;
+
+    for (i, raw) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = raw.trim();
+        if line.is_empty() {
+            comment = None;
+            continue;
+        }
+        if let Some(text) = line.strip_prefix('#') {
+            // A comment right above a job becomes its description, unless
+            // it is a commented-out job (`# 0 5 * * * /old/job`).
+            let text = text.trim();
+            comment = Some(text.to_string()).filter(|t| !t.is_empty() && parse_job(t, &env).is_err());
+            continue;
+        }
+        if let Some((key, value)) = env_assignment(line) {
+            match key.as_str() {
+                "SHELL" => env.shell = Some(value),
+                "MAILTO" => env.mail_to = Some(value).filter(|v| !v.is_empty()),
+                "CRON_TZ" | "TZ" => env.tz = Some(value),
+                "HOME" => env.home = Some(value),
+                _ => {
+                    env.vars.retain(|(k, _)| *k != key);
+                    env.vars.push((key, value));
+                }
+            }
+            continue;
+        }
+        match parse_job(line, &env) {
+            Ok(mut job) => {
+                job.line_no = line_no;
+                job.description = comment.take();
+                jobs.push(job);
+            }
+            Err(reason) => skipped.push(Skipped { line_no, line: line.to_string(), reason }),
+        }
+        comment = None;
+    }
+    (jobs, skipped)
+}
+
+/// `NAME = value` / `NAME="value"` lines. Job lines never start with an identifier
+/// followed by `=`.
+fn env_assignment(line: &str) -> Option<(String, String)> {
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    let mut chars = key.chars();
+    if !chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return None;
+    }
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value);
+    Some((key.to_string(), value.to_string()))
+}
+
+fn parse_job(line: &str, env: &CronEnv) -> Result<Job, String> {
+    let (schedule, command) = if line.starts_with('@') {
+        let (keyword, rest) = line.split_once(char::is_whitespace).ok_or("missing command")?;
+        (keyword.to_string(), rest.trim())
+    } else {
+        let mut rest = line;
+        let mut fields = Vec::new();
+        for _ in 0..5 {
+            let (field, tail) = rest
+                .split_once(char::is_whitespace)
+                .ok_or("expected 5 schedule fields followed by a command")?;
+            fields.push(field);
+            rest = tail.trim_start();
+        }
+        // cron runs the job when *either* day field matches; systemd requires both.
+        if !fields[2].starts_with('*') && !fields[4].starts_with('*') {
+            return Err(format!(
+                "day-of-month ({}) and day-of-week ({}) are both set; cron runs when either matches, \
+                 systemd only when both do. Split it into two jobs",
+                fields[2], fields[4]
+            ));
+        }
+        let month = month_numbers(fields[3]);
+        (format!("{} {} {} {} {}", fields[0], fields[1], fields[2], month, fields[4]), rest)
+    };
+    if command.is_empty() {
+        return Err("missing command".to_string());
+    }
+    let schedule = match env.tz {
+        Some(ref tz) if schedule != "@reboot" => format!("{} {}", schedule, tz),
+        _ => schedule,
+    };
+    cron::parse(&schedule).map_err(|e| format!("unsupported schedule '{}': {}", schedule, e))?;
+    if schedule == "@service" {
+        return Err("@service is not a crontab schedule".to_string());
+    }
+
+    let (source, stdin) = split_stdin(command);
+    let command = systemd_command(&source, stdin.as_deref(), env.shell.as_deref());
+    let env_vars = env
+        .vars
+        .iter()
+        .map(|(k, v)| {
+            let assignment = format!("{}={}", k, v.replace('%', "%%"));
+            if assignment.contains(char::is_whitespace) {
+                format!("\"{}\"", assignment)
+            } else {
+                assignment
+            }
+        })
+        .collect();
+
+    Ok(Job {
+        line_no: 0,
+        schedule,
+        command,
+        source,
+        description: None,
+        env: env_vars,
+        workdir: env.home.clone(),
+        mail_to: env.mail_to.clone(),
+    })
+}
+
+/// `jan-mar` → `1-3`; cron::parse only takes numeric months.
+fn month_numbers(field: &str) -> String {
+    let mut out = field.to_string();
+    for (i, name) in MONTH_NAMES.iter().enumerate() {
+        while let Some(pos) = out.to_lowercase().find(name) {
+            out.replace_range(pos..pos + 3, &(i + 1).to_string());
+        }
+    }
+    out
+}
+
+/// Split at the first unescaped `%`: the rest is stdin, with `%` as newlines.
+/// `\%` is a literal percent sign.
+fn split_stdin(command: &str) -> (String, Option<String>) {
+    let mut parts = vec![String::new()];
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'%') => {
+                parts.last_mut().unwrap().push('%');
+                chars.next();
+            }
+            '%' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(c),
+        }
+    }
+    let command = parts.remove(0).trim_end().to_string();
+    let stdin = if parts.is_empty() { None } else { Some(parts.join("\n")) };
+    (command, stdin)
+}
+
+/// Wrap in the crontab's shell when the command needs one, then escape for
+/// `ExecStart=`: systemd expands `%` specifiers and `$VAR` itself.
+fn systemd_command(command: &str, stdin: Option<&str>, shell: Option<&str>) -> String {
+    let needs_shell = stdin.is_some() || shell.is_some() || command.contains(SHELL_CHARS);
+    if !needs_shell {
+        return command.replace('%', "%%");
+    }
+    let script = match stdin {
+        Some(input) => {
+            let lines: Vec<String> = input.lines().map(|l| format!("'{}'", l.replace('\'', "'\\''"))).collect();
+            format!("printf '%s\\n' {} | {}", lines.join(" "), command)
+        }
+        None => command.to_string(),
+    };
+    let quoted = script
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("{} -c \"{}\"", shell.unwrap_or("/bin/sh"), quoted)
+}
+
+/// `backup`, then `backup-2`, `backup-3`, …
+fn unique_name(base: &str, taken: &HashSet<String>) -> String {
+    if !taken.contains(base) {
+        return base.to_string();
+    }
+    (2..).map(|n| format!("{}-{}", base, n)).find(|n| !taken.contains(n)).unwrap()
+}
+
+fn timer_entry(job: &Job, home: &str) -> TimerEntry {
+    let description = job.description.clone().unwrap_or_else(|| job.source.clone());
+    TimerEntry {
+        schedule: job.schedule.clone(),
+        command: job.command.clone(),
+        workdir: job.workdir.clone().unwrap_or_else(|| home.to_string()),
+        description: sdtabfile::description_if_different(&description, &job.command),
+        env_file: None,
+        memory_max: None,
+        cpu_quota: None,
+        io_weight: None,
+        timeout_stop: None,
+        exec_start_pre: None,
+        exec_stop_post: None,
+        log_level_max: None,
+        user: None,
+        group: None,
+        random_delay: None,
+        env: job.env.clone(),
+        no_notify: false,
+        enabled: true,
+        output_file: None,
+        output_max_size: None,
+        output_keep: None,
+        mail_to: job.mail_to.clone(),
+    }
+}
+
+fn add_options(name: String, job: Job, home: &str, dry_run: bool) -> AddOptions {
+    AddOptions {
+        schedule: job.schedule,
+        description: Some(job.description.unwrap_or(job.source)),
+        command: job.command,
+        name: Some(name),
+        workdir: Some(job.workdir.unwrap_or_else(|| home.to_string())),
+        env_file: None,
+        restart: None,
+        memory_max: None,
+        cpu_quota: None,
+        io_weight: None,
+        timeout_stop: None,
+        exec_start_pre: None,
+        exec_stop_post: None,
+        log_level_max: None,
+        user: None,
+        group: None,
+        random_delay: None,
+        env: job.env,
+        no_notify: false,
+        service_type: None,
+        pid_file: None,
+        watchdog: None,
+        exec_reload: None,
+        health_cmd: None,
+        health_interval: None,
+        health_retries: None,
+        ports: Vec::new(),
+        deploy_step: Vec::new(),
+        rollback_step: Vec::new(),
+        output_file: None,
+        output_max_size: None,
+        output_keep: None,
+        mail_to: job.mail_to,
+        dry_run,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::fake::FakeBackend;
+    use crate::testdir::TestDir;
+    use crate::{apply, backend, init};
+
+    const CRONTAB: &str = "\
+SHELL=/bin/sh
+PATH=/usr/local/bin:/usr/bin:/bin
+MAILTO=me@example.com
+
+# Nightly backup
+30 3 * * * /usr/local/bin/backup --full
+*/15 * * * * cd /srv/app && ./sync.sh > /dev/null 2>&1
+@reboot /usr/bin/start-agent
+0 9 * jan,feb mon /usr/bin/winter-report
+0 8 1 * mon /usr/bin/ambiguous
+0 0 * * * mail -s report me%line one%line two
+5 4 * * * date +\\%Y-\\%m-\\%d >> /tmp/dates
+not a job
+";
+
+    #[test]
+    fn parses_env_comments_and_jobs() {
+        let (jobs, skipped) = parse_crontab(CRONTAB);
+        assert_eq!(jobs.len(), 6);
+
+        let backup = &jobs[0];
+        assert_eq!(backup.line_no, 6);
+        assert_eq!(backup.schedule, "30 3 * * *");
+        assert_eq!(backup.description.as_deref(), Some("Nightly backup"));
+        assert_eq!(backup.env, vec!["PATH=/usr/local/bin:/usr/bin:/bin"]);
+        assert_eq!(backup.mail_to.as_deref(), Some("me@example.com"));
+        // SHELL= is set, so even simple commands run through it like cron would.
+        assert_eq!(backup.command, "/bin/sh -c \"/usr/local/bin/backup --full\"");
+
+        assert_eq!(jobs[2].schedule, "@reboot");
+        assert_eq!(jobs[2].description, None);
+        assert_eq!(jobs[3].schedule, "0 9 * 1,2 mon");
+
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(skipped[0].line_no, 10);
+        assert!(skipped[0].reason.contains("either matches"));
+        assert_eq!(skipped[1].line, "not a job");
+    }
+
+    #[test]
+    fn converts_percent_and_shell_syntax() {
+        let (jobs, _) = parse_crontab(CRONTAB);
+        assert_eq!(
+            jobs[4].command,
+            "/bin/sh -c \"printf '%%s\\\\n' 'line one' 'line two' | mail -s report me\""
+        );
+        assert_eq!(jobs[5].source, "date +%Y-%m-%d >> /tmp/dates");
+        assert_eq!(jobs[5].command, "/bin/sh -c \"date +%%Y-%%m-%%d >> /tmp/dates\"");
+
+        assert_eq!(systemd_command("/usr/bin/job --all", None, None), "/usr/bin/job --all");
+        assert_eq!(systemd_command("echo $HOME", None, None), "/bin/sh -c \"echo $$HOME\"");
+    }
+
+    #[test]
+    fn commented_out_jobs_are_not_descriptions() {
+        let (jobs, _) = parse_crontab("# 0 5 * * * /old/job\n0 6 * * * /new/job\n# Cleanup\n0 7 * * * /usr/bin/clean\n");
+        assert_eq!(jobs[0].description, None);
+        assert_eq!(jobs[1].description.as_deref(), Some("Cleanup"));
+    }
+
+    #[test]
+    fn month_names_are_replaced_everywhere() {
+        assert_eq!(month_numbers("JAN,MAR-JAN"), "1,3-1");
+        assert_eq!(month_numbers("dec,Jan-feb"), "12,1-2");
+    }
+
+    #[test]
+    fn names_get_collision_suffixes() {
+        let mut taken = HashSet::new();
+        assert_eq!(unique_name("backup", &taken), "backup");
+        taken.insert("backup".to_string());
+        taken.insert("backup-2".to_string());
+        assert_eq!(unique_name("backup", &taken), "backup-3");
+    }
+
+    #[test]
+    fn cron_tz_goes_on_the_schedule() {
+        let (jobs, _) = parse_crontab("CRON_TZ=Asia/Tokyo\n0 9 * * * /usr/bin/job\n@reboot /usr/bin/warm\n");
+        assert_eq!(jobs[0].schedule, "0 9 * * * Asia/Tokyo");
+        assert_eq!(cron::parse(&jobs[0].schedule).unwrap().on_calendar.as_deref(), Some("*-*-* 09:00:00 Asia/Tokyo"));
+        assert_eq!(jobs[1].schedule, "@reboot");
+        assert_eq!(jobs[0].command, "/usr/bin/job");
+    }
+
+    #[test]
+    fn imported_jobs_run_the_same_under_the_crontab_backend() {
+        let original = "\
+5 4 * * * date +\\%Y-\\%m-\\%d >> /tmp/dates
+*/15 * * * * echo \"$HOME\" costs 5$ > /tmp/home
+30 3 * * * /usr/local/bin/backup --full
+";
+        let (jobs, _) = parse_crontab(original);
+        let timers: BTreeMap<String, TimerEntry> = jobs
+            .iter()
+            .enumerate()
+            .map(|(i, job)| (format!("job{}", i), TimerEntry { no_notify: true, ..timer_entry(job, "/tmp") }))
+            .collect();
+        let home = TestDir::new("import");
+        fs::create_dir_all(home.join(".config/systemd/user")).unwrap();
+        let file = home.join("Sdtabfile.toml");
+        fs::write(&file, toml::to_string_pretty(&Sdtabfile { timers, ..Default::default() }).unwrap()).unwrap();
+
+        let lines: Vec<String> = init::with_home(&home, || {
+            backend::with_backend(Rc::new(FakeBackend::default()), || {
+                apply::run(file.to_str().unwrap(), false, false, false, false, None)
+            })
+            .unwrap();
+            (0..jobs.len())
+                .map(|i| crontab::job_lines(&format!("sdtab-job{}.timer", i)).unwrap().last().unwrap().clone())
+                .collect()
+        });
+        assert_eq!(
+            lines,
+            vec![
+                "5 4 * * * cd /tmp && /bin/sh -c \"date +\\%Y-\\%m-\\%d >> /tmp/dates\"",
+                "*/15 * * * * cd /tmp && /bin/sh -c \"echo \\\"$HOME\\\" costs 5$ > /tmp/home\"",
+                "30 3 * * * cd /tmp && /usr/local/bin/backup --full",
+            ]
+        );
+    }
+}
@@ -1,35 +1,39 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
 
 use anyhow::{bail, Context, Result};
 
-use crate::{config, systemctl};
+use crate::{backend, config, systemctl, unit};
 
 pub fn run(slack_webhook: Option<&str>, slack_mention: Option<&str>) -> Result<()> {
-    // 1. Enable linger for the current user
-    let user = std::env::var("USER").context("Could not determine current user")?;
-    println!("Enabling linger for user '{}'...", user);
-
-    let status = Command::new("loginctl")
-        .args(["enable-linger", &user])
-        .status()
-        .context("Failed to execute loginctl")?;
-
-    if !status.success() {
-        anyhow::bail!("loginctl enable-linger failed");
+    // 1. Enable linger for the current user (the system manager and the
+    //    crontab backend need none)
+    if !system() && backend::kind()? != "crontab" {
+        let user = std::env::var("USER").context("Could not determine current user")?;
+        println!("Enabling linger for user '{}'...", user);
+
+        let status = Command::new("loginctl")
+            .args(["enable-linger", &user])
+            .status()
+            .context("Failed to execute loginctl")?;
+
+        if !status.success() {
+            anyhow::bail!("loginctl enable-linger failed");
+        }
     }
 
-    // 2. Create systemd user directory
+    // 2. Create systemd unit directory
     let user_dir = unit_dir()?;
     println!("Creating directory: {}", user_dir);
-    fs::create_dir_all(&user_dir).context("Failed to create systemd user directory")?;
+    fs::create_dir_all(&user_dir).context("Failed to create systemd unit directory")?;
 
     // 3. Create config directory and env file
-    let config_dir = config_dir()?;
+    let config_dir = host_path(&config_dir()?);
     fs::create_dir_all(&config_dir).context("Failed to create sdtab config directory")?;
 
-    let env_path = global_env_path()?;
+    let env_path = host_path(&global_env_path()?);
     if !Path::new(&env_path).exists() {
         let template = "# sdtab global environment variables\n\
                         # All services and timers inherit these settings.\n\
@@ -53,7 +57,7 @@ pub fn run(slack_webhook: Option<&str>, slack_mention: Option<&str>) -> Result<(
     install_claude_skill()?;
 
     // 6. Reload systemd user daemon
-    println!("Reloading systemd user daemon...");
+    println!("Reloading systemd {} daemon...", if system() { "system" } else { "user" });
     systemctl::daemon_reload()?;
 
     println!("sdtab initialized successfully.");
@@ -94,9 +98,10 @@ fn setup_notify(webhook: Option<&str>, mention: Option<&str>) -> Result<()> {
     // Write notify.env
     let notify_env_path = notify_env_path()?;
     let content = format!("SDTAB_SLACK_WEBHOOK={}\n", webhook);
-    fs::write(&notify_env_path, &content)
-        .with_context(|| format!("Failed to write {}", notify_env_path))?;
-    println!("Created: {}", notify_env_path);
+    let notify_env_file = host_path(&notify_env_path);
+    fs::write(&notify_env_file, &content)
+        .with_context(|| format!("Failed to write {}", notify_env_file))?;
+    println!("Created: {}", notify_env_file);
 
     // Build notification message
     let mention_prefix = match &cfg.notify.slack_mention {
@@ -105,11 +110,11 @@ fn setup_notify(webhook: Option<&str>, mention: Option<&str>) -> Result<()> {
         None => String::new(),
     };
 
-    // Generate template unit sdtab-notify@.service
+    // Generate the notify template unit (sdtab-notify@.service)
     // Uses Environment= for systemd specifiers (%i, %H) to avoid shell injection,
     // and jq for safe JSON construction (handles special chars in hostname etc.)
     let unit_dir = unit_dir()?;
-    let template_path = format!("{}/sdtab-notify@.service", unit_dir);
+    let template_path = format!("{}/{}", unit_dir, unit::notify_template_filename());
     let template = format!(
         "[Unit]\n\
          Description=[sdtab] Failure notification for %i\n\
@@ -167,14 +172,176 @@ fn install_claude_skill() -> Result<()> {
     Ok(())
 }
 
+/// Global options that relocate sdtab's files: `--system`, `--unit-dir`,
+/// `--prefix`, `--root`.
+#[derive(Default)]
+pub struct Layout {
+    /// Manage units of the system manager (`/etc/systemd/system`) instead of the user's.
+    pub system: bool,
+    pub unit_dir: Option<String>,
+    pub prefix: Option<String>,
+    /// Render into this directory instead of the live system (image builds).
+    pub root: Option<String>,
+}
+
+static LAYOUT: OnceLock<Layout> = OnceLock::new();
+
+/// Set the layout for this run. Called once from `main` before any command.
+pub fn configure(mut layout: Layout) -> Result<()> {
+    if let Some(ref prefix) = layout.prefix {
+        if prefix.is_empty() || prefix.contains(['/', '@', '.']) || prefix.contains(char::is_whitespace) {
+            bail!("Invalid --prefix '{}': use letters, digits, '-' or '_' (e.g. myapp-)", prefix);
+        }
+    }
+    let absolute = |dir: &str| -> Result<String> {
+        Ok(std::path::absolute(dir)
+            .with_context(|| format!("Invalid directory '{}'", dir))?
+            .display()
+            .to_string())
+    };
+    if let Some(ref root) = layout.root {
+        layout.root = Some(absolute(root)?);
+    }
+    if let Some(ref dir) = layout.unit_dir {
+        if layout.root.is_none() {
+            layout.unit_dir = Some(absolute(dir)?);
+        } else if !dir.starts_with('/') {
+            bail!("--unit-dir must be absolute with --root (it is the path inside the root)");
+        }
+    }
+    if LAYOUT.set(layout).is_err() {
+        bail!("layout already configured");
+    }
+    Ok(())
+}
+
+fn layout() -> &'static Layout {
+    LAYOUT.get_or_init(Layout::default)
+}
+
+/// Whether units belong to the system manager (`--system`).
+pub fn system() -> bool {
+    layout().system
+}
+
+/// Prepended to every unit name (`sdtab-backup.service`).
+pub fn prefix() -> &'static str {
+    layout().prefix.as_deref().unwrap_or("sdtab-")
+}
+
+/// The `--root` directory, when rendering offline.
+pub fn root() -> Option<&'static str> {
+    layout().root.as_deref()
+}
+
+/// Where a path on the target system lives on this machine: under `--root`
+/// when set, unchanged otherwise. Paths written into units stay target paths.
+pub fn host_path(path: &str) -> String {
+    match root() {
+        Some(root) => format!("{}/{}", root.trim_end_matches('/'), path.trim_start_matches('/')),
+        None => path.to_string(),
+    }
+}
+
+/// How generated hooks (`__health-check`, `__output-rotate`) invoke sdtab:
+/// this executable, or under `--root` the copy installed in the image,
+/// followed by the options that select this run's units.
+pub fn sdtab_bin() -> String {
+    let mut bin = if root().is_some() {
+        ["/usr/local/bin/sdtab", "/usr/bin/sdtab"]
+            .into_iter()
+            .find(|path| Path::new(&host_path(path)).exists())
+            .unwrap_or("/usr/bin/sdtab")
+            .to_string()
+    } else {
+        std::env::current_exe()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| "sdtab".to_string())
+    };
+    bin.push_str(&layout_args(layout()));
+    bin
+}
+
+/// The options selecting `layout`'s units, quoted for an `ExecStart=` line.
+fn layout_args(layout: &Layout) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%"));
+    let mut args = String::new();
+    if layout.system {
+        args.push_str(" --system");
+    }
+    if let Some(ref dir) = layout.unit_dir {
+        args.push_str(&format!(" --unit-dir {}", quote(dir)));
+    }
+    if let Some(ref prefix) = layout.prefix {
+        args.push_str(&format!(" --prefix {}", quote(prefix)));
+    }
+    args
+}
+
+#[cfg(test)]
+thread_local! {
+    static TEST_HOME: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+fn home() -> Result<String> {
+    #[cfg(test)]
+    if let Some(home) = TEST_HOME.with(|h| h.borrow().clone()) {
+        return Ok(home);
+    }
+    std::env::var("HOME").context("Could not determine HOME directory")
+}
+
+/// Run `f` with `home` in place of `$HOME` for the unit, config and state dirs.
+#[cfg(test)]
+pub fn with_home<T>(home: &Path, f: impl FnOnce() -> T) -> T {
+    let previous = TEST_HOME.with(|h| h.replace(Some(home.display().to_string())));
+    let result = f();
+    TEST_HOME.with(|h| *h.borrow_mut() = previous);
+    result
+}
+
+/// `$XDG_CONFIG_HOME` / `$XDG_STATE_HOME`, falling back to `$HOME/<default>`.
+/// Relative values are ignored, as the XDG spec requires.
+fn xdg_dir(var: &str, default: &str) -> Result<String> {
+    #[cfg(test)]
+    if TEST_HOME.with(|h| h.borrow().is_some()) {
+        return Ok(format!("{}/{}", home()?, default));
+    }
+    match std::env::var(var) {
+        Ok(dir) if dir.starts_with('/') => Ok(dir),
+        _ => Ok(format!("{}/{}", home()?, default)),
+    }
+}
+
+/// The unit directory as the target system sees it (`--unit-dir`, else
+/// `/etc/systemd/system` with `--system`, else `$XDG_CONFIG_HOME/systemd/user`).
+pub fn target_unit_dir() -> Result<String> {
+    match layout().unit_dir {
+        Some(ref dir) => Ok(dir.clone()),
+        None if system() => Ok("/etc/systemd/system".to_string()),
+        None => Ok(format!("{}/systemd/user", xdg_dir("XDG_CONFIG_HOME", ".config")?)),
+    }
+}
+
+/// Where unit files are read and written (under `--root` when set).
 pub fn unit_dir() -> Result<String> {
-    let home = std::env::var("HOME").context("Could not determine HOME directory")?;
-    Ok(format!("{}/.config/systemd/user", home))
+    Ok(host_path(&target_unit_dir()?))
 }
 
+/// sdtab's config directory as a target path; wrap in `host_path` to read or write it.
 pub fn config_dir() -> Result<String> {
-    let home = std::env::var("HOME").context("Could not determine HOME directory")?;
-    Ok(format!("{}/.config/sdtab", home))
+    if system() {
+        return Ok("/etc/sdtab".to_string());
+    }
+    Ok(format!("{}/sdtab", xdg_dir("XDG_CONFIG_HOME", ".config")?))
+}
+
+/// Runtime state written by sdtab itself (health probes, history files).
+pub fn state_dir() -> Result<String> {
+    if system() {
+        return Ok(host_path("/var/lib/sdtab"));
+    }
+    Ok(host_path(&format!("{}/sdtab", xdg_dir("XDG_STATE_HOME", ".local/state")?)))
 }
 
 pub fn global_env_path() -> Result<String> {
@@ -184,7 +351,7 @@ pub fn global_env_path() -> Result<String> {
 
 /// Read PATH from the global env file and return the directories
 pub fn read_env_path() -> Result<Vec<PathBuf>> {
-    let env_path = global_env_path()?;
+    let env_path = host_path(&global_env_path()?);
     let content = match fs::read_to_string(&env_path) {
         Ok(c) => c,
         Err(_) => return Ok(vec![]),
@@ -225,7 +392,7 @@ pub fn resolve_command(command: &str) -> Result<String> {
     let path_dirs = read_env_path()?;
     for dir in &path_dirs {
         let candidate = dir.join(binary);
-        if candidate.exists() {
+        if Path::new(&host_path(&candidate.to_string_lossy())).exists() {
             let resolved = candidate.to_string_lossy();
             if args.is_empty() {
                 return Ok(resolved.to_string());
@@ -235,6 +402,22 @@ pub fn resolve_command(command: &str) -> Result<String> {
         }
     }
 
+    // Under --root, look in the image's standard directories instead of this host's PATH
+    if root().is_some() {
+        for dir in ["/usr/local/bin", "/usr/bin", "/bin"] {
+            let candidate = format!("{}/{}", dir, binary);
+            if Path::new(&host_path(&candidate)).exists() {
+                return Ok(if args.is_empty() { candidate } else { format!("{} {}", candidate, args) });
+            }
+        }
+        anyhow::bail!(
+            "Command '{}' not found under {}. Add its directory to PATH in {}",
+            binary,
+            root().unwrap_or_default(),
+            global_env_path().unwrap_or_default()
+        );
+    }
+
     // Fallback: try system which
     if let Ok(output) = Command::new("which")
         .arg(binary)
@@ -256,3 +439,23 @@ pub fn resolve_command(command: &str) -> Result<String> {
         global_env_path().unwrap_or_default()
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_args_are_quoted_for_exec_start() {
+        let layout = Layout {
+            system: true,
+            unit_dir: Some("/srv/my units/100%".to_string()),
+            prefix: Some("a\"b".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            layout_args(&layout),
+            " --system --unit-dir \"/srv/my units/100%%\" --prefix \"a\\\"b\""
+        );
+        assert_eq!(layout_args(&Layout::default()), "");
+    }
+}
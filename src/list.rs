@@ -3,7 +3,7 @@ use std::io::IsTerminal;
 use anyhow::Result;
 use serde::Serialize;
 
-use crate::{parse_unit, systemctl, unit, SortOrder};
+use crate::{health, parse_unit, systemctl, unit, SortOrder};
 
 #[derive(Serialize)]
 struct Entry {
@@ -19,6 +19,12 @@ struct Entry {
     /// in-place via `sdtab upgrade`. Legacy units still work but miss newer directives.
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     legacy: bool,
+    /// Project the unit was applied for (`project =` in its Sdtabfile).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+    /// Health probe summary (`ok`, `failing 2/3`, `pending`) for services with `--health-cmd`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<String>,
     #[serde(skip)]
     sort_key: u64, // datetime sort key for timers, u64::MAX for services
     #[serde(skip)]
@@ -32,8 +38,14 @@ enum Section {
     Disabled, // stopped timers/services
 }
 
-pub fn run(json: bool, sort: SortOrder) -> Result<()> {
-    let units = parse_unit::scan_all_units()?;
+/// What `list` reads of each unit, timers and services alike.
+pub const PROPERTIES: &[&str] = &["ActiveState", "NextElapseUSecRealtime"];
+
+pub fn run(json: bool, sort: SortOrder, project: Option<&str>) -> Result<()> {
+    let mut units = parse_unit::scan_all_units()?;
+    if let Some(project) = project {
+        units.retain(|u| u.project.as_deref() == Some(project));
+    }
 
     if units.is_empty() {
         if json {
@@ -46,12 +58,13 @@ pub fn run(json: bool, sort: SortOrder) -> Result<()> {
 
     let mut entries: Vec<Entry> = Vec::new();
 
-    for unit in &units {
+    let systemd_units: Vec<String> = units.iter().map(|u| parse_unit::primary_unit(&u.name, &u.unit_type)).collect();
+    let props = systemctl::show_many(&systemd_units, PROPERTIES).unwrap_or_default();
+
+    for (unit, systemd_unit) in units.iter().zip(&systemd_units) {
+        let active_state = props.get(systemd_unit, "ActiveState").unwrap_or("unknown").to_string();
         let (type_str, schedule, status, sort_key, section) = match unit.unit_type {
             parse_unit::UnitType::Service => {
-                let service_unit = unit::service_filename(&unit.name);
-                let active_state = systemctl::show_property(&service_unit, "ActiveState")
-                    .unwrap_or_else(|_| "unknown".to_string());
                 let section = if active_state == "active" {
                     Section::Service
                 } else {
@@ -60,16 +73,11 @@ pub fn run(json: bool, sort: SortOrder) -> Result<()> {
                 ("service", "@service".to_string(), active_state, u64::MAX, section)
             }
             parse_unit::UnitType::Timer => {
-                let timer_unit = unit::timer_filename(&unit.name);
-                let timer_active = systemctl::show_property(&timer_unit, "ActiveState")
-                    .unwrap_or_else(|_| "unknown".to_string());
-                let next_run_raw =
-                    systemctl::show_property(&timer_unit, "NextElapseUSecRealtime")
-                        .unwrap_or_else(|_| "?".to_string());
-                let next_run = format_next_run(&next_run_raw);
+                let next_run_raw = props.get(systemd_unit, "NextElapseUSecRealtime").unwrap_or("?");
+                let next_run = format_next_run(next_run_raw);
                 let schedule = unit.cron_expr.as_deref().unwrap_or("?").to_string();
-                let epoch = parse_datetime_sort_key(&next_run_raw);
-                let section = if timer_active != "active" {
+                let epoch = parse_datetime_sort_key(next_run_raw);
+                let section = if active_state != "active" {
                     Section::Disabled
                 } else {
                     Section::Timer
@@ -85,6 +93,13 @@ pub fn run(json: bool, sort: SortOrder) -> Result<()> {
             None
         };
 
+        let health = unit.health_cmd.as_ref().map(|_| {
+            let retries = unit.health_retries.unwrap_or(unit::DEFAULT_HEALTH_RETRIES);
+            health::load_state(&unit.name)
+                .map(|state| health::summary(&state, retries))
+                .unwrap_or_else(|_| "unknown".to_string())
+        });
+
         entries.push(Entry {
             name: unit.name.clone(),
             type_str,
@@ -93,6 +108,8 @@ pub fn run(json: bool, sort: SortOrder) -> Result<()> {
             status,
             description,
             legacy: unit.template_version < unit::TEMPLATE_VERSION,
+            project: unit.project.clone(),
+            health,
             sort_key,
             section,
         });
@@ -151,6 +168,21 @@ fn format_status(status: &str, use_color: bool) -> String {
     }
 }
 
+/// Pad before coloring so ANSI codes don't break column alignment.
+fn format_health(label: &str, width: usize, use_color: bool) -> String {
+    let padded = format!("{:<width$}", label, width = width);
+    if !use_color {
+        return padded;
+    }
+    if label == "ok" {
+        format!("\x1b[32m{}\x1b[0m", padded)
+    } else if label.starts_with("failing") {
+        format!("\x1b[31m{}\x1b[0m", padded)
+    } else {
+        format!("\x1b[90m{}\x1b[0m", padded)
+    }
+}
+
 fn print_section_header(label: &str, _width: usize, use_color: bool) {
     let dashes = "─".repeat(3);
     if use_color {
@@ -176,6 +208,14 @@ fn print_table(entries: &[Entry]) {
         .max()
         .unwrap_or(4)
         .max(4);
+    // PROJECT column only appears when at least one unit belongs to a project.
+    let show_project = entries.iter().any(|e| e.project.is_some());
+    let project_width = entries
+        .iter()
+        .filter_map(|e| e.project.as_ref().map(|p| p.len()))
+        .max()
+        .unwrap_or(7)
+        .max(7);
     let type_width = 7; // "service" is the longest
     let sched_width = entries
         .iter()
@@ -191,19 +231,31 @@ fn print_table(entries: &[Entry]) {
         .unwrap_or(7)
         .max(7);
 
+    // HEALTH column only appears when at least one service has a health check.
+    let show_health = entries.iter().any(|e| e.health.is_some());
+    let health_width = entries
+        .iter()
+        .filter_map(|e| e.health.as_ref().map(|h| h.len()))
+        .max()
+        .unwrap_or(6)
+        .max(6);
+
     println!(
-        "{:<name_w$}  {:<type_w$}  {:<sched_w$}  {:<cmd_w$}  STATUS",
+        "{:<name_w$}  {}{:<type_w$}  {:<sched_w$}  {:<cmd_w$}  {}STATUS",
         "NAME",
+        if show_project { format!("{:<project_w$}  ", "PROJECT", project_w = project_width) } else { String::new() },
         "TYPE",
         "SCHEDULE",
         "COMMAND",
+        if show_health { format!("{:<health_w$}  ", "HEALTH", health_w = health_width) } else { String::new() },
         name_w = name_width,
         type_w = type_width,
         sched_w = sched_width,
         cmd_w = cmd_width,
     );
 
-    let total_width = name_width + type_width + sched_width + cmd_width + 8 + 10; // columns + gaps + STATUS
+    let project_cell_width = if show_project { project_width + 2 } else { 0 };
+    let total_width = name_width + project_cell_width + type_width + sched_width + cmd_width + 8 + 10; // columns + gaps + STATUS
     let mut current_section: Option<Section> = None;
 
     for entry in entries {
@@ -248,13 +300,26 @@ fn print_table(entries: &[Entry]) {
             entry.name.len()
         };
         let name_padding = name_width.saturating_sub(name_raw_len);
+        let health_cell = if show_health {
+            let label = entry.health.as_deref().unwrap_or("-");
+            format!("{}  ", format_health(label, health_width, use_color))
+        } else {
+            String::new()
+        };
+        let project_cell = if show_project {
+            format!("{:<project_w$}  ", entry.project.as_deref().unwrap_or("-"), project_w = project_width)
+        } else {
+            String::new()
+        };
         println!(
-            "{}{}  {:<type_w$}  {:<sched_w$}  {:<cmd_w$}  {}",
+            "{}{}  {}{:<type_w$}  {:<sched_w$}  {:<cmd_w$}  {}{}",
             name_display,
             " ".repeat(name_padding),
+            project_cell,
             entry.type_str,
             entry.schedule,
             cmd,
+            health_cell,
             status,
             type_w = type_width,
             sched_w = sched_width,
@@ -3,7 +3,7 @@ use std::process::Command;
 
 use anyhow::{bail, Result};
 
-use crate::{init, parse_unit, systemctl, unit};
+use crate::{bluegreen, init, parse_unit, systemctl, unit};
 
 pub fn run(
     name: Option<&str>,
@@ -26,7 +26,7 @@ pub fn run(
     }
 
     let mut cmd = Command::new("journalctl");
-    cmd.arg("--user");
+    cmd.arg(systemctl::scope_flag());
     cmd.args(["-n", &lines.to_string()]);
     cmd.arg("--no-pager");
 
@@ -36,28 +36,24 @@ pub fn run(
     if let Some(ref prio) = priority {
         cmd.args(["-p", prio]);
     }
-    if let Some(ref s) = since {
+    if let Some(s) = since {
         cmd.args(["--since", s]);
     }
 
     if let Some(name) = name {
         let unit_dir = init::unit_dir()?;
         let dir_path = std::path::Path::new(&unit_dir);
-        let service_path = dir_path.join(unit::service_filename(name));
+        let service_path = dir_path.join(bluegreen::definition_filename(name));
         let timer_path = dir_path.join(unit::timer_filename(name));
         if !service_path.exists() && !timer_path.exists() {
             bail!("'{}' not found.", name);
         }
-        let unit_name = unit::service_filename(name);
-        cmd.args(["--user-unit", &unit_name]);
+        let unit_name = bluegreen::journal_unit(name);
+        cmd.args([systemctl::journal_unit_flag(), &unit_name]);
     } else {
         let units = parse_unit::scan_all_units()?;
         let target_names: Vec<String> = if failed {
-            units
-                .into_iter()
-                .filter(|u| is_failed(&u.name))
-                .map(|u| u.name)
-                .collect()
+            failed_units(&units).into_iter().map(|(name, _)| name).collect()
         } else {
             units.into_iter().map(|u| u.name).collect()
         };
@@ -72,8 +68,8 @@ pub fn run(
         }
 
         for n in &target_names {
-            cmd.arg("--user-unit");
-            cmd.arg(unit::service_filename(n));
+            cmd.arg(systemctl::journal_unit_flag());
+            cmd.arg(bluegreen::journal_unit(n));
         }
     }
 
@@ -81,15 +77,51 @@ pub fn run(
     bail!("Failed to exec journalctl: {}", err);
 }
 
-fn is_failed(name: &str) -> bool {
-    let service_unit = unit::service_filename(name);
-    let service_state = systemctl::show_property(&service_unit, "ActiveState")
-        .unwrap_or_else(|_| String::new());
-    if service_state == "failed" {
-        return true;
+/// Units whose service or timer is in the `failed` state, with which one
+/// failed (`service` or `timer`). One `systemctl show` call for all units.
+pub fn failed_units(units: &[parse_unit::ParsedUnit]) -> Vec<(String, &'static str)> {
+    let pairs: Vec<(String, String)> = units
+        .iter()
+        .map(|u| (bluegreen::service_unit(&u.name), unit::timer_filename(&u.name)))
+        .collect();
+    let systemd_units: Vec<String> = pairs.iter().flat_map(|(s, t)| [s.clone(), t.clone()]).collect();
+    let props = systemctl::show_many(&systemd_units, &["ActiveState"]).unwrap_or_default();
+    let failed = |unit: &str| props.get(unit, "ActiveState") == Some("failed");
+
+    units
+        .iter()
+        .zip(&pairs)
+        .filter_map(|(u, (service, timer))| {
+            if failed(service) {
+                Some((u.name.clone(), "service"))
+            } else if failed(timer) {
+                Some((u.name.clone(), "timer"))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::backend;
+    use crate::fake::FakeBackend;
+
+    #[test]
+    fn failed_units_checks_service_then_timer() {
+        let unit = |name: &str| {
+            parse_unit::parse_service_file(name, "[Unit]\nX-Sdtab-Type=timer\n", None, "")
+        };
+        let units = [unit("a"), unit("b"), unit("c")];
+        let fake = Rc::new(FakeBackend::default());
+        fake.set_failed("sdtab-a.service");
+        fake.set_failed("sdtab-b.timer");
+
+        let failed = backend::with_backend(fake, || failed_units(&units));
+        assert_eq!(failed, vec![("a".to_string(), "service"), ("b".to_string(), "timer")]);
     }
-    let timer_unit = unit::timer_filename(name);
-    let timer_state = systemctl::show_property(&timer_unit, "ActiveState")
-        .unwrap_or_else(|_| String::new());
-    timer_state == "failed"
 }
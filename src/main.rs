@@ -1,25 +1,47 @@
 mod add;
+mod adopt;
 mod apply;
+mod backend;
+mod bluegreen;
 mod completions;
 mod config;
 mod cron;
+mod crontab;
+mod dbus;
+mod deploy;
+mod diff;
+mod drift;
 mod disable;
 mod doctor;
 mod edit;
 mod enable;
 mod export;
+#[cfg(test)]
+mod fake;
+mod health;
+mod import;
 mod init;
 mod list;
 mod logs;
+mod output;
 mod parse_unit;
+mod plan;
+mod reload;
 mod remove;
+mod render;
 mod restart;
 mod run;
+mod schema;
 mod sdtabfile;
+mod snapshot;
 mod status;
 mod systemctl;
 mod unit;
+#[cfg(test)]
+mod testdir;
+mod unit_file;
 mod upgrade;
+mod validate;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand, ValueEnum};
@@ -29,6 +51,18 @@ use clap::{Parser, Subcommand, ValueEnum};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Manage system units in /etc/systemd/system (as root) instead of user units
+    #[arg(long, global = true)]
+    system: bool,
+    /// Unit directory (default: $XDG_CONFIG_HOME/systemd/user, or /etc/systemd/system with --system)
+    #[arg(long, global = true, value_name = "DIR")]
+    unit_dir: Option<String>,
+    /// Unit name prefix (default: sdtab-)
+    #[arg(long, global = true)]
+    prefix: Option<String>,
+    /// Write into an image root instead of the live system (see `sdtab render`)
+    #[arg(long, global = true, value_name = "DIR")]
+    root: Option<String>,
 }
 
 #[derive(Clone, ValueEnum)]
@@ -37,6 +71,26 @@ pub enum SortOrder {
     Name,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RestartStrategy {
+    /// systemctl restart (brief downtime)
+    Hard,
+    /// Start the idle slot, wait for its health check, then switch over
+    BlueGreen,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// Sdtabfile for `sdtab apply`
+    Toml,
+    /// A crontab for hosts without sdtab (services and some options are dropped)
+    Crontab,
+    /// The unit files and drop-ins plus an install.sh, written to the `-o` directory
+    Units,
+    /// Every parsed unit with all its fields
+    Json,
+}
+
 #[derive(Subcommand)]
 #[allow(clippy::large_enum_variant)]
 enum Commands {
@@ -62,6 +116,9 @@ enum Commands {
         /// Sort order: time (default, next run) or name
         #[arg(long, value_enum, default_value_t = SortOrder::Time)]
         sort: SortOrder,
+        /// Only list units of this project
+        #[arg(long)]
+        project: Option<String>,
     },
     /// Remove a timer or service
     Remove {
@@ -110,6 +167,22 @@ enum Commands {
         /// Restart all sdtab-managed services (timers excluded)
         #[arg(long)]
         all: bool,
+        /// Restart strategy; blue-green requires a service added with --ports
+        #[arg(long, value_enum, default_value = "hard")]
+        strategy: RestartStrategy,
+    },
+    /// Run a service's deploy steps, restart it, and roll back if it fails
+    Deploy {
+        /// Service name to deploy
+        name: String,
+        /// Seconds to watch the service after the restart before declaring success
+        #[arg(long, default_value_t = 30)]
+        grace: u64,
+    },
+    /// Reload a service via its ExecReload= command (no restart)
+    Reload {
+        /// Service name to reload
+        name: String,
     },
     /// Trigger a unit once manually (ignores timer schedule)
     Run {
@@ -131,28 +204,127 @@ enum Commands {
         /// Timer/service name to disable
         name: String,
     },
-    /// Export current configuration to TOML
+    /// Export current configuration (TOML, crontab, unit files or JSON)
     Export {
-        /// Output file path (stdout if omitted)
-        #[arg(short, long)]
+        /// Output file path (stdout if omitted); a directory for `--format units`
+        #[arg(short, long, required_if_eq("format", "units"))]
         output: Option<String>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Toml)]
+        format: ExportFormat,
+        /// Move values shared by all entries into [defaults] (TOML only)
+        #[arg(long)]
+        factor_defaults: bool,
+        /// Only export units of this project
+        #[arg(long)]
+        project: Option<String>,
+    },
+    /// Check an Sdtabfile for unknown keys, bad values and missing paths
+    Validate {
+        /// Path to Sdtabfile.toml or a directory of *.toml files
+        #[arg(required_unless_present = "schema")]
+        file: Option<String>,
+        /// Resolve for [profiles.<name>] (default: `profile` in config.toml)
+        #[arg(long)]
+        profile: Option<String>,
+        /// Print the JSON Schema of an Sdtabfile instead, for editors
+        #[arg(long, conflicts_with = "file")]
+        schema: bool,
     },
     /// Apply configuration from a TOML file
     Apply {
-        /// Path to Sdtabfile.toml
+        /// Path to Sdtabfile.toml, a directory of *.toml files, or a plan.json
+        /// saved by `sdtab plan`
         file: String,
         /// Remove units not in the file
         #[arg(long)]
         prune: bool,
-        /// Show changes without applying
+        /// Show changes and the resolved file without applying
         #[arg(long)]
         dry_run: bool,
+        /// Show a unified diff of the unit files and what gets restarted
+        #[arg(long)]
+        diff: bool,
+        /// On failure, leave units as they are instead of restoring them
+        /// (`sdtab rollback` still can)
+        #[arg(long)]
+        no_rollback: bool,
+        /// Overlay [profiles.<name>] (default: `profile` in config.toml)
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Report units that differ from what `apply` last wrote
+    ///
+    /// Hand edits, units added outside an Sdtabfile and units removed since
+    /// the apply count as drift; the exit status is 1 when there is any.
+    Drift,
+    /// Undo the last `apply`: restore the units it changed from its snapshot
+    ///
+    /// Each apply snapshots the units it touches; the last 10 are kept.
+    Rollback {
+        /// Snapshot to restore (default: the newest)
+        id: Option<u64>,
+        /// List snapshots instead
+        #[arg(long, conflicts_with = "id")]
+        list: bool,
+    },
+    /// Save what `apply` would do as a JSON plan, to review and apply later
+    ///
+    /// `sdtab apply plan.json` carries out exactly that plan, and refuses if
+    /// the installed units changed since it was made.
+    Plan {
+        /// Path to Sdtabfile.toml, or a directory of *.toml files
+        file: String,
+        /// Output file path (stdout if omitted)
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Remove units not in the file
+        #[arg(long)]
+        prune: bool,
+        /// Overlay [profiles.<name>] (default: `profile` in config.toml)
+        #[arg(long)]
+        profile: Option<String>,
     },
+    /// Write units from a TOML file without contacting systemd
+    ///
+    /// Units are enabled with `.wants/` symlinks, like `systemctl enable`.
+    /// Combine with --root to bake them into an image:
+    /// sdtab --root ./image --unit-dir /etc/xdg/systemd/user render Sdtabfile.toml
+    Render {
+        /// Path to Sdtabfile.toml, or a directory of *.toml files
+        file: String,
+        /// Overlay [profiles.<name>] (default: `profile` in config.toml)
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Adopt hand-written systemd user units as sdtab units
+    ///
+    /// `foo.service` (+ `foo.timer`) becomes `sdtab-foo.service` (+ `.timer`).
+    /// Directives sdtab doesn't model are kept in an `adopted.conf` drop-in;
+    /// originals are moved to ~/.local/state/sdtab/adopted/.
+    Adopt {
+        /// Unit to adopt (e.g. backup, backup.service or backup.timer)
+        #[arg(required_unless_present = "all")]
+        unit: Option<String>,
+        /// Adopt every hand-written .service in the unit directory
+        #[arg(long, conflicts_with = "unit")]
+        all: bool,
+        /// Keep the original unit text and only add sdtab metadata, instead of
+        /// regenerating it from sdtab's template
+        #[arg(long)]
+        wrap: bool,
+        /// Show what would be adopted without making any changes
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Import jobs from another scheduler
+    #[command(subcommand)]
+    Import(ImportSource),
     /// Upgrade legacy units to the current template version via drop-in overrides.
     ///
     /// Legacy units (generated by an older sdtab) still work but miss newer
     /// template directives. `sdtab upgrade` injects the missing directives as
-    /// systemd drop-ins and bumps the `# sdtab:template_version=N` comment,
+    /// systemd drop-ins and bumps the `X-Sdtab-Template-Version=N` key,
     /// without restarting the units.
     Upgrade {
         /// Upgrade a specific unit only (default: upgrade all legacy units)
@@ -174,6 +346,44 @@ enum Commands {
     /// Print managed unit names (one per line), used by shell completion
     #[command(name = "__names", hide = true)]
     Names,
+    /// Run one health probe for a service, used by sdtab-health@<name>.timer
+    #[command(name = "__health-check", hide = true)]
+    HealthCheck {
+        name: String,
+    },
+    /// Rotate a unit's output file (ExecStartPre hook)
+    #[command(name = "__output-rotate", hide = true)]
+    OutputRotate {
+        name: String,
+        max_size: String,
+        keep: u32,
+        file: String,
+    },
+    /// Mail a run's output if non-empty (ExecStopPost hook)
+    #[command(name = "__mail-output", hide = true)]
+    MailOutput {
+        name: String,
+        addr: String,
+        file: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImportSource {
+    /// Convert crontab entries into timers
+    ///
+    /// Reads `crontab -l` unless a file (or `-` for stdin) is given. Entries
+    /// that cron and systemd would run differently are listed and skipped.
+    Crontab {
+        /// Crontab file to read, or `-` for stdin (default: `crontab -l`)
+        file: Option<String>,
+        /// Write an Sdtabfile instead of installing the timers
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Show what would be installed without making any changes
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 fn main() {
@@ -185,27 +395,57 @@ fn main() {
 
 fn run() -> Result<()> {
     let cli = Cli::parse();
+    init::configure(init::Layout {
+        system: cli.system,
+        unit_dir: cli.unit_dir,
+        prefix: cli.prefix,
+        root: cli.root,
+    })?;
 
     match cli.command {
         Commands::Init { slack_webhook, slack_mention } => init::run(slack_webhook.as_deref(), slack_mention.as_deref())?,
         Commands::Add(opts) => add::run(opts)?,
-        Commands::List { json, sort } => list::run(json, sort)?,
+        Commands::List { json, sort, project } => list::run(json, sort, project.as_deref())?,
         Commands::Remove { name } => remove::run(&name)?,
         Commands::Edit { name } => edit::run(&name)?,
         Commands::Logs { name, follow, lines, priority, all, failed, since } => {
             logs::run(name.as_deref(), follow, lines, priority, all, failed, since.as_deref())?
         }
-        Commands::Restart { names, all } => restart::run(&names, all)?,
+        Commands::Restart { names, all, strategy } => restart::run(&names, all, strategy)?,
+        Commands::Deploy { name, grace } => deploy::run(&name, grace)?,
+        Commands::Reload { name } => reload::run(&name)?,
         Commands::Run { name } => run::run(&name)?,
         Commands::Status { name } => status::run(&name)?,
         Commands::Enable { name } => enable::run(&name)?,
         Commands::Disable { name } => disable::run(&name)?,
-        Commands::Export { output } => export::run(output.as_deref())?,
-        Commands::Apply { file, prune, dry_run } => apply::run(&file, prune, dry_run)?,
+        Commands::Export { output, format, factor_defaults, project } => {
+            export::run(output.as_deref(), format, factor_defaults, project.as_deref())?
+        }
+        Commands::Validate { file, profile, schema } => validate::run(file.as_deref(), profile.as_deref(), schema)?,
+        Commands::Apply { file, prune, dry_run, diff, no_rollback, profile } => {
+            apply::run(&file, prune, dry_run, diff, no_rollback, profile.as_deref())?
+        }
+        Commands::Drift => drift::run()?,
+        Commands::Rollback { id, list } => snapshot::run(id, list)?,
+        Commands::Plan { file, output, prune, profile } => {
+            plan::run(&file, output.as_deref(), prune, profile.as_deref())?
+        }
+        Commands::Render { file, profile } => render::run(&file, profile.as_deref())?,
+        Commands::Adopt { unit, all, wrap, dry_run } => adopt::run(unit.as_deref(), all, wrap, dry_run)?,
+        Commands::Import(ImportSource::Crontab { file, output, dry_run }) => {
+            import::run_crontab(file.as_deref(), output.as_deref(), dry_run)?
+        }
         Commands::Upgrade { name, dry_run } => upgrade::run(name.as_deref(), dry_run)?,
         Commands::Doctor => doctor::run()?,
         Commands::Completions { shell } => completions::run(shell)?,
         Commands::Names => completions::print_names()?,
+        Commands::HealthCheck { name } => health::run_check(&name)?,
+        Commands::OutputRotate { name, max_size, keep, file } => {
+            output::run_rotate(&name, &max_size, keep, &file)?
+        }
+        Commands::MailOutput { name, addr, file } => {
+            output::run_mail(&name, &addr, file.as_deref())?
+        }
     }
 
     Ok(())
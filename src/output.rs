@@ -0,0 +1,329 @@
+//! Cron-style output capture: append a unit's output to a rotating log file,
+//! and/or mail the output of a run when it is non-empty.
+//!
+//! The generated unit does the capturing (`StandardOutput=append:`); the
+//! hidden `__output-rotate` (ExecStartPre) and `__mail-output` (ExecStopPost)
+//! subcommands handle rotation and delivery. Rotation records the file size
+//! at start so `__mail-output` only sends what this run appended.
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{config, init, systemctl, unit};
+
+pub const DEFAULT_MAX_SIZE: &str = "10M";
+pub const DEFAULT_KEEP: u32 = 5;
+
+/// Mail bodies are capped so a runaway job doesn't produce a huge message.
+const MAIL_MAX_BYTES: u64 = 256 * 1024;
+
+/// Merge a unit's own output options with the `[output]` defaults from config.toml.
+pub fn resolve(
+    name: &str,
+    output_file: Option<&str>,
+    max_size: Option<&str>,
+    keep: Option<u32>,
+    mail_to: Option<&str>,
+    defaults: &config::OutputConfig,
+) -> Result<unit::OutputTarget> {
+    let file = output_file
+        .map(|f| f.to_string())
+        .or_else(|| defaults.dir.as_ref().map(|d| format!("{}/{}.log", d.trim_end_matches('/'), name)));
+    if let Some(ref f) = file {
+        if !Path::new(f).is_absolute() {
+            bail!("Output file for '{}' must be an absolute path: {}", name, f);
+        }
+        // `StandardOutput=append:` takes the rest of the line with no quoting.
+        if f.contains(char::is_whitespace) {
+            bail!("Output file for '{}' can't contain whitespace: {}", name, f);
+        }
+    }
+    let max_size = max_size
+        .or(defaults.max_size.as_deref())
+        .unwrap_or(DEFAULT_MAX_SIZE)
+        .to_string();
+    parse_size(&max_size).with_context(|| format!("Invalid output max size for '{}'", name))?;
+    let sdtab_bin = init::sdtab_bin();
+    Ok(unit::OutputTarget {
+        file,
+        max_size,
+        keep: keep.or(defaults.keep).unwrap_or(DEFAULT_KEEP),
+        mail_to: mail_to.or(defaults.mail_to.as_deref()).map(|a| a.to_string()),
+        sdtab_bin,
+    })
+}
+
+/// Where a unit's output goes, for comparing an entry's resolved target with
+/// the installed one; `None` when it only goes to the journal.
+pub fn summary(target: &unit::OutputTarget) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(ref file) = target.file {
+        parts.push(format!("{} (max {}, keep {})", file, target.max_size, target.keep));
+    }
+    if let Some(ref addr) = target.mail_to {
+        parts.push(format!("mail to {}", addr));
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+/// Parse sizes like `512K`, `10M`, `1G` or plain bytes.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (num, mult) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len() - 1], 1024),
+        Some('M') | Some('m') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('G') | Some('g') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    let n: u64 = num
+        .parse()
+        .map_err(|_| anyhow::anyhow!("'{}' is not a size (e.g. 512K, 10M, 1G)", s))?;
+    if n == 0 {
+        bail!("size must be greater than zero");
+    }
+    n.checked_mul(mult).with_context(|| format!("'{}' is too large", s))
+}
+
+/// Hidden `__output-rotate`: rotate `file` to `file.1` … `file.<keep>` once it
+/// exceeds `max_size`, then remember where this run's output starts.
+pub fn run_rotate(name: &str, max_size: &str, keep: u32, file: &str) -> Result<()> {
+    let limit = parse_size(max_size)?;
+    let path = Path::new(file);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    rotate(path, limit, keep)?;
+    let offset = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    save_offset(name, offset)
+}
+
+fn rotate(path: &Path, limit: u64, keep: u32) -> Result<bool> {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size < limit {
+        return Ok(false);
+    }
+    if keep == 0 {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        return Ok(true);
+    }
+    let _ = fs::remove_file(numbered(path, keep));
+    for n in (1..keep).rev() {
+        let from = numbered(path, n);
+        if from.exists() {
+            fs::rename(&from, numbered(path, n + 1))
+                .with_context(|| format!("Failed to rotate {}", from.display()))?;
+        }
+    }
+    fs::rename(path, numbered(path, 1))
+        .with_context(|| format!("Failed to rotate {}", path.display()))?;
+    Ok(true)
+}
+
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(format!(".{}", n));
+    PathBuf::from(s)
+}
+
+/// Hidden `__mail-output`: send this run's output to `addr` via sendmail,
+/// unless it is empty. Reads the output file if given, otherwise the journal
+/// entries of the current invocation.
+pub fn run_mail(name: &str, addr: &str, file: Option<&str>) -> Result<()> {
+    let body = match file {
+        Some(f) => read_since(Path::new(f), load_offset(name))?,
+        None => journal_output()?,
+    };
+    if body.trim().is_empty() {
+        return Ok(());
+    }
+
+    let result = std::env::var("SERVICE_RESULT").unwrap_or_else(|_| "unknown".to_string());
+    // Losing the output over a missing hostname would be worse than an odd subject.
+    let host = hostname().unwrap_or_else(|_| "unknown host".to_string());
+    let message = format_mail(addr, name, &host, &result, &body);
+
+    let sendmail = find_sendmail().context("sendmail not found (looked in PATH, /usr/sbin, /usr/lib)")?;
+    let mut child = Command::new(&sendmail)
+        .args(["-t", "-oi"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", sendmail.display()))?;
+    child
+        .stdin
+        .take()
+        .context("sendmail stdin unavailable")?
+        .write_all(message.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("{} exited with {}", sendmail.display(), status);
+    }
+    Ok(())
+}
+
+fn format_mail(addr: &str, name: &str, host: &str, result: &str, body: &str) -> String {
+    format!(
+        "To: {}\nSubject: [sdtab] {} on {} ({})\nContent-Type: text/plain; charset=utf-8\n\n{}",
+        addr, name, host, result, body
+    )
+}
+
+fn read_since(path: &Path, offset: u64) -> Result<String> {
+    let mut file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+    };
+    let len = file.metadata()?.len();
+    // The file was rotated or truncated since the offset was recorded.
+    let start = if offset > len { 0 } else { offset };
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    file.take(MAIL_MAX_BYTES).read_to_end(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+fn journal_output() -> Result<String> {
+    let Ok(invocation) = std::env::var("INVOCATION_ID") else {
+        bail!("INVOCATION_ID not set; __mail-output must run from ExecStopPost");
+    };
+    let output = Command::new("journalctl")
+        .args([systemctl::scope_flag(), "--no-pager", "-o", "cat"])
+        .arg(format!("_SYSTEMD_INVOCATION_ID={}", invocation))
+        .output()
+        .context("Failed to run journalctl")?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn find_sendmail() -> Option<PathBuf> {
+    let mut candidates: Vec<PathBuf> = std::env::var("PATH")
+        .unwrap_or_default()
+        .split(':')
+        .map(|d| Path::new(d).join("sendmail"))
+        .collect();
+    candidates.push(PathBuf::from("/usr/sbin/sendmail"));
+    candidates.push(PathBuf::from("/usr/lib/sendmail"));
+    candidates.into_iter().find(|p| p.exists())
+}
+
+/// The name `hosts` patterns and plans are matched against: `SDTAB_HOSTNAME`
+/// when set, else under `--root` the image's /etc/hostname (this machine's
+/// name says nothing about where the image runs), else the kernel's.
+pub fn hostname() -> Result<String> {
+    let named = |name: String| Some(name.trim().to_string()).filter(|n| !n.is_empty());
+    if let Some(name) = std::env::var("SDTAB_HOSTNAME").ok().and_then(named) {
+        return Ok(name);
+    }
+    if init::root().is_some() {
+        let path = init::host_path("/etc/hostname");
+        return match fs::read_to_string(&path).ok().and_then(named) {
+            Some(name) => Ok(name),
+            None => bail!("No hostname in {}; set SDTAB_HOSTNAME to the name the image runs as", path),
+        };
+    }
+    let uname = || {
+        let output = Command::new("uname").arg("-n").output().ok().filter(|o| o.status.success())?;
+        named(String::from_utf8_lossy(&output.stdout).to_string())
+    };
+    match fs::read_to_string("/proc/sys/kernel/hostname").ok().and_then(named).or_else(uname) {
+        Some(name) => Ok(name),
+        None => bail!("Could not determine the hostname; set SDTAB_HOSTNAME"),
+    }
+}
+
+fn offset_path(name: &str) -> Result<PathBuf> {
+    let state_dir = init::state_dir()?;
+    Ok(Path::new(&state_dir).join("output").join(format!("{}.offset", name)))
+}
+
+fn save_offset(name: &str, offset: u64) -> Result<()> {
+    let path = offset_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, offset.to_string()).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn load_offset(name: &str) -> u64 {
+    offset_path(name)
+        .ok()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn parse_size_suffixes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("4K").unwrap(), 4096);
+        assert_eq!(parse_size("10M").unwrap(), 10 * 1024 * 1024);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("lots").is_err());
+        assert_eq!(parse_size("99999999999G").unwrap_err().to_string(), "'99999999999G' is too large");
+    }
+
+    #[test]
+    fn rotate_shifts_numbered_files() {
+        let dir = TestDir::new("output");
+        let log = dir.join("job.log");
+
+        fs::write(&log, "first run\n").unwrap();
+        assert!(!rotate(&log, 1024, 2).unwrap());
+        assert!(rotate(&log, 4, 2).unwrap());
+        fs::write(&log, "second run\n").unwrap();
+        assert!(rotate(&log, 4, 2).unwrap());
+        fs::write(&log, "third run\n").unwrap();
+        assert!(rotate(&log, 4, 2).unwrap());
+
+        assert!(!log.exists());
+        assert_eq!(fs::read_to_string(numbered(&log, 1)).unwrap(), "third run\n");
+        assert_eq!(fs::read_to_string(numbered(&log, 2)).unwrap(), "second run\n");
+        assert!(!numbered(&log, 3).exists());
+
+        fs::write(&log, "old\nnew output\n").unwrap();
+        assert_eq!(read_since(&log, 4).unwrap(), "new output\n");
+        assert_eq!(read_since(&log, 999).unwrap(), "old\nnew output\n");
+    }
+
+    #[test]
+    fn resolve_applies_config_defaults() {
+        let defaults = config::OutputConfig {
+            dir: Some("/var/log/jobs/".to_string()),
+            max_size: Some("1M".to_string()),
+            keep: None,
+            mail_to: Some("ops@example.com".to_string()),
+        };
+        let target = resolve("backup", None, None, Some(2), None, &defaults).unwrap();
+        assert_eq!(target.file, Some("/var/log/jobs/backup.log".to_string()));
+        assert_eq!(target.max_size, "1M");
+        assert_eq!(target.keep, 2);
+        assert_eq!(target.mail_to, Some("ops@example.com".to_string()));
+
+        let own = resolve("backup", Some("/tmp/b.log"), None, None, None, &config::OutputConfig::default()).unwrap();
+        assert_eq!(own.file, Some("/tmp/b.log".to_string()));
+        assert_eq!(own.keep, DEFAULT_KEEP);
+        assert!(own.mail_to.is_none());
+
+        assert!(resolve("backup", Some("logs/b.log"), None, None, None, &config::OutputConfig::default()).is_err());
+        assert!(resolve("backup", Some("/tmp/my logs/b.log"), None, None, None, &config::OutputConfig::default()).is_err());
+    }
+
+    #[test]
+    fn mail_has_headers_and_body() {
+        let mail = format_mail("me@example.com", "backup", "host1", "success", "done\n");
+        assert!(mail.starts_with("To: me@example.com\nSubject: [sdtab] backup on host1 (success)\n"));
+        assert!(mail.ends_with("\n\ndone\n"));
+    }
+}
@@ -1,11 +1,15 @@
+use std::collections::BTreeSet;
 use std::fs;
 use std::path::Path;
 
 use anyhow::Result;
+use serde::Serialize;
 
-use crate::init;
+use crate::unit_file::UnitFile;
+use crate::{bluegreen, init, output, systemctl, unit};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum UnitType {
     Timer,
     Service,
@@ -20,7 +24,7 @@ impl UnitType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ParsedUnit {
     pub name: String,
     pub unit_type: UnitType,
@@ -37,11 +41,35 @@ pub struct ParsedUnit {
     pub exec_start_pre: Option<String>,
     pub exec_stop_post: Option<String>,
     pub log_level_max: Option<String>,
+    pub user: Option<String>,
+    pub group: Option<String>,
     pub random_delay: Option<String>,
     pub env: Vec<String>,
     pub no_notify: bool,
+    /// `Type=` of a daemon service. `None` means the default `simple`.
+    pub service_type: Option<String>,
+    pub pid_file: Option<String>,
+    pub watchdog: Option<String>,
+    pub exec_reload: Option<String>,
+    pub health_cmd: Option<String>,
+    pub health_interval: Option<String>,
+    pub health_retries: Option<u32>,
+    /// Blue/green port slots; non-empty means the unit is a `sdtab-<name>@.service` template.
+    pub ports: Vec<u16>,
+    pub deploy_steps: Vec<String>,
+    pub rollback_steps: Vec<String>,
+    pub output_file: Option<String>,
+    pub output_max_size: Option<String>,
+    pub output_keep: Option<u32>,
+    pub mail_to: Option<String>,
+    /// Output handling as rendered into the hooks, config.toml's `[output]`
+    /// defaults included (see [`output::summary`]).
+    pub output: Option<String>,
+    /// Sdtabfile the unit was applied from, and its project, if any.
+    pub origin: Option<String>,
+    pub project: Option<String>,
     /// Template version the service file was generated with.
-    /// Defaults to 1 when `# sdtab:template_version=` is missing (pre-versioning units).
+    /// Defaults to 1 when no template version metadata is present (pre-versioning units).
     pub template_version: u32,
 }
 
@@ -65,33 +93,42 @@ pub fn scan_all_units() -> Result<Vec<ParsedUnit>> {
         let os_name = entry.file_name();
         let filename = os_name.to_string_lossy();
 
-        if !filename.starts_with("sdtab-") || !filename.ends_with(".service") {
+        if !filename.starts_with(init::prefix()) || !filename.ends_with(".service") {
             continue;
         }
 
-        // Skip template units (e.g., sdtab-notify@.service)
-        if filename.contains('@') {
-            continue;
-        }
-
-        let name = filename
-            .strip_prefix("sdtab-")
+        let stem = filename
+            .strip_prefix(init::prefix())
             .unwrap()
             .strip_suffix(".service")
-            .unwrap()
-            .to_string();
-
-        let service_content = fs::read_to_string(entry.path())?;
+            .unwrap();
+
+        // Skip template/instance units (sdtab-notify@.service, sdtab-health@<name>),
+        // except blue/green service templates, which carry ports metadata.
+        // Drop-ins (`<file>.d/*.conf`, e.g. from `sdtab upgrade`) are merged in
+        // so the parsed unit reflects the effective configuration.
+        let (name, service) = if let Some(base) = stem.strip_suffix('@') {
+            let service = UnitFile::load_effective(&entry.path())?;
+            let has_ports = metadata(&service).iter().any(|(k, _)| k == "ports");
+            if !has_ports {
+                continue;
+            }
+            (base.to_string(), service)
+        } else if stem.contains('@') {
+            continue;
+        } else {
+            (stem.to_string(), UnitFile::load_effective(&entry.path())?)
+        };
 
         // Read timer file if it exists (try read directly, handle NotFound)
-        let timer_path = dir_path.join(format!("sdtab-{}.timer", name));
+        let timer_path = dir_path.join(unit::timer_filename(&name));
         let timer_content = match fs::read_to_string(&timer_path) {
             Ok(content) => Some(content),
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
             Err(e) => return Err(e.into()),
         };
 
-        let parsed = parse_service_file(&name, &service_content, timer_content.as_deref(), &global_env_path);
+        let parsed = parse_unit_file(&name, &service, timer_content.as_deref(), &global_env_path);
         units.push(parsed);
     }
 
@@ -99,6 +136,27 @@ pub fn scan_all_units() -> Result<Vec<ParsedUnit>> {
     Ok(units)
 }
 
+/// The unit that is enabled and started for a unit of this name: the timer,
+/// or the service systemd runs (the live slot of a blue/green service).
+pub fn primary_unit(name: &str, unit_type: &UnitType) -> String {
+    match unit_type {
+        UnitType::Timer => unit::timer_filename(name),
+        UnitType::Service => bluegreen::service_unit(name),
+    }
+}
+
+/// Names of `units` left disabled, e.g. by `sdtab disable`.
+pub fn disabled_names(units: &[ParsedUnit]) -> BTreeSet<String> {
+    let primaries: Vec<String> = units.iter().map(|u| primary_unit(&u.name, &u.unit_type)).collect();
+    let props = systemctl::show_many(&primaries, &["UnitFileState"]).unwrap_or_default();
+    units
+        .iter()
+        .zip(&primaries)
+        .filter(|(_, primary)| props.get(primary, "UnitFileState") == Some("disabled"))
+        .map(|(u, _)| u.name.clone())
+        .collect()
+}
+
 /// Shorten a fully-resolved command by extracting just the binary name.
 /// e.g. "/home/user/.cargo/bin/ambient-task-agent serve --port 3100"
 ///    → "ambient-task-agent serve --port 3100"
@@ -120,111 +178,144 @@ fn shorten_command(command: &str) -> String {
     }
 }
 
+/// Parse a single unit file's text, without drop-ins.
+#[cfg(test)]
 pub fn parse_service_file(
     name: &str,
     service_content: &str,
     timer_content: Option<&str>,
     global_env_path: &str,
+) -> ParsedUnit {
+    parse_unit_file(name, &UnitFile::parse(service_content), timer_content, global_env_path)
+}
+
+/// Metadata key/value pairs in file order: legacy `# sdtab:` comments, then
+/// `X-Sdtab-*` keys in `[Unit]`. Keys are kebab-case (`health-cmd`).
+pub fn metadata(service: &UnitFile) -> Vec<(String, String)> {
+    let legacy = service.comments().filter_map(unit::parse_meta);
+    let keys = service
+        .entries()
+        .filter(|(section, key, _)| *section == "Unit" && key.starts_with(unit::META_PREFIX))
+        .filter_map(|(_, key, value)| unit::parse_meta(&format!("{}={}", key, value)));
+    legacy.chain(keys).collect()
+}
+
+/// Build a `ParsedUnit` from the effective unit (base file plus any drop-ins).
+pub fn parse_unit_file(
+    name: &str,
+    service: &UnitFile,
+    timer_content: Option<&str>,
+    global_env_path: &str,
 ) -> ParsedUnit {
     let mut unit_type = UnitType::Timer;
     let mut cron_expr = None;
-    let mut command = String::new();
     let mut original_command: Option<String> = None;
-    let mut workdir = String::new();
-    let mut description = String::new();
     let mut restart_policy = None;
-    let mut env_file = None;
-    let mut memory_max = None;
-    let mut cpu_quota = None;
-    let mut io_weight = None;
-    let mut timeout_stop = None;
-    let mut exec_start_pre = None;
-    let mut exec_stop_post = None;
-    let mut log_level_max = None;
-    let mut env = Vec::new();
     let mut no_notify = false;
-    // Units without `# sdtab:template_version=` are pre-versioning (implicit v1).
+    let mut health_cmd = None;
+    let mut health_interval = None;
+    let mut health_retries = None;
+    let mut ports = Vec::new();
+    let mut deploy_steps = Vec::new();
+    let mut rollback_steps = Vec::new();
+    let mut output_file = None;
+    let mut output_max_size = None;
+    let mut output_keep = None;
+    let mut mail_to = None;
+    let mut origin = None;
+    let mut project = None;
+    // Units without a template version are pre-versioning (implicit v1).
     let mut template_version: u32 = 1;
 
-    for line in service_content.lines() {
-        let line = line.trim();
-
-        // Metadata comments
-        if line == "# sdtab:type=service" {
-            unit_type = UnitType::Service;
-        } else if line == "# sdtab:type=timer" {
-            unit_type = UnitType::Timer;
-        }
-        if let Some(val) = line.strip_prefix("# sdtab:cron=") {
-            cron_expr = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("# sdtab:restart=") {
-            restart_policy = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("# sdtab:command=") {
-            original_command = Some(val.to_string());
-        }
-        if line == "# sdtab:no-notify=true" {
-            no_notify = true;
-        }
-        if let Some(val) = line.strip_prefix("# sdtab:template_version=") {
-            if let Ok(v) = val.parse::<u32>() {
-                template_version = v;
+    for (key, val) in metadata(service) {
+        match key.as_str() {
+            "type" if val == "service" => unit_type = UnitType::Service,
+            "type" if val == "timer" => unit_type = UnitType::Timer,
+            "cron" => cron_expr = Some(val),
+            "restart" => restart_policy = Some(val),
+            "command" => original_command = Some(val),
+            "no-notify" => no_notify = val == "true",
+            "template-version" => {
+                if let Ok(v) = val.parse::<u32>() {
+                    template_version = v;
+                }
+            }
+            "health-cmd" => health_cmd = Some(val),
+            "health-interval" => health_interval = Some(val),
+            "health-retries" => health_retries = val.parse::<u32>().ok(),
+            "output-file" => output_file = Some(val),
+            "output-max-size" => output_max_size = Some(val),
+            "output-keep" => output_keep = val.parse::<u32>().ok(),
+            "mail-to" => mail_to = Some(val),
+            "origin" => origin = Some(val),
+            "project" => project = Some(val),
+            "deploy-step" => deploy_steps.push(val),
+            "rollback-step" => rollback_steps.push(val),
+            "ports" => {
+                ports = val.split(',').filter_map(|p| p.trim().parse::<u16>().ok()).collect();
             }
+            _ => {}
         }
+    }
 
-        // Unit file directives
-        if let Some(val) = line.strip_prefix("ExecStart=") {
-            command = val.to_string();
-        }
-        if let Some(val) = line.strip_prefix("WorkingDirectory=") {
-            workdir = val.to_string();
-        }
-        if let Some(val) = line.strip_prefix("Description=[sdtab] ") {
-            // Format: "name: desc" — extract description part
-            if let Some(pos) = val.find(": ") {
-                description = val[pos + 2..].to_string();
-            } else {
-                description = val.to_string();
-            }
+    let get = |key: &str| service.get("Service", key).map(|v| v.to_string());
+    let mut command = get("ExecStart").unwrap_or_default();
+    let workdir = get("WorkingDirectory").unwrap_or_default();
+    // Format: "[sdtab] name: desc" — extract description part
+    let mut description = match service.get("Unit", "Description").and_then(|d| d.strip_prefix("[sdtab] ")) {
+        Some(val) => match val.find(": ") {
+            Some(pos) => val[pos + 2..].to_string(),
+            None => val.to_string(),
+        },
+        None => String::new(),
+    };
+    let memory_max = get("MemoryMax");
+    let cpu_quota = get("CPUQuota");
+    let io_weight = get("IOWeight");
+    let timeout_stop = get("TimeoutStopSec");
+    let log_level_max = get("LogLevelMax");
+    let user = get("User");
+    let group = get("Group");
+    let raw_type = get("Type");
+    let pid_file = get("PIDFile");
+    let watchdog = get("WatchdogSec");
+    let exec_reload = get("ExecReload");
+    // sdtab models a single user hook; the generated output hooks are not part of it.
+    let exec_start_pre = service
+        .get_all("Service", "ExecStartPre")
+        .into_iter()
+        .rfind(|v| !unit::is_output_hook(v))
+        .map(|v| v.to_string());
+    let exec_stop_post = service
+        .get_all("Service", "ExecStopPost")
+        .into_iter()
+        .rfind(|v| !unit::is_output_hook(v))
+        .map(|v| v.to_string());
+    let env: Vec<String> = service.get_all("Service", "Environment").into_iter().map(|v| v.to_string()).collect();
+    let mut env_file = None;
+    for val in service.get_all("Service", "EnvironmentFile") {
+        // Blue/green slot env file is generated, not user-provided
+        if val.ends_with("/%i.env") {
+            continue;
         }
-        if let Some(val) = line.strip_prefix("EnvironmentFile=") {
-            // Skip global env file (starts with -)
-            if let Some(path) = val.strip_prefix('-') {
-                // Global env file — skip if it matches the known global path
-                if path != global_env_path {
-                    env_file = Some(path.to_string());
-                }
-            } else {
-                env_file = Some(val.to_string());
+        // Skip global env file (starts with -)
+        if let Some(path) = val.strip_prefix('-') {
+            // Global env file — skip if it matches the known global path
+            if path != global_env_path {
+                env_file = Some(path.to_string());
             }
-        }
-        if let Some(val) = line.strip_prefix("MemoryMax=") {
-            memory_max = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("CPUQuota=") {
-            cpu_quota = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("IOWeight=") {
-            io_weight = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("TimeoutStopSec=") {
-            timeout_stop = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("ExecStartPre=") {
-            exec_start_pre = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("ExecStopPost=") {
-            exec_stop_post = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("LogLevelMax=") {
-            log_level_max = Some(val.to_string());
-        }
-        if let Some(val) = line.strip_prefix("Environment=") {
-            env.push(val.to_string());
+        } else {
+            env_file = Some(val.to_string());
         }
     }
 
+    // Timers are always Type=oneshot; for daemons `simple` is the default and
+    // is reported as None so it round-trips to an omitted Sdtabfile key.
+    let service_type = match unit_type {
+        UnitType::Service => raw_type.filter(|t| t != "simple"),
+        UnitType::Timer => None,
+    };
+
     // Parse timer content for random delay
     let mut random_delay = None;
     if let Some(timer) = timer_content {
@@ -264,13 +355,59 @@ pub fn parse_service_file(
         exec_start_pre,
         exec_stop_post,
         log_level_max,
+        user,
+        group,
         random_delay,
         env,
         no_notify,
+        service_type,
+        pid_file,
+        watchdog,
+        exec_reload,
+        health_cmd,
+        health_interval,
+        health_retries,
+        ports,
+        deploy_steps,
+        rollback_steps,
+        output_file,
+        output_max_size,
+        output_keep,
+        mail_to,
+        output: rendered_output(service),
+        origin,
+        project,
         template_version,
     }
 }
 
+/// Read back the hooks `unit::output_lines` renders.
+fn rendered_output(service: &UnitFile) -> Option<String> {
+    let mut target = unit::OutputTarget {
+        file: None,
+        max_size: String::new(),
+        keep: 0,
+        mail_to: None,
+        sdtab_bin: String::new(),
+    };
+    for hook in service.get_all("Service", "ExecStartPre") {
+        // `<bin> __output-rotate <name> <max size> <keep> "<file>"`
+        let Some((_, args)) = hook.split_once(" __output-rotate ") else { continue };
+        if let [_, max_size, keep, file] = args.splitn(4, ' ').collect::<Vec<_>>()[..] {
+            target.max_size = max_size.to_string();
+            target.keep = keep.parse().unwrap_or_default();
+            target.file = Some(file.trim_matches('"').replace("%%", "%"));
+        }
+    }
+    for hook in service.get_all("Service", "ExecStopPost") {
+        // `<bin> __mail-output <name> <address> ["<file>"]`
+        if let Some((_, args)) = hook.split_once(" __mail-output ") {
+            target.mail_to = args.split(' ').nth(1).map(str::to_string);
+        }
+    }
+    output::summary(&target)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -352,7 +489,7 @@ EnvironmentFile=-/home/user/.config/sdtab/env
 ";
 
         let parsed = parse_service_file("task", service, None, "/home/user/.config/sdtab/env");
-        // No sdtab:command, so falls back to ExecStart (shortened)
+        // No X-Sdtab-Command, so falls back to ExecStart (shortened)
         assert_eq!(parsed.command, "echo hello");
     }
 
@@ -450,7 +587,7 @@ Restart=always
 RestartSec=5
 ";
 
-        // No sdtab:command= metadata, so should shorten ExecStart
+        // No X-Sdtab-Command= metadata, so should shorten ExecStart
         let parsed = parse_service_file("agent", service, None, "");
         assert_eq!(parsed.command, "ambient-task-agent serve --port 3100");
     }
@@ -535,6 +672,54 @@ SyslogIdentifier=sdtab-task
         assert_eq!(parsed.template_version, 2);
     }
 
+    #[test]
+    fn parse_x_sdtab_keys() {
+        let service = "\
+[Unit]
+X-Sdtab-Type=service
+X-Sdtab-Template-Version=5
+X-Sdtab-Restart=always
+X-Sdtab-Deploy-Step=git pull
+X-Sdtab-Deploy-Step=make \\\\\\n  install
+Description=[sdtab] web: web
+
+[Service]
+Type=simple
+ExecStart=/usr/bin/web
+WorkingDirectory=/srv/web
+";
+
+        let parsed = parse_service_file("web", service, None, "");
+        assert!(matches!(parsed.unit_type, UnitType::Service));
+        assert_eq!(parsed.template_version, 5);
+        assert_eq!(parsed.restart_policy, Some("always".to_string()));
+        assert_eq!(parsed.deploy_steps, vec!["git pull", "make \\\n  install"]);
+        assert_eq!(parsed.description, "web");
+    }
+
+    #[test]
+    fn parse_effective_config_with_dropins() {
+        let mut service = UnitFile::parse("\
+[Unit]
+X-Sdtab-Type=service
+Description=[sdtab] api: api
+
+[Service]
+ExecStart=/usr/bin/api \\
+  --port 8080
+ExecStartPre=/bin/migrate
+MemoryMax=512M
+Environment=A=1
+");
+        service.merge(&UnitFile::parse("[Service]\nMemoryMax=1G\nExecStartPre=\nEnvironment=B=2\n"));
+
+        let parsed = parse_unit_file("api", &service, None, "");
+        assert_eq!(parsed.command, "api --port 8080");
+        assert_eq!(parsed.memory_max, Some("1G".to_string()));
+        assert_eq!(parsed.exec_start_pre, None);
+        assert_eq!(parsed.env, vec!["A=1", "B=2"]);
+    }
+
     #[test]
     fn parse_without_on_failure() {
         let service = "\
@@ -552,4 +737,71 @@ WorkingDirectory=/home/user
         let parsed = parse_service_file("task", service, None, "");
         assert!(!parsed.no_notify);
     }
+
+    #[test]
+    fn parse_notify_service_with_watchdog() {
+        let service = "\
+# sdtab:type=service
+# sdtab:restart=always
+[Unit]
+Description=[sdtab] api: api
+
+[Service]
+Type=notify
+ExecStart=/usr/bin/api
+ExecReload=/bin/kill -HUP $MAINPID
+WatchdogSec=30s
+NotifyAccess=main
+WorkingDirectory=/home/user
+Restart=always
+";
+
+        let parsed = parse_service_file("api", service, None, "");
+        assert_eq!(parsed.service_type, Some("notify".to_string()));
+        assert_eq!(parsed.watchdog, Some("30s".to_string()));
+        assert_eq!(parsed.exec_reload, Some("/bin/kill -HUP $MAINPID".to_string()));
+        assert!(parsed.pid_file.is_none());
+    }
+
+    #[test]
+    fn parse_simple_and_oneshot_type_is_none() {
+        let daemon = "# sdtab:type=service\n[Service]\nType=simple\nExecStart=/usr/bin/app\n";
+        assert!(parse_service_file("app", daemon, None, "").service_type.is_none());
+
+        let timer = "# sdtab:type=timer\n[Service]\nType=oneshot\nExecStart=/usr/bin/job\n";
+        assert!(parse_service_file("job", timer, None, "").service_type.is_none());
+    }
+
+    #[test]
+    fn parse_health_metadata() {
+        let service = "\
+# sdtab:type=service
+# sdtab:health-cmd=curl -fs localhost:3000/health
+# sdtab:health-interval=15s
+# sdtab:health-retries=5
+[Service]
+ExecStart=/usr/bin/node server.js
+";
+
+        let parsed = parse_service_file("web", service, None, "");
+        assert_eq!(parsed.health_cmd, Some("curl -fs localhost:3000/health".to_string()));
+        assert_eq!(parsed.health_interval, Some("15s".to_string()));
+        assert_eq!(parsed.health_retries, Some(5));
+    }
+
+    #[test]
+    fn parse_blue_green_template() {
+        let service = "\
+# sdtab:type=service
+# sdtab:ports=3000,3001
+[Service]
+ExecStart=/usr/bin/node server.js
+EnvironmentFile=-/home/user/.config/sdtab/env
+EnvironmentFile=/home/user/.config/sdtab/slots/web/%i.env
+";
+
+        let parsed = parse_service_file("web", service, None, "/home/user/.config/sdtab/env");
+        assert_eq!(parsed.ports, vec![3000, 3001]);
+        assert!(parsed.env_file.is_none());
+    }
 }
@@ -0,0 +1,256 @@
+//! `sdtab plan`: compute what `apply` would do and save it as JSON, so CI can
+//! review the plan and `sdtab apply plan.json` can carry out exactly that.
+//!
+//! The plan records a hash of the installed unit files. Applying it is
+//! refused when they changed in between, since the actions were computed
+//! against the old state.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::sdtabfile::{self, Sdtabfile, ServiceEntry, TimerEntry};
+use crate::{apply, init, output};
+
+/// Bumped when the plan format changes incompatibly.
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    /// Host the plan was made on
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub prune: bool,
+    /// Hash of the installed unit files the actions were computed against
+    pub state_hash: String,
+    pub actions: Vec<Action>,
+    /// The resolved Sdtabfile the actions lead to
+    pub desired: Desired,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Action {
+    pub name: String,
+    /// `timer` or `service`
+    #[serde(rename = "type")]
+    pub unit_type: String,
+    /// `add`, `change` or `remove`
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<Change>,
+    /// Units started or restarted by this action
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restarts: Vec<String>,
+}
+
+/// Values as `apply` prints them: strings quoted, `(unset)` when absent.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Change {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// An [`Sdtabfile`] including the bookkeeping `load` adds, which its own
+/// serialization leaves out.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Desired {
+    #[serde(default)]
+    pub timers: BTreeMap<String, TimerEntry>,
+    #[serde(default)]
+    pub services: BTreeMap<String, ServiceEntry>,
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub origins: BTreeMap<String, String>,
+    #[serde(default)]
+    pub projects: BTreeMap<String, String>,
+    #[serde(default)]
+    pub scope: Vec<String>,
+    #[serde(default)]
+    pub scope_projects: BTreeSet<String>,
+}
+
+impl Desired {
+    pub fn from_sdtabfile(file: &Sdtabfile) -> Self {
+        Desired {
+            timers: file.timers.clone(),
+            services: file.services.clone(),
+            files: file.files.clone(),
+            origins: file.origins.clone(),
+            projects: file.projects.clone(),
+            scope: file.scope.clone(),
+            scope_projects: file.scope_projects.clone(),
+        }
+    }
+
+    pub fn into_sdtabfile(self) -> Sdtabfile {
+        Sdtabfile {
+            timers: self.timers,
+            services: self.services,
+            files: self.files,
+            origins: self.origins,
+            projects: self.projects,
+            scope: self.scope,
+            scope_projects: self.scope_projects,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn run(file: &str, output: Option<&str>, prune: bool, profile: Option<&str>) -> Result<()> {
+    let target = sdtabfile::Target::current(profile)?;
+    let sdtabfile = sdtabfile::load(file, &target)?;
+    let state_hash = state_hash()?;
+    let actions = apply::plan_actions(&sdtabfile, prune)?;
+
+    let summary = |action: &str| actions.iter().filter(|a| a.action == action).count();
+    let (added, changed, removed) = (summary("add"), summary("change"), summary("remove"));
+    let plan = Plan {
+        version: VERSION,
+        host: output::hostname()?,
+        profile: target.profile,
+        prune,
+        state_hash,
+        actions,
+        desired: Desired::from_sdtabfile(&sdtabfile),
+    };
+    let text = serde_json::to_string_pretty(&plan).context("Failed to serialize plan")? + "\n";
+
+    match output {
+        Some(path) => {
+            fs::write(path, &text).with_context(|| format!("Failed to write {}", path))?;
+            println!(
+                "Plan: {} to add, {} to update, {} to remove. Saved to: {}",
+                added, changed, removed, path
+            );
+            println!("Apply it with: sdtab apply {}", path);
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Whether `file` names a saved plan rather than an Sdtabfile.
+pub fn is_plan(file: &str) -> bool {
+    file.ends_with(".json")
+}
+
+/// Read a plan and check it still fits this host.
+pub fn load(path: &str) -> Result<Plan> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let plan: Plan = serde_json::from_str(&content).with_context(|| format!("Failed to parse {}", path))?;
+    if plan.version != VERSION {
+        bail!("{} is a version {} plan; this sdtab reads version {}", path, plan.version, VERSION);
+    }
+    let hostname = output::hostname()?;
+    if plan.host != hostname {
+        bail!("{} was made on {}, not on this host ({})", path, plan.host, hostname);
+    }
+    if state_hash()? != plan.state_hash {
+        bail!(
+            "Units changed since {} was made. Run sdtab plan again and review the new plan",
+            path
+        );
+    }
+    Ok(plan)
+}
+
+/// [`hash`] of every sdtab unit file in the unit directory, by name and
+/// content, with their drop-ins (`<unit>.d/`) and enablement links
+/// (`<target>.wants/`). Stable across builds, unlike `DefaultHasher`.
+pub fn state_hash() -> Result<String> {
+    let unit_dir = init::unit_dir()?;
+    let mut files = Vec::new();
+    for entry in read_dir(Path::new(&unit_dir))? {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() && (file_name.starts_with(init::prefix()) || file_name.ends_with(".wants")) {
+            for inner in read_dir(&entry.path())? {
+                let inner_name = inner.file_name().to_string_lossy().to_string();
+                if file_name.ends_with(".wants") && !inner_name.starts_with(init::prefix()) {
+                    continue;
+                }
+                files.push((format!("{}/{}", file_name, inner_name), unit_state(&inner.path())?));
+            }
+        } else if file_name.starts_with(init::prefix()) && file_type.is_file() {
+            files.push((file_name, unit_state(&entry.path())?));
+        }
+    }
+    files.sort();
+    Ok(hash(files.iter().flat_map(|(file_name, content)| [file_name.as_bytes(), content.as_slice()])))
+}
+
+/// The entries of `dir`; none when it doesn't exist.
+fn read_dir(dir: &Path) -> Result<Vec<fs::DirEntry>> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.collect::<std::io::Result<_>>().with_context(|| format!("Failed to read {}", dir.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    }
+}
+
+/// A link's target, or a file's content.
+fn unit_state(path: &Path) -> Result<Vec<u8>> {
+    if let Ok(target) = fs::read_link(path) {
+        return Ok(target.to_string_lossy().as_bytes().to_vec());
+    }
+    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// FNV-1a of `parts`, each followed by a NUL so boundaries count, as hex.
+pub fn hash<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.iter().copied().chain([0]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn state_hash_follows_unit_files() {
+        let home = TestDir::new("plan");
+        init::with_home(&home, || {
+            let empty = state_hash().unwrap();
+            let unit_dir = init::unit_dir().unwrap();
+            fs::create_dir_all(&unit_dir).unwrap();
+            fs::write(format!("{}/other.service", unit_dir), "[Service]\n").unwrap();
+            assert_eq!(state_hash().unwrap(), empty, "only sdtab units count");
+
+            fs::write(format!("{}/sdtab-a.service", unit_dir), "[Service]\nExecStart=a\n").unwrap();
+            let one = state_hash().unwrap();
+            assert_ne!(one, empty);
+            fs::write(format!("{}/sdtab-a.service", unit_dir), "[Service]\nExecStart=b\n").unwrap();
+            let two = state_hash().unwrap();
+            assert_ne!(two, one);
+
+            fs::create_dir_all(format!("{}/sdtab-a.service.d", unit_dir)).unwrap();
+            fs::write(format!("{}/sdtab-a.service.d/limit.conf", unit_dir), "[Service]\nMemoryMax=1G\n").unwrap();
+            let dropin = state_hash().unwrap();
+            assert_ne!(dropin, two, "drop-ins count");
+
+            let wants = format!("{}/default.target.wants", unit_dir);
+            fs::create_dir_all(&wants).unwrap();
+            std::os::unix::fs::symlink("/elsewhere/other.service", format!("{}/other.service", wants)).unwrap();
+            assert_eq!(state_hash().unwrap(), dropin, "only sdtab links count");
+            std::os::unix::fs::symlink(format!("{}/sdtab-a.service", unit_dir), format!("{}/sdtab-a.service", wants))
+                .unwrap();
+            assert_ne!(state_hash().unwrap(), dropin, "enabling counts");
+        });
+    }
+}
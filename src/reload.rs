@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::unit_file::UnitFile;
+use crate::{bluegreen, init, parse_unit, systemctl, unit};
+
+/// Reload a service's configuration in place via its `ExecReload=` command,
+/// without restarting the main process.
+pub fn run(name: &str) -> Result<()> {
+    let unit_dir = init::unit_dir()?;
+    let dir_path = Path::new(&unit_dir);
+
+    let service_path = dir_path.join(bluegreen::definition_filename(name));
+    let timer_path = dir_path.join(unit::timer_filename(name));
+
+    if !service_path.exists() {
+        bail!("'{}' not found.", name);
+    }
+    if timer_path.exists() {
+        bail!("'{}' is a timer; only services can be reloaded.", name);
+    }
+
+    let service = UnitFile::load_effective(&service_path)?;
+    let global_env_path = init::global_env_path().unwrap_or_default();
+    let parsed = parse_unit::parse_unit_file(name, &service, None, &global_env_path);
+    if parsed.exec_reload.is_none() {
+        bail!(
+            "'{}' has no reload command. Recreate it with --exec-reload, or use: sdtab restart {}",
+            name,
+            name
+        );
+    }
+
+    systemctl::reload(&bluegreen::service_unit(name))?;
+    println!("Reloaded service '{}'.", name);
+
+    Ok(())
+}
@@ -3,13 +3,13 @@ use std::path::Path;
 
 use anyhow::{bail, Result};
 
-use crate::{init, systemctl, unit};
+use crate::{bluegreen, health, init, systemctl, unit};
 
 pub fn run(name: &str) -> Result<()> {
     let unit_dir = init::unit_dir()?;
     let dir_path = Path::new(&unit_dir);
 
-    let service_path = dir_path.join(unit::service_filename(name));
+    let service_path = dir_path.join(bluegreen::definition_filename(name));
     let timer_path = dir_path.join(unit::timer_filename(name));
 
     if !timer_path.exists() && !service_path.exists() {
@@ -24,22 +24,44 @@ pub fn run(name: &str) -> Result<()> {
         if let Err(e) = systemctl::stop_and_disable(&timer_unit) {
             eprintln!("Warning: failed to disable timer: {}", e);
         }
+    } else if bluegreen::is_blue_green(name) {
+        for slot in bluegreen::SLOTS {
+            if let Err(e) = systemctl::stop_and_disable(&unit::instance_filename(name, slot)) {
+                eprintln!("Warning: failed to disable {} slot: {}", slot, e);
+            }
+        }
     } else {
-        let service_unit = unit::service_filename(name);
+        let service_unit = bluegreen::service_unit(name);
         if let Err(e) = systemctl::stop_and_disable(&service_unit) {
             eprintln!("Warning: failed to disable service: {}", e);
         }
     }
 
     // Remove unit files
+    for path in health::remove_units(name, dir_path)? {
+        println!("Removed: {}", path.display());
+    }
     if service_path.exists() {
+        let blue_green = bluegreen::is_blue_green(name);
         fs::remove_file(&service_path)?;
         println!("Removed: {}", service_path.display());
+        if blue_green {
+            bluegreen::remove_slots(name)?;
+        }
     }
     if timer_path.exists() {
         fs::remove_file(&timer_path)?;
         println!("Removed: {}", timer_path.display());
     }
+    // Drop-ins from `sdtab upgrade` / `sdtab adopt`
+    for path in [&service_path, &timer_path] {
+        let mut dropin_dir = path.as_os_str().to_owned();
+        dropin_dir.push(".d");
+        if Path::new(&dropin_dir).exists() {
+            fs::remove_dir_all(&dropin_dir)?;
+            println!("Removed: {}", Path::new(&dropin_dir).display());
+        }
+    }
 
     // Reload daemon
     systemctl::daemon_reload()?;
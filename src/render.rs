@@ -0,0 +1,132 @@
+//! `sdtab render`: write an Sdtabfile's units into a directory tree without
+//! talking to systemd, for baking them into images (`--root ./image`).
+//!
+//! Enabling is done the way `systemctl enable` does it on disk: a symlink
+//! `<WantedBy>.wants/<unit>` pointing at the unit file, so the units start
+//! on the first boot (or login) of the image.
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::unit_file::UnitFile;
+use crate::{apply, bluegreen, export, init, parse_unit, sdtabfile, unit};
+
+pub fn run(file: &str, profile: Option<&str>) -> Result<()> {
+    let sdtabfile = sdtabfile::load(file, &sdtabfile::Target::current(profile)?)?;
+    let unit_dir = init::unit_dir()?;
+    fs::create_dir_all(&unit_dir).with_context(|| format!("Failed to create {}", unit_dir))?;
+
+    let mut enable = Vec::new();
+    for (name, entry) in &sdtabfile.timers {
+        apply::write_entry(&sdtabfile, name, &parse_unit::UnitType::Timer)?;
+        if entry.enabled {
+            enable.push(unit::timer_filename(name));
+        }
+    }
+    for (name, entry) in &sdtabfile.services {
+        apply::write_entry(&sdtabfile, name, &parse_unit::UnitType::Service)?;
+        if !entry.enabled {
+            continue;
+        }
+        enable.push(bluegreen::service_unit(name));
+        if entry.health_cmd.is_some() {
+            enable.push(unit::health_timer_filename(name));
+        }
+    }
+
+    for unit_name in &enable {
+        for link in enable_unit(Path::new(&unit_dir), &init::target_unit_dir()?, unit_name)? {
+            println!("Enabled: {}", link);
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for entry in fs::read_dir(&unit_dir).with_context(|| format!("Failed to read {}", unit_dir))? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.starts_with(init::prefix()) || !entry.file_type()?.is_file() {
+            continue;
+        }
+        let content = fs::read_to_string(entry.path())?;
+        warnings.extend(export::unit_warnings(&file_name, &content));
+    }
+    warnings.sort();
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    println!(
+        "Rendered {} timer(s) and {} service(s) into {}",
+        sdtabfile.timers.len(),
+        sdtabfile.services.len(),
+        unit_dir
+    );
+    Ok(())
+}
+
+/// Create the `WantedBy=` symlinks for `unit_name`, whose file lives in
+/// `host_dir` (the unit dir as seen from here) and `target_dir` (as the
+/// image will see it). Instances without a file of their own link to their
+/// template. Returns the links.
+fn enable_unit(host_dir: &Path, target_dir: &str, unit_name: &str) -> Result<Vec<String>> {
+    let file_name = match unit_name.split_once('@').filter(|_| !host_dir.join(unit_name).exists()) {
+        Some((prefix, rest)) => match rest.rsplit_once('.') {
+            Some((_, suffix)) => format!("{}@.{}", prefix, suffix),
+            None => unit_name.to_string(),
+        },
+        None => unit_name.to_string(),
+    };
+    let path = host_dir.join(&file_name);
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let parsed = UnitFile::parse(&content);
+
+    let mut links = Vec::new();
+    for target in parsed.get_all("Install", "WantedBy").iter().flat_map(|v| v.split_whitespace()) {
+        let wants = host_dir.join(format!("{}.wants", target));
+        fs::create_dir_all(&wants).with_context(|| format!("Failed to create {}", wants.display()))?;
+        let link = wants.join(unit_name);
+        if fs::symlink_metadata(&link).is_ok() {
+            fs::remove_file(&link).with_context(|| format!("Failed to replace {}", link.display()))?;
+        }
+        symlink(format!("{}/{}", target_dir, file_name), &link)
+            .with_context(|| format!("Failed to create {}", link.display()))?;
+        links.push(link.display().to_string());
+    }
+    Ok(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn links_instances_to_their_template() {
+        let dir = TestDir::new("render");
+        fs::write(dir.join("sdtab-web@.service"), "[Service]\nExecStart=/bin/true\n\n[Install]\nWantedBy=default.target\n")
+            .unwrap();
+        fs::write(dir.join("sdtab-backup.timer"), "[Timer]\nOnCalendar=daily\n").unwrap();
+        fs::write(dir.join("sdtab-health@web.timer"), "[Install]\nWantedBy=timers.target\n").unwrap();
+
+        let links = enable_unit(&dir, "/etc/xdg/systemd/user", "sdtab-web@blue.service").unwrap();
+        let link = dir.join("default.target.wants/sdtab-web@blue.service");
+        assert_eq!(links, vec![link.display().to_string()]);
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            Path::new("/etc/xdg/systemd/user/sdtab-web@.service")
+        );
+        // Rendering again replaces the link instead of failing.
+        enable_unit(&dir, "/etc/xdg/systemd/user", "sdtab-web@blue.service").unwrap();
+        // Health timers are instance-named but have their own file.
+        enable_unit(&dir, "/etc/xdg/systemd/user", "sdtab-health@web.timer").unwrap();
+        assert_eq!(
+            fs::read_link(dir.join("timers.target.wants/sdtab-health@web.timer")).unwrap(),
+            Path::new("/etc/xdg/systemd/user/sdtab-health@web.timer")
+        );
+        // No [Install] section: nothing to enable.
+        assert!(enable_unit(&dir, "/etc/xdg/systemd/user", "sdtab-backup.timer").unwrap().is_empty());
+    }
+}
@@ -5,12 +5,15 @@
 //! timer is skipped with a warning. The loop continues past individual
 //! failures so users see the status of every requested service before the
 //! command exits non-zero.
+//!
+//! `--strategy blue-green` switches services declared with `--ports` to their
+//! idle slot instead, so they keep serving during the restart.
 
 use anyhow::{bail, Result};
 
-use crate::{parse_unit, systemctl, unit};
+use crate::{bluegreen, parse_unit, systemctl, RestartStrategy};
 
-pub fn run(names: &[String], all: bool) -> Result<()> {
+pub fn run(names: &[String], all: bool, strategy: RestartStrategy) -> Result<()> {
     let units = parse_unit::scan_all_units()?;
 
     let selected: Vec<&parse_unit::ParsedUnit> = if all {
@@ -31,8 +34,11 @@ pub fn run(names: &[String], all: bool) -> Result<()> {
 
     let mut failed = 0usize;
     for svc in &selected {
-        let unit_name = unit::service_filename(&svc.name);
-        match systemctl::restart(&unit_name) {
+        let result = match strategy {
+            RestartStrategy::Hard => systemctl::restart(&bluegreen::service_unit(&svc.name)),
+            RestartStrategy::BlueGreen => bluegreen::restart(svc),
+        };
+        match result {
             Ok(_) => println!("✓ restarted {}", svc.name),
             Err(e) => {
                 eprintln!("✗ {}: {}", svc.name, e);
@@ -0,0 +1,399 @@
+//! The keys of an Sdtabfile entry and the form of their values.
+//!
+//! One table drives three things: rejecting unknown keys (with a "did you
+//! mean" hint) when a file is parsed, the value checks of `sdtab validate`,
+//! and the JSON Schema published as `sdtabfile.schema.json` for editors.
+
+use serde_json::{json, Map, Value};
+
+use crate::cron;
+
+/// What a key's value looks like.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Text,
+    /// Cron expression or `@` shorthand
+    Schedule,
+    /// Directory or file that must exist on the host
+    Path,
+    /// `MemoryMax=`: bytes with K/M/G/T suffix, a percentage or `infinity`
+    Size,
+    /// Output rotation size: bytes with K/M/G suffix
+    OutputSize,
+    /// systemd time span: `30s`, `5min`, `1h 30min`
+    Duration,
+    /// `CPUQuota=`: `50%`, `200%`
+    Percent,
+    /// `IOWeight=`: 1 to 10000
+    Weight,
+    Bool,
+    Count,
+    Strings,
+    Ports,
+    Choice(&'static [&'static str]),
+}
+
+pub struct Key {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub description: &'static str,
+}
+
+const fn key(name: &'static str, kind: Kind, description: &'static str) -> Key {
+    Key { name, kind, description }
+}
+
+const LOG_LEVELS: &[&str] = &["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+pub const TIMER_KEYS: &[Key] = &[
+    key("schedule", Kind::Schedule, "Cron expression or @daily, @hourly, @reboot …"),
+    key("command", Kind::Text, "Command to run, relative to workdir"),
+    key("workdir", Kind::Path, "Working directory"),
+    key("description", Kind::Text, "Shown by sdtab list (default: the command)"),
+    key("env_file", Kind::Path, "EnvironmentFile= for this unit"),
+    key("memory_max", Kind::Size, "MemoryMax=, e.g. 512M"),
+    key("cpu_quota", Kind::Percent, "CPUQuota=, e.g. 50%"),
+    key("io_weight", Kind::Weight, "IOWeight=, 1-10000"),
+    key("timeout_stop", Kind::Duration, "TimeoutStopSec=, e.g. 30s"),
+    key("exec_start_pre", Kind::Text, "Command run before the main command"),
+    key("exec_stop_post", Kind::Text, "Command run after the unit stops"),
+    key("log_level_max", Kind::Choice(LOG_LEVELS), "LogLevelMax="),
+    key("user", Kind::Text, "Run as this user (system mode)"),
+    key("group", Kind::Text, "Run as this group (system mode)"),
+    key("random_delay", Kind::Duration, "RandomizedDelaySec=, e.g. 10m"),
+    key("env", Kind::Strings, "Environment variables, KEY=value"),
+    key("no_notify", Kind::Bool, "Skip the Slack failure notification"),
+    key("enabled", Kind::Bool, "false installs the unit disabled and stopped (default: true)"),
+    key("output_file", Kind::Text, "Append stdout/stderr to this file"),
+    key("output_max_size", Kind::OutputSize, "Rotate output_file beyond this size, e.g. 10M"),
+    key("output_keep", Kind::Count, "Rotated output files to keep"),
+    key("mail_to", Kind::Text, "Mail non-empty output of each run to this address"),
+    key("hosts", Kind::Strings, "Only apply on hosts matching one of these patterns"),
+];
+
+pub const SERVICE_KEYS: &[Key] = &[
+    key("command", Kind::Text, "Command to run, relative to workdir"),
+    key("workdir", Kind::Path, "Working directory"),
+    key("description", Kind::Text, "Shown by sdtab list (default: the command)"),
+    key("restart", Kind::Choice(&["always", "on-failure", "no"]), "Restart= policy (default: always)"),
+    key("env_file", Kind::Path, "EnvironmentFile= for this unit"),
+    key("memory_max", Kind::Size, "MemoryMax=, e.g. 512M"),
+    key("cpu_quota", Kind::Percent, "CPUQuota=, e.g. 50%"),
+    key("io_weight", Kind::Weight, "IOWeight=, 1-10000"),
+    key("timeout_stop", Kind::Duration, "TimeoutStopSec=, e.g. 30s"),
+    key("exec_start_pre", Kind::Text, "Command run before the main command"),
+    key("exec_stop_post", Kind::Text, "Command run after the unit stops"),
+    key("log_level_max", Kind::Choice(LOG_LEVELS), "LogLevelMax="),
+    key("user", Kind::Text, "Run as this user (system mode)"),
+    key("group", Kind::Text, "Run as this group (system mode)"),
+    key("env", Kind::Strings, "Environment variables, KEY=value"),
+    key("no_notify", Kind::Bool, "Skip the Slack failure notification"),
+    key("enabled", Kind::Bool, "false installs the unit disabled and stopped (default: true)"),
+    key("type", Kind::Choice(&["simple", "exec", "notify", "forking"]), "Type= (default: simple)"),
+    key("pid_file", Kind::Text, "PIDFile=, with type = \"forking\""),
+    key("watchdog", Kind::Duration, "WatchdogSec=, with type = \"notify\""),
+    key("exec_reload", Kind::Text, "ExecReload= command for sdtab reload"),
+    key("health_cmd", Kind::Text, "Probe command; restart after health_retries failures"),
+    key("health_interval", Kind::Duration, "How often the probe runs, e.g. 30s"),
+    key("health_retries", Kind::Count, "Consecutive probe failures before a restart"),
+    key("ports", Kind::Ports, "Blue/green port pair; needs health_cmd"),
+    key("deploy_steps", Kind::Strings, "Commands sdtab deploy runs before restarting"),
+    key("rollback_steps", Kind::Strings, "Commands run when a deploy fails"),
+    key("output_file", Kind::Text, "Append stdout/stderr to this file"),
+    key("output_max_size", Kind::OutputSize, "Rotate output_file beyond this size, e.g. 10M"),
+    key("output_keep", Kind::Count, "Rotated output files to keep"),
+    key("mail_to", Kind::Text, "Mail non-empty output of each run to this address"),
+    key("hosts", Kind::Strings, "Only apply on hosts matching one of these patterns"),
+];
+
+/// Keys the file itself may have.
+pub const TOP_KEYS: &[&str] = &["include", "project", "vars", "defaults", "profiles", "timers", "services"];
+
+pub fn find(keys: &'static [Key], name: &str) -> Option<&'static Key> {
+    keys.iter().find(|k| k.name == name)
+}
+
+/// The candidate closest to `unknown`, if it is close enough to be a typo.
+pub fn suggest<'a>(unknown: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|c| (distance(unknown, c), c))
+        .filter(|(d, c)| *d <= 2.max(c.len() / 4))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb { prev } else { 1 + prev.min(row[j]).min(cur) };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Why `value` doesn't fit `kind`, if it doesn't. Paths are checked by the
+/// caller, which knows where the file lives.
+pub fn check_value(kind: Kind, value: &str) -> Option<String> {
+    let ok = match kind {
+        Kind::Schedule => return cron::parse(value).err().map(|e| format!("{:#}", e)),
+        Kind::Size => value == "infinity" || is_percent(value) || is_size(value, "KMGTPE"),
+        Kind::OutputSize => is_size(value, "KkMmGg"),
+        Kind::Duration => is_duration(value),
+        Kind::Percent => is_percent(value),
+        Kind::Weight => value.parse::<u32>().is_ok_and(|w| (1..=10000).contains(&w)),
+        Kind::Choice(choices) => choices.contains(&value),
+        _ => true,
+    };
+    if ok {
+        return None;
+    }
+    Some(match kind {
+        Kind::Size => format!("'{}' is not a size (e.g. 512M, 2G, 50%, infinity)", value),
+        Kind::OutputSize => format!("'{}' is not a size (e.g. 512K, 10M, 1G)", value),
+        Kind::Duration => format!("'{}' is not a duration (e.g. 30s, 5min, 1h 30min)", value),
+        Kind::Percent => format!("'{}' is not a percentage (e.g. 50%)", value),
+        Kind::Weight => format!("'{}' is not a weight between 1 and 10000", value),
+        Kind::Choice(choices) => format!("'{}' is not one of: {}", value, choices.join(", ")),
+        _ => unreachable!(),
+    })
+}
+
+fn is_size(value: &str, suffixes: &str) -> bool {
+    let digits = value.strip_suffix(|c| suffixes.contains(c)).unwrap_or(value);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_percent(value: &str) -> bool {
+    value
+        .strip_suffix('%')
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// systemd time span: numbers with optional units, space separated or not.
+fn is_duration(value: &str) -> bool {
+    const UNITS: &[&str] = &[
+        "usec", "us", "µs", "msec", "ms", "seconds", "second", "sec", "s", "minutes", "minute", "min", "m",
+        "hours", "hour", "hr", "h", "days", "day", "d", "weeks", "week", "w", "months", "month", "M", "years",
+        "year", "y",
+    ];
+    if value == "infinity" {
+        return true;
+    }
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return false;
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        if digits == 0 {
+            return false;
+        }
+        rest = rest[digits..].trim_start();
+        let unit = rest.find(|c: char| c.is_ascii_digit() || c.is_whitespace()).unwrap_or(rest.len());
+        if unit > 0 && !UNITS.contains(&&rest[..unit]) {
+            return false;
+        }
+        rest = rest[unit..].trim_start();
+    }
+    true
+}
+
+/// JSON Schema (draft 2020-12) of an Sdtabfile.
+pub fn json_schema() -> Value {
+    let timer = entry_schema(TIMER_KEYS, &["schedule", "command", "workdir"]);
+    let service = entry_schema(SERVICE_KEYS, &["command", "workdir"]);
+    let overlay = |entry: &Value| {
+        let mut entry = entry.clone();
+        entry.as_object_mut().unwrap().remove("required");
+        json!({ "type": "object", "additionalProperties": entry })
+    };
+
+    let mut default_keys = Map::new();
+    for key in TIMER_KEYS.iter().chain(SERVICE_KEYS) {
+        if !matches!(key.name, "schedule" | "command" | "hosts") {
+            default_keys.insert(key.name.to_string(), value_schema(key));
+        }
+    }
+    let mut defaults = json!({ "type": "object", "additionalProperties": false });
+    default_keys.insert("timers".into(), overlay(&timer)["additionalProperties"].clone());
+    default_keys.insert("services".into(), overlay(&service)["additionalProperties"].clone());
+    defaults["properties"] = Value::Object(default_keys);
+
+    let strings = json!({ "type": "array", "items": { "type": "string" } });
+    let vars = json!({ "type": "object", "additionalProperties": { "type": "string" } });
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Sdtabfile",
+        "description": "Timers and services for sdtab apply",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "include": { "description": "More Sdtabfiles to merge in, relative to this file", "type": "array", "items": { "type": "string" } },
+            "project": { "description": "Owner of this file's units", "type": "string", "pattern": "^[A-Za-z0-9._-]+$" },
+            "vars": vars,
+            "defaults": defaults,
+            "timers": { "type": "object", "additionalProperties": timer },
+            "services": { "type": "object", "additionalProperties": service },
+            "profiles": {
+                "type": "object",
+                "additionalProperties": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "vars": vars,
+                        "exclude": strings,
+                        "timers": overlay(&timer),
+                        "services": overlay(&service),
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn entry_schema(keys: &[Key], required: &[&str]) -> Value {
+    let properties: Map<String, Value> = keys.iter().map(|k| (k.name.to_string(), value_schema(k))).collect();
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": required,
+        "properties": properties,
+    })
+}
+
+fn value_schema(key: &Key) -> Value {
+    let mut schema = match key.kind {
+        Kind::Text | Kind::Schedule | Kind::Path => json!({ "type": "string" }),
+        Kind::Size => json!({ "type": "string", "pattern": "^([0-9]+[KMGTPE]?|[0-9]+%|infinity)$" }),
+        Kind::OutputSize => json!({ "type": "string", "pattern": "^[0-9]+[KkMmGg]?$" }),
+        Kind::Duration => json!({ "type": "string", "pattern": "^(infinity|([0-9.]+ *[a-zµ]* *)+)$" }),
+        Kind::Percent => json!({ "type": "string", "pattern": "^[0-9]+%$" }),
+        Kind::Weight => json!({ "type": "string", "pattern": "^[0-9]+$" }),
+        Kind::Bool => json!({ "type": "boolean" }),
+        Kind::Count => json!({ "type": "integer", "minimum": 0 }),
+        Kind::Strings => json!({ "type": "array", "items": { "type": "string" } }),
+        Kind::Ports => json!({
+            "type": "array",
+            "items": { "type": "integer", "minimum": 1, "maximum": 65535 },
+            "minItems": 2,
+            "maxItems": 2,
+        }),
+        Kind::Choice(choices) => json!({ "type": "string", "enum": choices }),
+    };
+    schema["description"] = Value::String(key.description.to_string());
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_close_keys_only() {
+        let names = TIMER_KEYS.iter().map(|k| k.name);
+        assert_eq!(suggest("memmory_max", names.clone()), Some("memory_max"));
+        assert_eq!(suggest("shedule", names.clone()), Some("schedule"));
+        assert_eq!(suggest("frobnicate", names), None);
+    }
+
+    #[test]
+    fn checks_value_formats() {
+        assert!(check_value(Kind::Size, "512M").is_none());
+        assert!(check_value(Kind::Size, "50%").is_none());
+        assert!(check_value(Kind::Size, "512MB").is_some());
+        assert!(check_value(Kind::Duration, "1h 30min").is_none());
+        assert!(check_value(Kind::Duration, "90").is_none());
+        assert!(check_value(Kind::Duration, "5 minutes").is_none());
+        assert!(check_value(Kind::Duration, "soon").is_some());
+        assert!(check_value(Kind::Percent, "50").is_some());
+        assert!(check_value(Kind::Weight, "0").is_some());
+        assert!(check_value(Kind::Schedule, "0 9 * *").is_some());
+        assert!(check_value(Kind::Choice(LOG_LEVELS), "warn").is_some());
+    }
+
+    #[test]
+    fn every_key_is_accepted_by_its_entry() {
+        use crate::sdtabfile::{self, Target};
+
+        let sample = |key: &Key| match key.kind {
+            Kind::Schedule => "\"@daily\"".to_string(),
+            Kind::Bool => "true".to_string(),
+            Kind::Count => "3".to_string(),
+            Kind::Ports => "[8001, 8002]".to_string(),
+            Kind::Strings if key.name == "hosts" => "[\"*\"]".to_string(),
+            Kind::Strings => "[\"A=1\"]".to_string(),
+            _ => "\"x\"".to_string(),
+        };
+        let mut toml = String::from("[timers.t]\n");
+        for key in TIMER_KEYS {
+            toml.push_str(&format!("{} = {}\n", key.name, sample(key)));
+        }
+        toml.push_str("[services.s]\n");
+        for key in SERVICE_KEYS {
+            toml.push_str(&format!("{} = {}\n", key.name, sample(key)));
+        }
+        let target = Target { hostname: Some("host".to_string()), ..Default::default() };
+        sdtabfile::parse(&toml, "/", &target).unwrap();
+    }
+
+    /// The fields `T`'s derived `Deserialize` reads: the list it hands to
+    /// `deserialize_struct`.
+    fn serde_fields<'de, T: serde::Deserialize<'de>>() -> &'static [&'static str] {
+        use serde::de::{Error, Visitor};
+
+        struct Fields(Option<&'static [&'static str]>);
+        impl<'de> serde::Deserializer<'de> for &mut Fields {
+            type Error = serde::de::value::Error;
+            fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+                Err(Error::custom("not a struct"))
+            }
+            fn deserialize_struct<V: Visitor<'de>>(
+                self,
+                _: &'static str,
+                fields: &'static [&'static str],
+                _: V,
+            ) -> Result<V::Value, Self::Error> {
+                self.0 = Some(fields);
+                Err(Error::custom("only the fields are wanted"))
+            }
+            serde::forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+                unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+            }
+        }
+        let mut fields = Fields(None);
+        let _ = T::deserialize(&mut fields);
+        fields.0.expect("a struct")
+    }
+
+    #[test]
+    fn every_field_has_a_key() {
+        use crate::sdtabfile::{Sdtabfile, ServiceEntry, TimerEntry};
+
+        let missing = |fields: &[&'static str], keys: &[&str]| -> Vec<&'static str> {
+            fields.iter().copied().filter(|f| !keys.contains(f)).collect()
+        };
+        let names = |keys: &[Key]| keys.iter().map(|k| k.name).collect::<Vec<_>>();
+        assert_eq!(missing(serde_fields::<TimerEntry>(), &names(TIMER_KEYS)), Vec::<&str>::new(), "timer fields");
+        assert_eq!(missing(serde_fields::<ServiceEntry>(), &names(SERVICE_KEYS)), Vec::<&str>::new(), "service fields");
+        assert_eq!(missing(serde_fields::<Sdtabfile>(), TOP_KEYS), Vec::<&str>::new(), "top-level fields");
+    }
+
+    #[test]
+    fn published_schema_is_up_to_date() {
+        let published = include_str!("../sdtabfile.schema.json");
+        let generated = serde_json::to_string_pretty(&json_schema()).unwrap() + "\n";
+        assert!(
+            published == generated,
+            "sdtabfile.schema.json is stale; regenerate it with: sdtab validate --schema > sdtabfile.schema.json"
+        );
+    }
+}
@@ -1,16 +1,82 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
 
-#[derive(Serialize, Deserialize)]
+use crate::{config, init, output, schema};
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Sdtabfile {
+    /// More Sdtabfiles to merge in: paths relative to this file, `*` and `?`
+    /// allowed (`jobs/*.toml`)
+    #[serde(default, skip_serializing)]
+    pub include: Vec<String>,
+    /// Owner of this file's units (inherited by included files); `apply
+    /// --prune` then covers exactly that project's units
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub timers: BTreeMap<String, TimerEntry>,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub services: BTreeMap<String, ServiceEntry>,
+    /// Files read by [`load`], in order
+    #[serde(skip)]
+    pub files: Vec<String>,
+    /// File each entry came from, by name, relative to the directory [`load`]
+    /// started from so that moving the checkout keeps it
+    #[serde(skip)]
+    pub origins: BTreeMap<String, String>,
+    /// Project of each entry, by name
+    #[serde(skip)]
+    pub projects: BTreeMap<String, String>,
+    /// Path patterns covering the files this set is made of, relative like
+    /// the origins; `apply --prune` only removes units whose origin matches one
+    #[serde(skip)]
+    pub scope: Vec<String>,
+    /// Projects of the files read; `apply --prune` covers all their units
+    #[serde(skip)]
+    pub scope_projects: BTreeSet<String>,
+    /// Profiles defined in the files read
+    #[serde(skip)]
+    pub profiles: BTreeSet<String>,
+    /// Names the selected profile excludes, with the file that excludes them
+    /// (set when merged); [`load`] drops them from every file read
+    #[serde(skip)]
+    pub excludes: BTreeMap<String, String>,
+    /// Entries already dropped by an exclude or a `hosts` pattern
+    #[serde(skip)]
+    pub dropped: BTreeSet<String>,
+}
+
+impl Sdtabfile {
+    pub fn origin(&self, name: &str) -> Option<&str> {
+        self.origins.get(name).map(String::as_str)
+    }
+
+    pub fn project(&self, name: &str) -> Option<&str> {
+        self.projects.get(name).map(String::as_str)
+    }
+
+    /// Whether a unit with this origin and project belongs to this set: any
+    /// unit of its projects, and untagged units of its files. Units with
+    /// neither (added with `sdtab add` or applied before origins were
+    /// recorded) are only covered by a lone file that sets neither `project`
+    /// nor `include`, as every file covered them then.
+    pub fn in_scope(&self, origin: Option<&str>, project: Option<&str>) -> bool {
+        match (project, origin) {
+            (Some(project), _) => self.scope_projects.contains(project),
+            (None, Some(origin)) => self.scope.iter().any(|pattern| path_matches(pattern, origin)),
+            (None, None) => self.scope_projects.is_empty() && self.scope.len() == 1,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TimerEntry {
     pub schedule: String,
     pub command: String,
@@ -34,14 +100,33 @@ pub struct TimerEntry {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub log_level_max: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub random_delay: Option<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub env: Vec<String>,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub no_notify: bool,
+    /// `false` installs the unit but keeps it disabled and stopped, as
+    /// `sdtab disable` leaves it
+    #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// Append stdout/stderr to this file, rotated by size before each run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_max_size: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_keep: Option<u32>,
+    /// Mail non-empty output of each run to this address via sendmail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mail_to: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServiceEntry {
     pub command: String,
     pub workdir: String,
@@ -68,10 +153,698 @@ pub struct ServiceEntry {
     pub exec_stop_post: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub log_level_max: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub env: Vec<String>,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub no_notify: bool,
+    /// `false` installs the unit but keeps it disabled and stopped, as
+    /// `sdtab disable` leaves it
+    #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// systemd service type: notify, exec, forking (default: simple)
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub service_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watchdog: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec_reload: Option<String>,
+    /// Probe command; the service is restarted after `health_retries` consecutive failures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_cmd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_interval: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_retries: Option<u32>,
+    /// Blue/green port slots; the service runs as `sdtab-<name>@blue` / `@green`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<u16>,
+    /// Commands `sdtab deploy` runs in the workdir before restarting
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deploy_steps: Vec<String>,
+    /// Commands run when the service fails after a deploy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rollback_steps: Vec<String>,
+    /// Append stdout/stderr to this file, rotated by size before each run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_max_size: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_keep: Option<u32>,
+    /// Mail non-empty output of each run to this address via sendmail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mail_to: Option<String>,
+}
+
+/// Keys that identify an entry rather than configure it; never factored into
+/// `[defaults]`.
+const OWN_KEYS: &[&str] = &["schedule", "command", "description"];
+
+/// Read an Sdtabfile and the files it includes, or every `*.toml` in a
+/// directory, into one set of entries for `target`. A name defined twice is
+/// an error, as is a required profile no file defines.
+pub fn load(path: &str, target: &Target) -> Result<Sdtabfile> {
+    let path = normalize(&std::path::absolute(path).with_context(|| format!("Invalid path {}", path))?);
+    let mut merged = Sdtabfile::default();
+    if path.is_dir() {
+        let pattern = path.join("*.toml");
+        merged.scope.push(pattern.display().to_string());
+        for file in glob(&pattern) {
+            merge_file(&mut merged, &file, None, target)?;
+        }
+    } else {
+        merged.scope.push(path.display().to_string());
+        merge_file(&mut merged, &path, None, target)?;
+    }
+    // An exclude also reaches entries of the files its file includes (or is
+    // included by), so it is resolved on the merged set.
+    for (name, origin) in std::mem::take(&mut merged.excludes) {
+        let removed = merged.timers.remove(&name).is_some() | merged.services.remove(&name).is_some();
+        merged.origins.remove(&name);
+        merged.projects.remove(&name);
+        if !removed && !merged.dropped.contains(&name) {
+            let profile = target.profile.as_deref().unwrap_or_default();
+            bail!("{}: profiles.{}.exclude: no entry named '{}'", origin, profile, name);
+        }
+    }
+    let missing = target.profile.as_deref().filter(|p| target.require_profile && !merged.profiles.contains(*p));
+    if let Some(profile) = missing {
+        let known: Vec<&str> = merged.profiles.iter().map(String::as_str).collect();
+        if known.is_empty() {
+            bail!("Profile '{}' is not defined (no [profiles] in {})", profile, path.display());
+        }
+        bail!("Profile '{}' is not defined (available: {})", profile, known.join(", "));
+    }
+    let root = if path.is_dir() { path.as_path() } else { path.parent().unwrap_or(Path::new("/")) };
+    for origin in merged.origins.values_mut() {
+        *origin = relative_to(Path::new(origin), root);
+    }
+    for pattern in &mut merged.scope {
+        *pattern = relative_to(Path::new(pattern), root);
+    }
+    Ok(merged)
+}
+
+/// `path` relative to `base`, both absolute and normalized; `..` leads out
+/// of `base` for included files outside it.
+fn relative_to(path: &Path, base: &Path) -> String {
+    let path: Vec<_> = path.components().collect();
+    let base: Vec<_> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    relative.display().to_string()
+}
+
+fn merge_file(merged: &mut Sdtabfile, file: &Path, parent_project: Option<&str>, target: &Target) -> Result<()> {
+    let origin = file.display().to_string();
+    if merged.files.contains(&origin) {
+        return Ok(());
+    }
+    merged.files.push(origin.clone());
+
+    let toml_content = fs::read_to_string(file).with_context(|| format!("Failed to read {}", origin))?;
+    let dir = file.parent().unwrap_or(Path::new("/"));
+    let parsed = parse(&toml_content, &dir.display().to_string(), target).map_err(|e| anyhow::anyhow!("{}: {:#}", origin, e))?;
+
+    let project = parsed.project.as_deref().or(parent_project);
+    if let Some(project) = project {
+        check_project(project).map_err(|e| anyhow::anyhow!("{}: {}", origin, e))?;
+        merged.scope_projects.insert(project.to_string());
+    }
+
+    let names = parsed.timers.keys().chain(parsed.services.keys());
+    for name in names {
+        if let Some(other) = merged.origins.get(name) {
+            if *other == origin {
+                bail!("{}: '{}' is both a timer and a service", origin, name);
+            }
+            bail!("'{}' is defined in both {} and {}", name, other, origin);
+        }
+        merged.origins.insert(name.clone(), origin.clone());
+        if let Some(project) = project {
+            merged.projects.insert(name.clone(), project.to_string());
+        }
+    }
+    merged.profiles.extend(parsed.profiles);
+    for name in parsed.excludes.into_keys() {
+        merged.excludes.entry(name).or_insert_with(|| origin.clone());
+    }
+    merged.dropped.extend(parsed.dropped);
+    merged.timers.extend(parsed.timers);
+    merged.services.extend(parsed.services);
+
+    for include in &parsed.include {
+        let pattern = normalize(&dir.join(include));
+        let files = glob(&pattern);
+        if files.is_empty() && !is_pattern(include) {
+            bail!("{}: included file {} not found", origin, pattern.display());
+        }
+        merged.scope.push(pattern.display().to_string());
+        for included in files {
+            merge_file(merged, &included, project, target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Project names end up in unit files and on the command line.
+pub fn check_project(project: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if project.is_empty() || !project.chars().all(valid) {
+        bail!("invalid project name '{}' (use letters, digits, '-', '_' and '.')", project);
+    }
+    Ok(())
+}
+
+/// Resolve `.` and `..` without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?'])
+}
+
+/// The files matching an absolute path pattern, sorted. `*` and `?` match
+/// within one path component and skip dotfiles, as in the shell.
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("/")];
+    for component in pattern.components().skip(1) {
+        let part = component.as_os_str().to_string_lossy();
+        let mut next = Vec::new();
+        for base in &paths {
+            if !is_pattern(&part) {
+                let path = base.join(&*part);
+                if path.exists() {
+                    next.push(path);
+                }
+                continue;
+            }
+            let Ok(entries) = fs::read_dir(base) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if wildcard_match(&part, &name) && (!name.starts_with('.') || part.starts_with('.')) {
+                    next.push(entry.path());
+                }
+            }
+        }
+        paths = next;
+    }
+    paths.retain(|path| path.is_file());
+    paths.sort();
+    paths
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<_> = Path::new(pattern).components().collect();
+    let path: Vec<_> = Path::new(path).components().collect();
+    pattern.len() == path.len()
+        && pattern.iter().zip(&path).all(|(p, c)| {
+            wildcard_match(&p.as_os_str().to_string_lossy(), &c.as_os_str().to_string_lossy())
+        })
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and where in `name` it started matching.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the `*` swallow one more character.
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Which profile and host an Sdtabfile is resolved for.
+#[derive(Default)]
+pub struct Target {
+    pub profile: Option<String>,
+    /// The profile was asked for explicitly (not just set for the host in
+    /// config.toml), so some file must define it.
+    pub require_profile: bool,
+    /// `None` under `--root` when the image has no hostname yet; only
+    /// `hosts` patterns need one then.
+    pub hostname: Option<String>,
+}
+
+impl Target {
+    /// This host, with `profile` or else the `profile` from config.toml.
+    pub fn current(profile: Option<&str>) -> Result<Target> {
+        let require_profile = profile.is_some();
+        let profile = match profile {
+            Some(profile) => Some(profile.to_string()),
+            None => config::load()?.profile,
+        };
+        let hostname = match output::hostname() {
+            Ok(hostname) => Some(hostname),
+            Err(_) if init::root().is_some() => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Target { profile, require_profile, hostname })
+    }
+}
+
+/// Parse an Sdtabfile's text for `target`. The selected `[profiles.<name>]`
+/// overlays its vars and entries first (its keys win) and drops the entries
+/// in its `exclude` (those of other files are left to [`load`]). Entries then inherit missing keys from
+/// `[defaults.timers]`/`[defaults.services]`, then `[defaults]`; those whose
+/// `hosts` patterns don't match the hostname are dropped, and every string
+/// is interpolated (see [`interpolate`]).
+pub fn parse(content: &str, file_dir: &str, target: &Target) -> Result<Sdtabfile> {
+    let mut table: Table = toml::from_str(content)?;
+    check_keys(content, &table)?;
+
+    let mut profiles = match table.remove("profiles") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => bail!("[profiles] must be a table"),
+        None => Table::new(),
+    };
+    let profile_names: BTreeSet<String> = profiles.keys().cloned().collect();
+    let profile = target.profile.as_deref().unwrap_or_default();
+    let mut overlay = match profiles.remove(profile) {
+        Some(Value::Table(overlay)) => overlay,
+        Some(_) => bail!("[profiles.{}] must be a table", profile),
+        None => Table::new(),
+    };
+
+    let mut raw_vars = match table.remove("vars") {
+        Some(Value::Table(raw)) => raw,
+        Some(_) => bail!("[vars] must be a table"),
+        None => Table::new(),
+    };
+    match overlay.remove("vars") {
+        Some(Value::Table(raw)) => raw_vars.extend(raw),
+        Some(_) => bail!("[profiles.{}.vars] must be a table", profile),
+        None => {}
+    }
+    let mut vars = BTreeMap::new();
+    for (name, value) in raw_vars {
+        let Value::String(value) = value else {
+            bail!("vars.{} must be a string", name);
+        };
+        // Vars may use the environment and file_dir, not each other.
+        let value = interpolate(&value, &BTreeMap::new(), file_dir).with_context(|| format!("vars.{}", name))?;
+        vars.insert(name, value);
+    }
+
+    let exclude = match overlay.remove("exclude") {
+        Some(value) => string_list(value).with_context(|| format!("profiles.{}.exclude", profile))?,
+        None => Vec::new(),
+    };
+    for kind in ["timers", "services"] {
+        match overlay.remove(kind) {
+            Some(Value::Table(over)) => {
+                let Value::Table(entries) = table.entry(kind).or_insert_with(|| Value::Table(Table::new())) else {
+                    bail!("[{}] must be a table", kind);
+                };
+                for (name, value) in over {
+                    match (entries.get_mut(&name), value) {
+                        (Some(Value::Table(entry)), Value::Table(value)) => entry.extend(value),
+                        (_, value) => {
+                            entries.insert(name, value);
+                        }
+                    }
+                }
+            }
+            Some(_) => bail!("[profiles.{}.{}] must be a table", profile, kind),
+            None => {}
+        }
+    }
+    if let Some(key) = overlay.keys().next() {
+        bail!("unknown key profiles.{}.{} (expected vars, exclude, timers or services)", profile, key);
+    }
+    // Names this file lacks may be in another; [`load`] checks them.
+    let mut dropped = BTreeSet::new();
+    for name in &exclude {
+        for kind in ["timers", "services"] {
+            if let Some(Value::Table(entries)) = table.get_mut(kind) {
+                if entries.remove(name).is_some() {
+                    dropped.insert(name.clone());
+                }
+            }
+        }
+    }
+
+    let mut defaults = match table.remove("defaults") {
+        Some(Value::Table(defaults)) => defaults,
+        Some(_) => bail!("[defaults] must be a table"),
+        None => Table::new(),
+    };
+    for kind in ["timers", "services"] {
+        let own = match defaults.remove(kind) {
+            Some(Value::Table(own)) => own,
+            Some(_) => bail!("[defaults.{}] must be a table", kind),
+            None => Table::new(),
+        };
+        let Some(Value::Table(entries)) = table.get_mut(kind) else {
+            continue;
+        };
+        let mut elsewhere = Vec::new();
+        for (name, entry) in entries.iter_mut() {
+            let Value::Table(entry) = entry else {
+                bail!("{}.{} must be a table", kind, name);
+            };
+            // Shared defaults only reach the kinds that have the key (`restart`
+            // is for services, `random_delay` for timers).
+            let shared = defaults.iter().filter(|(k, _)| schema::find(keys_of(kind), k).is_some());
+            for (key, value) in own.iter().chain(shared) {
+                if !entry.contains_key(key) {
+                    entry.insert(key.clone(), value.clone());
+                }
+            }
+            if let Some(hosts) = entry.remove("hosts") {
+                let hosts = string_list(hosts).with_context(|| format!("{}.{}.hosts", kind, name))?;
+                let Some(ref hostname) = target.hostname else {
+                    bail!("{}.{}.hosts: no hostname to match (the image has no /etc/hostname); set SDTAB_HOSTNAME", kind, name);
+                };
+                if !hosts.iter().any(|pattern| wildcard_match(pattern, hostname)) {
+                    elsewhere.push(name.clone());
+                    continue;
+                }
+            }
+            interpolate_strings(entry, &vars, file_dir).with_context(|| format!("{}.{}", kind, name))?;
+        }
+        for name in elsewhere {
+            entries.remove(&name);
+            dropped.insert(name);
+        }
+    }
+
+    let mut sdtabfile: Sdtabfile = Value::Table(table).try_into()?;
+    sdtabfile.profiles = profile_names;
+    sdtabfile.excludes = exclude.into_iter().map(|name| (name, String::new())).collect();
+    sdtabfile.dropped = dropped;
+    Ok(sdtabfile)
+}
+
+fn keys_of(kind: &str) -> &'static [schema::Key] {
+    match kind {
+        "timers" => schema::TIMER_KEYS,
+        _ => schema::SERVICE_KEYS,
+    }
+}
+
+/// Reject keys that no entry, default or profile understands, so a typo like
+/// `memmory_max` fails instead of being ignored.
+fn check_keys(content: &str, table: &Table) -> Result<()> {
+    let tables = |value: Option<&Value>| value.and_then(Value::as_table).cloned().unwrap_or_default();
+    let names = |keys: &'static [schema::Key]| keys.iter().map(|k| k.name);
+
+    check_known(content, &[], table, schema::TOP_KEYS.iter().copied())?;
+    let defaults = tables(table.get("defaults"));
+    let default_keys = names(schema::TIMER_KEYS)
+        .chain(names(schema::SERVICE_KEYS))
+        .filter(|k| !matches!(*k, "schedule" | "command" | "hosts"))
+        .chain(["timers", "services"]);
+    check_known(content, &["defaults"], &defaults, default_keys)?;
+
+    for kind in ["timers", "services"] {
+        let known = names(keys_of(kind));
+        check_known(content, &["defaults", kind], &tables(defaults.get(kind)), known.clone())?;
+        for (name, entry) in tables(table.get(kind)) {
+            check_known(content, &[kind, &name], &tables(Some(&entry)), known.clone())?;
+        }
+        for (profile, overlay) in tables(table.get("profiles")) {
+            let overlay = tables(Some(&overlay));
+            for (name, entry) in tables(overlay.get(kind)) {
+                check_known(content, &["profiles", &profile, kind, &name], &tables(Some(&entry)), known.clone())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_known<'a>(
+    content: &str,
+    path: &[&str],
+    table: &Table,
+    known: impl Iterator<Item = &'a str> + Clone,
+) -> Result<()> {
+    let Some(key) = table.keys().find(|key| !known.clone().any(|k| k == key.as_str())) else {
+        return Ok(());
+    };
+    let mut message = match path {
+        [] => format!("unknown key '{}'", key),
+        _ => format!("unknown key '{}' in [{}]", key, path.join(".")),
+    };
+    if let Some(suggestion) = schema::suggest(key, known) {
+        message.push_str(&format!(" (did you mean '{}'?)", suggestion));
+    }
+    match locate(content, path, key) {
+        Some((line, column)) => bail!("line {}, column {}: {}", line, column, message),
+        None => bail!("{}", message),
+    }
+}
+
+/// Line and column (1-based) where `key` is set in the table at `path`:
+/// under its `[header]`, or failing that (dotted keys, inline tables) the
+/// first `key =` anywhere.
+pub fn locate(content: &str, path: &[&str], key: &str) -> Option<(usize, usize)> {
+    let assigns = |line: &str, at: usize| {
+        let rest = line[at + key.len()..].trim_start_matches(['"', '\'']).trim_start();
+        rest.starts_with('=')
+    };
+    let mut header: Vec<String> = Vec::new();
+    let mut fallback = None;
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(inner) = trimmed.strip_prefix('[').and_then(|rest| rest.split(']').next()) {
+            header = inner.split('.').map(|part| part.trim().trim_matches(['"', '\'']).to_string()).collect();
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        let quoted = usize::from(trimmed.starts_with(['"', '\'']));
+        if header == path && trimmed[quoted..].starts_with(key) && assigns(line, indent + quoted) {
+            return Some((index + 1, indent + 1));
+        }
+        if fallback.is_none() {
+            let found = line.match_indices(key).find(|(at, _)| {
+                let before = line[..*at].chars().next_back();
+                !before.is_some_and(|c| c.is_alphanumeric() || c == '_') && assigns(line, *at)
+            });
+            fallback = found.map(|(at, _)| (index + 1, at + 1));
+        }
+    }
+    fallback
+}
+
+fn string_list(value: Value) -> Result<Vec<String>> {
+    let Value::Array(items) = value else {
+        bail!("must be a list of strings");
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::String(s) => Ok(s),
+            _ => bail!("must be a list of strings"),
+        })
+        .collect()
+}
+
+fn interpolate_strings(table: &mut Table, vars: &BTreeMap<String, String>, file_dir: &str) -> Result<()> {
+    fn walk(value: &mut Value, vars: &BTreeMap<String, String>, file_dir: &str) -> Result<()> {
+        match value {
+            Value::String(s) => *s = interpolate(s, vars, file_dir)?,
+            Value::Array(items) => {
+                for item in items {
+                    walk(item, vars, file_dir)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+    for (key, value) in table.iter_mut() {
+        walk(value, vars, file_dir).with_context(|| key.clone())?;
+    }
+    Ok(())
+}
+
+/// Replace `${name}` with the var `name`, `${env:NAME}` with the environment
+/// variable and `${file_dir}` with the Sdtabfile's directory. Other `${...}`
+/// are left alone for the shell or systemd to expand, as is systemd's `$$`
+/// (so `$${name}` reaches the unit as written); `\${` is a literal `${`.
+pub fn interpolate(s: &str, vars: &BTreeMap<String, String>, file_dir: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find(['$', '\\']) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("\\${") {
+            out.push_str("${");
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push_str("$$");
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix('\\') {
+            out.push('\\');
+            rest = after;
+            continue;
+        }
+        let reference = rest.strip_prefix("${").and_then(|r| r.find('}').map(|end| &r[..end]));
+        let value = match reference {
+            Some("file_dir") => Some(file_dir.to_string()),
+            Some(name) => match name.strip_prefix("env:") {
+                Some(var) => match std::env::var(var) {
+                    Ok(value) => Some(value),
+                    Err(_) => bail!("environment variable '{}' is not set", var),
+                },
+                None => vars.get(name).cloned(),
+            },
+            None => None,
+        };
+        match (reference, value) {
+            (Some(name), Some(value)) => {
+                out.push_str(&value);
+                rest = &rest[name.len() + 3..];
+            }
+            _ => {
+                out.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Escape `s` so that [`interpolate`] gives it back unchanged.
+fn escape(s: &str) -> String {
+    // `$$` is passed on as is, so only the `${` between them matter.
+    let escape_part = |part: &str| {
+        if part.contains("\\${") || part.contains("${file_dir}") || part.contains("${env:") {
+            part.replace("${", "\\${")
+        } else {
+            part.to_string()
+        }
+    };
+    s.split("$$").map(escape_part).collect::<Vec<_>>().join("$$")
+}
+
+/// Serialize an Sdtabfile for `sdtab export`. With `factor_defaults`, values
+/// shared by every timer and service move to `[defaults]`, and those shared
+/// by every timer (or service) to `[defaults.timers]` (`[defaults.services]`).
+pub fn to_toml(file: &Sdtabfile, factor_defaults: bool) -> Result<String> {
+    let mut table = Table::try_from(file)?;
+    let mut groups = BTreeMap::new();
+    for kind in ["timers", "services"] {
+        if let Some(Value::Table(entries)) = table.get_mut(kind) {
+            for (_, entry) in entries.iter_mut() {
+                if let Value::Table(entry) = entry {
+                    escape_strings(entry);
+                }
+            }
+            groups.insert(kind, entries.clone());
+        }
+    }
+    if !factor_defaults {
+        return Ok(toml::to_string_pretty(&table)?);
+    }
+
+    let all: Vec<&Table> = groups
+        .values()
+        .flat_map(|entries| entries.values())
+        .filter_map(Value::as_table)
+        .collect();
+    let mut defaults = if groups.len() == 2 { common(&all) } else { Table::new() };
+    for (kind, entries) in &groups {
+        let entries: Vec<&Table> = entries.values().filter_map(Value::as_table).collect();
+        let mut own = common(&entries);
+        own.retain(|key, _| !defaults.contains_key(key));
+        if !own.is_empty() {
+            defaults.insert(kind.to_string(), Value::Table(own));
+        }
+    }
+    if defaults.is_empty() {
+        return Ok(toml::to_string_pretty(&table)?);
+    }
+
+    for kind in ["timers", "services"] {
+        let Some(Value::Table(entries)) = table.get_mut(kind) else {
+            continue;
+        };
+        let own = defaults.get(kind).and_then(Value::as_table);
+        for entry in entries.iter_mut().filter_map(|(_, entry)| entry.as_table_mut()) {
+            entry.retain(|key, _| !defaults.contains_key(key) && !own.is_some_and(|own| own.contains_key(key)));
+        }
+    }
+    let mut out = Table::new();
+    out.insert("defaults".to_string(), Value::Table(defaults));
+    out.extend(table);
+    Ok(toml::to_string_pretty(&out)?)
+}
+
+fn escape_strings(table: &mut Table) {
+    for (_, value) in table.iter_mut() {
+        match value {
+            Value::String(s) => *s = escape(s),
+            Value::Array(items) => {
+                for item in items {
+                    if let Value::String(s) = item {
+                        *s = escape(s);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The key/values every entry has in common, when there are at least two.
+fn common(entries: &[&Table]) -> Table {
+    let mut shared = Table::new();
+    let Some((first, others)) = entries.split_first() else {
+        return shared;
+    };
+    if others.is_empty() {
+        return shared;
+    }
+    for (key, value) in first.iter() {
+        if !OWN_KEYS.contains(&key.as_str()) && others.iter().all(|entry| entry.get(key) == Some(value)) {
+            shared.insert(key.clone(), value.clone());
+        }
+    }
+    shared
 }
 
 /// Convert description to Option: None if it equals command (convention: omit when same)
@@ -88,6 +861,14 @@ pub fn desc_matches(current_desc: &str, current_cmd: &str, desired_desc: &Option
     description_if_different(current_desc, current_cmd) == *desired_desc
 }
 
+fn enabled_by_default() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
 fn is_default_restart(val: &Option<String>) -> bool {
     match val {
         None => true,
@@ -98,6 +879,7 @@ fn is_default_restart(val: &Option<String>) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testdir::TestDir;
 
     #[test]
     fn test_is_default_restart() {
@@ -125,14 +907,21 @@ mod tests {
                 exec_start_pre: None,
                 exec_stop_post: None,
                 log_level_max: None,
+                user: None,
+                group: None,
                 random_delay: None,
                 env: vec![],
                 no_notify: false,
+                enabled: true,
+                output_file: None,
+                output_max_size: None,
+                output_keep: None,
+                mail_to: None,
             },
         );
         let file = Sdtabfile {
             timers,
-            services: BTreeMap::new(),
+            ..Default::default()
         };
         let toml_str = toml::to_string_pretty(&file).unwrap();
         let parsed: Sdtabfile = toml::from_str(&toml_str).unwrap();
@@ -158,13 +947,30 @@ mod tests {
                 exec_start_pre: None,
                 exec_stop_post: None,
                 log_level_max: None,
+                user: None,
+                group: None,
                 env: vec!["NODE_ENV=production".to_string()],
                 no_notify: false,
+                enabled: true,
+                service_type: None,
+                pid_file: None,
+                watchdog: None,
+                exec_reload: None,
+                health_cmd: None,
+                health_interval: None,
+                health_retries: None,
+                ports: vec![],
+                deploy_steps: vec![],
+                rollback_steps: vec![],
+                output_file: None,
+                output_max_size: None,
+                output_keep: None,
+                mail_to: None,
             },
         );
         let file = Sdtabfile {
-            timers: BTreeMap::new(),
             services,
+            ..Default::default()
         };
         let toml_str = toml::to_string_pretty(&file).unwrap();
         assert!(toml_str.contains("restart = \"on-failure\""));
@@ -194,13 +1000,30 @@ mod tests {
                 exec_start_pre: None,
                 exec_stop_post: None,
                 log_level_max: None,
+                user: None,
+                group: None,
                 env: vec![],
                 no_notify: false,
+                enabled: true,
+                service_type: None,
+                pid_file: None,
+                watchdog: None,
+                exec_reload: None,
+                health_cmd: None,
+                health_interval: None,
+                health_retries: None,
+                ports: vec![],
+                deploy_steps: vec![],
+                rollback_steps: vec![],
+                output_file: None,
+                output_max_size: None,
+                output_keep: None,
+                mail_to: None,
             },
         );
         let file = Sdtabfile {
-            timers: BTreeMap::new(),
             services,
+            ..Default::default()
         };
         let toml_str = toml::to_string_pretty(&file).unwrap();
         assert!(!toml_str.contains("restart"));
@@ -231,14 +1054,21 @@ mod tests {
                 exec_start_pre: None,
                 exec_stop_post: None,
                 log_level_max: None,
+                user: None,
+                group: None,
                 random_delay: None,
                 env: vec![],
                 no_notify: true,
+                enabled: true,
+                output_file: None,
+                output_max_size: None,
+                output_keep: None,
+                mail_to: None,
             },
         );
         let file = Sdtabfile {
             timers,
-            services: BTreeMap::new(),
+            ..Default::default()
         };
         let toml_str = toml::to_string_pretty(&file).unwrap();
         assert!(toml_str.contains("no_notify = true"));
@@ -262,14 +1092,21 @@ mod tests {
                 exec_start_pre: None,
                 exec_stop_post: None,
                 log_level_max: None,
+                user: None,
+                group: None,
                 random_delay: None,
                 env: vec![],
                 no_notify: false,
+                enabled: true,
+                output_file: None,
+                output_max_size: None,
+                output_keep: None,
+                mail_to: None,
             },
         );
         let file = Sdtabfile {
             timers,
-            services: BTreeMap::new(),
+            ..Default::default()
         };
         let toml_str = toml::to_string_pretty(&file).unwrap();
         assert!(!toml_str.contains("no_notify"));
@@ -299,4 +1136,234 @@ workdir = "/home/user"
         let file: Sdtabfile = toml::from_str(toml_str).unwrap();
         assert!(!file.timers["normal"].no_notify);
     }
+
+    #[test]
+    fn test_service_type_uses_type_key() {
+        let toml_str = r#"
+[services.api]
+command = "./api"
+workdir = "/home/user"
+type = "notify"
+watchdog = "30s"
+exec_reload = "/bin/kill -HUP $MAINPID"
+"#;
+        let file: Sdtabfile = toml::from_str(toml_str).unwrap();
+        let api = &file.services["api"];
+        assert_eq!(api.service_type, Some("notify".to_string()));
+        assert_eq!(api.watchdog, Some("30s".to_string()));
+
+        let out = toml::to_string_pretty(&file).unwrap();
+        assert!(out.contains("type = \"notify\""));
+        assert!(!out.contains("service_type"));
+    }
+
+    #[test]
+    fn defaults_and_vars_fill_in_entries() {
+        let toml_str = r#"
+[vars]
+app = "${file_dir}/app"
+
+[defaults]
+workdir = "${app}"
+no_notify = true
+
+[defaults.timers]
+memory_max = "512M"
+workdir = "/srv"
+
+[timers.backup]
+schedule = "0 3 * * *"
+command = './backup.sh ${app} ${PATH} \${app} $${app} \n'
+
+[timers.report]
+schedule = "0 9 * * *"
+command = "./report.sh"
+memory_max = "1G"
+
+[services.web]
+command = "./web"
+env = ["DATA=${app}/data"]
+"#;
+        let file = parse(toml_str, "/home/user/deploy", &Target::default()).unwrap();
+        let backup = &file.timers["backup"];
+        // [defaults.timers] wins over [defaults], the entry over both.
+        assert_eq!(backup.workdir, "/srv");
+        assert_eq!(backup.memory_max.as_deref(), Some("512M"));
+        assert_eq!(file.timers["report"].memory_max.as_deref(), Some("1G"));
+        assert!(backup.no_notify);
+        // Unknown references are left for the shell; \${ is a literal ${, and
+        // systemd's $$ is passed on (it used to be sdtab's escape).
+        assert_eq!(backup.command, "./backup.sh /home/user/deploy/app ${PATH} ${app} $${app} \\n");
+
+        let web = &file.services["web"];
+        assert_eq!(web.workdir, "/home/user/deploy/app");
+        assert_eq!(web.env, vec!["DATA=/home/user/deploy/app/data"]);
+        assert_eq!(web.memory_max, None);
+
+        let err = parse("[timers.x]\nschedule = \"daily\"\ncommand = \"${env:SDTAB_TEST_UNSET}\"\nworkdir = \"/\"\n", "/", &Target::default())
+            .err()
+            .unwrap();
+        assert_eq!(format!("{:#}", err), "timers.x: command: environment variable 'SDTAB_TEST_UNSET' is not set");
+    }
+
+    #[test]
+    fn factored_export_parses_back_to_the_same_entries() {
+        let toml_str = r#"
+[timers.a]
+schedule = "0 3 * * *"
+command = 'echo \${file_dir} $${file_dir}'
+workdir = "/srv"
+memory_max = "512M"
+no_notify = true
+
+[timers.b]
+schedule = "0 4 * * *"
+command = "echo b"
+workdir = "/srv"
+memory_max = "512M"
+no_notify = true
+
+[services.web]
+command = "./web"
+workdir = "/srv"
+no_notify = true
+"#;
+        let file = parse(toml_str, "/ignored", &Target::default()).unwrap();
+        let plain = to_toml(&file, false).unwrap();
+        assert!(plain.contains("command = 'echo \\${file_dir} $${file_dir}'"));
+
+        let factored = to_toml(&file, true).unwrap();
+        assert!(factored.starts_with("[defaults]\nworkdir = \"/srv\"\nno_notify = true\n\n[defaults.timers]\nmemory_max = \"512M\"\n"));
+        assert!(factored.contains("[timers.a]\nschedule = \"0 3 * * *\"\ncommand = 'echo \\${file_dir} $${file_dir}'\n\n"));
+        assert_eq!(to_toml(&parse(&factored, "/other", &Target::default()).unwrap(), false).unwrap(), plain);
+    }
+
+    #[test]
+    fn includes_merge_with_origins_and_scope() {
+        let dir = TestDir::new("include");
+        fs::create_dir_all(dir.join("jobs")).unwrap();
+        let timer = |name: &str| format!("[timers.{}]\nschedule = \"daily\"\ncommand = \"true\"\nworkdir = \"${{file_dir}}\"\n", name);
+        fs::write(dir.join("Sdtabfile.toml"), format!("project = \"billing\"\ninclude = [\"jobs/*.toml\"]\n\n{}", timer("main"))).unwrap();
+        fs::write(dir.join("jobs/a.toml"), timer("a")).unwrap();
+        fs::write(dir.join("jobs/.b.toml"), timer("hidden")).unwrap();
+
+        let file = load(dir.join("./Sdtabfile.toml").to_str().unwrap(), &Target::default()).unwrap();
+        assert_eq!(file.timers.keys().collect::<Vec<_>>(), ["a", "main"]);
+        assert_eq!(file.timers["a"].workdir, dir.join("jobs").display().to_string());
+        // Origins and scope are relative to the applied file's directory.
+        assert_eq!(file.origin("a"), Some("jobs/a.toml"));
+        assert_eq!(file.scope, ["Sdtabfile.toml", "jobs/*.toml"]);
+        // Included files inherit the project.
+        assert_eq!(file.project("a"), Some("billing"));
+        assert!(file.in_scope(Some("jobs/a.toml"), None));
+        assert!(file.in_scope(Some("jobs/gone.toml"), None));
+        assert!(!file.in_scope(Some("other.toml"), None));
+        assert!(file.in_scope(Some("other.toml"), Some("billing")));
+        assert!(!file.in_scope(Some("jobs/a.toml"), Some("search")));
+        // A project's files leave units without origin or project alone.
+        assert!(!file.in_scope(None, None));
+        assert_eq!(relative_to(Path::new("/srv/shared/x.toml"), Path::new("/srv/app")), "../shared/x.toml");
+
+        assert!(wildcard_match("*-?.toml", "web-1.toml"));
+        assert!(!wildcard_match("*.toml", "web.toml.bak"));
+    }
+
+    #[test]
+    fn profiles_overlay_and_hosts_select_entries() {
+        let toml_str = r#"
+[vars]
+env = "staging"
+
+[timers.backup]
+schedule = "0 3 * * *"
+command = "./backup.sh ${env}"
+workdir = "/srv"
+memory_max = "512M"
+
+[timers.cleanup]
+schedule = "@daily"
+command = "./cleanup.sh"
+workdir = "/srv"
+
+[services.web]
+command = "./web"
+workdir = "/srv"
+hosts = ["web-*"]
+
+[profiles.prod]
+exclude = ["cleanup"]
+vars = { env = "prod" }
+
+[profiles.prod.timers.backup]
+memory_max = "2G"
+
+[profiles.prod.timers.report]
+schedule = "0 9 * * *"
+command = "./report.sh"
+workdir = "/srv"
+"#;
+        let staging = Target { hostname: Some("db-1".to_string()), ..Default::default() };
+        let file = parse(toml_str, "/", &staging).unwrap();
+        assert_eq!(file.timers.keys().collect::<Vec<_>>(), ["backup", "cleanup"]);
+        assert_eq!(file.timers["backup"].command, "./backup.sh staging");
+        assert!(file.services.is_empty());
+        assert!(file.profiles.contains("prod"));
+
+        let prod = Target { profile: Some("prod".to_string()), require_profile: true, hostname: Some("web-2".to_string()) };
+        let file = parse(toml_str, "/", &prod).unwrap();
+        assert_eq!(file.timers.keys().collect::<Vec<_>>(), ["backup", "report"]);
+        assert_eq!(file.timers["backup"].command, "./backup.sh prod");
+        assert_eq!(file.timers["backup"].memory_max.as_deref(), Some("2G"));
+        assert_eq!(file.timers["backup"].schedule, "0 3 * * *");
+        assert!(file.services.contains_key("web"));
+    }
+
+    #[test]
+    fn excludes_reach_included_files() {
+        let dir = TestDir::new("exclude");
+        fs::create_dir_all(dir.join("jobs")).unwrap();
+        let timer = |name: &str| format!("[timers.{}]\nschedule = \"daily\"\ncommand = \"true\"\nworkdir = \"/\"\n", name);
+        let main = dir.join("Sdtabfile.toml");
+        let write_main = |exclude: &str| {
+            let profile = format!("[profiles.prod]\nexclude = [{}]\n", exclude);
+            fs::write(&main, format!("include = [\"jobs/*.toml\"]\n\n{}\n{}", timer("main"), profile)).unwrap();
+        };
+        fs::write(dir.join("jobs/a.toml"), timer("a")).unwrap();
+        fs::write(dir.join("jobs/b.toml"), format!("{}hosts = [\"db-*\"]\n", timer("b"))).unwrap();
+        let prod = Target { profile: Some("prod".to_string()), require_profile: true, hostname: Some("web-2".to_string()) };
+
+        write_main("\"a\", \"b\"");
+        let file = load(main.to_str().unwrap(), &prod).unwrap();
+        assert_eq!(file.timers.keys().collect::<Vec<_>>(), ["main"]);
+        assert!(file.origin("a").is_none());
+        let staging = Target { hostname: Some("web-2".to_string()), ..Default::default() };
+        assert_eq!(load(main.to_str().unwrap(), &staging).unwrap().timers.len(), 2);
+
+        write_main("\"clean\"");
+        let err = load(main.to_str().unwrap(), &prod).err().unwrap();
+        assert_eq!(err.to_string(), format!("{}: profiles.prod.exclude: no entry named 'clean'", main.display()));
+    }
+
+    #[test]
+    fn unknown_keys_point_at_the_typo() {
+        let err = |toml: &str| format!("{:#}", parse(toml, "/", &Target::default()).err().unwrap());
+        let entry = "[timers.backup]\nschedule = \"@daily\"\ncommand = \"./b\"\nworkdir = \"/\"\n";
+        assert_eq!(
+            err(&format!("{}  memmory_max = \"1G\"\n", entry)),
+            "line 5, column 3: unknown key 'memmory_max' in [timers.backup] (did you mean 'memory_max'?)"
+        );
+        assert_eq!(
+            err(&format!("[defaults]\nrestrat = \"no\"\n\n{}", entry)),
+            "line 2, column 1: unknown key 'restrat' in [defaults] (did you mean 'restart'?)"
+        );
+        assert_eq!(
+            err(&format!("{}\n[services]\nweb = {{ command = \"./w\", workdir = \"/\", shedule = \"x\" }}\n", entry)),
+            "line 7, column 41: unknown key 'shedule' in [services.web]"
+        );
+        assert_eq!(err("frobnicate = 1\n"), "line 1, column 1: unknown key 'frobnicate'");
+
+        // A shared default only reaches the kind that has the key.
+        let file = parse(&format!("[defaults]\nrestart = \"no\"\nrandom_delay = \"5m\"\n\n{}", entry), "/", &Target::default()).unwrap();
+        assert_eq!(file.timers["backup"].random_delay.as_deref(), Some("5m"));
+    }
 }
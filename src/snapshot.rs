@@ -0,0 +1,345 @@
+//! Snapshots of units taken before `apply` touches them, so a failed apply
+//! can put them back and `sdtab rollback` can undo a finished one.
+//!
+//! A snapshot holds the unit files and drop-ins of the affected names,
+//! verbatim, their blue/green slot files, and whether each of their units
+//! was enabled and active. The
+//! last [`KEEP`] are stored as JSON under `<state dir>/snapshots/`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{bluegreen, health, init, systemctl, unit};
+
+/// Snapshots kept for `sdtab rollback`; older ones are deleted.
+const KEEP: usize = 10;
+
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Milliseconds since the epoch when it was taken; also its file name
+    pub id: u64,
+    /// What was being applied
+    pub source: String,
+    /// Entry names covered
+    pub names: Vec<String>,
+    /// Contents of the files that existed, by path relative to the unit dir
+    pub files: BTreeMap<String, String>,
+    /// Blue/green slot files of the names that had them; `None` in
+    /// snapshots taken before slots were recorded, which leave them alone
+    #[serde(default)]
+    pub slots: Option<BTreeMap<String, bluegreen::SlotFiles>>,
+    pub units: BTreeMap<String, UnitStatus>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct UnitStatus {
+    pub enabled: bool,
+    pub active: bool,
+}
+
+/// Record the files and unit states of `names` before `source` changes them.
+pub fn take(source: &str, names: &[String]) -> Result<Snapshot> {
+    let unit_dir = init::unit_dir()?;
+    let mut files = BTreeMap::new();
+    for name in names {
+        for file_name in file_names(name) {
+            let path = Path::new(&unit_dir).join(&file_name);
+            if path.is_file() {
+                files.insert(file_name.clone(), read(&path)?);
+            }
+            let dropin_dir = Path::new(&unit_dir).join(format!("{}.d", file_name));
+            if let Ok(entries) = fs::read_dir(&dropin_dir) {
+                for entry in entries {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file() {
+                        let relative = format!("{}.d/{}", file_name, entry.file_name().to_string_lossy());
+                        files.insert(relative, read(&entry.path())?);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut slots = BTreeMap::new();
+    for name in names {
+        if let Some(files) = bluegreen::read_slots(name)? {
+            slots.insert(name.clone(), files);
+        }
+    }
+
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    Ok(Snapshot {
+        id,
+        source: source.to_string(),
+        names: names.to_vec(),
+        files,
+        slots: Some(slots),
+        units: unit_states(names)?,
+    })
+}
+
+impl Snapshot {
+    /// Store the snapshot and drop the oldest beyond [`KEEP`].
+    pub fn save(&self) -> Result<()> {
+        let dir = snapshots_dir()?;
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.json", self.id));
+        let text = serde_json::to_string_pretty(self).context("Failed to serialize snapshot")?;
+        fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        let ids = list_ids()?;
+        for id in ids.iter().skip(KEEP) {
+            let _ = fs::remove_file(dir.join(format!("{}.json", id)));
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self) -> Result<()> {
+        let path = snapshots_dir()?.join(format!("{}.json", self.id));
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))
+    }
+
+    /// Put the files back and return each unit to its recorded state.
+    /// Keeps going after a failed step and reports all of them at the end.
+    pub fn restore(&self) -> Result<()> {
+        let unit_dir = init::unit_dir()?;
+        let dir_path = Path::new(&unit_dir);
+        let now = unit_states(&self.names)?;
+        let mut errors = Vec::new();
+        let mut check = |unit: &str, result: Result<()>| {
+            if let Err(e) = result {
+                errors.push(format!("{}: {:#}", unit, e));
+            }
+        };
+
+        // Stop what did not exist before, while its files still do.
+        for (unit_name, status) in &now {
+            if *status != UnitStatus::default() && !self.units.contains_key(unit_name) {
+                check(unit_name, systemctl::stop_and_disable(unit_name));
+            }
+        }
+
+        for name in &self.names {
+            for file_name in file_names(name) {
+                let path = dir_path.join(&file_name);
+                if path.is_file() {
+                    check(&file_name, fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display())));
+                }
+                let dropin_dir = dir_path.join(format!("{}.d", file_name));
+                if dropin_dir.is_dir() {
+                    check(
+                        &file_name,
+                        fs::remove_dir_all(&dropin_dir).with_context(|| format!("Failed to remove {}", dropin_dir.display())),
+                    );
+                }
+            }
+            if let Some(ref slots) = self.slots {
+                check(name, bluegreen::restore_slots(name, slots.get(name)));
+            }
+        }
+        for (relative, content) in &self.files {
+            let path = dir_path.join(relative);
+            let written = path
+                .parent()
+                .map_or(Ok(()), |parent| fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display())))
+                .and_then(|()| fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display())));
+            check(relative, written);
+        }
+        check("daemon-reload", systemctl::daemon_reload());
+
+        // Enablement and running state are restored separately: a unit can
+        // be enabled but stopped, or running without being enabled.
+        for (unit_name, before) in &self.units {
+            let status = now.get(unit_name).copied().unwrap_or_default();
+            if before.enabled && !status.enabled {
+                check(unit_name, systemctl::enable(unit_name));
+            } else if !before.enabled && status.enabled {
+                check(unit_name, systemctl::disable(unit_name));
+            }
+            if before.active {
+                // Restart even if running, to pick up the restored files.
+                check(unit_name, systemctl::restart(unit_name));
+            } else if status.active {
+                check(unit_name, systemctl::stop(unit_name));
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("Some units could not be restored:\n  {}", errors.join("\n  "));
+        }
+        Ok(())
+    }
+}
+
+/// `sdtab rollback`: restore the newest snapshot, or the one given, and
+/// drop it (and any newer ones) so the next rollback goes further back.
+pub fn run(id: Option<u64>, list: bool) -> Result<()> {
+    let ids = list_ids()?;
+    if list {
+        if ids.is_empty() {
+            println!("No snapshots.");
+        }
+        for id in &ids {
+            let snapshot = load(*id)?;
+            println!(
+                "{}  {:<8}  {}  ({})",
+                snapshot.id,
+                health::format_ago(snapshot.id / 1000),
+                snapshot.source,
+                snapshot.names.join(", ")
+            );
+        }
+        return Ok(());
+    }
+
+    let id = match id {
+        Some(id) if ids.contains(&id) => id,
+        Some(id) => bail!("No snapshot {} (see: sdtab rollback --list)", id),
+        None => match ids.first() {
+            Some(id) => *id,
+            None => bail!("No snapshots to roll back to. They are taken by sdtab apply."),
+        },
+    };
+    let snapshot = load(id)?;
+    snapshot.restore()?;
+    for newer in ids.iter().take_while(|newer| **newer >= id) {
+        load(*newer)?.delete()?;
+    }
+    println!(
+        "Rolled back {} to before applying {} ({})",
+        snapshot.names.join(", "),
+        snapshot.source,
+        health::format_ago(snapshot.id / 1000)
+    );
+    Ok(())
+}
+
+/// Unit files (and drop-in dirs, with `.d`) an entry may own.
+fn file_names(name: &str) -> Vec<String> {
+    vec![
+        unit::service_filename(name),
+        unit::timer_filename(name),
+        unit::template_filename(name),
+        unit::health_service_filename(name),
+        unit::health_timer_filename(name),
+    ]
+}
+
+/// Enabled/active state of every unit `names` may run as.
+fn unit_states(names: &[String]) -> Result<BTreeMap<String, UnitStatus>> {
+    let mut units = Vec::new();
+    for name in names {
+        units.push(unit::service_filename(name));
+        units.push(unit::timer_filename(name));
+        for slot in bluegreen::SLOTS {
+            units.push(unit::instance_filename(name, slot));
+        }
+        units.push(unit::health_timer_filename(name));
+    }
+    let props = systemctl::show_many(&units, &["UnitFileState", "ActiveState"])?;
+    let mut states = BTreeMap::new();
+    for unit_name in units {
+        let status = UnitStatus {
+            enabled: props.get(&unit_name, "UnitFileState") == Some("enabled"),
+            active: props.get(&unit_name, "ActiveState") == Some("active"),
+        };
+        if status != UnitStatus::default() {
+            states.insert(unit_name, status);
+        }
+    }
+    Ok(states)
+}
+
+/// Snapshot ids, newest first.
+fn list_ids() -> Result<Vec<u64>> {
+    let dir = snapshots_dir()?;
+    let mut ids: Vec<u64> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().to_string_lossy().strip_suffix(".json")?.parse().ok())
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(ids)
+}
+
+fn load(id: u64) -> Result<Snapshot> {
+    let path = snapshots_dir()?.join(format!("{}.json", id));
+    let content = read(&path)?;
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn snapshots_dir() -> Result<PathBuf> {
+    Ok(Path::new(&init::state_dir()?).join("snapshots"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::backend;
+    use crate::fake::{FakeBackend, UnitState};
+    use crate::testdir::TestDir;
+
+    /// Snapshot `sdtab-web.service` in the state `prepare` leaves it, let a
+    /// changed file be enabled and started, then restore.
+    fn restore_after_apply(prepare: &[&str]) -> (Vec<String>, UnitState, String) {
+        let home = TestDir::new("snapshot");
+        let fake = Rc::new(FakeBackend::default());
+        let unit = "sdtab-web.service";
+        init::with_home(&home, || {
+            backend::with_backend(fake.clone(), || {
+                let unit_dir = init::unit_dir().unwrap();
+                fs::create_dir_all(&unit_dir).unwrap();
+                let path = Path::new(&unit_dir).join(unit);
+                fs::write(&path, "[Service]\nExecStart=./old\n").unwrap();
+                systemctl::daemon_reload().unwrap();
+                for call in prepare {
+                    match *call {
+                        "enable" => systemctl::enable(unit).unwrap(),
+                        _ => systemctl::start(unit).unwrap(),
+                    }
+                }
+                let snapshot = take("Sdtabfile.toml", &["web".to_string()]).unwrap();
+
+                fs::write(&path, "[Service]\nExecStart=./new\n").unwrap();
+                systemctl::daemon_reload().unwrap();
+                systemctl::enable_and_start(unit).unwrap();
+                fake.take_calls();
+
+                snapshot.restore().unwrap();
+                (fake.take_calls(), fake.state(unit), fs::read_to_string(&path).unwrap())
+            })
+        })
+    }
+
+    #[test]
+    fn restores_enabled_but_stopped_units() {
+        let (calls, state, content) = restore_after_apply(&["enable"]);
+        assert_eq!(content, "[Service]\nExecStart=./old\n");
+        assert_eq!(calls, vec!["daemon-reload", "stop sdtab-web.service"]);
+        assert!(state.enabled && !state.active, "{:?}", state);
+    }
+
+    #[test]
+    fn restores_running_but_disabled_units() {
+        let (calls, state, _) = restore_after_apply(&["start"]);
+        assert_eq!(calls, vec!["daemon-reload", "disable sdtab-web.service", "restart sdtab-web.service"]);
+        assert!(!state.enabled && state.active, "{:?}", state);
+    }
+}
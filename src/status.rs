@@ -3,49 +3,72 @@ use std::path::Path;
 
 use anyhow::{bail, Result};
 
-use crate::{cron, init, parse_unit, systemctl, unit};
+use crate::unit_file::UnitFile;
+use crate::{bluegreen, cron, deploy, health, init, parse_unit, systemctl, unit};
+
+/// What `status` reads of both the service and the timer of an entry.
+pub const PROPERTIES: &[&str] = &[
+    "ActiveState",
+    "SubState",
+    "MainPID",
+    "MemoryCurrent",
+    "NextElapseUSecRealtime",
+    "ExecMainStartTimestamp",
+    "Result",
+    "ExecStart",
+    "WorkingDirectory",
+];
 
 pub fn run(name: &str) -> Result<()> {
     let unit_dir = init::unit_dir()?;
     let dir_path = Path::new(&unit_dir);
 
-    let service_path = dir_path.join(unit::service_filename(name));
+    let service_path = dir_path.join(bluegreen::definition_filename(name));
     let timer_path = dir_path.join(unit::timer_filename(name));
 
     if !service_path.exists() {
         bail!("'{}' not found.", name);
     }
 
-    let service_content = fs::read_to_string(&service_path)?;
+    let service = UnitFile::load_effective(&service_path)?;
     let global_env_path = init::global_env_path().unwrap_or_default();
     let timer_content = fs::read_to_string(&timer_path).ok();
-    let parsed = parse_unit::parse_service_file(
+    let parsed = parse_unit::parse_unit_file(
         name,
-        &service_content,
+        &service,
         timer_content.as_deref(),
         &global_env_path,
     );
 
     let is_timer = matches!(parsed.unit_type, parse_unit::UnitType::Timer);
 
+    let service_unit = bluegreen::service_unit(name);
+    let timer_unit = unit::timer_filename(name);
+    let props = systemctl::show_many(&[service_unit.clone(), timer_unit.clone()], PROPERTIES).unwrap_or_default();
+
     println!("Name:    {}", name);
 
     if is_timer {
         println!("Type:    timer");
-        print_timer_status(name, parsed.cron_expr.as_deref())?;
+        print_timer_status(&props, &service_unit, &timer_unit, parsed.cron_expr.as_deref())?;
     } else {
         println!("Type:    service");
-        print_service_status(name)?;
+        print_service_status(&props, &service_unit)?;
+        if let Some(ref cmd) = parsed.health_cmd {
+            print_health_status(name, cmd, parsed.health_retries);
+        }
+        if !parsed.deploy_steps.is_empty() {
+            print_deploy_history(name);
+        }
     }
 
     // Show common service properties
-    let service_unit = unit::service_filename(name);
-    if let Ok(cmd) = systemctl::show_property(&service_unit, "ExecStart") {
+    if let Some(cmd) = props.get(&service_unit, "ExecStart") {
         // ExecStart format: { path=... ; argv[]=... } - extract the meaningful part
-        let cmd_display = extract_exec_command(&cmd);
+        let cmd_display = extract_exec_command(cmd);
         println!("Command: {}", cmd_display);
     }
-    if let Ok(workdir) = systemctl::show_property(&service_unit, "WorkingDirectory") {
+    if let Some(workdir) = props.get(&service_unit, "WorkingDirectory") {
         if !workdir.is_empty() {
             println!("WorkDir: {}", workdir);
         }
@@ -54,22 +77,17 @@ pub fn run(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn print_service_status(name: &str) -> Result<()> {
-    let service_unit = unit::service_filename(name);
-
-    let active = systemctl::show_property(&service_unit, "ActiveState")
-        .unwrap_or_else(|_| "unknown".to_string());
-    let sub = systemctl::show_property(&service_unit, "SubState")
-        .unwrap_or_else(|_| "unknown".to_string());
-    let pid = systemctl::show_property(&service_unit, "MainPID")
-        .unwrap_or_else(|_| "?".to_string());
+fn print_service_status(props: &systemctl::UnitProperties, service_unit: &str) -> Result<()> {
+    let active = props.get(service_unit, "ActiveState").unwrap_or("unknown");
+    let sub = props.get(service_unit, "SubState").unwrap_or("unknown");
+    let pid = props.get(service_unit, "MainPID").unwrap_or("?");
 
     println!("Status:  {} ({})", active, sub);
     if active == "active" && pid != "0" {
         println!("PID:     {}", pid);
     }
 
-    if let Ok(memory) = systemctl::show_property(&service_unit, "MemoryCurrent") {
+    if let Some(memory) = props.get(service_unit, "MemoryCurrent") {
         if memory != "[not set]" && memory != "infinity" {
             if let Ok(bytes) = memory.parse::<u64>() {
                 println!("Memory:  {}", format_bytes(bytes));
@@ -80,27 +98,86 @@ fn print_service_status(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn print_timer_status(name: &str, cron_expr: Option<&str>) -> Result<()> {
-    let timer_unit = unit::timer_filename(name);
-    let service_unit = unit::service_filename(name);
+fn print_health_status(name: &str, cmd: &str, retries: Option<u32>) {
+    let retries = retries.unwrap_or(unit::DEFAULT_HEALTH_RETRIES);
+    println!("Health:  {}", cmd);
+    let state = match health::load_state(name) {
+        Ok(state) => state,
+        Err(e) => {
+            println!("  Probe: unreadable state ({})", e);
+            return;
+        }
+    };
+    match state.last_ok {
+        None => println!("  Probe: no result yet"),
+        Some(ok) => {
+            let result = if ok { "ok".to_string() } else { health::summary(&state, retries) };
+            println!(
+                "  Probe: {} in {} ms ({})",
+                result,
+                state.last_latency_ms,
+                health::format_ago(state.last_checked)
+            );
+            if let Some(ref err) = state.last_error {
+                println!("  Error: {}", err);
+            }
+        }
+    }
+    if state.restarts > 0 {
+        println!("  Health restarts: {}", state.restarts);
+    }
+}
+
+fn print_deploy_history(name: &str) {
+    let records = match deploy::load_history(name, 5) {
+        Ok(records) => records,
+        Err(e) => {
+            println!("Deploys: unreadable history ({})", e);
+            return;
+        }
+    };
+    if records.is_empty() {
+        println!("Deploys: none yet (sdtab deploy {})", name);
+        return;
+    }
+    println!("Deploys:");
+    for r in &records {
+        let rev = r.revision.as_deref().map(|v| format!("  {}", v)).unwrap_or_default();
+        println!(
+            "  {:<15} {:>8}  {}s{}",
+            r.outcome.label(),
+            health::format_ago(r.started),
+            r.duration_secs,
+            rev
+        );
+        if let Some(ref step) = r.failed_step {
+            println!("    at: {}", step);
+        }
+    }
+}
 
-    let active = systemctl::show_property(&timer_unit, "ActiveState")
-        .unwrap_or_else(|_| "unknown".to_string());
+fn print_timer_status(
+    props: &systemctl::UnitProperties,
+    service_unit: &str,
+    timer_unit: &str,
+    cron_expr: Option<&str>,
+) -> Result<()> {
+    let active = props.get(timer_unit, "ActiveState").unwrap_or("unknown");
     println!("Status:  {}", active);
 
-    if let Ok(next) = systemctl::show_property(&timer_unit, "NextElapseUSecRealtime") {
+    if let Some(next) = props.get(timer_unit, "NextElapseUSecRealtime") {
         if !next.is_empty() && next != "n/a" {
             println!("Next:    {}", next);
         }
     }
 
-    if let Ok(last) = systemctl::show_property(&service_unit, "ExecMainStartTimestamp") {
+    if let Some(last) = props.get(service_unit, "ExecMainStartTimestamp") {
         if !last.is_empty() && last != "n/a" {
             println!("Last:    {}", last);
         }
     }
 
-    if let Ok(result) = systemctl::show_property(&service_unit, "Result") {
+    if let Some(result) = props.get(service_unit, "Result") {
         println!("Result:  {}", result);
     }
 
@@ -1,10 +1,34 @@
+use std::collections::HashMap;
 use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
 
+use crate::backend::{self, Backend};
+use crate::init;
+
+/// `--user`, or `--system` in system mode; `journalctl` takes the same flag.
+pub fn scope_flag() -> &'static str {
+    if init::system() {
+        "--system"
+    } else {
+        "--user"
+    }
+}
+
+/// How `journalctl` selects a unit's messages in the current mode.
+pub fn journal_unit_flag() -> &'static str {
+    if init::system() {
+        "--unit"
+    } else {
+        "--user-unit"
+    }
+}
+
 fn run(args: &[&str]) -> Result<String> {
     let output = Command::new("systemctl")
-        .arg("--user")
+        .arg(scope_flag())
         .args(args)
         .output()
         .context("Failed to execute systemctl")?;
@@ -14,7 +38,8 @@ fn run(args: &[&str]) -> Result<String> {
 
     if !output.status.success() {
         bail!(
-            "systemctl --user {} failed: {}",
+            "systemctl {} {} failed: {}",
+            scope_flag(),
             args.join(" "),
             stderr.trim()
         );
@@ -23,38 +48,251 @@ fn run(args: &[&str]) -> Result<String> {
     Ok(stdout.trim().to_string())
 }
 
+/// The `systemctl` CLI backend.
+pub struct Systemctl;
+
+impl Backend for Systemctl {
+    fn daemon_reload(&self) -> Result<()> {
+        run(&["daemon-reload"])?;
+        Ok(())
+    }
+
+    fn enable_and_start(&self, unit: &str) -> Result<()> {
+        run(&["enable", "--now", unit])?;
+        Ok(())
+    }
+
+    fn stop_and_disable(&self, unit: &str) -> Result<()> {
+        run(&["disable", "--now", unit])?;
+        Ok(())
+    }
+
+    fn enable(&self, unit: &str) -> Result<()> {
+        run(&["enable", unit])?;
+        Ok(())
+    }
+
+    fn disable(&self, unit: &str) -> Result<()> {
+        run(&["disable", unit])?;
+        Ok(())
+    }
+
+    fn start(&self, unit: &str) -> Result<()> {
+        run(&["start", unit])?;
+        Ok(())
+    }
+
+    fn stop(&self, unit: &str) -> Result<()> {
+        run(&["stop", unit])?;
+        Ok(())
+    }
+
+    fn restart(&self, unit: &str) -> Result<()> {
+        run(&["restart", unit])?;
+        Ok(())
+    }
+
+    fn reload(&self, unit: &str) -> Result<()> {
+        run(&["reload", unit])?;
+        Ok(())
+    }
+
+    fn show(&self, units: &[String], properties: &[&str]) -> Result<UnitProperties> {
+        show_many_with(units, properties, run)
+    }
+
+    fn journal_tail(&self, unit: &str, lines: u32) -> Vec<String> {
+        journal_tail_cli(unit, lines)
+    }
+
+    fn next_runs(&self, on_calendar: &str, count: u32) -> Result<Vec<String>> {
+        next_runs_cli(on_calendar, count)
+    }
+}
+
 pub fn daemon_reload() -> Result<()> {
-    run(&["daemon-reload"])?;
-    Ok(())
+    backend::current()?.daemon_reload()
 }
 
 pub fn enable_and_start(unit: &str) -> Result<()> {
-    run(&["enable", "--now", unit])?;
-    Ok(())
+    backend::current()?.enable_and_start(unit)
 }
 
 pub fn stop_and_disable(unit: &str) -> Result<()> {
-    run(&["disable", "--now", unit])?;
-    Ok(())
+    backend::current()?.stop_and_disable(unit)
+}
+
+pub fn enable(unit: &str) -> Result<()> {
+    backend::current()?.enable(unit)
+}
+
+pub fn disable(unit: &str) -> Result<()> {
+    backend::current()?.disable(unit)
+}
+
+pub fn stop(unit: &str) -> Result<()> {
+    backend::current()?.stop(unit)
 }
 
 pub fn restart(unit: &str) -> Result<()> {
-    run(&["restart", unit])?;
-    Ok(())
+    backend::current()?.restart(unit)
 }
 
 pub fn start(unit: &str) -> Result<()> {
-    run(&["start", unit])?;
-    Ok(())
+    backend::current()?.start(unit)
+}
+
+pub fn reload(unit: &str) -> Result<()> {
+    backend::current()?.reload(unit)
 }
 
 pub fn show_property(unit: &str, property: &str) -> Result<String> {
-    let output = run(&["show", "-p", property, "--value", unit])?;
-    Ok(output)
+    let props = show_many(&[unit.to_string()], &[property])?;
+    match props.get(unit, property) {
+        Some(value) => Ok(value.to_string()),
+        None => bail!("{} has no property {}", unit, property),
+    }
+}
+
+/// Properties of many units, from a single `systemctl show` call.
+#[derive(Debug, Default)]
+pub struct UnitProperties {
+    units: HashMap<String, HashMap<String, String>>,
 }
 
-/// Get next N execution times for an OnCalendar expression using systemd-analyze.
+impl UnitProperties {
+    pub fn get(&self, unit: &str, property: &str) -> Option<&str> {
+        self.units.get(unit)?.get(property).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, unit: &str, property: &str, value: String) {
+        self.units.entry(unit.to_string()).or_default().insert(property.to_string(), value);
+    }
+}
+
+/// Fetch `properties` for every unit at once — with the CLI backend, one
+/// `systemctl show -p A,B u1 u2 …` call instead of one process per unit and
+/// property. Units systemd doesn't know still get a record (`ActiveState=inactive`).
+pub fn show_many(units: &[String], properties: &[&str]) -> Result<UnitProperties> {
+    if units.is_empty() {
+        return Ok(UnitProperties::default());
+    }
+    backend::current()?.show(units, properties)
+}
+
+fn show_many_with(
+    units: &[String],
+    properties: &[&str],
+    run: impl FnOnce(&[&str]) -> Result<String>,
+) -> Result<UnitProperties> {
+    if units.is_empty() {
+        return Ok(UnitProperties::default());
+    }
+    let props = properties.join(",");
+    let mut args = vec!["show", "-p", props.as_str()];
+    args.extend(units.iter().map(String::as_str));
+    let output = run(&args)?;
+
+    let records = parse_show_records(&output);
+    if records.len() != units.len() {
+        bail!("systemctl show returned {} records for {} units", records.len(), units.len());
+    }
+    // Records come back in argument order.
+    Ok(UnitProperties { units: units.iter().cloned().zip(records).collect() })
+}
+
+/// Split `systemctl show` output for several units into one map per unit.
+/// Records are `Key=value` lines separated by a blank line.
+fn parse_show_records(output: &str) -> Vec<HashMap<String, String>> {
+    output
+        .split("\n\n")
+        .filter(|record| !record.trim().is_empty())
+        .map(|record| {
+            record
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        })
+        .collect()
+}
+
+/// Wait until a service is actually up, not merely started.
+///
+/// `Type=notify`/`forking` units are ready once systemd reports `active`.
+/// `Type=simple`/`exec` have no readiness protocol, so the unit must also stay
+/// `active` for `settle` — long enough to catch a crash during startup.
+/// A unit that falls into `failed` or `auto-restart` is reported as an error.
+pub fn wait_until_ready(unit: &str, timeout: Duration, settle: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut active_since: Option<Instant> = None;
+
+    let units = [unit.to_string()];
+    loop {
+        let props = show_many(&units, &["ActiveState", "SubState"]).unwrap_or_default();
+        let active = props.get(unit, "ActiveState").unwrap_or_default().to_string();
+        let sub = props.get(unit, "SubState").unwrap_or_default().to_string();
+
+        match active.as_str() {
+            "active" | "reloading" => {
+                let since = *active_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= settle {
+                    return Ok(());
+                }
+            }
+            "failed" => bail!("{} entered failed state ({})", unit, sub),
+            "inactive" if active_since.is_some() => bail!("{} exited during startup", unit),
+            _ => {
+                if sub == "auto-restart" {
+                    bail!("{} crashed during startup and is being restarted", unit);
+                }
+                active_since = None;
+            }
+        }
+
+        if Instant::now() >= deadline {
+            bail!(
+                "{} did not become ready within {}s (state: {} / {})",
+                unit,
+                timeout.as_secs(),
+                active,
+                sub
+            );
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Last `lines` journal lines of a unit, without metadata prefixes.
+pub fn journal_tail(unit: &str, lines: u32) -> Vec<String> {
+    match backend::current() {
+        Ok(backend) => backend.journal_tail(unit, lines),
+        Err(_) => vec![],
+    }
+}
+
+/// Get next N execution times for an OnCalendar expression.
 pub fn next_runs(on_calendar: &str, count: u32) -> Result<Vec<String>> {
+    backend::current()?.next_runs(on_calendar, count)
+}
+
+/// `journalctl` reads the journal files directly, whatever the backend.
+pub fn journal_tail_cli(unit: &str, lines: u32) -> Vec<String> {
+    let output = Command::new("journalctl")
+        .args([scope_flag(), journal_unit_flag(), unit, "--no-pager", "-o", "cat", "-n"])
+        .arg(lines.to_string())
+        .output();
+    match output {
+        Ok(out) => String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .map(|l| l.to_string())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Evaluate a calendar expression with `systemd-analyze`, whatever the backend.
+pub fn next_runs_cli(on_calendar: &str, count: u32) -> Result<Vec<String>> {
     let output = Command::new("systemd-analyze")
         .args(["calendar", on_calendar, &format!("--iterations={}", count)])
         .output()
@@ -80,3 +318,65 @@ pub fn next_runs(on_calendar: &str, count: u32) -> Result<Vec<String>> {
     }
     Ok(times)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn parse_records_in_order() {
+        let output = "ActiveState=active\nNextElapseUSecRealtime=Mon 2026-03-02 09:00:00 JST\n\n\
+                      ActiveState=failed\nNextElapseUSecRealtime=\n";
+        let units = ["sdtab-a.timer".to_string(), "sdtab-b.timer".to_string()];
+        let props = show_many_with(&units, &["ActiveState", "NextElapseUSecRealtime"], |args| {
+            assert_eq!(args, ["show", "-p", "ActiveState,NextElapseUSecRealtime", "sdtab-a.timer", "sdtab-b.timer"]);
+            Ok(output.to_string())
+        })
+        .unwrap();
+        assert_eq!(props.get("sdtab-a.timer", "ActiveState"), Some("active"));
+        assert_eq!(props.get("sdtab-a.timer", "NextElapseUSecRealtime"), Some("Mon 2026-03-02 09:00:00 JST"));
+        assert_eq!(props.get("sdtab-b.timer", "ActiveState"), Some("failed"));
+        assert_eq!(props.get("sdtab-b.timer", "NextElapseUSecRealtime"), Some(""));
+        assert_eq!(props.get("sdtab-c.timer", "ActiveState"), None);
+    }
+
+    #[test]
+    fn record_count_mismatch_is_an_error() {
+        let units = ["sdtab-a.timer".to_string(), "sdtab-b.timer".to_string()];
+        let result = show_many_with(&units, &["ActiveState"], |_| Ok("ActiveState=active".to_string()));
+        assert!(result.is_err());
+    }
+
+    /// 500 units × 3 properties in one `systemctl show`.
+    #[test]
+    fn bench_500_units() {
+        let units: Vec<String> = (0..500)
+            .flat_map(|i| [format!("sdtab-job{}.service", i), format!("sdtab-job{}.timer", i)])
+            .collect();
+        let fixture = units
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                format!(
+                    "ActiveState={}\nSubState=waiting\nNextElapseUSecRealtime=Mon 2026-03-02 {:02}:00:00 JST\n",
+                    if i % 7 == 0 { "failed" } else { "active" },
+                    i % 24
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let calls = Cell::new(0);
+        let props = show_many_with(&units, &["ActiveState", "SubState", "NextElapseUSecRealtime"], |args| {
+            calls.set(calls.get() + 1);
+            assert_eq!(args.len(), 3 + units.len());
+            Ok(fixture.clone())
+        })
+        .unwrap();
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(props.get("sdtab-job0.service", "ActiveState"), Some("failed"));
+        assert_eq!(props.get("sdtab-job499.timer", "NextElapseUSecRealtime"), Some("Mon 2026-03-02 15:00:00 JST"));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::cron::CronSchedule;
 use crate::init;
 
-/// Unit file template version. Embedded as `# sdtab:template_version=N` in
+/// Unit file template version. Embedded as `X-Sdtab-Template-Version=N` in
 /// generated service files so `sdtab list` can detect legacy units that were
 /// generated by an older sdtab and recommend `sdtab upgrade`.
 ///
@@ -14,7 +14,13 @@ use crate::init;
 /// - 2: adds `SyslogIdentifier=sdtab-<name>` so `journalctl --user-unit`
 ///   captures child process stdout on systems where journald fails to
 ///   attach user-unit metadata to child stream records.
-pub const TEMPLATE_VERSION: u32 = 4;
+/// - 5: metadata moves from `# sdtab:key=value` comments above `[Unit]` to
+///   `X-Sdtab-*` keys inside `[Unit]` (see [`meta_line`]).
+pub const TEMPLATE_VERSION: u32 = 5;
+
+/// Prefix of the metadata keys in `[Unit]`. systemd ignores `X-` keys, so
+/// they survive `systemctl` round-trips and read as ordinary settings in an editor.
+pub const META_PREFIX: &str = "X-Sdtab-";
 
 #[derive(Default)]
 pub struct UnitConfig {
@@ -33,26 +39,74 @@ pub struct UnitConfig {
     pub exec_start_pre: Option<String>,
     pub exec_stop_post: Option<String>,
     pub log_level_max: Option<String>,
+    pub user: Option<String>,
+    pub group: Option<String>,
     pub random_delay: Option<String>,
     pub env: Vec<String>,
     pub original_command: Option<String>,
     pub on_failure: Option<String>,
     pub no_notify: bool,
+    /// systemd `Type=` for daemon services (`simple` when unset).
+    pub service_type: Option<String>,
+    pub pid_file: Option<String>,
+    pub watchdog: Option<String>,
+    pub exec_reload: Option<String>,
+    /// Probe command run by the companion `sdtab-health@<name>` timer.
+    pub health_cmd: Option<String>,
+    pub health_interval: Option<String>,
+    pub health_retries: Option<u32>,
+    /// Two ports for blue/green slots. When set, the service is generated as
+    /// a template (`sdtab-<name>@.service`) run as `@blue` / `@green`.
+    pub ports: Vec<u16>,
+    /// Commands run by `sdtab deploy` before restarting, and after a failed restart.
+    pub deploy_steps: Vec<String>,
+    pub rollback_steps: Vec<String>,
+    /// Per-unit output settings as given by the user (stored as metadata).
+    pub output_file: Option<String>,
+    pub output_max_size: Option<String>,
+    pub output_keep: Option<u32>,
+    pub mail_to: Option<String>,
+    /// Sdtabfile the unit was applied from and the file's `project`, so
+    /// `apply --prune` leaves units of other files and projects alone.
+    pub origin: Option<String>,
+    pub project: Option<String>,
+    /// Output settings after applying `config.toml` defaults; drives the
+    /// generated directives.
+    pub output: OutputTarget,
+}
+
+/// Where a unit's stdout/stderr go beyond the journal.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct OutputTarget {
+    /// `StandardOutput=append:` file, rotated by size before each start.
+    pub file: Option<String>,
+    pub max_size: String,
+    pub keep: u32,
+    /// Recipient of a run's output when it is non-empty.
+    pub mail_to: Option<String>,
+    /// sdtab invocation used by the hooks (absolute binary path plus layout options).
+    pub sdtab_bin: String,
 }
 
+/// Defaults for health checks when `--health-interval`/`--health-retries` are omitted.
+pub const DEFAULT_HEALTH_INTERVAL: &str = "30s";
+pub const DEFAULT_HEALTH_RETRIES: u32 = 3;
+
 pub fn generate_service(config: &UnitConfig) -> String {
     let cron = config.cron_expr.as_deref().unwrap_or("");
     let resource_lines = generate_service_options(config);
     let global_env = global_env_line();
     let command_meta = match &config.original_command {
-        Some(cmd) => format!("# sdtab:command={}\n", cmd),
+        Some(cmd) => meta_line("command", cmd),
         None => String::new(),
     };
     let no_notify_meta = if config.no_notify {
-        "# sdtab:no-notify=true\n"
+        meta_line("no-notify", "true")
     } else {
-        ""
+        String::new()
     };
+    let apply_meta = apply_metadata(config);
+    let output_meta = output_metadata(config);
     let on_failure_line = match &config.on_failure {
         Some(target) => format!("OnFailure={}\n", target),
         None => String::new(),
@@ -62,12 +116,14 @@ pub fn generate_service(config: &UnitConfig) -> String {
         None => String::new(),
     };
     format!(
-        "# sdtab:type=timer\n\
-         # sdtab:template_version={template_version}\n\
-         # sdtab:cron={cron}\n\
+        "[Unit]\n\
+         {type_meta}\
+         {version_meta}\
+         {cron_meta}\
          {command_meta}\
          {no_notify_meta}\
-         [Unit]\n\
+         {apply_meta}\
+         {output_meta}\
          Description=[sdtab] {name}: {desc}\n\
          {on_failure_line}\
          \n\
@@ -75,14 +131,18 @@ pub fn generate_service(config: &UnitConfig) -> String {
          Type=oneshot\n\
          ExecStart={command}\n\
          WorkingDirectory={workdir}\n\
-         SyslogIdentifier=sdtab-{name}\n\
+         SyslogIdentifier={prefix}{name}\n\
          {global_env}\
          {env_line}\
          {resource_lines}",
-        template_version = TEMPLATE_VERSION,
-        cron = cron,
+        prefix = init::prefix(),
+        type_meta = meta_line("type", "timer"),
+        version_meta = meta_line("template-version", &TEMPLATE_VERSION.to_string()),
+        cron_meta = meta_line("cron", cron),
         command_meta = command_meta,
         no_notify_meta = no_notify_meta,
+        apply_meta = apply_meta,
+        output_meta = output_meta,
         name = config.name,
         desc = config.description,
         on_failure_line = on_failure_line,
@@ -99,16 +159,18 @@ pub fn generate_daemon_service(config: &UnitConfig) -> String {
         .restart_policy
         .as_deref()
         .unwrap_or("always");
-    let restart_meta = format!("# sdtab:restart={}\n", restart);
+    let restart_meta = meta_line("restart", restart);
     let command_meta = match &config.original_command {
-        Some(cmd) => format!("# sdtab:command={}\n", cmd),
+        Some(cmd) => meta_line("command", cmd),
         None => String::new(),
     };
     let no_notify_meta = if config.no_notify {
-        "# sdtab:no-notify=true\n"
+        meta_line("no-notify", "true")
     } else {
-        ""
+        String::new()
     };
+    let apply_meta = apply_metadata(config);
+    let output_meta = output_metadata(config);
     let on_failure_line = match &config.on_failure {
         Some(target) => format!("OnFailure={}\n", target),
         None => String::new(),
@@ -119,16 +181,36 @@ pub fn generate_daemon_service(config: &UnitConfig) -> String {
         None => String::new(),
     };
 
+    let service_type = config.service_type.as_deref().unwrap_or("simple");
+    let daemon_lines = generate_daemon_options(config);
     let resource_lines = generate_service_options(config);
     let global_env = global_env_line();
+    let health_meta = health_metadata(config);
+    let deploy_meta = deploy_metadata(config);
+    let ports_meta = if config.ports.is_empty() {
+        String::new()
+    } else {
+        let ports: Vec<String> = config.ports.iter().map(|p| p.to_string()).collect();
+        meta_line("ports", &ports.join(","))
+    };
+    let slot_env = if config.ports.is_empty() {
+        String::new()
+    } else {
+        slot_env_line(&config.name)
+    };
 
     format!(
-        "# sdtab:type=service\n\
-         # sdtab:template_version={template_version}\n\
+        "[Unit]\n\
+         {type_meta}\
+         {version_meta}\
          {restart_meta}\
          {command_meta}\
          {no_notify_meta}\
-         [Unit]\n\
+         {apply_meta}\
+         {output_meta}\
+         {health_meta}\
+         {ports_meta}\
+         {deploy_meta}\
          Description=[sdtab] {name}: {desc}\n\
          {on_failure_line}\
          After=network-online.target\n\
@@ -136,30 +218,43 @@ pub fn generate_daemon_service(config: &UnitConfig) -> String {
          StartLimitBurst=3\n\
          \n\
          [Service]\n\
-         Type=simple\n\
+         Type={service_type}\n\
          ExecStart={command}\n\
+         {daemon_lines}\
          WorkingDirectory={workdir}\n\
-         SyslogIdentifier=sdtab-{name}\n\
+         SyslogIdentifier={prefix}{name}\n\
          Restart={restart}\n\
          RestartSec=5\n\
          SuccessExitStatus=143 SIGTERM\n\
          {global_env}\
          {env_line}\
+         {slot_env}\
          {resource_lines}\
          [Install]\n\
-         WantedBy=default.target\n",
-        template_version = TEMPLATE_VERSION,
+         WantedBy={wanted_by}\n",
+        type_meta = meta_line("type", "service"),
+        prefix = init::prefix(),
+        wanted_by = default_target(),
+        version_meta = meta_line("template-version", &TEMPLATE_VERSION.to_string()),
         restart_meta = restart_meta,
         command_meta = command_meta,
         no_notify_meta = no_notify_meta,
+        apply_meta = apply_meta,
+        output_meta = output_meta,
+        health_meta = health_meta,
+        ports_meta = ports_meta,
+        deploy_meta = deploy_meta,
         name = config.name,
         desc = config.description,
         on_failure_line = on_failure_line,
+        service_type = service_type,
         command = config.command,
+        daemon_lines = daemon_lines,
         workdir = config.workdir,
         restart = restart,
         global_env = global_env,
         env_line = env_line,
+        slot_env = slot_env,
         resource_lines = resource_lines,
     )
 }
@@ -196,6 +291,221 @@ pub fn generate_timer(config: &UnitConfig) -> String {
     )
 }
 
+/// One `X-Sdtab-<Key>=<value>` line. `key` is the kebab-case metadata name
+/// (`health-cmd` → `X-Sdtab-Health-Cmd`). Backslashes and newlines in the
+/// value are escaped so multi-line values stay on one line; list-valued
+/// metadata (deploy steps) is written as repeated keys.
+pub fn meta_line(key: &str, value: &str) -> String {
+    format!("{}={}\n", meta_key(key), escape_meta(value))
+}
+
+/// `health-cmd` → `X-Sdtab-Health-Cmd`.
+pub fn meta_key(key: &str) -> String {
+    let key: Vec<String> = key
+        .split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    format!("{}{}", META_PREFIX, key.join("-"))
+}
+
+pub fn escape_meta(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Inverse of [`meta_line`]'s escaping.
+pub fn unescape_meta(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Parse one metadata line into its kebab-case key and value. Accepts both
+/// `X-Sdtab-Health-Cmd=...` and the pre-v5 `# sdtab:health-cmd=...` comments
+/// (whose `template_version` key used an underscore).
+pub fn parse_meta(line: &str) -> Option<(String, String)> {
+    if let Some(rest) = line.strip_prefix(META_PREFIX) {
+        let (key, val) = rest.split_once('=')?;
+        return Some((key.trim().to_ascii_lowercase(), unescape_meta(val)));
+    }
+    let rest = line.strip_prefix("# sdtab:")?;
+    let (key, val) = rest.split_once('=')?;
+    Some((key.replace('_', "-"), val.to_string()))
+}
+
+/// Join `lines`, inserting the metadata `block` after the first `[Unit]`
+/// header (or in a new `[Unit]` section at the top when there is none).
+pub fn insert_metadata(lines: &[&str], block: &str) -> String {
+    let mut out = String::new();
+    let mut inserted = false;
+    for line in lines {
+        out.push_str(line);
+        out.push('\n');
+        if !inserted && line.trim() == "[Unit]" {
+            out.push_str(block);
+            inserted = true;
+        }
+    }
+    if !inserted && !block.is_empty() {
+        out = format!("[Unit]\n{}\n{}", block, out);
+    }
+    out
+}
+
+fn health_metadata(config: &UnitConfig) -> String {
+    let mut meta = String::new();
+    if let Some(ref cmd) = config.health_cmd {
+        meta.push_str(&meta_line("health-cmd", cmd));
+    }
+    if let Some(ref interval) = config.health_interval {
+        meta.push_str(&meta_line("health-interval", interval));
+    }
+    if let Some(retries) = config.health_retries {
+        meta.push_str(&meta_line("health-retries", &retries.to_string()));
+    }
+    meta
+}
+
+/// `StandardOutput=append:` plus the rotate/mail hooks. The hooks are
+/// `-`-prefixed so a failing hook never fails the unit itself. systemd
+/// expands specifiers in all of them, so a `%` in the path is doubled.
+fn output_lines(name: &str, output: &OutputTarget) -> String {
+    let mut lines = String::new();
+    let file = output.file.as_ref().map(|f| f.replace('%', "%%"));
+    if let Some(ref file) = file {
+        lines.push_str(&format!("StandardOutput=append:{}\n", file));
+        lines.push_str(&format!("StandardError=append:{}\n", file));
+        lines.push_str(&format!(
+            "ExecStartPre=-{} __output-rotate {} {} {} \"{}\"\n",
+            output.sdtab_bin, name, output.max_size, output.keep, file
+        ));
+    }
+    if let Some(ref addr) = output.mail_to {
+        let file_arg = file
+            .as_ref()
+            .map(|f| format!(" \"{}\"", f))
+            .unwrap_or_default();
+        lines.push_str(&format!(
+            "ExecStopPost=-{} __mail-output {} {}{}\n",
+            output.sdtab_bin, name, addr, file_arg
+        ));
+    }
+    lines
+}
+
+/// True for the `ExecStartPre=`/`ExecStopPost=` lines generated by `output_lines`.
+pub fn is_output_hook(exec: &str) -> bool {
+    exec.contains(" __output-rotate ") || exec.contains(" __mail-output ")
+}
+
+fn apply_metadata(config: &UnitConfig) -> String {
+    let mut meta = String::new();
+    if let Some(ref project) = config.project {
+        meta.push_str(&meta_line("project", project));
+    }
+    if let Some(ref origin) = config.origin {
+        meta.push_str(&meta_line("origin", origin));
+    }
+    meta
+}
+
+fn output_metadata(config: &UnitConfig) -> String {
+    let mut meta = String::new();
+    if let Some(ref file) = config.output_file {
+        meta.push_str(&meta_line("output-file", file));
+    }
+    if let Some(ref size) = config.output_max_size {
+        meta.push_str(&meta_line("output-max-size", size));
+    }
+    if let Some(keep) = config.output_keep {
+        meta.push_str(&meta_line("output-keep", &keep.to_string()));
+    }
+    if let Some(ref addr) = config.mail_to {
+        meta.push_str(&meta_line("mail-to", addr));
+    }
+    meta
+}
+
+/// One metadata line per step, in order.
+fn deploy_metadata(config: &UnitConfig) -> String {
+    let mut meta = String::new();
+    for step in &config.deploy_steps {
+        meta.push_str(&meta_line("deploy-step", step));
+    }
+    for step in &config.rollback_steps {
+        meta.push_str(&meta_line("rollback-step", step));
+    }
+    meta
+}
+
+/// Companion oneshot that runs the probe via `sdtab __health-check <name>`.
+/// The probe itself is read from the service's metadata at run time, so the
+/// health units don't need regenerating when only the command changes.
+pub fn generate_health_service(config: &UnitConfig, sdtab_bin: &str) -> String {
+    let global_env = global_env_line();
+    format!(
+        "[Unit]\n\
+         Description=[sdtab] {name} health check\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={sdtab_bin} __health-check {name}\n\
+         WorkingDirectory={workdir}\n\
+         SyslogIdentifier={prefix}health-{name}\n\
+         {global_env}",
+        prefix = init::prefix(),
+        name = config.name,
+        sdtab_bin = sdtab_bin,
+        workdir = config.workdir,
+        global_env = global_env,
+    )
+}
+
+pub fn generate_health_timer(config: &UnitConfig) -> String {
+    let interval = config
+        .health_interval
+        .as_deref()
+        .unwrap_or(DEFAULT_HEALTH_INTERVAL);
+    format!(
+        "[Unit]\n\
+         Description=[sdtab] {name} health check timer\n\
+         \n\
+         [Timer]\n\
+         OnActiveSec={interval}\n\
+         OnUnitActiveSec={interval}\n\
+         AccuracySec=1s\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        name = config.name,
+        interval = interval,
+    )
+}
+
+/// Blue/green instances read their `PORT` from `<config>/slots/<name>/<slot>.env`.
+fn slot_env_line(name: &str) -> String {
+    match init::config_dir() {
+        Ok(dir) => format!("EnvironmentFile={}/slots/{}/%i.env\n", dir, name),
+        Err(_) => String::new(),
+    }
+}
+
 fn global_env_line() -> String {
     match init::global_env_path() {
         Ok(path) => format!("EnvironmentFile=-{}\n", path),
@@ -203,8 +513,32 @@ fn global_env_line() -> String {
     }
 }
 
+/// Directives that only apply to daemon services: reload command, PID file
+/// for `Type=forking`, and the watchdog (which needs `NotifyAccess=` so the
+/// main process may send `WATCHDOG=1`).
+fn generate_daemon_options(config: &UnitConfig) -> String {
+    let mut lines = String::new();
+    if let Some(ref val) = config.exec_reload {
+        lines.push_str(&format!("ExecReload={}\n", val));
+    }
+    if let Some(ref val) = config.pid_file {
+        lines.push_str(&format!("PIDFile={}\n", val));
+    }
+    if let Some(ref val) = config.watchdog {
+        lines.push_str(&format!("WatchdogSec={}\n", val));
+        lines.push_str("NotifyAccess=main\n");
+    }
+    lines
+}
+
 fn generate_service_options(config: &UnitConfig) -> String {
     let mut lines = String::new();
+    if let Some(ref val) = config.user {
+        lines.push_str(&format!("User={}\n", val));
+    }
+    if let Some(ref val) = config.group {
+        lines.push_str(&format!("Group={}\n", val));
+    }
     if let Some(ref val) = config.exec_start_pre {
         lines.push_str(&format!("ExecStartPre={}\n", val));
     }
@@ -226,18 +560,57 @@ fn generate_service_options(config: &UnitConfig) -> String {
     if let Some(ref val) = config.log_level_max {
         lines.push_str(&format!("LogLevelMax={}\n", val));
     }
+    lines.push_str(&output_lines(&config.name, &config.output));
     for env_val in &config.env {
         lines.push_str(&format!("Environment={}\n", env_val));
     }
     lines
 }
 
+/// The target long-running services are installed into.
+pub fn default_target() -> &'static str {
+    if init::system() {
+        "multi-user.target"
+    } else {
+        "default.target"
+    }
+}
+
 pub fn service_filename(name: &str) -> String {
-    format!("sdtab-{}.service", name)
+    format!("{}{}.service", init::prefix(), name)
 }
 
 pub fn timer_filename(name: &str) -> String {
-    format!("sdtab-{}.timer", name)
+    format!("{}{}.timer", init::prefix(), name)
+}
+
+/// Blue/green services are generated as a template; `@blue`/`@green` are the instances.
+pub fn template_filename(name: &str) -> String {
+    format!("{}{}@.service", init::prefix(), name)
+}
+
+pub fn instance_filename(name: &str, slot: &str) -> String {
+    format!("{}{}@{}.service", init::prefix(), name, slot)
+}
+
+/// Health units are instance-named (`sdtab-health@<name>`) so unit scans,
+/// which skip `@` files, never mistake them for user units.
+pub fn health_service_filename(name: &str) -> String {
+    format!("{}health@{}.service", init::prefix(), name)
+}
+
+pub fn health_timer_filename(name: &str) -> String {
+    format!("{}health@{}.timer", init::prefix(), name)
+}
+
+/// The failure-notification template installed by `sdtab init`.
+pub fn notify_template_filename() -> String {
+    format!("{}notify@.service", init::prefix())
+}
+
+/// `OnFailure=` target that hands the failed unit's name to the notify template.
+pub fn notify_on_failure() -> String {
+    format!("{}notify@%n.service", init::prefix())
 }
 
 /// Extract a timer name from a command string.
@@ -326,8 +699,8 @@ mod tests {
         };
 
         let service = generate_service(&config);
-        assert!(service.contains("# sdtab:type=timer"));
-        assert!(service.contains("# sdtab:cron=0 9 * * *"));
+        assert!(service.contains("X-Sdtab-Type=timer"));
+        assert!(service.contains("X-Sdtab-Cron=0 9 * * *"));
         assert!(service.contains("Description=[sdtab] report: daily report"));
         assert!(service.contains("ExecStart=uv run ./report.py"));
         assert!(service.contains("WorkingDirectory=/home/user/project"));
@@ -335,7 +708,7 @@ mod tests {
         // SyslogIdentifier enables `journalctl --user-unit` to capture child stdout
         assert!(service.contains("SyslogIdentifier=sdtab-report"));
         // template_version stamp lets sdtab detect legacy units
-        assert!(service.contains(&format!("# sdtab:template_version={}", TEMPLATE_VERSION)));
+        assert!(service.contains(&format!("X-Sdtab-Template-Version={}", TEMPLATE_VERSION)));
     }
 
     #[test]
@@ -391,8 +764,8 @@ mod tests {
         };
 
         let service = generate_daemon_service(&config);
-        assert!(service.contains("# sdtab:type=service"));
-        assert!(service.contains("# sdtab:restart=on-failure"));
+        assert!(service.contains("X-Sdtab-Type=service"));
+        assert!(service.contains("X-Sdtab-Restart=on-failure"));
         assert!(service.contains("Type=simple"));
         assert!(service.contains("ExecStart=ambient-task-agent serve --port 3000"));
         assert!(service.contains("Restart=on-failure"));
@@ -408,7 +781,7 @@ mod tests {
         // SyslogIdentifier enables `journalctl --user-unit` to capture child stdout
         assert!(service.contains("SyslogIdentifier=sdtab-agent"));
         // template_version stamp lets sdtab detect legacy units
-        assert!(service.contains(&format!("# sdtab:template_version={}", TEMPLATE_VERSION)));
+        assert!(service.contains(&format!("X-Sdtab-Template-Version={}", TEMPLATE_VERSION)));
     }
 
     #[test]
@@ -422,6 +795,9 @@ mod tests {
         };
 
         let service = generate_daemon_service(&config);
+        assert!(service.contains("Type=simple"));
+        assert!(!service.contains("WatchdogSec"));
+        assert!(!service.contains("ExecReload"));
         assert!(service.contains("Restart=always"));
         // Global env is always present (with - prefix for optional)
         assert!(service.contains("EnvironmentFile=-"));
@@ -446,11 +822,14 @@ mod tests {
             io_weight: Some("10".to_string()),
             timeout_stop: Some("30s".to_string()),
             log_level_max: Some("warning".to_string()),
+            user: Some("backup".to_string()),
+            group: Some("adm".to_string()),
             random_delay: Some("5m".to_string()),
             ..Default::default()
         };
 
         let service = generate_service(&config);
+        assert!(service.contains("User=backup\nGroup=adm\n"));
         assert!(service.contains("MemoryMax=512M"));
         assert!(service.contains("CPUQuota=50%"));
         assert!(service.contains("IOWeight=10"));
@@ -520,7 +899,7 @@ mod tests {
 
         let service = generate_service(&config);
         assert!(service.contains("OnFailure=sdtab-notify@%n.service"));
-        assert!(!service.contains("# sdtab:no-notify=true"));
+        assert!(!service.contains("X-Sdtab-No-Notify=true"));
     }
 
     #[test]
@@ -540,7 +919,7 @@ mod tests {
         };
 
         let service = generate_service(&config);
-        assert!(service.contains("# sdtab:no-notify=true"));
+        assert!(service.contains("X-Sdtab-No-Notify=true"));
         assert!(!service.contains("OnFailure="));
     }
 
@@ -558,6 +937,190 @@ mod tests {
 
         let service = generate_daemon_service(&config);
         assert!(service.contains("OnFailure=sdtab-notify@%n.service"));
-        assert!(!service.contains("# sdtab:no-notify=true"));
+        assert!(!service.contains("X-Sdtab-No-Notify=true"));
+    }
+
+    #[test]
+    fn test_daemon_notify_with_watchdog() {
+        let config = UnitConfig {
+            name: "api".to_string(),
+            command: "./api serve".to_string(),
+            workdir: "/home/user".to_string(),
+            description: "api".to_string(),
+            service_type: Some("notify".to_string()),
+            watchdog: Some("30s".to_string()),
+            exec_reload: Some("/bin/kill -HUP $MAINPID".to_string()),
+            ..Default::default()
+        };
+
+        let service = generate_daemon_service(&config);
+        assert!(service.contains("Type=notify"));
+        assert!(service.contains("WatchdogSec=30s"));
+        assert!(service.contains("NotifyAccess=main"));
+        assert!(service.contains("ExecReload=/bin/kill -HUP $MAINPID"));
+    }
+
+    #[test]
+    fn test_daemon_forking_pid_file() {
+        let config = UnitConfig {
+            name: "legacyd".to_string(),
+            command: "/usr/sbin/legacyd".to_string(),
+            workdir: "/home/user".to_string(),
+            description: "legacyd".to_string(),
+            service_type: Some("forking".to_string()),
+            pid_file: Some("/run/user/1000/legacyd.pid".to_string()),
+            ..Default::default()
+        };
+
+        let service = generate_daemon_service(&config);
+        assert!(service.contains("Type=forking"));
+        assert!(service.contains("PIDFile=/run/user/1000/legacyd.pid"));
+        assert!(!service.contains("NotifyAccess"));
+    }
+
+    #[test]
+    fn test_health_units() {
+        let config = UnitConfig {
+            name: "web".to_string(),
+            command: "node server.js".to_string(),
+            workdir: "/home/user/app".to_string(),
+            description: "web".to_string(),
+            health_cmd: Some("curl -fs localhost:3000/health".to_string()),
+            health_retries: Some(5),
+            ..Default::default()
+        };
+
+        let service = generate_daemon_service(&config);
+        assert!(service.contains("X-Sdtab-Health-Cmd=curl -fs localhost:3000/health"));
+        assert!(service.contains("X-Sdtab-Health-Retries=5"));
+        // Interval was not given, so no metadata (the default stays implicit)
+        assert!(!service.contains("X-Sdtab-Health-Interval"));
+
+        let probe = generate_health_service(&config, "/usr/local/bin/sdtab");
+        assert!(probe.contains("ExecStart=/usr/local/bin/sdtab __health-check web"));
+        assert!(probe.contains("WorkingDirectory=/home/user/app"));
+
+        let timer = generate_health_timer(&config);
+        assert!(timer.contains(&format!("OnUnitActiveSec={}", DEFAULT_HEALTH_INTERVAL)));
+        assert_eq!(health_timer_filename("web"), "sdtab-health@web.timer");
+    }
+
+    #[test]
+    fn test_blue_green_template() {
+        let config = UnitConfig {
+            name: "web".to_string(),
+            command: "node server.js".to_string(),
+            workdir: "/home/user/app".to_string(),
+            description: "web".to_string(),
+            ports: vec![3000, 3001],
+            ..Default::default()
+        };
+
+        let service = generate_daemon_service(&config);
+        assert!(service.contains("X-Sdtab-Ports=3000,3001"));
+        assert!(service.contains("/slots/web/%i.env"));
+        assert_eq!(template_filename("web"), "sdtab-web@.service");
+        assert_eq!(instance_filename("web", "green"), "sdtab-web@green.service");
+    }
+
+    #[test]
+    fn test_output_file_and_mail_hooks() {
+        let config = UnitConfig {
+            name: "backup".to_string(),
+            command: "/usr/bin/backup.sh".to_string(),
+            workdir: "/home/user".to_string(),
+            description: "backup".to_string(),
+            cron_expr: Some("0 3 * * *".to_string()),
+            output_file: Some("/home/user/logs/backup.log".to_string()),
+            mail_to: Some("me@example.com".to_string()),
+            output: OutputTarget {
+                file: Some("/home/user/logs/backup.log".to_string()),
+                max_size: "10M".to_string(),
+                keep: 5,
+                mail_to: Some("me@example.com".to_string()),
+                sdtab_bin: "/usr/bin/sdtab".to_string(),
+            },
+            ..Default::default()
+        };
+
+        let service = generate_service(&config);
+        assert!(service.contains("X-Sdtab-Output-File=/home/user/logs/backup.log"));
+        assert!(service.contains("X-Sdtab-Mail-To=me@example.com"));
+        assert!(service.contains("StandardOutput=append:/home/user/logs/backup.log\n"));
+        assert!(service.contains("StandardError=append:/home/user/logs/backup.log\n"));
+        assert!(service.contains(
+            "ExecStartPre=-/usr/bin/sdtab __output-rotate backup 10M 5 \"/home/user/logs/backup.log\"\n"
+        ));
+        assert!(service.contains(
+            "ExecStopPost=-/usr/bin/sdtab __mail-output backup me@example.com \"/home/user/logs/backup.log\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_output_defaults_not_written_as_metadata() {
+        let config = UnitConfig {
+            name: "job".to_string(),
+            output: OutputTarget {
+                file: Some("/var/log/jobs/job.log".to_string()),
+                max_size: "10M".to_string(),
+                keep: 5,
+                mail_to: None,
+                sdtab_bin: "sdtab".to_string(),
+            },
+            ..Default::default()
+        };
+        let service = generate_service(&config);
+        assert!(service.contains("StandardOutput=append:/var/log/jobs/job.log"));
+        assert!(!service.contains("X-Sdtab-Output-File="));
+        assert!(!service.contains("__mail-output"));
+    }
+
+    #[test]
+    fn test_output_path_specifiers_are_escaped() {
+        let config = UnitConfig {
+            name: "job".to_string(),
+            output: OutputTarget {
+                file: Some("/var/log/jobs/100%.log".to_string()),
+                max_size: "10M".to_string(),
+                keep: 5,
+                mail_to: Some("me@example.com".to_string()),
+                sdtab_bin: "sdtab".to_string(),
+            },
+            ..Default::default()
+        };
+        let service = generate_service(&config);
+        assert!(service.contains("StandardOutput=append:/var/log/jobs/100%%.log\n"));
+        assert!(service.contains("__output-rotate job 10M 5 \"/var/log/jobs/100%%.log\"\n"));
+        assert!(service.contains("__mail-output job me@example.com \"/var/log/jobs/100%%.log\"\n"));
+        let parsed = crate::parse_unit::parse_unit_file("job", &crate::unit_file::UnitFile::parse(&service), None, "");
+        assert_eq!(parsed.output.as_deref(), Some("/var/log/jobs/100%.log (max 10M, keep 5), mail to me@example.com"));
+    }
+
+    #[test]
+    fn meta_line_round_trips_through_parse_meta() {
+        let line = meta_line("health-cmd", "curl -fs \\\n localhost");
+        assert_eq!(line, "X-Sdtab-Health-Cmd=curl -fs \\\\\\n localhost\n");
+        assert_eq!(
+            parse_meta(line.trim_end()),
+            Some(("health-cmd".to_string(), "curl -fs \\\n localhost".to_string()))
+        );
+        assert_eq!(
+            parse_meta("# sdtab:template_version=4"),
+            Some(("template-version".to_string(), "4".to_string()))
+        );
+        assert_eq!(parse_meta("Description=x"), None);
+    }
+
+    #[test]
+    fn metadata_is_inside_unit_section() {
+        let config = UnitConfig {
+            name: "web".to_string(),
+            command: "/usr/bin/web".to_string(),
+            restart_policy: Some("always".to_string()),
+            ..Default::default()
+        };
+        let service = generate_daemon_service(&config);
+        assert!(service.starts_with("[Unit]\nX-Sdtab-Type=service\n"));
+        assert!(!service.contains("# sdtab:"));
     }
 }
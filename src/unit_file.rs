@@ -0,0 +1,287 @@
+//! Lossless model of a systemd unit file.
+//!
+//! Every line is kept verbatim (comments, blank lines, ordering, backslash
+//! continuations), so `to_string()` reproduces the input exactly and `set` /
+//! `remove` only touch the keys they are asked to. Lookups follow systemd's
+//! rules: sections may repeat, later assignments win, and an empty assignment
+//! (`ExecStartPre=`) resets a list-valued key.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    /// Blank line or `#`/`;` comment.
+    Other(String),
+    Section { name: String, raw: String },
+    /// `raw` spans several physical lines when the value was continued with `\`.
+    Entry { key: String, value: String, raw: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitFile {
+    lines: Vec<Line>,
+    trailing_newline: bool,
+}
+
+impl UnitFile {
+    pub fn parse(text: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut physical = text.lines();
+        while let Some(line) = physical.next() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+                lines.push(Line::Other(line.to_string()));
+            } else if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                lines.push(Line::Section {
+                    name: trimmed[1..trimmed.len() - 1].to_string(),
+                    raw: line.to_string(),
+                });
+            } else if let Some((key, value)) = trimmed.split_once('=') {
+                let mut raw = line.to_string();
+                let mut value = value.trim().to_string();
+                // systemd joins continued lines with a space and skips comment
+                // lines inside a continuation.
+                while let Some(head) = value.strip_suffix('\\') {
+                    value = head.trim_end().to_string();
+                    let Some(next) = physical.next() else { break };
+                    raw.push('\n');
+                    raw.push_str(next);
+                    let next = next.trim();
+                    if next.starts_with('#') || next.starts_with(';') {
+                        value.push('\\');
+                        continue;
+                    }
+                    value.push(' ');
+                    value.push_str(next);
+                }
+                lines.push(Line::Entry { key: key.trim().to_string(), value, raw });
+            } else {
+                // Not valid unit syntax; keep it so the file round-trips.
+                lines.push(Line::Other(line.to_string()));
+            }
+        }
+        UnitFile { lines, trailing_newline: text.ends_with('\n') }
+    }
+
+    /// Read a unit file plus its drop-ins (`<path>.d/*.conf`, in filename
+    /// order) as one effective configuration.
+    pub fn load_effective(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut file = Self::parse(&content);
+        let mut dropin_dir = path.as_os_str().to_owned();
+        dropin_dir.push(".d");
+        let mut dropins: Vec<_> = match fs::read_dir(&dropin_dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|ext| ext == "conf"))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        dropins.sort();
+        for dropin in dropins {
+            let content = fs::read_to_string(&dropin)
+                .with_context(|| format!("Failed to read {}", dropin.display()))?;
+            file.merge(&Self::parse(&content));
+        }
+        Ok(file)
+    }
+
+    /// Apply a drop-in on top of this file. Appending is enough: sections may
+    /// repeat and later assignments win.
+    pub fn merge(&mut self, dropin: &UnitFile) {
+        self.lines.extend(dropin.lines.iter().cloned());
+    }
+
+    /// `(section, key, value)` for every assignment, in file order.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str, &str)> {
+        let mut section = "";
+        self.lines.iter().filter_map(move |line| match line {
+            Line::Section { name, .. } => {
+                section = name;
+                None
+            }
+            Line::Entry { key, value, .. } => Some((section, key.as_str(), value.as_str())),
+            Line::Other(_) => None,
+        })
+    }
+
+    /// Comment lines (trimmed), in file order.
+    pub fn comments(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Other(raw) if raw.trim().starts_with('#') => Some(raw.trim()),
+            _ => None,
+        })
+    }
+
+    /// Effective value of a single-valued key: the last assignment wins, and
+    /// an empty assignment resets it to unset.
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.entries()
+            .filter(|(s, k, _)| *s == section && *k == key)
+            .last()
+            .map(|(_, _, v)| v)
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Effective values of a list-valued key (`ExecStartPre=`, `Environment=`):
+    /// assignments accumulate and an empty assignment clears what came before.
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<&str> {
+        let mut values = Vec::new();
+        for (_, _, value) in self.entries().filter(|(s, k, _)| *s == section && *k == key) {
+            if value.is_empty() {
+                values.clear();
+            } else {
+                values.push(value);
+            }
+        }
+        values
+    }
+
+    /// Set a single-valued key. The last existing assignment is rewritten in
+    /// place and earlier ones are dropped; otherwise the key is appended to
+    /// the end of the section, which is created if missing.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let positions = self.positions(section, key);
+        let entry = Line::Entry { key: key.to_string(), value: value.to_string(), raw: format!("{}={}", key, value) };
+        if let Some((&last, earlier)) = positions.split_last() {
+            self.lines[last] = entry;
+            for &i in earlier.iter().rev() {
+                self.lines.remove(i);
+            }
+            return;
+        }
+        match self.section_end(section) {
+            Some(end) => self.lines.insert(end, entry),
+            None => {
+                if self.lines.last().is_some_and(|l| !matches!(l, Line::Other(raw) if raw.trim().is_empty())) {
+                    self.lines.push(Line::Other(String::new()));
+                }
+                self.lines.push(Line::Section { name: section.to_string(), raw: format!("[{}]", section) });
+                self.lines.push(entry);
+                self.trailing_newline = true;
+            }
+        }
+    }
+
+    fn positions(&self, section: &str, key: &str) -> Vec<usize> {
+        let mut current = "";
+        let mut positions = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Section { name, .. } => current = name,
+                Line::Entry { key: k, .. } if current == section && k == key => positions.push(i),
+                _ => {}
+            }
+        }
+        positions
+    }
+
+    /// Index just after the last assignment of the last `[section]` block.
+    fn section_end(&self, section: &str) -> Option<usize> {
+        let mut current = "";
+        let mut end = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Section { name, .. } => {
+                    current = name;
+                    if current == section {
+                        end = Some(i + 1);
+                    }
+                }
+                Line::Entry { .. } if current == section => end = Some(i + 1),
+                _ => {}
+            }
+        }
+        end
+    }
+}
+
+impl fmt::Display for UnitFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match line {
+                Line::Other(raw) | Line::Section { raw, .. } | Line::Entry { raw, .. } => f.write_str(raw)?,
+            }
+        }
+        if self.trailing_newline && !self.lines.is_empty() {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICE: &str = "\
+# leading comment
+[Unit]
+Description=web
+X-Sdtab-Type=service
+
+[Service]
+ExecStart=/usr/bin/web \\
+  --port 3000 \\
+  # inline comment
+  --verbose
+ExecStartPre=/bin/a
+ExecStartPre=/bin/b
+Environment=A=1
+MemoryMax=1G
+";
+
+    #[test]
+    fn round_trips_losslessly() {
+        assert_eq!(UnitFile::parse(SERVICE).to_string(), SERVICE);
+        assert_eq!(UnitFile::parse("[Unit]\nDescription=x").to_string(), "[Unit]\nDescription=x");
+    }
+
+    #[test]
+    fn joins_continuations_and_tracks_sections() {
+        let file = UnitFile::parse(SERVICE);
+        assert_eq!(file.get("Service", "ExecStart"), Some("/usr/bin/web --port 3000 --verbose"));
+        assert_eq!(file.get("Unit", "ExecStart"), None);
+        assert_eq!(file.get_all("Service", "ExecStartPre"), vec!["/bin/a", "/bin/b"]);
+        // Comments inside a continuation belong to the entry, not the file.
+        assert_eq!(file.comments().collect::<Vec<_>>(), vec!["# leading comment"]);
+    }
+
+    #[test]
+    fn dropins_override_and_reset() {
+        let mut file = UnitFile::parse(SERVICE);
+        file.merge(&UnitFile::parse("[Service]\nExecStartPre=\nExecStartPre=/bin/c\nMemoryMax=2G\nEnvironment=B=2\n"));
+        assert_eq!(file.get_all("Service", "ExecStartPre"), vec!["/bin/c"]);
+        assert_eq!(file.get("Service", "MemoryMax"), Some("2G"));
+        assert_eq!(file.get_all("Service", "Environment"), vec!["A=1", "B=2"]);
+
+        file.merge(&UnitFile::parse("[Service]\nMemoryMax=\n"));
+        assert_eq!(file.get("Service", "MemoryMax"), None);
+    }
+
+    #[test]
+    fn set_touches_only_its_key() {
+        let mut file = UnitFile::parse(SERVICE);
+        file.set("Service", "MemoryMax", "2G");
+        file.set("Unit", "After", "network.target");
+        assert_eq!(
+            file.to_string(),
+            SERVICE
+                .replace("MemoryMax=1G", "MemoryMax=2G")
+                .replace("X-Sdtab-Type=service\n", "X-Sdtab-Type=service\nAfter=network.target\n")
+        );
+
+        file.set("Service", "ExecStartPre", "/bin/c");
+        assert_eq!(file.get_all("Service", "ExecStartPre"), vec!["/bin/c"]);
+
+        file.set("Install", "WantedBy", "default.target");
+        assert!(file.to_string().ends_with("MemoryMax=2G\n\n[Install]\nWantedBy=default.target\n"));
+    }
+}
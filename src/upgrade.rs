@@ -4,17 +4,18 @@
 //! Strategy: systemd drop-in overrides.
 //! For each legacy unit, we create `~/.config/systemd/user/sdtab-<name>.service.d/sdtab.conf`
 //! and write just the new directives introduced since the unit was generated.
-//! The original `.service` file is updated only in one place: the
-//! `# sdtab:template_version=N` comment is bumped so `sdtab list` stops
-//! flagging the unit as legacy. systemd ignores comment changes, so no
-//! restart is needed — only a `daemon-reload`.
+//! The original `.service` file is only touched for metadata: v5 moves the
+//! `# sdtab:` comments into `X-Sdtab-*` keys, and the `X-Sdtab-Template-Version`
+//! stamp is bumped so `sdtab list` stops flagging the unit as legacy. systemd
+//! ignores both, so no restart is needed — only a `daemon-reload`.
 
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 
-use crate::{init, parse_unit, systemctl, unit};
+use crate::unit_file::UnitFile;
+use crate::{bluegreen, init, parse_unit, systemctl, unit};
 
 /// Run the upgrade flow. When `name` is `Some`, upgrade a single unit; otherwise
 /// upgrade every legacy unit found by `scan_all_units`. With `dry_run=true`,
@@ -105,12 +106,12 @@ fn upgrade_unit(u: &parse_unit::ParsedUnit, dry_run: bool) -> Result<Vec<String>
         actions.extend(step_actions);
     }
 
-    // Bump the `# sdtab:template_version=N` stamp in the .service file.
-    // Comments are not parsed by systemd, so this is a no-op from systemd's perspective.
+    // Bump the template version stamp in the .service file.
+    // systemd ignores `X-` keys, so this is a no-op from systemd's perspective.
     if !dry_run {
         stamp_service_file(&u.name)?;
     }
-    actions.push(format!("stamped # sdtab:template_version={}", unit::TEMPLATE_VERSION));
+    actions.push(format!("stamped X-Sdtab-Template-Version={}", unit::TEMPLATE_VERSION));
 
     Ok(actions)
 }
@@ -122,6 +123,7 @@ fn apply_migration(u: &parse_unit::ParsedUnit, target_version: u32, dry_run: boo
         2 => migrate_v2_syslog_identifier(u, dry_run),
         3 => migrate_v3_success_exit_status(u, dry_run),
         4 => migrate_v4_start_limit(u, dry_run),
+        5 => migrate_v5_metadata_keys(u, dry_run),
         _ => anyhow::bail!("no migration defined for template version {}", target_version),
     }
 }
@@ -130,7 +132,7 @@ fn apply_migration(u: &parse_unit::ParsedUnit, target_version: u32, dry_run: boo
 /// child-process stdout on systems where journald fails to attach user-unit
 /// metadata to child stream records.
 fn migrate_v2_syslog_identifier(u: &parse_unit::ParsedUnit, dry_run: bool) -> Result<Vec<String>> {
-    let directive = format!("SyslogIdentifier=sdtab-{}", u.name);
+    let directive = format!("SyslogIdentifier={}{}", init::prefix(), u.name);
     if !dry_run {
         let content = format!(
             "# Added by `sdtab upgrade` to reach template_version=2\n\
@@ -184,6 +186,42 @@ fn migrate_v4_start_limit(u: &parse_unit::ParsedUnit, dry_run: bool) -> Result<V
     Ok(vec!["drop-in: StartLimitIntervalSec=300 + StartLimitBurst=3".to_string()])
 }
 
+/// v5: move `# sdtab:key=value` comments into `X-Sdtab-*` keys in `[Unit]`.
+/// Unlike earlier steps this rewrites the .service file itself — the keys are
+/// metadata, not directives, so a drop-in would not help `parse_unit_file`.
+fn migrate_v5_metadata_keys(u: &parse_unit::ParsedUnit, dry_run: bool) -> Result<Vec<String>> {
+    if !dry_run {
+        let path = service_path(&u.name)?;
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        fs::write(&path, convert_legacy_metadata(&content))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(vec!["metadata: # sdtab: comments → X-Sdtab-* keys".to_string()])
+}
+
+/// Drop the legacy metadata comments and re-insert them as `X-Sdtab-*` keys
+/// right after the `[Unit]` header, preserving their order.
+fn convert_legacy_metadata(content: &str) -> String {
+    let mut meta = String::new();
+    let mut rest = Vec::new();
+    for line in content.lines() {
+        match unit::parse_meta(line.trim()) {
+            Some((key, val)) if line.trim().starts_with("# sdtab:") => meta.push_str(&unit::meta_line(&key, &val)),
+            _ => rest.push(line),
+        }
+    }
+    unit::insert_metadata(&rest, &meta)
+}
+
+/// Update (or insert) the `X-Sdtab-Template-Version` key, leaving the rest
+/// of the file untouched.
+fn stamp_content(content: &str) -> String {
+    let mut file = UnitFile::parse(content);
+    file.set("Unit", &unit::meta_key("template-version"), &unit::TEMPLATE_VERSION.to_string());
+    file.to_string()
+}
+
 /// Write (or overwrite) a drop-in file for the given unit.
 /// Creates `~/.config/systemd/user/sdtab-<name>.service.d/<filename>`.
 fn write_dropin(unit_name: &str, filename: &str, content: &str) -> Result<()> {
@@ -198,57 +236,20 @@ fn write_dropin(unit_name: &str, filename: &str, content: &str) -> Result<()> {
 
 fn dropin_dir(unit_name: &str) -> Result<PathBuf> {
     let unit_dir = init::unit_dir()?;
-    Ok(Path::new(&unit_dir).join(format!("sdtab-{}.service.d", unit_name)))
+    Ok(Path::new(&unit_dir).join(format!("{}.d", bluegreen::definition_filename(unit_name))))
 }
 
-/// Update (or insert) the `# sdtab:template_version=N` line in the .service file.
-/// This is a pure comment change and does not require systemctl daemon-reload,
-/// but we daemon-reload anyway once drop-ins are placed.
-fn stamp_service_file(unit_name: &str) -> Result<()> {
+fn service_path(unit_name: &str) -> Result<PathBuf> {
     let unit_dir = init::unit_dir()?;
-    let path = Path::new(&unit_dir).join(unit::service_filename(unit_name));
+    Ok(Path::new(&unit_dir).join(bluegreen::definition_filename(unit_name)))
+}
+
+/// Bump the template version stamp in the .service file.
+fn stamp_service_file(unit_name: &str) -> Result<()> {
+    let path = service_path(unit_name)?;
     let content = fs::read_to_string(&path)
         .with_context(|| format!("Failed to read {}", path.display()))?;
-
-    let stamp = format!("# sdtab:template_version={}", unit::TEMPLATE_VERSION);
-    let new_content = if content.lines().any(|l| l.trim().starts_with("# sdtab:template_version=")) {
-        // Replace existing line
-        let mut out = String::with_capacity(content.len());
-        for line in content.lines() {
-            if line.trim().starts_with("# sdtab:template_version=") {
-                out.push_str(&stamp);
-            } else {
-                out.push_str(line);
-            }
-            out.push('\n');
-        }
-        out
-    } else {
-        // Insert after the `# sdtab:type=...` line (first existing metadata comment).
-        // If none is present, prepend at the top.
-        let mut out = String::with_capacity(content.len() + stamp.len() + 1);
-        let mut inserted = false;
-        for line in content.lines() {
-            out.push_str(line);
-            out.push('\n');
-            if !inserted && line.trim().starts_with("# sdtab:type=") {
-                out.push_str(&stamp);
-                out.push('\n');
-                inserted = true;
-            }
-        }
-        if !inserted {
-            // No type metadata — prepend.
-            let mut prefixed = String::with_capacity(content.len() + stamp.len() + 1);
-            prefixed.push_str(&stamp);
-            prefixed.push('\n');
-            prefixed.push_str(&out);
-            out = prefixed;
-        }
-        out
-    };
-
-    fs::write(&path, new_content)
+    fs::write(&path, stamp_content(&content))
         .with_context(|| format!("Failed to write {}", path.display()))?;
     Ok(())
 }
@@ -258,44 +259,38 @@ mod tests {
     use super::*;
 
     #[test]
-    fn stamp_replaces_existing_line() {
-        let input = "# sdtab:type=timer\n# sdtab:template_version=1\n# sdtab:cron=@daily\n[Unit]\n";
-        // Simulate the replacement logic in isolation.
-        let stamp = format!("# sdtab:template_version={}", unit::TEMPLATE_VERSION);
-        let mut out = String::new();
-        for line in input.lines() {
-            if line.trim().starts_with("# sdtab:template_version=") {
-                out.push_str(&stamp);
-            } else {
-                out.push_str(line);
-            }
-            out.push('\n');
-        }
-        assert!(out.contains(&format!("# sdtab:template_version={}", unit::TEMPLATE_VERSION)));
-        assert!(!out.contains("# sdtab:template_version=1"));
+    fn stamp_replaces_existing_key() {
+        let input = "[Unit]\nX-Sdtab-Type=timer\nX-Sdtab-Template-Version=4\nDescription=x\n";
+        let out = stamp_content(input);
+        assert!(out.contains(&format!("X-Sdtab-Template-Version={}\n", unit::TEMPLATE_VERSION)));
+        assert!(!out.contains("X-Sdtab-Template-Version=4"));
+        assert_eq!(out.lines().count(), input.lines().count());
     }
 
     #[test]
-    fn stamp_inserts_after_type_when_missing() {
-        let input = "# sdtab:type=timer\n# sdtab:cron=@daily\n[Unit]\n";
-        let stamp = format!("# sdtab:template_version={}", unit::TEMPLATE_VERSION);
-        let mut out = String::new();
-        let mut inserted = false;
-        for line in input.lines() {
-            out.push_str(line);
-            out.push('\n');
-            if !inserted && line.trim().starts_with("# sdtab:type=") {
-                out.push_str(&stamp);
-                out.push('\n');
-                inserted = true;
-            }
-        }
-        assert!(inserted);
-        assert!(out.contains(&format!("# sdtab:template_version={}", unit::TEMPLATE_VERSION)));
-        // Stamp should be on the line right after type=
+    fn stamp_inserts_into_unit_section_when_missing() {
+        let out = stamp_content("[Unit]\nDescription=x\n\n[Service]\nExecStart=/bin/true\n");
         let lines: Vec<&str> = out.lines().collect();
-        assert_eq!(lines[0], "# sdtab:type=timer");
-        assert_eq!(lines[1], format!("# sdtab:template_version={}", unit::TEMPLATE_VERSION));
+        assert_eq!(lines[0], "[Unit]");
+        assert_eq!(lines[1], "Description=x");
+        assert_eq!(lines[2], format!("X-Sdtab-Template-Version={}", unit::TEMPLATE_VERSION));
+        assert_eq!(lines[3], "");
+    }
+
+    #[test]
+    fn convert_moves_comments_into_unit_section() {
+        let input = "# sdtab:type=timer\n# sdtab:template_version=4\n# sdtab:cron=@daily\n\
+                     # sdtab:deploy-step=make\n[Unit]\nDescription=[sdtab] job: job\n\n\
+                     [Service]\n# a user comment\nExecStart=/bin/job\n";
+        let out = convert_legacy_metadata(input);
+        assert_eq!(
+            out,
+            "[Unit]\nX-Sdtab-Type=timer\nX-Sdtab-Template-Version=4\nX-Sdtab-Cron=@daily\n\
+             X-Sdtab-Deploy-Step=make\nDescription=[sdtab] job: job\n\n\
+             [Service]\n# a user comment\nExecStart=/bin/job\n"
+        );
+        // Already-converted files are left alone.
+        assert_eq!(convert_legacy_metadata(&out), out);
     }
 
     #[test]
@@ -316,9 +311,28 @@ mod tests {
             exec_start_pre: None,
             exec_stop_post: None,
             log_level_max: None,
+            user: None,
+            group: None,
             random_delay: None,
             env: vec![],
             no_notify: false,
+            service_type: None,
+            pid_file: None,
+            watchdog: None,
+            exec_reload: None,
+            health_cmd: None,
+            health_interval: None,
+            health_retries: None,
+            ports: vec![],
+            deploy_steps: vec![],
+            rollback_steps: vec![],
+            output_file: None,
+            output_max_size: None,
+            output_keep: None,
+            mail_to: None,
+            output: None,
+            origin: None,
+            project: None,
             template_version: 1,
         };
         // We can't actually write files in this test without mocking init::unit_dir(),
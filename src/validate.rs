@@ -0,0 +1,122 @@
+//! `sdtab validate`: check an Sdtabfile without touching the host's units.
+//!
+//! Loading already rejects TOML syntax errors, unknown keys and names defined
+//! twice. On top of that every value is checked against its form in
+//! [`schema`], workdirs and env files must exist, and each entry must render
+//! into unit files the way `apply` would.
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use toml::{Table, Value};
+
+use crate::sdtabfile::{self, Sdtabfile};
+use crate::{apply, parse_unit, schema};
+
+pub fn run(file: Option<&str>, profile: Option<&str>, print_schema: bool) -> Result<()> {
+    if print_schema {
+        println!("{}", serde_json::to_string_pretty(&schema::json_schema())?);
+        return Ok(());
+    }
+    let Some(file) = file else {
+        bail!("Give an Sdtabfile to validate, or --schema");
+    };
+    let sdtabfile = sdtabfile::load(file, &sdtabfile::Target::current(profile)?)?;
+    let problems = check(&sdtabfile)?;
+    if !problems.is_empty() {
+        bail!("{} problem(s) in {}:\n  {}", problems.len(), file, problems.join("\n  "));
+    }
+    println!(
+        "{} is valid: {} timer(s), {} service(s)",
+        file,
+        sdtabfile.timers.len(),
+        sdtabfile.services.len()
+    );
+    Ok(())
+}
+
+/// Everything wrong with the loaded entries, as `<kind>.<name>.<key>: why`.
+fn check(sdtabfile: &Sdtabfile) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    for name in sdtabfile.timers.keys() {
+        let entry = Table::try_from(&sdtabfile.timers[name])?;
+        check_entry(sdtabfile, name, "timers", &entry, schema::TIMER_KEYS, &mut problems);
+    }
+    for name in sdtabfile.services.keys() {
+        let entry = Table::try_from(&sdtabfile.services[name])?;
+        check_entry(sdtabfile, name, "services", &entry, schema::SERVICE_KEYS, &mut problems);
+    }
+    Ok(problems)
+}
+
+fn check_entry(
+    sdtabfile: &Sdtabfile,
+    name: &str,
+    kind: &str,
+    entry: &Table,
+    keys: &'static [schema::Key],
+    problems: &mut Vec<String>,
+) {
+    let before = problems.len();
+    for (key, value) in entry {
+        let (Some(spec), Value::String(value)) = (schema::find(keys, key), value) else {
+            continue;
+        };
+        let problem = match spec.kind {
+            schema::Kind::Path if key == "workdir" && !Path::new(value).is_dir() => {
+                Some(format!("directory {} does not exist", value))
+            }
+            schema::Kind::Path if !Path::new(value).exists() => Some(format!("{} does not exist", value)),
+            kind => schema::check_value(kind, value),
+        };
+        if let Some(problem) = problem {
+            problems.push(format!("{}.{}.{}: {}", kind, name, key, problem));
+        }
+    }
+
+    // Rendering repeats the value checks, so only try it on entries that
+    // passed them; it catches the rest (users, service type options, ports).
+    if problems.len() == before {
+        let unit_type = match kind {
+            "timers" => parse_unit::UnitType::Timer,
+            _ => parse_unit::UnitType::Service,
+        };
+        if let Err(e) = apply::entry_files(sdtabfile, name, &unit_type) {
+            problems.push(format!("{}.{}: {:#}", kind, name, e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn reports_every_bad_value() {
+        let dir = TestDir::new("validate");
+        let toml = format!(
+            "[timers.backup]\nschedule = \"@dayly\"\ncommand = \"./backup.sh\"\nworkdir = \"{dir}\"\n\
+             memory_max = \"512MB\"\nno_notify = true\n\n\
+             [services.web]\ncommand = \"./web\"\nworkdir = \"{dir}/missing\"\ncpu_quota = \"50\"\n\
+             env_file = \"{dir}/.env\"\nno_notify = true\n\n\
+             [services.api]\ncommand = \"./api\"\nworkdir = \"{dir}\"\ntimeout_stop = \"30s\"\nno_notify = true\n",
+            dir = dir.display()
+        );
+        let file = sdtabfile::parse(&toml, "/", &sdtabfile::Target::default()).unwrap();
+        let problems = check(&file).unwrap();
+        let keys: Vec<&str> = problems.iter().map(|p| p.split(':').next().unwrap()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "timers.backup.schedule",
+                "timers.backup.memory_max",
+                "services.web.workdir",
+                "services.web.env_file",
+                "services.web.cpu_quota",
+            ],
+            "{:#?}",
+            problems
+        );
+    }
+}